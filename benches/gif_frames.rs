@@ -0,0 +1,41 @@
+//! Compares counting GIF frames with `gif::count_frames()` against loading full metadata.
+//!
+//! Run with `cargo bench --bench gif_frames`.
+
+extern crate immeta;
+
+use std::fs::File;
+use std::io::{Read, Cursor};
+use std::time::{Duration, Instant};
+
+use immeta::LoadableMetadata;
+use immeta::formats::gif;
+
+const ITERATIONS: u32 = 200;
+
+fn measure<F: FnMut() -> usize>(name: &str, mut f: F) {
+    let start = Instant::now();
+    let mut frames = 0;
+    for _ in 0..ITERATIONS {
+        frames = f();
+    }
+    let elapsed = start.elapsed();
+    let per_iteration = elapsed / ITERATIONS;
+    println!("{:<16} {:>6} frames, {:>10.3} us/iter", name, frames, micros(per_iteration));
+}
+
+fn micros(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1e6 + d.subsec_nanos() as f64 / 1e3
+}
+
+fn main() {
+    let mut data = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut data).unwrap();
+
+    measure("load metadata", || {
+        gif::Metadata::load(&mut Cursor::new(&data)).unwrap().frames_number()
+    });
+    measure("count frames", || {
+        gif::count_frames(&mut Cursor::new(&data)).unwrap()
+    });
+}
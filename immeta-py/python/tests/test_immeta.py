@@ -0,0 +1,49 @@
+import os
+
+import pytest
+
+import immeta
+
+IMAGES = os.path.join(os.path.dirname(__file__), "..", "..", "..", "tests", "images")
+
+
+def image_path(name):
+    return os.path.join(IMAGES, name)
+
+
+def test_load_path():
+    md = immeta.load(image_path("owlet.png"))
+    assert md["format"] == "png"
+    assert md["mime_type"] == "image/png"
+    assert (md["width"], md["height"]) == (1280, 857)
+    assert md["png"]["color_type"] == "rgb"
+    assert "jpeg" not in md
+    assert md.get("jpeg") is None
+    assert md.get("jpeg", {}) == {}
+    assert set(md) == set(md.keys())
+    assert dict(md.items()) == md.to_dict()
+
+
+def test_load_bytes():
+    with open(image_path("drop.gif"), "rb") as f:
+        md = immeta.load(f.read())
+    assert md["format"] == "gif"
+    assert md["gif"]["frames"] == 30
+
+
+def test_to_dict_is_a_copy():
+    md = immeta.load(image_path("owlet.jpg"))
+    d = md.to_dict()
+    d["jpeg"]["precision"] = 0
+    assert md["jpeg"]["precision"] == 8
+
+
+def test_errors():
+    with pytest.raises(immeta.ImmetaError):
+        immeta.load(b"not an image")
+    with pytest.raises(ValueError):
+        immeta.load(b"")
+    with pytest.raises(FileNotFoundError):
+        immeta.load(image_path("missing.png"))
+    with pytest.raises(KeyError):
+        immeta.load(image_path("owlet.png"))["missing"]
@@ -0,0 +1,143 @@
+//! Python bindings for immeta.
+//!
+//! The `immeta` Python module exposes a single `load()` function which accepts a path or
+//! a `bytes` object and returns a `Metadata` object. `Metadata` behaves like a read-only
+//! dictionary whose contents follow the stable export schema described in `immeta::export`:
+//!
+//! ```python
+//! import immeta
+//!
+//! md = immeta.load("kitty.png")
+//! print(md["format"], md["width"], md["height"])
+//! print(md.get("png", {}).get("color_type"))
+//! ```
+
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyIterator, PyList, PyString};
+
+create_exception!(immeta, ImmetaError, PyValueError, "Raised when image metadata can't be loaded.");
+
+/// Image metadata following the immeta export schema.
+///
+/// Supports read-only dictionary operations: indexing, `in`, `len()`, iteration over keys,
+/// `keys()`, `values()`, `items()` and `get()`. `to_dict()` returns a copy as a plain `dict`.
+#[pyclass(module = "immeta", frozen)]
+struct Metadata {
+    data: Py<PyDict>,
+}
+
+#[pymethods]
+impl Metadata {
+    fn __getitem__(&self, py: Python<'_>, key: &Bound<'_, PyAny>) -> PyResult<Py<PyAny>> {
+        match self.data.bind(py).get_item(key)? {
+            Some(value) => Ok(value.unbind()),
+            None => Err(PyKeyError::new_err(key.clone().unbind())),
+        }
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        self.data.bind(py).contains(key)
+    }
+
+    fn __len__(&self, py: Python<'_>) -> usize {
+        self.data.bind(py).len()
+    }
+
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        self.data.bind(py).as_any().try_iter()
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!("Metadata({})", self.data.bind(py).repr()?))
+    }
+
+    #[pyo3(signature = (key, default = None))]
+    fn get(&self, py: Python<'_>, key: &Bound<'_, PyAny>, default: Option<Py<PyAny>>) -> PyResult<Py<PyAny>> {
+        match self.data.bind(py).get_item(key)? {
+            Some(value) => Ok(value.unbind()),
+            None => Ok(default.unwrap_or_else(|| py.None())),
+        }
+    }
+
+    fn keys<'py>(&self, py: Python<'py>) -> Bound<'py, PyList> {
+        self.data.bind(py).keys()
+    }
+
+    fn values<'py>(&self, py: Python<'py>) -> Bound<'py, PyList> {
+        self.data.bind(py).values()
+    }
+
+    fn items<'py>(&self, py: Python<'py>) -> Bound<'py, PyList> {
+        self.data.bind(py).items()
+    }
+
+    /// Returns a deep copy of the metadata as a plain dictionary.
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        py.import("copy")?.call_method1("deepcopy", (self.data.bind(py),))
+    }
+}
+
+/// Loads image metadata from a path (`str` or `os.PathLike`) or from a `bytes` object.
+///
+/// Raises `ImmetaError` if the image format is unknown or the image is broken, and `OSError`
+/// if the file can't be read.
+#[pyfunction]
+fn load(py: Python<'_>, source: &Bound<'_, PyAny>) -> PyResult<Metadata> {
+    let result = if let Ok(bytes) = source.downcast::<PyBytes>() {
+        let data = bytes.as_bytes();
+        py.allow_threads(|| immeta::load_from_buf(data))
+    } else {
+        let path: PathBuf = source.extract()?;
+        py.allow_threads(|| immeta::load_from_file(path))
+    };
+    let md = result.map_err(|e| match e {
+        immeta::Error::Io(e) => PyErr::from(e),
+        e => ImmetaError::new_err(e.to_string()),
+    })?;
+
+    let data = to_python(py, &md.to_json_value())?;
+    Ok(Metadata { data: data.downcast_into::<PyDict>()?.unbind() })
+}
+
+/// Converts a JSON value to the equivalent Python object.
+fn to_python<'py>(py: Python<'py>, value: &serde_json::Value) -> PyResult<Bound<'py, PyAny>> {
+    use serde_json::Value;
+
+    Ok(match *value {
+        Value::Null => py.None().into_bound(py),
+        Value::Bool(b) => PyBool::new(py, b).to_owned().into_any(),
+        Value::Number(ref n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => i.into_pyobject(py)?.into_any(),
+            (None, Some(u)) => u.into_pyobject(py)?.into_any(),
+            (None, None) => n.as_f64().unwrap_or(f64::NAN).into_pyobject(py)?.into_any(),
+        },
+        Value::String(ref s) => PyString::new(py, s).into_any(),
+        Value::Array(ref items) => {
+            let list = PyList::empty(py);
+            for item in items {
+                list.append(to_python(py, item)?)?;
+            }
+            list.into_any()
+        }
+        Value::Object(ref fields) => {
+            let dict = PyDict::new(py);
+            for (name, item) in fields {
+                dict.set_item(name, to_python(py, item)?)?;
+            }
+            dict.into_any()
+        }
+    })
+}
+
+#[pymodule]
+#[pyo3(name = "immeta")]
+fn immeta_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(load, m)?)?;
+    m.add_class::<Metadata>()?;
+    m.add("ImmetaError", m.py().get_type::<ImmetaError>())?;
+    Ok(())
+}
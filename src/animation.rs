@@ -0,0 +1,79 @@
+//! Format-independent information about animated images.
+//!
+//! Animated image formats describe frames in different ways, but the information needed to
+//! play them back is mostly the same: the area covered by each frame, how long it is shown,
+//! how it is composed with the previous frames and how many times the animation is played.
+//! `Animated` trait provides access to this information for all supported animated formats.
+//!
+//! `Animated::frames()` requires the metadata of the whole image to be loaded first. To look
+//! at frames as they are read, e.g. to probe just the first few frames of a long animation,
+//! use `for_each_frame()` of `gif`, `png` and `webp` modules instead.
+
+use types::Dimensions;
+
+/// Describes how the area of a frame is treated before the next frame is rendered.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Disposal {
+    /// The frame is left in place.
+    None,
+    /// The frame area is cleared to the background.
+    Background,
+    /// The frame area is restored to its state before the frame was rendered.
+    Previous,
+}
+
+/// Describes how a frame is composed with the contents of the canvas.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Blend {
+    /// The frame replaces the contents of its area, including alpha.
+    Source,
+    /// The frame is alpha-composited over the contents of its area.
+    Over,
+}
+
+/// Number of times an animation is played.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LoopCount {
+    /// The animation is played indefinitely.
+    Infinite,
+    /// The animation is played the given number of times in total.
+    Finite(u32),
+}
+
+/// Information about a single frame of an animation.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FrameInfo {
+    /// Horizontal offset of the frame on the canvas.
+    pub left: u32,
+    /// Vertical offset of the frame on the canvas.
+    pub top: u32,
+    /// Width and height of the frame.
+    pub dimensions: Dimensions,
+    /// Time in milliseconds the frame is displayed.
+    pub delay_ms: u32,
+    /// What happens to the frame area after the frame is displayed.
+    pub disposal: Disposal,
+    /// How the frame is composed with the canvas.
+    pub blend: Blend,
+}
+
+/// Provides access to animation information of an image.
+pub trait Animated {
+    /// Returns `true` if the image is animated, i.e. consists of more than one frame.
+    fn is_animated(&self) -> bool;
+
+    /// Returns how many times the animation is played, if the image specifies it.
+    fn loop_count(&self) -> Option<LoopCount>;
+
+    /// Returns information about the frames of the animation in display order.
+    fn frames(&self) -> Vec<FrameInfo>;
+
+    /// Returns the total duration of one play of the animation in milliseconds.
+    fn total_duration_ms(&self) -> u64 {
+        self.frames().iter().map(|f| f.delay_ms as u64).sum()
+    }
+}
@@ -0,0 +1,178 @@
+//! Loading metadata of images stored inside archives.
+//!
+//! This module is only available when `zip` and/or `tar` features are enabled.
+//!
+//! Archive members are read into memory one by one and inspected there, so nothing is
+//! extracted to disk. Members which are not images of a supported format (e.g. text files
+//! inside a `.cbz` comic book archive) are skipped, while members which look like images but
+//! can't be loaded are reported as errors.
+
+use std::io::Read;
+#[cfg(feature = "zip")]
+use std::io::Seek;
+
+use types::{Result, Error};
+use generic::{self, GenericMetadata};
+use detect;
+use utils;
+
+/// Reads the whole archive member and tries to load image metadata from it.
+///
+/// Returns `Ok(None)` if the member is not an image of a supported format, i.e. if it can't
+/// be loaded and does not start with the signature of a supported format; members which do
+/// start with one are broken images, and the error is returned. Members larger than
+/// `utils::MAX_EXACT_VEC_LEN`, according to either the size declared in the archive or the
+/// data actually read, are rejected with `Error::LimitExceeded`. The declared size is
+/// otherwise only a hint.
+fn load_member<R: Read>(member: &mut R, size_hint: u64) -> Result<Option<GenericMetadata>> {
+    let limit = utils::MAX_EXACT_VEC_LEN;
+    if size_hint > limit {
+        return Err(limit_exceeded!("archive member is larger than {} bytes", limit));
+    }
+    let mut data = Vec::with_capacity(size_hint as usize);
+    try!(member.take(limit + 1).read_to_end(&mut data));
+    if data.len() as u64 > limit {
+        return Err(limit_exceeded!("archive member is larger than {} bytes", limit));
+    }
+    match generic::load_from_buf(&data) {
+        Ok(md) => Ok(Some(md)),
+        Err(e @ Error::LimitExceeded(_)) => Err(e),
+        Err(e) => if detect::detect(&data).is_some() { Err(e) } else { Ok(None) }
+    }
+}
+
+#[cfg(feature = "zip")]
+fn zip_error(e: ::zip::result::ZipError) -> Error {
+    match e {
+        ::zip::result::ZipError::Io(e) => Error::Io(e),
+        e => invalid_format!("invalid ZIP archive: {}", e)
+    }
+}
+
+/// An iterator over images stored in a ZIP archive.
+///
+/// Yields pairs of member names and their metadata. Directories and members which are not
+/// images of a supported format are skipped. Values of this type are obtained with
+/// `zip_members()` function.
+#[cfg(feature = "zip")]
+pub struct ZipMembers<R: Read + Seek> {
+    archive: ::zip::ZipArchive<R>,
+    index: usize,
+}
+
+#[cfg(feature = "zip")]
+impl<R: Read + Seek> Iterator for ZipMembers<R> {
+    type Item = Result<(String, GenericMetadata)>;
+
+    fn next(&mut self) -> Option<Result<(String, GenericMetadata)>> {
+        while self.index < self.archive.len() {
+            let index = self.index;
+            self.index += 1;
+
+            let mut file = match self.archive.by_index(index) {
+                Ok(file) => file,
+                Err(e) => return Some(Err(zip_error(e)))
+            };
+            if file.is_dir() {
+                continue;
+            }
+
+            let size = file.size();
+            match load_member(&mut file, size) {
+                Ok(Some(md)) => return Some(Ok((file.name().to_owned(), md))),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e))
+            }
+        }
+        None
+    }
+}
+
+/// Returns an iterator over images stored in the ZIP archive read from the provided stream.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+///
+/// let archive = File::open("comic.cbz").unwrap();
+/// for member in immeta::archive::zip_members(archive).unwrap() {
+///     let (name, md) = member.unwrap();
+///     println!("{}: {:?}", name, md.dimensions());
+/// }
+/// ```
+#[cfg(feature = "zip")]
+pub fn zip_members<R: Read + Seek>(r: R) -> Result<ZipMembers<R>> {
+    let archive = try!(::zip::ZipArchive::new(r).map_err(zip_error));
+    Ok(ZipMembers {
+        archive: archive,
+        index: 0,
+    })
+}
+
+/// An iterator over images stored in a TAR archive.
+///
+/// Yields pairs of member paths and their metadata. Members which are not regular files or
+/// which are not images of a supported format are skipped. Values of this type are obtained
+/// with `tar_members()` function.
+#[cfg(feature = "tar")]
+pub struct TarMembers<'a, R: Read + 'a> {
+    entries: ::tar::Entries<'a, R>,
+}
+
+#[cfg(feature = "tar")]
+impl<'a, R: Read + 'a> Iterator for TarMembers<'a, R> {
+    type Item = Result<(String, GenericMetadata)>;
+
+    fn next(&mut self) -> Option<Result<(String, GenericMetadata)>> {
+        for entry in &mut self.entries {
+            let mut entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e.into()))
+            };
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let name = match entry.path() {
+                Ok(path) => path.to_string_lossy().into_owned(),
+                Err(e) => return Some(Err(e.into()))
+            };
+
+            let size = entry.size();
+            match load_member(&mut entry, size) {
+                Ok(Some(md)) => return Some(Ok((name, md))),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e))
+            }
+        }
+        None
+    }
+}
+
+/// Returns an iterator over images stored in the provided TAR archive.
+///
+/// TAR archives can only be read sequentially, so this function does not require the
+/// underlying stream to be seekable.
+///
+/// # Examples
+///
+/// ```no_run
+/// extern crate tar;
+/// # extern crate immeta;
+/// use std::fs::File;
+///
+/// # fn main() {
+/// let mut archive = tar::Archive::new(File::open("images.tar").unwrap());
+/// for member in immeta::archive::tar_members(&mut archive).unwrap() {
+///     let (name, md) = member.unwrap();
+///     println!("{}: {:?}", name, md.dimensions());
+/// }
+/// # }
+/// ```
+#[cfg(feature = "tar")]
+pub fn tar_members<'a, R: Read + 'a>(archive: &'a mut ::tar::Archive<R>) -> Result<TarMembers<'a, R>> {
+    Ok(TarMembers {
+        entries: try!(archive.entries())
+    })
+}
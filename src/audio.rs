@@ -0,0 +1,405 @@
+//! Cover art embedded into audio files.
+//!
+//! This module is only available when `audio` feature is enabled.
+//!
+//! Music files often carry pictures, like the front cover of the album, which are regular
+//! JPEG or PNG images embedded into the tags of the file. `load_pictures()` locates them in
+//! the following places and loads their metadata with `immeta::load_from_buf()`:
+//!
+//!   * `APIC` and `PIC` frames of ID3v2 tags at the beginning of MP3 files (and of any other
+//!     files with leading ID3v2 tags);
+//!   * `PICTURE` metadata blocks of FLAC files;
+//!   * `covr` items of the iTunes metadata (`moov/udta/meta/ilst`) of MP4 files.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use immeta::audio;
+//!
+//! let mut f = BufReader::new(File::open("song.mp3").unwrap());
+//! if let Some(cover) = audio::load_front_cover(&mut f).unwrap() {
+//!     println!("{:?} {:?}", cover.format(), cover.dimensions());
+//! }
+//! ```
+//!
+//! Pictures are read into memory one at a time and are not kept after their metadata is
+//! loaded. Compressed and encrypted ID3v2 frames and ID3v2 tags at the end of files are not
+//! supported.
+
+use std::io::BufRead;
+
+use byteorder::{ReadBytesExt, BigEndian};
+
+use types::{Result, Dimensions, ImageFormat};
+use generic::{self, GenericMetadata};
+use utils::{self, BufReadExt};
+use common::bmff::{Boxes, BoxType};
+
+/// ID3v2 and FLAC picture type of the front cover.
+pub const FRONT_COVER: u32 = 3;
+
+/// Container in which a picture is found.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Source {
+    /// ID3v2 `APIC` or `PIC` frame.
+    Id3,
+    /// FLAC `PICTURE` metadata block.
+    Flac,
+    /// MP4 `covr` metadata item.
+    Mp4,
+}
+
+/// A picture embedded into an audio file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Picture {
+    /// Where the picture is found.
+    pub source: Source,
+    /// Picture type as defined by ID3v2 and FLAC, e.g. `FRONT_COVER`.
+    ///
+    /// MP4 files do not store picture types.
+    pub picture_type: Option<u32>,
+    /// MIME type of the picture as declared by the container, if any.
+    ///
+    /// It is not necessarily correct; the actual format is available with `format()`.
+    pub mime_type: Option<String>,
+    /// Description of the picture; empty if there is none.
+    pub description: String,
+    /// Size of the picture data in bytes.
+    pub size: u64,
+    /// Metadata of the picture, or `None` if it is not an image of a supported format.
+    pub metadata: Option<GenericMetadata>,
+}
+
+impl Picture {
+    fn new(source: Source, picture_type: Option<u32>, mime_type: Option<String>,
+           description: String, data: &[u8]) -> Picture {
+        Picture {
+            source: source,
+            picture_type: picture_type,
+            mime_type: mime_type,
+            description: description,
+            size: data.len() as u64,
+            metadata: generic::load_from_buf(data).ok(),
+        }
+    }
+
+    /// Returns the image format of the picture, if it is supported.
+    #[inline]
+    pub fn format(&self) -> Option<ImageFormat> {
+        self.metadata.as_ref().map(|md| md.format())
+    }
+
+    /// Returns the dimensions of the picture, if it is an image of a supported format.
+    #[inline]
+    pub fn dimensions(&self) -> Option<Dimensions> {
+        self.metadata.as_ref().map(|md| md.dimensions())
+    }
+
+    /// Returns `true` if the picture is marked as the front cover.
+    #[inline]
+    pub fn is_front_cover(&self) -> bool {
+        self.picture_type == Some(FRONT_COVER)
+    }
+}
+
+/// Finds all pictures embedded into an audio file.
+///
+/// The file must start at the current position of the stream. The container is detected by
+/// its signature; a FLAC stream may be preceded by an ID3v2 tag. Returns an empty vector for
+/// files without pictures and an error for files of unknown formats.
+pub fn load_pictures<R: ?Sized + BufRead>(r: &mut R) -> Result<Vec<Picture>> {
+    let mut pictures = Vec::new();
+    let mut recognized = false;
+
+    if try!(r.fill_buf()).starts_with(b"ID3") {
+        recognized = true;
+        try!(read_id3(r, &mut pictures));
+    }
+
+    let head = try!(r.fill_buf()).iter().take(8).cloned().collect::<Vec<u8>>();
+    if head.starts_with(b"fLaC") {
+        try!(read_flac(r, &mut pictures));
+    } else if head.len() == 8 && &head[4..] == b"ftyp" {
+        try!(read_mp4(r, &mut pictures));
+    } else if !recognized {
+        return Err(invalid_format!("unknown or unsupported audio file type"));
+    }
+
+    Ok(pictures)
+}
+
+/// Finds the front cover of an audio file.
+///
+/// This is the picture marked as the front cover if there is one, or the first picture
+/// otherwise, which is what most players display.
+pub fn load_front_cover<R: ?Sized + BufRead>(r: &mut R) -> Result<Option<Picture>> {
+    let mut pictures = try!(load_pictures(r));
+    let index = pictures.iter().position(|p| p.is_front_cover()).unwrap_or(0);
+    if pictures.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(pictures.swap_remove(index)))
+    }
+}
+
+fn syncsafe(b: &[u8]) -> u32 {
+    b.iter().fold(0, |n, &b| (n << 7) | (b & 0x7f) as u32)
+}
+
+/// Removes the bytes inserted by the ID3v2 unsynchronisation scheme, i.e. zero bytes
+/// following 0xFF.
+fn resynchronize(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut prev = 0;
+    for &b in data {
+        if !(prev == 0xff && b == 0) {
+            result.push(b);
+        }
+        prev = b;
+    }
+    result
+}
+
+fn read_id3<R: ?Sized + BufRead>(r: &mut R, pictures: &mut Vec<Picture>) -> Result<()> {
+    let mut header = [0u8; 10];
+    try!(r.read_exact(&mut header).map_err(if_eof!(std, "when reading ID3v2 header")));
+    let version = header[3];
+    let flags = header[5];
+    let size = syncsafe(&header[6..]) as u64;
+    let tag = try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(r, size), "when reading ID3v2 tag");
+    if flags & 0x10 != 0 {
+        // the footer repeats the header
+        try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(r, 10), "when reading ID3v2 footer");
+    }
+    if version < 2 || version > 4 {
+        log_debug!("ignoring ID3v2.{} tag", version);
+        return Ok(());
+    }
+
+    // in versions before 2.4, unsynchronisation applies to the whole tag
+    let tag = if flags & 0x80 != 0 && version < 4 { resynchronize(&tag) } else { tag };
+    let mut pos = 0;
+    if flags & 0x40 != 0 && version > 2 {
+        if tag.len() < 4 {
+            return Err(unexpected_eof!("when reading ID3v2 extended header"));
+        }
+        pos = if version == 3 {
+            4 + try!((&tag[..4]).read_u32::<BigEndian>()) as usize
+        } else {
+            syncsafe(&tag[..4]) as usize
+        };
+    }
+
+    let header_len = if version == 2 { 6 } else { 10 };
+    while pos + header_len <= tag.len() && tag[pos] != 0 {
+        let frame = &tag[pos..pos + header_len];
+        let (id, size, frame_flags) = match version {
+            2 => (&frame[..3], (frame[3] as usize) << 16 | (frame[4] as usize) << 8 | frame[5] as usize, 0),
+            3 => (&frame[..4], try!((&frame[4..8]).read_u32::<BigEndian>()) as usize,
+                  try!((&frame[8..]).read_u16::<BigEndian>())),
+            _ => (&frame[..4], syncsafe(&frame[4..8]) as usize, try!((&frame[8..]).read_u16::<BigEndian>())),
+        };
+        pos += header_len;
+        if size > tag.len() - pos {
+            return Err(unexpected_eof!("when reading ID3v2 frame {}", String::from_utf8_lossy(id)));
+        }
+        let mut data = &tag[pos..pos + size];
+        pos += size;
+
+        if id != b"APIC" && id != b"PIC" {
+            continue;
+        }
+        let (compressed, encrypted, grouped) = match version {
+            3 => (frame_flags & 0x0080 != 0, frame_flags & 0x0040 != 0, frame_flags & 0x0020 != 0),
+            _ => (frame_flags & 0x0008 != 0, frame_flags & 0x0004 != 0, frame_flags & 0x0040 != 0),
+        };
+        if compressed || encrypted {
+            log_debug!("skipping compressed or encrypted ID3v2 picture frame");
+            continue;
+        }
+        let skip = if grouped { 1 } else { 0 } + if version == 4 && frame_flags & 0x0001 != 0 { 4 } else { 0 };
+        if skip > data.len() {
+            return Err(unexpected_eof!("when reading ID3v2 picture frame"));
+        }
+        data = &data[skip..];
+        let resynchronized;
+        if version == 4 && frame_flags & 0x0002 != 0 {
+            resynchronized = resynchronize(data);
+            data = &resynchronized;
+        }
+
+        match parse_id3_picture(data, version) {
+            Ok(picture) => pictures.push(picture),
+            Err(e) => log_debug!("ignoring invalid ID3v2 picture frame: {}", e)
+        }
+    }
+    Ok(())
+}
+
+fn parse_id3_picture(data: &[u8], version: u8) -> Result<Picture> {
+    if data.is_empty() {
+        return Err(unexpected_eof!("when reading text encoding"));
+    }
+    let encoding = data[0];
+    let mut rest = &data[1..];
+
+    let mime_type = if version == 2 {
+        // a three-character image format instead of a MIME type
+        if rest.len() < 3 {
+            return Err(unexpected_eof!("when reading image format"));
+        }
+        let format = &rest[..3];
+        rest = &rest[3..];
+        match format {
+            b"JPG" => "image/jpeg".to_owned(),
+            b"PNG" => "image/png".to_owned(),
+            _ => String::from_utf8_lossy(format).into_owned(),
+        }
+    } else {
+        let end = try!(rest.iter().position(|&b| b == 0)
+            .ok_or_else(|| unexpected_eof!("when reading MIME type")));
+        let mime_type = latin1(&rest[..end]);
+        rest = &rest[end + 1..];
+        mime_type
+    };
+    if mime_type == "-->" {
+        return Err(invalid_format!("linked pictures are not supported"));
+    }
+
+    if rest.is_empty() {
+        return Err(unexpected_eof!("when reading picture type"));
+    }
+    let picture_type = rest[0] as u32;
+    rest = &rest[1..];
+
+    let (description, data) = match encoding {
+        // UTF-16 strings are terminated by two zero bytes at an even offset
+        1 | 2 => {
+            let end = try!((0..rest.len() / 2).map(|i| i * 2).find(|&i| rest[i] == 0 && rest[i + 1] == 0)
+                .ok_or_else(|| unexpected_eof!("when reading picture description")));
+            (utf16(&rest[..end], encoding == 2), &rest[end + 2..])
+        }
+        _ => {
+            let end = try!(rest.iter().position(|&b| b == 0)
+                .ok_or_else(|| unexpected_eof!("when reading picture description")));
+            let description = if encoding == 3 {
+                String::from_utf8_lossy(&rest[..end]).into_owned()
+            } else {
+                latin1(&rest[..end])
+            };
+            (description, &rest[end + 1..])
+        }
+    };
+
+    Ok(Picture::new(Source::Id3, Some(picture_type), Some(mime_type), description, data))
+}
+
+fn latin1(data: &[u8]) -> String {
+    data.iter().map(|&b| b as char).collect()
+}
+
+/// Decodes UTF-16 text which starts with a BOM, or big-endian text without a BOM.
+fn utf16(data: &[u8], big_endian: bool) -> String {
+    let (data, big_endian) = if data.starts_with(&[0xfe, 0xff]) {
+        (&data[2..], true)
+    } else if data.starts_with(&[0xff, 0xfe]) {
+        (&data[2..], false)
+    } else {
+        (data, big_endian)
+    };
+    let units: Vec<u16> = data.chunks(2).filter(|c| c.len() == 2).map(|c| if big_endian {
+        (c[0] as u16) << 8 | c[1] as u16
+    } else {
+        (c[1] as u16) << 8 | c[0] as u16
+    }).collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn read_flac<R: ?Sized + BufRead>(r: &mut R, pictures: &mut Vec<Picture>) -> Result<()> {
+    let mut signature = [0u8; 4];
+    try!(r.read_exact(&mut signature).map_err(if_eof!(std, "when reading FLAC signature")));
+
+    loop {
+        let header = try_if_eof!(r.read_u32::<BigEndian>(), "when reading FLAC metadata block header");
+        let last = header & 0x8000_0000 != 0;
+        let block_type = (header >> 24) & 0x7f;
+        let len = (header & 0x00ff_ffff) as u64;
+
+        if block_type == 6 {
+            let block = try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(r, len), "when reading FLAC picture block");
+            match parse_flac_picture(&block) {
+                Ok(picture) => pictures.push(picture),
+                Err(e) => log_debug!("ignoring invalid FLAC picture block: {}", e)
+            }
+        } else if try!(r.skip_exact(len)) != len {
+            return Err(unexpected_eof!("when skipping FLAC metadata block"));
+        }
+
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn parse_flac_picture(mut block: &[u8]) -> Result<Picture> {
+    fn read_field<'a>(block: &mut &'a [u8], what: &'static str) -> Result<&'a [u8]> {
+        let len = try_if_eof!(block.read_u32::<BigEndian>(), "when reading length of {}", what) as usize;
+        if len > block.len() {
+            return Err(unexpected_eof!("when reading {}", what));
+        }
+        let (field, rest) = block.split_at(len);
+        *block = rest;
+        Ok(field)
+    }
+
+    let picture_type = try_if_eof!(block.read_u32::<BigEndian>(), "when reading picture type");
+    let mime_type = latin1(try!(read_field(&mut block, "MIME type")));
+    let description = String::from_utf8_lossy(try!(read_field(&mut block, "description"))).into_owned();
+    // width, height, color depth and number of colors are declared, but they are not trusted
+    if block.len() < 16 {
+        return Err(unexpected_eof!("when reading picture dimensions"));
+    }
+    block = &block[16..];
+    let data = try!(read_field(&mut block, "picture data"));
+
+    let mime_type = if mime_type.is_empty() { None } else { Some(mime_type) };
+    Ok(Picture::new(Source::Flac, Some(picture_type), mime_type, description, data))
+}
+
+fn read_mp4<R: ?Sized + BufRead>(r: &mut R, pictures: &mut Vec<Picture>) -> Result<()> {
+    const PATH: [&'static [u8; 4]; 5] = [b"moov", b"udta", b"meta", b"ilst", b"covr"];
+
+    // walks the path of boxes recursively, since each level borrows the previous one
+    fn walk<R: BufRead>(boxes: &mut Boxes<R>, depth: usize, pictures: &mut Vec<Picture>) -> Result<()> {
+        while let Some(b) = boxes.next() {
+            let mut b = try!(b);
+            if depth == PATH.len() {
+                if b.box_type() != BoxType(*b"data") {
+                    continue;
+                }
+                let payload = try!(b.read_payload());
+                if payload.len() < 8 {
+                    return Err(unexpected_eof!("when reading MP4 cover data"));
+                }
+                // type indicator and locale precede the image
+                let mime_type = match try!((&payload[..4]).read_u32::<BigEndian>()) {
+                    13 => Some("image/jpeg".to_owned()),
+                    14 => Some("image/png".to_owned()),
+                    27 => Some("image/bmp".to_owned()),
+                    _ => None,
+                };
+                pictures.push(Picture::new(Source::Mp4, None, mime_type, String::new(), &payload[8..]));
+            } else if b.box_type() == BoxType(*PATH[depth]) {
+                if PATH[depth] == b"meta" {
+                    try!(b.read_full_box_header());
+                }
+                return walk(&mut b.children(), depth + 1, pictures);
+            }
+        }
+        Ok(())
+    }
+
+    walk(&mut Boxes::new(r), 0, pictures)
+}
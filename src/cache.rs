@@ -0,0 +1,374 @@
+//! Caching of metadata loaded from files.
+//!
+//! Repeatedly scanning large image libraries is dominated by parsing files which have not
+//! changed since the previous scan. `MetadataCache` memoizes results of
+//! `immeta::load_from_file()` keyed by file path, size and modification time, so unchanged
+//! files are not parsed again.
+//!
+//! The actual storage of cached values is pluggable via `CacheStorage` trait. An in-memory
+//! LRU storage is always available; if `serde_json` feature is enabled, there is also
+//! a storage persisted to a JSON file.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::fs;
+
+use types::Result;
+use generic::{self, GenericMetadata};
+
+/// A key identifying a particular version of a file.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CacheKey {
+    /// Path to the file.
+    pub path: PathBuf,
+    /// Length of the file in bytes.
+    pub len: u64,
+    /// Last modification time of the file.
+    pub mtime: SystemTime,
+}
+
+impl CacheKey {
+    /// Creates a cache key for the file identified by the provided path.
+    ///
+    /// Fails if the file metadata can't be obtained or if the platform does not support
+    /// file modification times.
+    pub fn for_file<P: AsRef<Path>>(path: P) -> Result<CacheKey> {
+        let path = path.as_ref();
+        let md = try!(fs::metadata(path));
+        Ok(CacheKey {
+            path: path.to_owned(),
+            len: md.len(),
+            mtime: try!(md.modified()),
+        })
+    }
+}
+
+/// A storage for cached metadata values.
+pub trait CacheStorage {
+    /// Returns the value stored for the given key, if any.
+    fn get(&mut self, key: &CacheKey) -> Option<GenericMetadata>;
+
+    /// Stores the given value for the given key.
+    fn put(&mut self, key: CacheKey, md: GenericMetadata);
+}
+
+/// An in-memory storage which keeps a limited number of the most recently used values.
+pub struct LruStorage {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<CacheKey, (GenericMetadata, u64)>,
+    // keys in the order of their usage; a key is only valid here if its tick matches
+    // the tick stored in `entries`
+    usage: VecDeque<(CacheKey, u64)>,
+}
+
+impl LruStorage {
+    /// Creates a new storage which keeps at most `capacity` values.
+    pub fn new(capacity: usize) -> LruStorage {
+        LruStorage {
+            capacity: capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            usage: VecDeque::new(),
+        }
+    }
+
+    /// Returns the number of values in the storage.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the storage does not contain any values.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn touch(&mut self, key: CacheKey) -> u64 {
+        self.tick += 1;
+        self.usage.push_back((key, self.tick));
+        // drop stale usage records so that the queue does not grow indefinitely
+        if self.usage.len() > self.capacity * 2 + 16 {
+            let entries = &self.entries;
+            self.usage.retain(|&(ref k, t)| entries.get(k).map(|&(_, et)| et == t).unwrap_or(false));
+        }
+        self.tick
+    }
+
+    fn evict(&mut self) {
+        while let Some((key, tick)) = self.usage.pop_front() {
+            let is_current = self.entries.get(&key).map(|&(_, t)| t == tick).unwrap_or(false);
+            if is_current {
+                self.entries.remove(&key);
+                return;
+            }
+        }
+    }
+}
+
+impl CacheStorage for LruStorage {
+    fn get(&mut self, key: &CacheKey) -> Option<GenericMetadata> {
+        let md = match self.entries.get(key) {
+            Some(&(ref md, _)) => md.clone(),
+            None => return None
+        };
+        let tick = self.touch(key.clone());
+        self.entries.get_mut(key).unwrap().1 = tick;
+        Some(md)
+    }
+
+    fn put(&mut self, key: CacheKey, md: GenericMetadata) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        let tick = self.touch(key.clone());
+        self.entries.insert(key, (md, tick));
+    }
+}
+
+/// A storage which keeps all values in memory and persists them to a JSON file.
+///
+/// The file is read when the storage is opened and written by `save()` method.
+///
+/// This type is only available when `serde_json` feature is enabled.
+#[cfg(feature = "serde_json")]
+pub struct JsonFileStorage {
+    path: PathBuf,
+    entries: HashMap<CacheKey, GenericMetadata>,
+}
+
+#[cfg(feature = "serde_json")]
+impl JsonFileStorage {
+    /// Opens a storage backed by the file at the provided path.
+    ///
+    /// If the file does not exist, an empty storage is returned. A file which can't be parsed,
+    /// e.g. one truncated by an interrupted `save()`, is reported as an invalid format error.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<JsonFileStorage> {
+        let path = path.as_ref().to_owned();
+        let entries = match fs::File::open(&path) {
+            Ok(f) => {
+                let entries: Vec<(CacheKey, GenericMetadata)> = try!(
+                    ::serde_json::from_reader(::std::io::BufReader::new(f))
+                        .map_err(|e| invalid_format!("invalid metadata cache file: {}", e))
+                );
+                entries.into_iter().collect()
+            }
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into())
+        };
+        Ok(JsonFileStorage {
+            path: path,
+            entries: entries,
+        })
+    }
+
+    /// Writes the contents of the storage to its file.
+    pub fn save(&self) -> Result<()> {
+        let f = ::std::io::BufWriter::new(try!(fs::File::create(&self.path)));
+        let entries: Vec<(&CacheKey, &GenericMetadata)> = self.entries.iter().collect();
+        ::serde_json::to_writer(f, &entries)
+            .map_err(|e| ::types::Error::Io(e.into()))
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl CacheStorage for JsonFileStorage {
+    fn get(&mut self, key: &CacheKey) -> Option<GenericMetadata> {
+        self.entries.get(key).cloned()
+    }
+
+    fn put(&mut self, key: CacheKey, md: GenericMetadata) {
+        self.entries.insert(key, md);
+    }
+}
+
+/// A cache of metadata loaded from files.
+///
+/// # Examples
+///
+/// ```no_run
+/// use immeta::cache::MetadataCache;
+///
+/// let mut cache = MetadataCache::new(1024);
+/// let md = cache.load_from_file("kitty.jpg").unwrap();
+/// // the file is not parsed again unless it has changed
+/// let md = cache.load_from_file("kitty.jpg").unwrap();
+/// ```
+pub struct MetadataCache<S: CacheStorage = LruStorage> {
+    storage: S,
+}
+
+impl MetadataCache<LruStorage> {
+    /// Creates a new cache backed by an in-memory LRU storage of the given capacity.
+    pub fn new(capacity: usize) -> MetadataCache<LruStorage> {
+        MetadataCache::with_storage(LruStorage::new(capacity))
+    }
+}
+
+impl<S: CacheStorage> MetadataCache<S> {
+    /// Creates a new cache backed by the provided storage.
+    pub fn with_storage(storage: S) -> MetadataCache<S> {
+        MetadataCache {
+            storage: storage
+        }
+    }
+
+    /// Returns a reference to the underlying storage.
+    #[inline]
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Returns a mutable reference to the underlying storage.
+    #[inline]
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Unwraps this cache, returning the underlying storage.
+    #[inline]
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+
+    /// Loads metadata for an image contained in a file identified by the provided path,
+    /// returning a cached value if the file has not changed since it was last loaded.
+    ///
+    /// Only successfully loaded metadata is cached. If the modification time of the file
+    /// can't be determined, the file is always loaded anew.
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<GenericMetadata> {
+        let key = match CacheKey::for_file(&path) {
+            Ok(key) => key,
+            Err(_) => return generic::load_from_file(path)
+        };
+
+        if let Some(md) = self.storage.get(&key) {
+            return Ok(md);
+        }
+
+        let md = try!(generic::load_from_file(&key.path));
+        self.storage.put(key, md.clone());
+        Ok(md)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::UNIX_EPOCH;
+
+    use generic::{self, GenericMetadata};
+
+    use super::{CacheKey, CacheStorage, LruStorage};
+    #[cfg(feature = "serde_json")]
+    use super::JsonFileStorage;
+
+    fn key(name: &str) -> CacheKey {
+        CacheKey {
+            path: PathBuf::from(name),
+            len: 1,
+            mtime: UNIX_EPOCH,
+        }
+    }
+
+    fn md(n: usize) -> GenericMetadata {
+        let files = ["owlet.png", "owlet.gif", "drop.gif"];
+        generic::load_from_file(format!("tests/images/{}", files[n - 1])).unwrap()
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut s = LruStorage::new(2);
+        s.put(key("a"), md(1));
+        s.put(key("b"), md(2));
+        assert_eq!(s.get(&key("a")), Some(md(1)));
+
+        // "b" is the least recently used one now
+        s.put(key("c"), md(3));
+        assert_eq!(s.len(), 2);
+        assert_eq!(s.get(&key("b")), None);
+        assert_eq!(s.get(&key("a")), Some(md(1)));
+        assert_eq!(s.get(&key("c")), Some(md(3)));
+    }
+
+    #[test]
+    fn test_lru_many_touches() {
+        let mut s = LruStorage::new(1);
+        s.put(key("a"), md(1));
+        for _ in 0..100 {
+            assert_eq!(s.get(&key("a")), Some(md(1)));
+        }
+        s.put(key("b"), md(2));
+        assert_eq!(s.len(), 1);
+        assert_eq!(s.get(&key("a")), None);
+        assert_eq!(s.get(&key("b")), Some(md(2)));
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn temp_path(name: &str) -> PathBuf {
+        ::std::env::temp_dir().join(format!("immeta-cache-{}-{}.json", ::std::process::id(), name))
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_json_round_trip() {
+        use std::fs;
+        use cache::MetadataCache;
+
+        let path = temp_path("round-trip");
+        let _ = fs::remove_file(&path);
+        let image = "tests/images/owlet.png";
+
+        let mut cache = MetadataCache::with_storage(JsonFileStorage::open(&path).unwrap());
+        let loaded = cache.load_from_file(image).unwrap();
+        cache.storage_mut().put(key("a"), md(2));
+        cache.storage().save().unwrap();
+        drop(cache);
+
+        let mut s = JsonFileStorage::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(s.get(&CacheKey::for_file(image).unwrap()), Some(loaded));
+        assert_eq!(s.get(&key("a")), Some(md(2)));
+        assert_eq!(s.get(&key("b")), None);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_json_corrupt_file() {
+        use std::fs;
+
+        let path = temp_path("corrupt");
+        let mut s = JsonFileStorage::open(&path).unwrap();
+        s.put(key("a"), md(1));
+        s.put(key("b"), md(3));
+        s.save().unwrap();
+        let data = fs::read(&path).unwrap();
+
+        // truncations of a valid file are rejected
+        for n in (0..data.len()).step_by(97).chain(Some(data.len() - 1)) {
+            fs::write(&path, &data[..n]).unwrap();
+            assert!(JsonFileStorage::open(&path).is_err(), "truncated to {} bytes", n);
+        }
+
+        let json = String::from_utf8(data).unwrap();
+        let corrupt = [
+            "{}".to_owned(),
+            "[[1, 2]]".to_owned(),
+            json.replace("\"secs_since_epoch\":0", &format!("\"secs_since_epoch\":{}", u64::max_value())),
+            json.replace("\"width\"", "\"breadth\""),
+            json.replace("Png", "Tiff"),
+        ];
+        for contents in &corrupt {
+            assert!(*contents != json);
+            fs::write(&path, contents).unwrap();
+            assert!(JsonFileStorage::open(&path).is_err(), "{}", contents);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
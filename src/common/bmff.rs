@@ -0,0 +1,413 @@
+//! ISO base media file format (ISO/IEC 14496-12) boxes.
+//!
+//! An ISO-BMFF file, like HEIF, AVIF, MP4 or Canon CR3, is a sequence of boxes. Each box has
+//! a 32-bit big-endian size, which includes the header, and a four-character type. A size of 1
+//! means that a 64-bit size follows the type, and a size of 0 means that the box extends to
+//! the end of its container or of the file. Boxes of `uuid` type have a 16-byte extended type
+//! after the header. Many boxes are "full boxes" whose payload starts with a version byte and
+//! 24 bits of flags, and container boxes like `moov` or `meta` contain nested boxes.
+//!
+//! `Boxes` reads boxes lazily, skipping the payloads which are not read, so it can be used
+//! with non-seekable streams; nested boxes are walked with `BmffBox::children()`.
+
+use std::io::{self, BufRead, Read, Take};
+use std::str;
+use std::fmt;
+
+use byteorder::{ReadBytesExt, BigEndian};
+
+use types::Result;
+use utils::{self, BufReadExt, Tracked};
+
+/// Type of a box (FourCC), e.g. `ftyp`.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct BoxType(pub [u8; 4]);
+
+impl BoxType {
+    #[inline]
+    pub fn as_str(&self) -> Option<&str> {
+        str::from_utf8(&self.0).ok()
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for BoxType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.as_str() {
+            Some(s) => f.write_str(s),
+            None => write!(f, "{:?}", self.as_bytes())
+        }
+    }
+}
+
+/// Type of boxes with a user-defined extended type.
+pub const UUID_BOX_TYPE: BoxType = BoxType([b'u', b'u', b'i', b'd']);
+
+/// A reader of ISO-BMFF boxes.
+///
+/// `Boxes` reads boxes one by one, providing access to their payload; the payload which is
+/// not read explicitly is skipped when the next box is requested. Boxes are read until the
+/// end of the stream, which is only allowed at a box boundary.
+pub struct Boxes<R: BufRead> {
+    source: Tracked<R>,
+    base_offset: u64,
+    /// End of the payload of the last box, or `None` if it extends to the end of the stream.
+    box_end: Option<u64>,
+    finished: bool,
+}
+
+impl<R: BufRead> Boxes<R> {
+    /// Creates a new box reader for a sequence of boxes which starts at the current position
+    /// of the provided reader.
+    pub fn new(source: R) -> Boxes<R> {
+        Boxes::with_base_offset(source, 0)
+    }
+
+    fn with_base_offset(source: R, base_offset: u64) -> Boxes<R> {
+        Boxes {
+            source: Tracked::new(source),
+            base_offset: base_offset,
+            box_end: Some(0),
+            finished: false,
+        }
+    }
+
+    /// Returns the number of bytes consumed from the underlying reader so far.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.source.position
+    }
+
+    /// Unwraps this box reader, returning the underlying reader.
+    ///
+    /// Unread payload of the last box is not skipped.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.source.inner
+    }
+
+    /// Reads the next box.
+    ///
+    /// Returns `None` at the end of the stream or after a box which extends to the end of
+    /// the stream. If a box header is invalid or truncated, an error is returned, and
+    /// subsequent calls return `None`.
+    pub fn next(&mut self) -> Option<Result<BmffBox<R>>> {
+        if self.finished {
+            return None;
+        }
+        match self.read_header() {
+            Ok(Some(header)) => Some(Ok(self.bmff_box(header))),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn read_header(&mut self) -> Result<Option<Header>> {
+        let box_end = match self.box_end {
+            Some(end) => end,
+            // the previous box extends to the end of the stream
+            None => return Ok(None),
+        };
+        if self.source.position < box_end {
+            let n = box_end - self.source.position;
+            log_trace!("skipping {} bytes of box payload", n);
+            if try!(self.source.skip_exact(n)) != n {
+                return Err(unexpected_eof!("when skipping box payload"));
+            }
+        }
+
+        if try!(self.source.fill_buf()).is_empty() {
+            return Ok(None);
+        }
+
+        let offset = self.base_offset + self.source.position;
+        let size = try_if_eof!(self.source.read_u32::<BigEndian>(), "when reading box size");
+        let mut box_type = [0u8; 4];
+        try!(self.source.read_exact(&mut box_type).map_err(if_eof!(std, "when reading box type")));
+        let box_type = BoxType(box_type);
+        let mut header_len = 8;
+
+        let size = match size {
+            0 => None,
+            1 => {
+                header_len += 8;
+                Some(try_if_eof!(self.source.read_u64::<BigEndian>(),
+                                 "when reading large size of box {}", box_type))
+            }
+            n => Some(n as u64),
+        };
+
+        let extended_type = if box_type == UUID_BOX_TYPE {
+            let mut uuid = [0u8; 16];
+            try!(self.source.read_exact(&mut uuid).map_err(if_eof!(std, "when reading extended type of box {}", box_type)));
+            header_len += 16;
+            Some(uuid)
+        } else {
+            None
+        };
+
+        let payload_len = match size {
+            Some(size) if size < header_len => return Err(invalid_format!(
+                "size of box {} at offset {} is smaller than its header: {}", box_type, offset, size
+            )),
+            Some(size) => Some(size - header_len),
+            None => None,
+        };
+        log_trace!("box {} at offset {}, payload length {:?}", box_type, offset, payload_len);
+
+        Ok(Some(Header {
+            box_type: box_type,
+            extended_type: extended_type,
+            offset: offset,
+            header_len: header_len as u8,
+            payload_len: payload_len,
+        }))
+    }
+
+    fn bmff_box(&mut self, header: Header) -> BmffBox<R> {
+        self.box_end = header.payload_len.map(|len| self.source.position.saturating_add(len));
+        let limit = header.payload_len.unwrap_or(u64::max_value());
+        BmffBox {
+            box_type: header.box_type,
+            extended_type: header.extended_type,
+            offset: header.offset,
+            header_len: header.header_len,
+            len: header.payload_len,
+            payload: (&mut self.source).take(limit),
+        }
+    }
+}
+
+struct Header {
+    box_type: BoxType,
+    extended_type: Option<[u8; 16]>,
+    offset: u64,
+    header_len: u8,
+    payload_len: Option<u64>,
+}
+
+/// A single ISO-BMFF box.
+///
+/// Values of this type are obtained with `Boxes::next()`.
+pub struct BmffBox<'a, R: BufRead + 'a> {
+    box_type: BoxType,
+    extended_type: Option<[u8; 16]>,
+    offset: u64,
+    header_len: u8,
+    len: Option<u64>,
+    payload: Take<&'a mut Tracked<R>>,
+}
+
+impl<'a, R: BufRead + 'a> BmffBox<'a, R> {
+    /// Returns the box type, e.g. `ftyp`.
+    #[inline]
+    pub fn box_type(&self) -> BoxType { self.box_type }
+
+    /// Returns the extended type of a `uuid` box.
+    #[inline]
+    pub fn extended_type(&self) -> Option<[u8; 16]> { self.extended_type }
+
+    /// Returns the offset of the box, i.e. of its size field, from the beginning of the
+    /// stream.
+    #[inline]
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// Returns the size of the box header, including the large size and the extended type
+    /// if they are present.
+    #[inline]
+    pub fn header_len(&self) -> u8 { self.header_len }
+
+    /// Returns the size of the box payload, not including the header.
+    ///
+    /// Returns `None` if the box extends to the end of its container or of the stream.
+    #[inline]
+    pub fn len(&self) -> Option<u64> { self.len }
+
+    /// Returns the offset of the payload from the beginning of the stream.
+    #[inline]
+    pub fn payload_offset(&self) -> u64 {
+        self.offset + self.header_len as u64
+    }
+
+    /// Returns a reader of the box payload.
+    #[inline]
+    pub fn payload(&mut self) -> &mut BufRead {
+        &mut self.payload
+    }
+
+    /// Reads the remaining box payload into a vector.
+    ///
+    /// Payloads larger than 256 MiB, including the payloads of boxes extending to the end of
+    /// a larger stream, result in an error.
+    pub fn read_payload(&mut self) -> Result<Vec<u8>> {
+        let data = match self.len {
+            Some(_) => {
+                let n = self.payload.limit();
+                try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(&mut self.payload, n),
+                            "when reading payload of box {}", self.box_type)
+            }
+            None => {
+                let mut data = Vec::new();
+                let max = utils::MAX_EXACT_VEC_LEN;
+                try!((&mut self.payload).take(max + 1).read_to_end(&mut data));
+                if data.len() as u64 > max {
+                    return Err(io::Error::new(io::ErrorKind::Other, utils::InputLimitExceeded(max)).into());
+                }
+                data
+            }
+        };
+        Ok(data)
+    }
+
+    /// Reads the version and flags of a full box from the beginning of the remaining payload.
+    pub fn read_full_box_header(&mut self) -> Result<(u8, u32)> {
+        let header = try_if_eof!(self.payload.read_u32::<BigEndian>(),
+                                 "when reading version and flags of box {}", self.box_type);
+        Ok(((header >> 24) as u8, header & 0x00ff_ffff))
+    }
+
+    /// Returns a reader of the boxes nested in the remaining payload of this box.
+    ///
+    /// For boxes whose payload starts with other fields, like the `meta` full box, these
+    /// fields must be read before calling this method.
+    pub fn children(&mut self) -> Boxes<&mut BufRead> {
+        let consumed = self.len.map(|len| len - self.payload.limit())
+            .unwrap_or(u64::max_value() - self.payload.limit());
+        let base_offset = self.payload_offset() + consumed;
+        Boxes::with_base_offset(&mut self.payload as &mut BufRead, base_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use byteorder::{WriteBytesExt, BigEndian};
+
+    use super::{Boxes, BoxType};
+
+    macro_rules! build {
+        ($($arg:expr),+) => {{
+            let mut data = Vec::new();
+            $(data.write_all($arg).unwrap();)+
+            data
+        }}
+    }
+
+    fn n(n: u32) -> [u8; 4] {
+        let mut r = [0u8; 4];
+        (&mut r as &mut [u8]).write_u32::<BigEndian>(n).unwrap();
+        r
+    }
+
+    fn check_next_box<R: ::std::io::BufRead>(boxes: &mut Boxes<R>, box_type: BoxType, offset: u64, data: &[u8]) {
+        let mut b = boxes.next().unwrap().unwrap();
+
+        assert_eq!(b.box_type(), box_type);
+        assert_eq!(b.offset(), offset);
+        assert_eq!(b.len(), Some(data.len() as u64));
+        assert_eq!(&*b.read_payload().unwrap(), data);
+    }
+
+    #[test]
+    fn test_flat_boxes() {
+        let data = build! {
+            &n(12), b"ftyp", b"avif",
+            &n(1), b"free", &[0, 0, 0, 0, 0, 0, 0, 19], b"abc",
+            &n(0), b"mdat", b"image data"
+        };
+
+        let mut boxes = Boxes::new(&data[..]);
+        check_next_box(&mut boxes, BoxType(*b"ftyp"), 0, b"avif");
+        {
+            let b = boxes.next().unwrap().unwrap();
+            assert_eq!(b.box_type(), BoxType(*b"free"));
+            assert_eq!(b.offset(), 12);
+            assert_eq!(b.header_len(), 16);
+            assert_eq!(b.len(), Some(3));
+            assert_eq!(b.payload_offset(), 28);
+        }
+        {
+            let mut b = boxes.next().unwrap().unwrap();
+            assert_eq!(b.box_type(), BoxType(*b"mdat"));
+            assert_eq!(b.offset(), 31);
+            assert_eq!(b.len(), None);
+            assert_eq!(&*b.read_payload().unwrap(), b"image data");
+        }
+        assert!(boxes.next().is_none());
+        assert_eq!(boxes.position(), data.len() as u64);
+    }
+
+    #[test]
+    fn test_nested_boxes() {
+        let data = build! {
+            &n(8), b"ftyp",
+            &n(48), b"meta", &n(0),
+                &n(12), b"hdlr", b"pict",
+                &n(24), b"iprp",
+                    &n(16), b"ispe", &n(640), &n(480),
+            &n(28), b"uuid", b"0123456789abcdef", b"data",
+            &n(4), b"bad "
+        };
+
+        let mut boxes = Boxes::new(&data[..]);
+        check_next_box(&mut boxes, BoxType(*b"ftyp"), 0, b"");
+
+        {
+            let mut meta = boxes.next().unwrap().unwrap();
+            assert_eq!(meta.box_type(), BoxType(*b"meta"));
+            assert_eq!(meta.read_full_box_header().unwrap(), (0, 0));
+
+            let mut children = meta.children();
+            check_next_box(&mut children, BoxType(*b"hdlr"), 20, b"pict");
+            {
+                let mut iprp = children.next().unwrap().unwrap();
+                assert_eq!(iprp.offset(), 32);
+                let mut properties = iprp.children();
+                let mut ispe = properties.next().unwrap().unwrap();
+                assert_eq!(ispe.box_type(), BoxType(*b"ispe"));
+                assert_eq!(ispe.offset(), 40);
+                let mut size = [0u8; 8];
+                ispe.payload().read_exact(&mut size).unwrap();
+                assert_eq!(size, [0, 0, 2, 128, 0, 0, 1, 224]);
+            }
+            assert!(children.next().is_none());
+        }
+
+        {
+            let mut uuid = boxes.next().unwrap().unwrap();
+            assert_eq!(uuid.box_type(), BoxType(*b"uuid"));
+            assert_eq!(uuid.extended_type(), Some(*b"0123456789abcdef"));
+            assert_eq!(uuid.header_len(), 24);
+            assert_eq!(&*uuid.read_payload().unwrap(), b"data");
+        }
+
+        // the size is smaller than the header
+        assert!(boxes.next().unwrap().is_err());
+        assert!(boxes.next().is_none());
+    }
+
+    #[test]
+    fn test_truncated_boxes() {
+        let data = build! { &n(16), b"ftyp", b"avif" };
+        let mut boxes = Boxes::new(&data[..]);
+        assert!(boxes.next().unwrap().unwrap().read_payload().is_err());
+
+        let mut boxes = Boxes::new(&data[..]);
+        boxes.next().unwrap().unwrap();
+        assert!(boxes.next().unwrap().is_err());
+
+        let mut boxes = Boxes::new(&data[..6]);
+        assert!(boxes.next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Date and time values embedded into images.
+//!
+//! Image formats store timestamps in several textual and binary forms: EXIF uses
+//! `YYYY:MM:DD HH:MM:SS` strings with an optional separate UTC offset, XMP uses ISO 8601 and
+//! PNG stores a binary UTC timestamp in the tIME chunk. `DateTime` is a common representation
+//! of all of them.
+
+use std::fmt;
+
+macro_rules! try_opt {
+    ($e:expr) => {
+        match $e {
+            Some(value) => value,
+            None => return None
+        }
+    }
+}
+
+/// A calendar date and time of day, optionally with an offset from UTC.
+///
+/// The fields are only checked to be within their natural ranges, e.g. February 31 is
+/// accepted. Fractions of seconds are discarded.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DateTime {
+    /// Year, e.g. 2016.
+    pub year: u16,
+    /// Month, from 1 to 12.
+    pub month: u8,
+    /// Day of the month, from 1 to 31.
+    pub day: u8,
+    /// Hour, from 0 to 23.
+    pub hour: u8,
+    /// Minute, from 0 to 59.
+    pub minute: u8,
+    /// Second, from 0 to 60 (to allow for leap seconds).
+    pub second: u8,
+    /// Offset from UTC in minutes, if it is known.
+    ///
+    /// EXIF timestamps are usually in unspecified local time, in which case the offset is
+    /// `None`.
+    pub utc_offset_minutes: Option<i16>,
+}
+
+impl DateTime {
+    /// Creates a value from its components, checking that they are within their ranges.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<DateTime> {
+        if month < 1 || month > 12 || day < 1 || day > 31 || hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        Some(DateTime {
+            year: year,
+            month: month,
+            day: day,
+            hour: hour,
+            minute: minute,
+            second: second,
+            utc_offset_minutes: None,
+        })
+    }
+
+    /// Returns the same date and time with the given UTC offset.
+    #[inline]
+    pub fn with_utc_offset(mut self, minutes: Option<i16>) -> DateTime {
+        self.utc_offset_minutes = minutes;
+        self
+    }
+
+    /// Parses an EXIF timestamp in the `YYYY:MM:DD HH:MM:SS` form.
+    ///
+    /// Returns `None` if the value is malformed or unknown, which EXIF represents with blanks
+    /// or zeros in place of digits.
+    pub fn parse_exif(s: &str) -> Option<DateTime> {
+        let s = s.trim_end_matches('\0').trim();
+        let b = s.as_bytes();
+        if b.len() != 19 || b[4] != b':' || b[7] != b':' || b[10] != b' ' || b[13] != b':' || b[16] != b':' {
+            return None;
+        }
+        DateTime::new(
+            try_opt!(number(&s[0..4])), try_opt!(number(&s[5..7])), try_opt!(number(&s[8..10])),
+            try_opt!(number(&s[11..13])), try_opt!(number(&s[14..16])), try_opt!(number(&s[17..19]))
+        )
+    }
+
+    /// Parses an ISO 8601 timestamp as used by XMP, e.g. `2016-05-21T14:03:10.25+02:00`.
+    ///
+    /// The date is mandatory; the time of day may be omitted or given without seconds, in
+    /// which case the missing components are zero. Reduced precision dates without a day
+    /// (`YYYY` or `YYYY-MM`) are not supported.
+    pub fn parse_iso8601(s: &str) -> Option<DateTime> {
+        let s = s.trim();
+        if s.len() < 10 || !s.is_char_boundary(10) {
+            return None;
+        }
+        let (date, rest) = s.split_at(10);
+        let d = date.as_bytes();
+        if d[4] != b'-' || d[7] != b'-' {
+            return None;
+        }
+        let (year, month, day) = (try_opt!(number(&date[0..4])), try_opt!(number(&date[5..7])),
+                                  try_opt!(number(&date[8..10])));
+        if rest.is_empty() {
+            return DateTime::new(year, month, day, 0, 0, 0);
+        }
+        if !rest.starts_with('T') {
+            return None;
+        }
+        let rest = &rest[1..];
+
+        // the time zone designator follows the time
+        let tz_start = rest.find(|c| c == 'Z' || c == '+' || c == '-').unwrap_or(rest.len());
+        let (time, tz) = rest.split_at(tz_start);
+        let time = time.split('.').next().unwrap();
+        let (hour, minute, second) = match time.len() {
+            5 if time.as_bytes()[2] == b':' =>
+                (try_opt!(number(&time[0..2])), try_opt!(number(&time[3..5])), 0),
+            8 if time.as_bytes()[2] == b':' && time.as_bytes()[5] == b':' =>
+                (try_opt!(number(&time[0..2])), try_opt!(number(&time[3..5])), try_opt!(number(&time[6..8]))),
+            _ => return None
+        };
+        let offset = match tz {
+            "" => None,
+            "Z" => Some(0),
+            tz => Some(try_opt!(parse_utc_offset(tz)))
+        };
+        DateTime::new(year, month, day, hour, minute, second).map(|dt| dt.with_utc_offset(offset))
+    }
+}
+
+/// Parses a UTC offset in the `+HH:MM` or `-HH:MM` form, as used by ISO 8601 and by the
+/// EXIF `OffsetTime` tags, returning it in minutes.
+pub fn parse_utc_offset(s: &str) -> Option<i16> {
+    let s = s.trim_end_matches('\0').trim();
+    let b = s.as_bytes();
+    if b.len() != 6 || b[3] != b':' {
+        return None;
+    }
+    let sign = match b[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None
+    };
+    let (hours, minutes): (i16, i16) = (try_opt!(number(&s[1..3])), try_opt!(number(&s[4..6])));
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Parses a string consisting only of ASCII digits.
+fn number<T: ::std::str::FromStr>(s: &str) -> Option<T> {
+    if s.bytes().all(|b| b >= b'0' && b <= b'9') {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// Formats the value in the ISO 8601 form, e.g. `2016-05-21T14:03:10+02:00`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                    self.year, self.month, self.day, self.hour, self.minute, self.second));
+        match self.utc_offset_minutes {
+            Some(0) => write!(f, "Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.abs();
+                write!(f, "{}{:02}:{:02}", sign, offset / 60, offset % 60)
+            }
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DateTime, parse_utc_offset};
+
+    #[test]
+    fn test_parse_exif() {
+        assert_eq!(DateTime::parse_exif("2016:05:21 14:03:10\0"), DateTime::new(2016, 5, 21, 14, 3, 10));
+        assert_eq!(DateTime::parse_exif("0000:00:00 00:00:00"), None);
+        assert_eq!(DateTime::parse_exif("    :  :     :  :  "), None);
+        assert_eq!(DateTime::parse_exif("2016-05-21 14:03:10"), None);
+        assert_eq!(DateTime::parse_exif("2016:05:21"), None);
+    }
+
+    #[test]
+    fn test_parse_iso8601() {
+        let dt = DateTime::new(2016, 5, 21, 14, 3, 10).unwrap();
+        assert_eq!(DateTime::parse_iso8601("2016-05-21T14:03:10"), Some(dt));
+        assert_eq!(DateTime::parse_iso8601("2016-05-21T14:03:10.25Z"), Some(dt.with_utc_offset(Some(0))));
+        assert_eq!(DateTime::parse_iso8601("2016-05-21T14:03:10+02:00"), Some(dt.with_utc_offset(Some(120))));
+        assert_eq!(DateTime::parse_iso8601("2016-05-21T14:03-05:30"),
+                   DateTime::new(2016, 5, 21, 14, 3, 0).map(|dt| dt.with_utc_offset(Some(-330))));
+        assert_eq!(DateTime::parse_iso8601("2016-05-21"), DateTime::new(2016, 5, 21, 0, 0, 0));
+        assert_eq!(DateTime::parse_iso8601("2016-05"), None);
+        assert_eq!(DateTime::parse_iso8601("2016-13-21"), None);
+        assert_eq!(DateTime::parse_iso8601("2016-05-21 14:03:10"), None);
+        assert_eq!(DateTime::parse_iso8601("2016-05-21T14:03:10+2"), None);
+    }
+
+    #[test]
+    fn test_utc_offset() {
+        assert_eq!(parse_utc_offset("+09:00\0"), Some(540));
+        assert_eq!(parse_utc_offset("-00:30"), Some(-30));
+        assert_eq!(parse_utc_offset("09:00"), None);
+        assert_eq!(parse_utc_offset("   :  "), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let dt = DateTime::new(2016, 5, 21, 14, 3, 10).unwrap();
+        assert_eq!(dt.to_string(), "2016-05-21T14:03:10");
+        assert_eq!(dt.with_utc_offset(Some(0)).to_string(), "2016-05-21T14:03:10Z");
+        assert_eq!(dt.with_utc_offset(Some(-330)).to_string(), "2016-05-21T14:03:10-05:30");
+    }
+}
@@ -0,0 +1,231 @@
+//! Raw EXIF data.
+//!
+//! EXIF metadata is stored as a TIFF document embedded into the image container, e.g. into
+//! the APP1 segment of a JPEG file or into the `eXIf` chunk of a PNG file. immeta extracts
+//! this document but does not interpret individual EXIF tags; the raw data can be inspected
+//! with `common::tiff` or handed over to a dedicated EXIF library.
+//!
+//! When `exif` feature is enabled, `ExifData::to_exif()` converts the data to the `Exif`
+//! type of the [kamadak-exif](https://crates.io/crates/kamadak-exif) crate.
+
+use std::io::Cursor;
+
+#[cfg(feature = "exif")] use exif;
+
+use types::Result;
+use utils::{self, ByteOrderReadExt};
+use common::tiff::{TiffReader, entry_types};
+use common::datetime::{self, DateTime};
+
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD_POINTER: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_DATE_TIME_DIGITIZED: u16 = 0x9004;
+const TAG_OFFSET_TIME: u16 = 0x9010;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const TAG_OFFSET_TIME_DIGITIZED: u16 = 0x9012;
+
+/// Byte order of a TIFF document.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ByteOrder {
+    /// Little-endian byte order (`II`).
+    Little,
+    /// Big-endian byte order (`MM`).
+    Big,
+}
+
+/// Timestamps stored in EXIF data.
+///
+/// Each timestamp is combined with the respective `OffsetTime` tag, if it is present.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ExifTimestamps {
+    /// Time when the file was last changed, from the `DateTime` tag of IFD0.
+    pub modified: Option<DateTime>,
+    /// Time when the original image was taken, from the `DateTimeOriginal` tag.
+    pub original: Option<DateTime>,
+    /// Time when the image was stored as digital data, from the `DateTimeDigitized` tag.
+    pub digitized: Option<DateTime>,
+}
+
+/// EXIF data, that is, a TIFF document starting with its header.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExifData {
+    data: Vec<u8>,
+    byte_order: ByteOrder,
+    offset: u64,
+}
+
+impl ExifData {
+    /// Creates EXIF data from a TIFF document located at the given offset from the beginning
+    /// of the stream.
+    ///
+    /// Fails if the data does not start with a valid TIFF header.
+    pub fn new(data: Vec<u8>, offset: u64) -> Result<ExifData> {
+        if data.len() < 8 {
+            return Err(unexpected_eof!("when reading TIFF header of EXIF data"));
+        }
+        let byte_order = match (&data[..2], &data[2..4]) {
+            (b"II", b"\x2a\x00") => ByteOrder::Little,
+            (b"MM", b"\x00\x2a") => ByteOrder::Big,
+            _ => return Err(invalid_format!("invalid TIFF header of EXIF data: {:?}", &data[..4]))
+        };
+        Ok(ExifData {
+            data: data,
+            byte_order: byte_order,
+            offset: offset,
+        })
+    }
+
+    /// Returns the TIFF document.
+    ///
+    /// This is the form expected by most EXIF libraries, e.g. by `exif::Reader::read_raw()`
+    /// of kamadak-exif.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the TIFF document, consuming this value.
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Returns the byte order of the TIFF document.
+    #[inline]
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    /// Returns the offset of the TIFF document from the beginning of the stream.
+    ///
+    /// Offsets inside the TIFF document are relative to its beginning, so this value is the
+    /// base which should be added to them to locate the data in the original stream.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns a TIFF reader over the data.
+    #[inline]
+    pub fn tiff_reader(&self) -> TiffReader<Cursor<&[u8]>> {
+        TiffReader::new(Cursor::new(&self.data[..]))
+    }
+
+    /// Reads the timestamps from IFD0 and the EXIF IFD.
+    ///
+    /// Malformed and unknown timestamps are ignored.
+    pub fn timestamps(&self) -> Result<ExifTimestamps> {
+        let mut strings = Vec::new();
+        let ifds = try!(self.tiff_reader().ifds());
+        let ifd0 = match (&ifds).into_iter().next() {
+            Some(ifd) => try!(ifd),
+            None => return Ok(ExifTimestamps::default())
+        };
+
+        let mut exif_ifd_offset = None;
+        for entry in ifd0 {
+            let entry = try!(entry);
+            match entry.tag() {
+                TAG_DATE_TIME => if let Some(values) = entry.all_values::<entry_types::Ascii>() {
+                    strings.push((entry.tag(), try!(values)));
+                },
+                TAG_EXIF_IFD_POINTER => if let Some(values) = entry.all_values::<entry_types::Long>() {
+                    exif_ifd_offset = try!(values).first().cloned();
+                },
+                _ => {}
+            }
+        }
+
+        if let Some(offset) = exif_ifd_offset {
+            for entry in try!(ifds.ifd_at(offset as u64)) {
+                let entry = try!(entry);
+                match entry.tag() {
+                    TAG_DATE_TIME_ORIGINAL | TAG_DATE_TIME_DIGITIZED | TAG_OFFSET_TIME |
+                    TAG_OFFSET_TIME_ORIGINAL | TAG_OFFSET_TIME_DIGITIZED =>
+                        if let Some(values) = entry.all_values::<entry_types::Ascii>() {
+                            strings.push((entry.tag(), try!(values)));
+                        },
+                    _ => {}
+                }
+            }
+        }
+
+        let find = |tag| strings.iter()
+            .find(|&&(t, _)| t == tag)
+            .and_then(|&(_, ref values)| values.first())
+            .map(|s| &**s);
+        let timestamp = |tag, offset_tag| find(tag)
+            .and_then(DateTime::parse_exif)
+            .map(|dt| dt.with_utc_offset(find(offset_tag).and_then(datetime::parse_utc_offset)));
+
+        Ok(ExifTimestamps {
+            modified: timestamp(TAG_DATE_TIME, TAG_OFFSET_TIME),
+            original: timestamp(TAG_DATE_TIME_ORIGINAL, TAG_OFFSET_TIME_ORIGINAL),
+            digitized: timestamp(TAG_DATE_TIME_DIGITIZED, TAG_OFFSET_TIME_DIGITIZED),
+        })
+    }
+
+    /// Returns the value of the Orientation tag in IFD0, if it is present.
+    ///
+    /// Values from 1 to 8 describe how the image has to be rotated and flipped for display,
+    /// 1 meaning no transformation.
+    pub fn orientation(&self) -> Result<Option<u16>> {
+        find_orientation(&self.data).map(|location| location.map(|(_, _, value)| value))
+    }
+
+    /// Parses the data with kamadak-exif.
+    ///
+    /// This method is only available when `exif` feature is enabled.
+    #[cfg(feature = "exif")]
+    pub fn to_exif(&self) -> Result<exif::Exif> {
+        exif::Reader::new().read_raw(self.data.clone())
+            .map_err(|e| invalid_format!("invalid EXIF data: {}", e))
+    }
+}
+
+/// Finds the value of the Orientation tag in IFD0 of a TIFF document.
+///
+/// Returns the offset of the two value bytes from the beginning of the document, the byte
+/// order of the document and the current value, or `None` if there is no Orientation tag.
+pub(crate) fn find_orientation(tiff: &[u8]) -> Result<Option<(usize, utils::ByteOrder, u16)>> {
+    if tiff.len() < 8 {
+        return Err(unexpected_eof!("when reading TIFF header of EXIF data"));
+    }
+    let byte_order = match &tiff[..2] {
+        b"II" => utils::ByteOrder::Little,
+        b"MM" => utils::ByteOrder::Big,
+        bom => return Err(invalid_format!("invalid TIFF BOM: {:?}", bom))
+    };
+
+    // slices are read through readers because byte order reads on them require alignment
+    let u16_at = |i: usize| (&tiff[i..i + 2]).read_u16(byte_order);
+    let u32_at = |i: usize| (&tiff[i..i + 4]).read_u32(byte_order);
+
+    let ifd0 = try!(u32_at(4)) as usize;
+    if ifd0.saturating_add(2) > tiff.len() {
+        return Err(unexpected_eof!("when reading number of entries in IFD0"));
+    }
+    let count = try!(u16_at(ifd0)) as usize;
+    for i in 0..count {
+        let entry = ifd0 + 2 + i * 12;
+        if entry + 12 > tiff.len() {
+            return Err(unexpected_eof!("when reading IFD0 entries"));
+        }
+        let tag = try!(u16_at(entry));
+        let entry_type = try!(u16_at(entry + 2));
+        let n = try!(u32_at(entry + 4));
+        if tag == TAG_ORIENTATION {
+            // a single SHORT value is stored in the first two bytes of the value field
+            if entry_type != 3 || n != 1 {
+                return Err(invalid_format!("invalid Orientation entry: type {}, count {}", entry_type, n));
+            }
+            let value = try!(u16_at(entry + 8));
+            return Ok(Some((entry + 8, byte_order, value)));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,182 @@
+//! FLIR File Format (FFF) containers.
+//!
+//! Thermal cameras made by FLIR store radiometric data in an FFF container. In JPEG images
+//! the container is split into a sequence of APP1 segments with "FLIR" identifier; it may
+//! also be stored standalone in `.fff` and `.seq` files.
+//!
+//! An FFF container consists of a header followed by a directory of records. Only the records
+//! describing the raw thermal image, the embedded visible-light image and the camera are
+//! interpreted.
+
+use std::str;
+
+use types::{Result, Dimensions};
+use utils::ByteOrder;
+
+const HEADER_SIZE: usize = 0x40;
+const DIRECTORY_ENTRY_SIZE: usize = 0x20;
+
+const RECORD_RAW_DATA: u16 = 0x01;
+const RECORD_EMBEDDED_IMAGE: u16 = 0x0e;
+const RECORD_CAMERA_INFO: u16 = 0x20;
+
+/// Metadata stored in a FLIR FFF container.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FlirMetadata {
+    /// Name of the software which created the container.
+    pub creator: String,
+    /// Dimensions of the raw thermal image.
+    pub raw_thermal_dimensions: Option<Dimensions>,
+    /// Dimensions of the embedded visible-light image.
+    pub embedded_image_dimensions: Option<Dimensions>,
+    /// Camera model.
+    pub camera_model: Option<String>,
+    /// Camera serial number.
+    pub camera_serial_number: Option<String>,
+}
+
+/// Reads a zero-terminated string of at most `len` bytes at the given offset.
+fn read_str(data: &[u8], offset: usize, len: usize) -> Option<String> {
+    if data.len() < offset + len {
+        return None;
+    }
+    let s = &data[offset..offset + len];
+    let end = s.iter().position(|&b| b == 0).unwrap_or(len);
+    str::from_utf8(&s[..end]).ok()
+        .map(|s| s.trim().to_owned())
+        .and_then(|s| if s.is_empty() { None } else { Some(s) })
+}
+
+/// Reads dimensions from a raw data or embedded image record.
+///
+/// These records start with a 16-bit value equal to 2 in the byte order of the record,
+/// followed by the width and the height.
+fn read_image_dimensions(record: &[u8]) -> Option<Dimensions> {
+    if record.len() < 6 {
+        return None;
+    }
+    let byte_order = if ByteOrder::Little.read_u16(&record[0..2]) == 2 {
+        ByteOrder::Little
+    } else {
+        ByteOrder::Big
+    };
+    let w = byte_order.read_u16(&record[2..4]);
+    let h = byte_order.read_u16(&record[4..6]);
+    Some((w, h).into())
+}
+
+/// Parses an FFF container.
+pub fn parse_fff(data: &[u8]) -> Result<FlirMetadata> {
+    if data.len() < HEADER_SIZE {
+        return Err(unexpected_eof!("when reading FFF header"));
+    }
+    if &data[0..4] != b"FFF\0" && &data[0..4] != b"AFF\0" {
+        return Err(invalid_format!("invalid FFF signature: {:?}", &data[0..4]));
+    }
+
+    // the version is 100 or 101; it determines the byte order of the container
+    let byte_order = match ByteOrder::Big.read_u32(&data[0x14..0x18]) {
+        100...199 => ByteOrder::Big,
+        _ => ByteOrder::Little
+    };
+    let directory_offset = byte_order.read_u32(&data[0x18..0x1c]) as usize;
+    let entries = byte_order.read_u32(&data[0x1c..0x20]) as usize;
+
+    let mut md = FlirMetadata {
+        creator: read_str(data, 0x04, 16).unwrap_or_default(),
+        raw_thermal_dimensions: None,
+        embedded_image_dimensions: None,
+        camera_model: None,
+        camera_serial_number: None,
+    };
+
+    for i in 0..entries {
+        let entry_offset = directory_offset.saturating_add(i.saturating_mul(DIRECTORY_ENTRY_SIZE));
+        if entry_offset.saturating_add(DIRECTORY_ENTRY_SIZE) > data.len() {
+            return Err(unexpected_eof!("when reading FFF record directory"));
+        }
+        let entry = &data[entry_offset..entry_offset + DIRECTORY_ENTRY_SIZE];
+        let record_type = byte_order.read_u16(&entry[0x00..0x02]);
+        let offset = byte_order.read_u32(&entry[0x0c..0x10]) as usize;
+        let len = byte_order.read_u32(&entry[0x10..0x14]) as usize;
+        log_trace!("FFF record of type 0x{:02X} at offset {}, length {}", record_type, offset, len);
+
+        if record_type == 0 || len == 0 {
+            continue;
+        }
+        if offset.saturating_add(len) > data.len() {
+            log_debug!("FFF record of type 0x{:02X} is truncated", record_type);
+            continue;
+        }
+        let record = &data[offset..offset + len];
+
+        match record_type {
+            RECORD_RAW_DATA => md.raw_thermal_dimensions = read_image_dimensions(record),
+            RECORD_EMBEDDED_IMAGE => md.embedded_image_dimensions = read_image_dimensions(record),
+            RECORD_CAMERA_INFO => {
+                md.camera_model = read_str(record, 0xd4, 32);
+                md.camera_serial_number = read_str(record, 0x104, 16);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(md)
+}
+
+#[cfg(test)]
+mod tests {
+    use types::Dimensions;
+
+    use super::parse_fff;
+
+    fn put_u16(data: &mut [u8], offset: usize, n: u16) {
+        data[offset..offset + 2].copy_from_slice(&n.to_be_bytes());
+    }
+
+    fn put_u32(data: &mut [u8], offset: usize, n: u32) {
+        data[offset..offset + 4].copy_from_slice(&n.to_be_bytes());
+    }
+
+    /// Builds an FFF container with raw data and camera info records.
+    fn build_fff() -> Vec<u8> {
+        let mut data = vec![0u8; 0x40 + 2 * 0x20 + 0x20 + 0x120];
+        data[0..4].copy_from_slice(b"FFF\0");
+        data[4..8].copy_from_slice(b"ATAU");
+        put_u32(&mut data, 0x14, 100);
+        put_u32(&mut data, 0x18, 0x40);
+        put_u32(&mut data, 0x1c, 2);
+
+        // raw data record, stored in little-endian byte order
+        put_u16(&mut data, 0x40, 0x01);
+        put_u32(&mut data, 0x4c, 0x80);
+        put_u32(&mut data, 0x50, 0x20);
+        data[0x80..0x86].copy_from_slice(&[0x02, 0x00, 0x40, 0x01, 0xf0, 0x00]);
+
+        // camera info record
+        put_u16(&mut data, 0x60, 0x20);
+        put_u32(&mut data, 0x6c, 0xa0);
+        put_u32(&mut data, 0x70, 0x120);
+        data[0xa0 + 0xd4..0xa0 + 0xd4 + 7].copy_from_slice(b"FLIR E8");
+        data[0xa0 + 0x104..0xa0 + 0x104 + 8].copy_from_slice(b"12345678");
+
+        data
+    }
+
+    #[test]
+    fn test_parse_fff() {
+        let md = parse_fff(&build_fff()).unwrap();
+        assert_eq!(md.creator, "ATAU");
+        assert_eq!(md.raw_thermal_dimensions, Some(Dimensions { width: 320, height: 240 }));
+        assert_eq!(md.embedded_image_dimensions, None);
+        assert_eq!(md.camera_model, Some("FLIR E8".to_owned()));
+        assert_eq!(md.camera_serial_number, Some("12345678".to_owned()));
+    }
+
+    #[test]
+    fn test_invalid_fff() {
+        assert!(parse_fff(b"FFF\0").is_err());
+        assert!(parse_fff(&[0u8; 0x40]).is_err());
+    }
+}
@@ -1,2 +1,7 @@
 pub mod riff;
+pub mod bmff;
 pub mod tiff;
+pub mod xmp;
+pub mod flir;
+pub mod exif;
+pub mod datetime;
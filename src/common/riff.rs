@@ -1,4 +1,12 @@
-use std::io::{self, Read, Take};
+//! RIFF containers.
+//!
+//! A RIFF file consists of a header, which contains the total size of the file and its form
+//! type, followed by a sequence of chunks. Each chunk has a four-character identifier, a 32-bit
+//! little-endian length and a payload padded to an even number of bytes. `RIFF` and `LIST`
+//! chunks contain a type identifier followed by nested chunks.
+
+use std::cmp;
+use std::io::{BufRead, BufReader, Read, Take};
 use std::str;
 use std::result;
 use std::fmt;
@@ -6,7 +14,7 @@ use std::fmt;
 use byteorder::{ReadBytesExt, LittleEndian};
 
 use types::Result;
-use utils::ReadExt;
+use utils::{self, BufReadExt, Tracked};
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct ChunkId(pub [u8; 4]);
@@ -34,62 +42,286 @@ impl fmt::Display for ChunkId {
     }
 }
 
-pub struct RiffReader<R: Read> {
-    source: R
+/// Identifier of the RIFF file header.
+pub const RIFF_CHUNK_ID: ChunkId = ChunkId([b'R', b'I', b'F', b'F']);
+/// Identifier of the chunk which contains nested chunks.
+pub const LIST_CHUNK_ID: ChunkId = ChunkId([b'L', b'I', b'S', b'T']);
+
+enum Header {
+    /// RIFF file header with an optional expected form type.
+    Riff(Option<ChunkId>),
+    /// Type identifier of a `LIST` chunk.
+    List,
+    /// No header, chunks start immediately.
+    None,
 }
 
-impl<R: Read> RiffReader<R> {
-    pub fn new(source: R) -> RiffReader<R> {
-        RiffReader {
-            source: source
+/// A reader of RIFF chunks.
+///
+/// `Chunks` reads chunks one by one, providing access to their payload; the payload which is
+/// not read explicitly, as well as its padding, is skipped when the next chunk is requested.
+/// Chunks with nested chunks can be walked with `Chunk::subchunks()`.
+pub struct Chunks<R: BufRead> {
+    source: Tracked<R>,
+    header: Header,
+    form_type: Option<ChunkId>,
+    base_offset: u64,
+    end: Option<u64>,
+    chunk_end: u64,
+    started: bool,
+    finished: bool,
+}
+
+impl<R: BufRead> Chunks<R> {
+    /// Creates a new chunk reader for a RIFF file of any form type which starts at the current
+    /// position of the provided reader.
+    pub fn new(source: R) -> Chunks<R> {
+        Chunks::with_header(source, Header::Riff(None), 0)
+    }
+
+    /// Creates a new chunk reader for a RIFF file which must have the given form type,
+    /// e.g. `WEBP`.
+    ///
+    /// If the form type of the file is different, an error is returned when the first chunk
+    /// is requested.
+    pub fn with_form_type(source: R, form_type: ChunkId) -> Chunks<R> {
+        Chunks::with_header(source, Header::Riff(Some(form_type)), 0)
+    }
+
+    fn with_header(source: R, header: Header, base_offset: u64) -> Chunks<R> {
+        Chunks {
+            source: Tracked::new(source),
+            header: header,
+            form_type: None,
+            base_offset: base_offset,
+            end: None,
+            chunk_end: 0,
+            started: false,
+            finished: false,
         }
     }
 
-    pub fn root(&mut self) -> Result<RiffListChunk> {
-        let (id, len) = match try!(read_id_and_len(&mut self.source)) {
-            Some(t) => t,
-            None => return Err(unexpected_eof!())
-        };
+    /// Returns the form type of the RIFF file or the type of the `LIST` chunk.
+    ///
+    /// The type is only known after the first chunk has been requested.
+    #[inline]
+    pub fn form_type(&self) -> Option<ChunkId> {
+        self.form_type
+    }
 
-        if id.as_bytes() != b"RIFF" {
-            return Err(invalid_format!("RIFF file header is invalid"));
+    /// Returns the size of the RIFF file declared in its header, including the header itself.
+    ///
+    /// The size is only known after the first chunk has been requested; it is never known for
+    /// readers of nested chunks.
+    #[inline]
+    pub fn declared_size(&self) -> Option<u64> {
+        self.end
+    }
+
+    /// Returns the number of bytes consumed from the underlying reader so far.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.source.position
+    }
+
+    /// Unwraps this chunk reader, returning the underlying reader.
+    ///
+    /// Unread payload of the last chunk is not skipped.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.source.inner
+    }
+
+    /// Reads the next chunk.
+    ///
+    /// Returns `None` when there are no more chunks, that is, at the end of the RIFF file as
+    /// declared in its header or at the end of the stream, whichever comes first. The header
+    /// is checked before the first chunk is read; if it is invalid, an error is returned.
+    pub fn next(&mut self) -> Option<Result<Chunk<R>>> {
+        if self.finished {
+            return None;
+        }
+        match self.read_header() {
+            Ok(Some((offset, chunk_id, len))) => Some(Ok(self.chunk(offset, chunk_id, len))),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
         }
+    }
 
-        Ok(try!(RiffChunk {
-            data: Counter {
-                delegate: (&mut self.source as &mut Read).take(len as u64),
-                counter: None
-            },
-            tainted: false,
-            chunk_id: id,
-            len: len
-        }.into_list_unchecked()))
+    fn read_header(&mut self) -> Result<Option<(u64, ChunkId, u32)>> {
+        if !self.started {
+            self.started = true;
+            match self.header {
+                Header::Riff(expected) => {
+                    let mut id = [0u8; 4];
+                    try!(self.source.read_exact(&mut id).map_err(if_eof!(std, "when reading RIFF header")));
+                    if ChunkId(id) != RIFF_CHUNK_ID {
+                        return Err(invalid_format!("RIFF file header is invalid"));
+                    }
+                    let len = try_if_eof!(self.source.read_u32::<LittleEndian>(), "when reading RIFF file size");
+                    self.end = Some(8 + len as u64);
+                    let form_type = try!(self.read_type());
+                    if let Some(expected) = expected {
+                        if form_type != expected {
+                            return Err(invalid_format!("invalid RIFF form type: {}", form_type));
+                        }
+                    }
+                }
+                Header::List => {
+                    try!(self.read_type());
+                }
+                Header::None => {}
+            }
+        }
+
+        if self.source.position < self.chunk_end {
+            let n = self.chunk_end - self.source.position;
+            log_trace!("skipping {} bytes of RIFF chunk payload", n);
+            if try!(self.source.skip_exact(n)) != n {
+                // the padding byte of the last chunk is often missing
+                if self.source.position + 1 < self.chunk_end {
+                    return Err(unexpected_eof!("when skipping chunk payload"));
+                }
+            }
+        }
+
+        // the end of the stream is only allowed at a chunk boundary
+        if self.end.map(|end| self.source.position >= end).unwrap_or(false) ||
+           try!(self.source.fill_buf()).is_empty() {
+            return Ok(None);
+        }
+
+        let offset = self.base_offset + self.source.position;
+        let mut id = [0u8; 4];
+        try!(self.source.read_exact(&mut id).map_err(if_eof!(std, "when reading chunk id")));
+        let id = ChunkId(id);
+        let len = try_if_eof!(self.source.read_u32::<LittleEndian>(), "when reading length of chunk {}", id);
+        log_trace!("RIFF chunk {} at offset {}, length {}", id, offset, len);
+
+        Ok(Some((offset, id, len)))
+    }
+
+    fn read_type(&mut self) -> Result<ChunkId> {
+        let mut form_type = [0u8; 4];
+        try!(self.source.read_exact(&mut form_type).map_err(if_eof!(std, "when reading RIFF chunk type")));
+        self.form_type = Some(ChunkId(form_type));
+        Ok(ChunkId(form_type))
+    }
+
+    fn chunk(&mut self, offset: u64, chunk_id: ChunkId, len: u32) -> Chunk<R> {
+        self.chunk_end = self.source.position + len as u64 + (len & 1) as u64;
+
+        Chunk {
+            chunk_id: chunk_id,
+            offset: offset,
+            len: len,
+            payload: (&mut self.source).take(len as u64),
+        }
     }
 }
 
-struct Counter<'a, R> {
-    delegate: R,
-    counter: Option<&'a mut u32>
+/// A single RIFF chunk.
+///
+/// Values of this type are obtained with `Chunks::next()`.
+pub struct Chunk<'a, R: BufRead + 'a> {
+    chunk_id: ChunkId,
+    offset: u64,
+    len: u32,
+    payload: Take<&'a mut Tracked<R>>,
 }
 
-impl<'a, R: Read> Read for Counter<'a, R> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.delegate.read(buf).map(|n| {
-            if let Some(ref mut counter) = self.counter {
-                **counter += n as u32;
-            }
-            n
-        })
+impl<'a, R: BufRead + 'a> Chunk<'a, R> {
+    /// Returns the chunk identifier (FourCC), e.g. `LIST`.
+    #[inline]
+    pub fn chunk_id(&self) -> ChunkId { self.chunk_id }
+
+    /// Returns the offset of the chunk, i.e. of its identifier, from the beginning of the file.
+    #[inline]
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// Returns the size of the chunk payload, not including the chunk header and padding.
+    #[inline]
+    pub fn len(&self) -> u32 { self.len }
+
+    /// Returns `true` if this chunk is a `RIFF` or `LIST` chunk, i.e. it contains a type
+    /// identifier followed by nested chunks.
+    #[inline]
+    pub fn is_list(&self) -> bool {
+        self.chunk_id == RIFF_CHUNK_ID || self.chunk_id == LIST_CHUNK_ID
+    }
+
+    /// Returns a reader of the chunk payload.
+    #[inline]
+    pub fn payload(&mut self) -> &mut BufRead {
+        &mut self.payload
+    }
+
+    /// Reads the remaining chunk payload into a vector.
+    pub fn read_payload(&mut self) -> Result<Vec<u8>> {
+        let n = self.payload.limit();
+        Ok(try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(&mut self.payload, n),
+                       "when reading payload of RIFF chunk {}", self.chunk_id))
+    }
+
+    /// Returns a reader of the chunks nested in the remaining payload of this chunk.
+    ///
+    /// If this is a list chunk whose payload has not been read yet, the type identifier is
+    /// read first and is available with `Chunks::form_type()`. Otherwise the remaining payload
+    /// is treated as a plain sequence of chunks, like the frame data of WebP `ANMF` chunks.
+    pub fn subchunks(&mut self) -> Chunks<&mut BufRead> {
+        let consumed = self.len as u64 - self.payload.limit();
+        let header = if self.is_list() && consumed == 0 { Header::List } else { Header::None };
+        let base_offset = self.offset + 8 + consumed;
+        Chunks::with_header(&mut self.payload as &mut BufRead, header, base_offset)
     }
 }
 
+/// A reader of RIFF files.
+///
+/// This is a thin wrapper over `Chunks`, kept for compatibility.
+#[deprecated(note = "use `riff::Chunks` instead")]
+pub struct RiffReader<R: Read> {
+    source: BufReader<R>
+}
+
+#[allow(deprecated)]
+impl<R: Read> RiffReader<R> {
+    pub fn new(source: R) -> RiffReader<R> {
+        RiffReader {
+            source: BufReader::new(source)
+        }
+    }
+
+    pub fn root(&mut self) -> Result<RiffListChunk> {
+        let mut id = [0u8; 4];
+        try!(self.source.read_exact(&mut id).map_err(if_eof!(std, "when reading RIFF header")));
+        if ChunkId(id) != RIFF_CHUNK_ID {
+            return Err(invalid_format!("RIFF file header is invalid"));
+        }
+        let len = try_if_eof!(self.source.read_u32::<LittleEndian>(), "when reading RIFF file size");
+
+        let data: Box<BufRead> = Box::new((&mut self.source).take(len as u64));
+        RiffListChunk::new(RIFF_CHUNK_ID, 0, len, data)
+    }
+}
+
+/// A chunk returned by `RiffListChunk::next()`.
+#[deprecated(note = "use `riff::Chunk` instead")]
 pub struct RiffChunk<'a> {
     chunk_id: ChunkId,
+    offset: u64,
     len: u32,
     tainted: bool,
-    data: Counter<'a, Take<&'a mut Read>>
+    data: Box<BufRead + 'a>
 }
 
+#[allow(deprecated)]
 impl<'a> RiffChunk<'a> {
     #[inline]
     pub fn chunk_id(&self) -> ChunkId { self.chunk_id }
@@ -98,56 +330,50 @@ impl<'a> RiffChunk<'a> {
     pub fn len(&self) -> u32 { self.len }
 
     #[inline]
-    pub fn contents(&mut self) -> &mut Read { 
+    pub fn contents(&mut self) -> &mut Read {
         self.tainted = true;
         &mut self.data
     }
 
     #[inline]
     pub fn can_have_subchunks(&self) -> bool {
-        !self.tainted && match &self.chunk_id.0 {
-            b"RIFF" | b"LIST" => true,
-            _ => false
-        }
+        !self.tainted && (self.chunk_id == RIFF_CHUNK_ID || self.chunk_id == LIST_CHUNK_ID)
     }
 
     #[inline]
     pub fn into_list(self) -> result::Result<Result<RiffListChunk<'a>>, RiffChunk<'a>> {
         if self.can_have_subchunks() {
-            Ok(self.into_list_unchecked())
+            Ok(RiffListChunk::new(self.chunk_id, self.offset, self.len, self.data))
         } else {
             Err(self)
         }
     }
-
-    fn into_list_unchecked(mut self) -> Result<RiffListChunk<'a>> {
-        let mut chunk_type = [0u8; 4];
-
-        if try!(self.data.read_exact_0(&mut chunk_type)) != 4 {
-            return Err(unexpected_eof!("when reading chunk type of chunk {}", self.chunk_id));
-        }
-
-        Ok(RiffListChunk {
-            chunk_id: self.chunk_id,
-            len: self.len,
-            chunk_type: ChunkId(chunk_type),
-            data: self.data,
-            cur_chunk_len: 0,
-            cur_chunk_read: 0
-        })
-    }
 }
 
+/// A chunk with nested chunks, i.e. the RIFF file itself or a `LIST` chunk.
+#[deprecated(note = "use `riff::Chunks` instead")]
 pub struct RiffListChunk<'a> {
     chunk_id: ChunkId,
     len: u32,
     chunk_type: ChunkId,
-    data: Counter<'a, Take<&'a mut Read>>,
-    cur_chunk_len: u32,
-    cur_chunk_read: u32
+    chunks: Chunks<Box<BufRead + 'a>>
 }
 
+#[allow(deprecated)]
 impl<'a> RiffListChunk<'a> {
+    fn new(chunk_id: ChunkId, offset: u64, len: u32, mut data: Box<BufRead + 'a>) -> Result<RiffListChunk<'a>> {
+        let mut chunk_type = [0u8; 4];
+        try!(data.read_exact(&mut chunk_type)
+             .map_err(if_eof!(std, "when reading chunk type of chunk {}", chunk_id)));
+
+        Ok(RiffListChunk {
+            chunk_id: chunk_id,
+            len: len,
+            chunk_type: ChunkId(chunk_type),
+            chunks: Chunks::with_header(data, Header::None, offset + 12)
+        })
+    }
+
     #[inline]
     pub fn chunk_id(&self) -> ChunkId { self.chunk_id }
 
@@ -157,61 +383,154 @@ impl<'a> RiffListChunk<'a> {
     #[inline]
     pub fn chunk_type(&self) -> ChunkId { self.chunk_type }
 
-    #[inline]
     pub fn next(&mut self) -> Option<Result<RiffChunk>> {
-        if self.cur_chunk_read < self.cur_chunk_len {
-            let to_skip = (self.cur_chunk_len - self.cur_chunk_read) as u64;
-            match self.data.skip_exact_0(to_skip) {
-                Ok(n) if n == to_skip => {}
-                Ok(_) => return Some(Err(unexpected_eof!())),
-                Err(e) => return Some(Err(e.into()))
-            }
-        }
-
-        let (id, len) = match read_id_and_len(&mut self.data) {
-            Ok(Some(t)) => t,
-            Ok(None) => return None,
-            Err(e) => return Some(Err(e.into()))
-        };
-
-        self.cur_chunk_read = 0;
-        self.cur_chunk_len = len;
-
-        Some(Ok(RiffChunk {
-            chunk_id: id,
-            len: len,
+        self.chunks.next().map(|chunk| chunk.map(|chunk| RiffChunk {
+            chunk_id: chunk.chunk_id,
+            offset: chunk.offset,
+            len: chunk.len,
             tainted: false,
-            data: Counter {
-                delegate: (&mut self.data as &mut Read).take(len as u64),
-                counter: Some(&mut self.cur_chunk_read)
-            }
+            data: Box::new(chunk.payload)
         }))
     }
 }
 
-fn read_id_and_len<R: Read>(source: &mut R) -> Result<Option<(ChunkId, u32)>> {
-    let mut id = [0u8; 4];
+/// A chunk located by `SliceChunks`.
+pub(crate) struct SliceChunk<'a> {
+    pub chunk_id: ChunkId,
+    pub offset: u64,
+    /// The payload, which is truncated if the buffer ends inside of it.
+    pub payload: &'a [u8],
+}
+
+/// Locates chunks in a buffer like `Chunks` does in a stream, failing in the same cases.
+///
+/// This is used by parsers of in-memory data, which borrow chunk payloads from the buffer
+/// instead of reading them.
+pub(crate) struct SliceChunks<'a> {
+    data: &'a [u8],
+    header: Option<Option<ChunkId>>,
+    base_offset: u64,
+    end: Option<u64>,
+    pos: u64,
+    finished: bool,
+}
+
+impl<'a> SliceChunks<'a> {
+    /// Creates a new chunk locator for a RIFF file which must have the given form type.
+    pub fn with_form_type(data: &'a [u8], form_type: ChunkId) -> SliceChunks<'a> {
+        SliceChunks {
+            data: data,
+            header: Some(Some(form_type)),
+            base_offset: 0,
+            end: None,
+            pos: 0,
+            finished: false,
+        }
+    }
 
-    match try!(source.read_exact_0(&mut id)) {
-        0 => return Ok(None),
-        4 => {}
-        _ => return Err(unexpected_eof!())
+    /// Creates a new chunk locator for a plain sequence of chunks, like the payload of a
+    /// WebP `ANMF` chunk following its header, located at `base_offset` in the file.
+    pub fn sequence(data: &'a [u8], base_offset: u64) -> SliceChunks<'a> {
+        SliceChunks {
+            data: data,
+            header: None,
+            base_offset: base_offset,
+            end: None,
+            pos: 0,
+            finished: false,
+        }
     }
 
-    let len = try!(source.read_u32::<LittleEndian>());
+    /// Locates the next chunk; see `Chunks::next()`.
+    pub fn next(&mut self) -> Option<Result<SliceChunk<'a>>> {
+        if self.finished {
+            return None;
+        }
+        match self.read_chunk() {
+            Ok(Some(chunk)) => Some(Ok(chunk)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<SliceChunk<'a>>> {
+        if let Some(expected) = self.header.take() {
+            let data = self.data;
+            if data.len() < 4 {
+                return Err(unexpected_eof!("when reading RIFF header"));
+            }
+            if ChunkId([data[0], data[1], data[2], data[3]]) != RIFF_CHUNK_ID {
+                return Err(invalid_format!("RIFF file header is invalid"));
+            }
+            if data.len() < 8 {
+                return Err(unexpected_eof!("when reading RIFF file size"));
+            }
+            self.end = Some(8 + read_u32(&data[4..]) as u64);
+            if data.len() < 12 {
+                return Err(unexpected_eof!("when reading RIFF chunk type"));
+            }
+            let form_type = ChunkId([data[8], data[9], data[10], data[11]]);
+            if let Some(expected) = expected {
+                if form_type != expected {
+                    return Err(invalid_format!("invalid RIFF form type: {}", form_type));
+                }
+            }
+            self.pos = 12;
+        }
+
+        let data_len = self.data.len() as u64;
+        if self.pos > data_len {
+            // the padding byte of the last chunk is often missing
+            if self.pos > data_len + 1 {
+                return Err(unexpected_eof!("when skipping chunk payload"));
+            }
+            self.pos = data_len;
+        }
+
+        // the end of the data is only allowed at a chunk boundary
+        let rest = &self.data[self.pos as usize..];
+        if self.end.map(|end| self.pos >= end).unwrap_or(false) || rest.is_empty() {
+            return Ok(None);
+        }
 
-    Ok(Some((ChunkId(id), len)))
+        if rest.len() < 4 {
+            return Err(unexpected_eof!("when reading chunk id"));
+        }
+        let id = ChunkId([rest[0], rest[1], rest[2], rest[3]]);
+        if rest.len() < 8 {
+            return Err(unexpected_eof!("when reading length of chunk {}", id));
+        }
+        let len = read_u32(&rest[4..]);
+        let offset = self.base_offset + self.pos;
+        log_trace!("RIFF chunk {} at offset {}, length {}", id, offset, len);
+
+        let chunk = SliceChunk {
+            chunk_id: id,
+            offset: offset,
+            payload: &rest[8..cmp::min(8 + len as u64, rest.len() as u64) as usize],
+        };
+        self.pos += 8 + len as u64 + (len & 1) as u64;
+        Ok(Some(chunk))
+    }
+}
+
+fn read_u32(b: &[u8]) -> u32 {
+    b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Read, Write};
+    use std::io::Write;
 
     use byteorder::{WriteBytesExt, LittleEndian};
 
-    use utils::ReadExt;
-
-    use super::{RiffReader, RiffListChunk, ChunkId};
+    use super::{Chunks, ChunkId, SliceChunks};
 
     macro_rules! build {
         ($($arg:expr),+) => {{
@@ -227,145 +546,319 @@ mod tests {
         r
     }
 
-    fn check_next_chunk<'a>(cl: &mut RiffListChunk<'a>, id: ChunkId, len: u32, data: &[u8]) {
-        let chunk = cl.next();
-        let mut chunk = chunk.unwrap().unwrap();
+    fn check_next_chunk<R: ::std::io::BufRead>(cl: &mut Chunks<R>, id: ChunkId, offset: u64, data: &[u8]) {
+        let mut chunk = cl.next().unwrap().unwrap();
 
         assert_eq!(chunk.chunk_id(), id);
-        assert_eq!(chunk.len(), len);
-        let contents = chunk.contents().read_to_vec().unwrap();
-        assert_eq!(&*contents, data);
-        assert!(!chunk.can_have_subchunks());
+        assert_eq!(chunk.offset(), offset);
+        assert_eq!(chunk.len(), data.len() as u32);
+        assert_eq!(&*chunk.read_payload().unwrap(), data);
+        assert!(!chunk.is_list());
     }
 
     #[test]
     fn test_invalid_header() {
-        let mut data = b"XXXX\x04abcd" as &[u8];
-
-        let mut r = RiffReader::new(&mut data);
+        let data = b"XXXX\x04abcd" as &[u8];
+        assert!(Chunks::new(data).next().unwrap().is_err());
 
-        let root = r.root();
-        assert!(root.is_err());
+        let data = b"RIFF\x04\x00\x00\x00abcd" as &[u8];
+        assert!(Chunks::with_form_type(data, ChunkId(*b"WEBP")).next().unwrap().is_err());
     }
 
     #[test]
     fn test_flat_chunks() {
         let data = build! {
-            b"RIFF", &n(37), b"abcd",
+            b"RIFF", &n(30), b"abcd",
             b"A   ", &n(4), b"1234",
-            b"B   ", &n(5), b"56789"
+            b"B   ", &n(5), b"56789", b"\0",
+            b"trailing data"
         };
-        let mut data: &[u8] = &data;
 
-        let mut r = RiffReader::new(&mut data);
-
-        let mut root = r.root().unwrap();
-
-        assert_eq!(root.chunk_id(), ChunkId(*b"RIFF"));
-        assert_eq!(root.len(), 37);
-        assert_eq!(root.chunk_type(), ChunkId(*b"abcd"));
+        let mut chunks = Chunks::new(&data[..]);
+        check_next_chunk(&mut chunks, ChunkId(*b"A   "), 12, b"1234");
+        assert_eq!(chunks.form_type(), Some(ChunkId(*b"abcd")));
+        assert_eq!(chunks.declared_size(), Some(38));
+        check_next_chunk(&mut chunks, ChunkId(*b"B   "), 24, b"56789");
+        assert!(chunks.next().is_none());
+        assert_eq!(chunks.position(), 38);
+    }
 
-        check_next_chunk(&mut root, ChunkId(*b"A   "), 4, b"1234");
-        check_next_chunk(&mut root, ChunkId(*b"B   "), 5, b"56789");
+    #[test]
+    fn test_missing_padding() {
+        let data = build! {
+            b"RIFF", &n(17), b"abcd",
+            b"A   ", &n(1), b"z"
+        };
 
-        assert!(root.next().is_none());
+        let mut chunks = Chunks::new(&data[..]);
+        check_next_chunk(&mut chunks, ChunkId(*b"A   "), 12, b"z");
+        assert!(chunks.next().is_none());
     }
 
     #[test]
     fn test_nested_chunks() {
         let data = build! {
-            b"RIFF", &n(77), b"abcd",
-            b"A   ", &n(1), b"z",
+            b"RIFF", &n(78), b"abcd",
+            b"A   ", &n(1), b"z", b"\0",
             b"LIST", &n(56), b"wxyz",
-                b" B  ", &n(3), b"123",
+                b" B  ", &n(3), b"123", b"\0",
                 b"LIST", &n(22), b"hi  ",
                     b"  C ", &n(0),
                     b"   D", &n(2), b"op",
-                b"E   ", &n(3), b"fuz"
+                b"E   ", &n(2), b"fu"
         };
-        let mut data: &[u8] = &data;
-
-        let mut r = RiffReader::new(&mut data);
 
-        let mut root = r.root().unwrap();
-
-        assert_eq!(root.chunk_id(), ChunkId(*b"RIFF"));
-        assert_eq!(root.len(), 77);
-        assert_eq!(root.chunk_type(), ChunkId(*b"abcd"));
-
-        check_next_chunk(&mut root, ChunkId(*b"A   "), 1, b"z");
+        let mut chunks = Chunks::new(&data[..]);
+        check_next_chunk(&mut chunks, ChunkId(*b"A   "), 12, b"z");
 
         {
-            let chunk = root.next().unwrap().unwrap();
+            let mut chunk = chunks.next().unwrap().unwrap();
             assert_eq!(chunk.chunk_id(), ChunkId(*b"LIST"));
+            assert_eq!(chunk.offset(), 22);
             assert_eq!(chunk.len(), 56);
-            assert!(chunk.can_have_subchunks());
-
-            let chunk = chunk.into_list();
-            assert!(chunk.is_ok());
-            let mut chunk = chunk.ok().unwrap().unwrap();
+            assert!(chunk.is_list());
 
-            check_next_chunk(&mut chunk, ChunkId(*b" B  "), 3, b"123");
+            let mut list = chunk.subchunks();
+            check_next_chunk(&mut list, ChunkId(*b" B  "), 34, b"123");
+            assert_eq!(list.form_type(), Some(ChunkId(*b"wxyz")));
 
             {
-                let sublist = chunk.next().unwrap().unwrap();
+                let mut sublist = list.next().unwrap().unwrap();
                 assert_eq!(sublist.chunk_id(), ChunkId(*b"LIST"));
+                assert_eq!(sublist.offset(), 46);
                 assert_eq!(sublist.len(), 22);
-                assert!(sublist.can_have_subchunks());
 
-                let sublist = sublist.into_list();
-                assert!(sublist.is_ok());
-                let mut sublist = sublist.ok().unwrap().unwrap();
-
-                check_next_chunk(&mut sublist, ChunkId(*b"  C "), 0, b"");
-                check_next_chunk(&mut sublist, ChunkId(*b"   D"), 2, b"op");
+                let mut sublist = sublist.subchunks();
+                check_next_chunk(&mut sublist, ChunkId(*b"  C "), 58, b"");
+                check_next_chunk(&mut sublist, ChunkId(*b"   D"), 66, b"op");
+                assert!(sublist.next().is_none());
             }
 
-            check_next_chunk(&mut chunk, ChunkId(*b"E   "), 3, b"fuz");
-
-            assert!(chunk.next().is_none());
+            check_next_chunk(&mut list, ChunkId(*b"E   "), 76, b"fu");
+            assert!(list.next().is_none());
         }
 
-        assert!(root.next().is_none());
+        assert!(chunks.next().is_none());
     }
 
     #[test]
     fn test_skip_chunk_data() {
         let data = build! {
-            b"RIFF", &n(77), b"abcd",
+            b"RIFF", &n(58), b"abcd",
             b"A   ", &n(10), b"abcdefghij",
-            b" B  ", &n(12), b"123456789012",
+            b" B  ", &n(12), b"1234", &n(4), b"wxyz",
             b"  C ", &n(8),  b"ABCDEFGH"
         };
-        let mut data: &[u8] = &data;
-
-        let mut r = RiffReader::new(&mut data);
 
-        let mut root = r.root().unwrap();
+        let mut chunks = Chunks::new(&data[..]);
 
         {
-            let mut chunk = root.next().unwrap().unwrap();
+            let mut chunk = chunks.next().unwrap().unwrap();
             assert_eq!(chunk.chunk_id(), ChunkId(*b"A   "));
-            assert_eq!(chunk.len(), 10);
-            assert_eq!(
-                (&mut chunk.contents() as &mut Read).take(5).read_to_vec().unwrap(),
-                b"abcde".to_owned()
-            );
+            let mut prefix = [0u8; 5];
+            chunk.payload().read_exact(&mut prefix).unwrap();
+            assert_eq!(&prefix, b"abcde");
         }
 
         {
-            let chunk = root.next().unwrap().unwrap();
+            let mut chunk = chunks.next().unwrap().unwrap();
             assert_eq!(chunk.chunk_id(), ChunkId(*b" B  "));
             assert_eq!(chunk.len(), 12);
+            // the remaining payload is treated as a chunk sequence
+            let mut sequence = chunk.subchunks();
+            check_next_chunk(&mut sequence, ChunkId(*b"1234"), 38, b"wxyz");
+            assert!(sequence.next().is_none());
         }
 
-        {
-            let mut chunk = root.next().unwrap().unwrap();
-            assert_eq!(chunk.chunk_id(), ChunkId(*b"  C "));
-            assert_eq!(chunk.len(), 8);
-            assert_eq!(chunk.contents().read_to_vec().unwrap(), b"ABCDEFGH".to_owned());
+        check_next_chunk(&mut chunks, ChunkId(*b"  C "), 50, b"ABCDEFGH");
+        assert!(chunks.next().is_none());
+    }
+
+    #[test]
+    fn test_slice_chunks() {
+        let data = build! {
+            b"RIFF", &n(53), b"abcd",
+            b"A   ", &n(4), b"1234",
+            b"B   ", &n(11), b"C   ", &n(3), b"xyz", b"\0",
+            b"D   ", &n(9), b"5"
+        };
+
+        let mut chunks = SliceChunks::with_form_type(&data, ChunkId(*b"abcd"));
+        let chunk = chunks.next().unwrap().unwrap();
+        assert_eq!((chunk.chunk_id, chunk.offset, chunk.payload), (ChunkId(*b"A   "), 12, &b"1234"[..]));
+
+        let chunk = chunks.next().unwrap().unwrap();
+        assert_eq!((chunk.chunk_id, chunk.offset), (ChunkId(*b"B   "), 24));
+        // the missing padding byte of the last chunk is tolerated
+        let mut sequence = SliceChunks::sequence(chunk.payload, chunk.offset + 8);
+        let subchunk = sequence.next().unwrap().unwrap();
+        assert_eq!((subchunk.chunk_id, subchunk.offset, subchunk.payload), (ChunkId(*b"C   "), 32, &b"xyz"[..]));
+        assert!(sequence.next().is_none());
+
+        // the payload of a truncated chunk is truncated as well, and skipping it fails
+        let chunk = chunks.next().unwrap().unwrap();
+        assert_eq!((chunk.chunk_id, chunk.offset, chunk.payload), (ChunkId(*b"D   "), 44, &b"5"[..]));
+        assert!(chunks.next().unwrap().is_err());
+        assert!(chunks.next().is_none());
+
+        assert!(SliceChunks::with_form_type(&data, ChunkId(*b"WEBP")).next().unwrap().is_err());
+        assert!(SliceChunks::with_form_type(&data[..10], ChunkId(*b"abcd")).next().unwrap().is_err());
+    }
+
+    #[allow(deprecated)]
+    mod riff_reader {
+        use std::io::{Read, Write};
+
+        use super::n;
+        use super::super::{RiffReader, RiffListChunk, ChunkId};
+
+        fn read_to_vec(r: &mut Read) -> Vec<u8> {
+            let mut buf = Vec::new();
+            r.read_to_end(&mut buf).unwrap();
+            buf
         }
 
-        assert!(root.next().is_none());
+        fn check_next_chunk<'a>(cl: &mut RiffListChunk<'a>, id: ChunkId, len: u32, data: &[u8]) {
+            let chunk = cl.next();
+            let mut chunk = chunk.unwrap().unwrap();
+
+            assert_eq!(chunk.chunk_id(), id);
+            assert_eq!(chunk.len(), len);
+            let contents = read_to_vec(chunk.contents());
+            assert_eq!(&*contents, data);
+            assert!(!chunk.can_have_subchunks());
+        }
+
+        #[test]
+        fn test_invalid_header() {
+            let mut data = b"XXXX\x04abcd" as &[u8];
+
+            let mut r = RiffReader::new(&mut data);
+
+            let root = r.root();
+            assert!(root.is_err());
+        }
+
+        #[test]
+        fn test_flat_chunks() {
+            let data = build! {
+                b"RIFF", &n(37), b"abcd",
+                b"A   ", &n(4), b"1234",
+                b"B   ", &n(5), b"56789"
+            };
+            let mut data: &[u8] = &data;
+
+            let mut r = RiffReader::new(&mut data);
+
+            let mut root = r.root().unwrap();
+
+            assert_eq!(root.chunk_id(), ChunkId(*b"RIFF"));
+            assert_eq!(root.len(), 37);
+            assert_eq!(root.chunk_type(), ChunkId(*b"abcd"));
+
+            check_next_chunk(&mut root, ChunkId(*b"A   "), 4, b"1234");
+            check_next_chunk(&mut root, ChunkId(*b"B   "), 5, b"56789");
+
+            assert!(root.next().is_none());
+        }
+
+        #[test]
+        fn test_nested_chunks() {
+            // odd-sized chunks are followed by a padding byte
+            let data = build! {
+                b"RIFF", &n(79), b"abcd",
+                b"A   ", &n(1), b"z", b"\0",
+                b"LIST", &n(57), b"wxyz",
+                    b" B  ", &n(3), b"123", b"\0",
+                    b"LIST", &n(22), b"hi  ",
+                        b"  C ", &n(0),
+                        b"   D", &n(2), b"op",
+                    b"E   ", &n(3), b"fuz"
+            };
+            let mut data: &[u8] = &data;
+
+            let mut r = RiffReader::new(&mut data);
+
+            let mut root = r.root().unwrap();
+
+            assert_eq!(root.chunk_id(), ChunkId(*b"RIFF"));
+            assert_eq!(root.len(), 79);
+            assert_eq!(root.chunk_type(), ChunkId(*b"abcd"));
+
+            check_next_chunk(&mut root, ChunkId(*b"A   "), 1, b"z");
+
+            {
+                let chunk = root.next().unwrap().unwrap();
+                assert_eq!(chunk.chunk_id(), ChunkId(*b"LIST"));
+                assert_eq!(chunk.len(), 57);
+                assert!(chunk.can_have_subchunks());
+
+                let chunk = chunk.into_list();
+                assert!(chunk.is_ok());
+                let mut chunk = chunk.ok().unwrap().unwrap();
+
+                check_next_chunk(&mut chunk, ChunkId(*b" B  "), 3, b"123");
+
+                {
+                    let sublist = chunk.next().unwrap().unwrap();
+                    assert_eq!(sublist.chunk_id(), ChunkId(*b"LIST"));
+                    assert_eq!(sublist.len(), 22);
+                    assert!(sublist.can_have_subchunks());
+
+                    let sublist = sublist.into_list();
+                    assert!(sublist.is_ok());
+                    let mut sublist = sublist.ok().unwrap().unwrap();
+
+                    check_next_chunk(&mut sublist, ChunkId(*b"  C "), 0, b"");
+                    check_next_chunk(&mut sublist, ChunkId(*b"   D"), 2, b"op");
+                }
+
+                check_next_chunk(&mut chunk, ChunkId(*b"E   "), 3, b"fuz");
+
+                assert!(chunk.next().is_none());
+            }
+
+            assert!(root.next().is_none());
+        }
+
+        #[test]
+        fn test_skip_chunk_data() {
+            let data = build! {
+                b"RIFF", &n(77), b"abcd",
+                b"A   ", &n(10), b"abcdefghij",
+                b" B  ", &n(12), b"123456789012",
+                b"  C ", &n(8),  b"ABCDEFGH"
+            };
+            let mut data: &[u8] = &data;
+
+            let mut r = RiffReader::new(&mut data);
+
+            let mut root = r.root().unwrap();
+
+            {
+                let mut chunk = root.next().unwrap().unwrap();
+                assert_eq!(chunk.chunk_id(), ChunkId(*b"A   "));
+                assert_eq!(chunk.len(), 10);
+                assert_eq!(
+                    read_to_vec(&mut (&mut chunk.contents() as &mut Read).take(5)),
+                    b"abcde".to_owned()
+                );
+            }
+
+            {
+                let chunk = root.next().unwrap().unwrap();
+                assert_eq!(chunk.chunk_id(), ChunkId(*b" B  "));
+                assert_eq!(chunk.len(), 12);
+            }
+
+            {
+                let mut chunk = root.next().unwrap().unwrap();
+                assert_eq!(chunk.chunk_id(), ChunkId(*b"  C "));
+                assert_eq!(chunk.len(), 8);
+                assert_eq!(read_to_vec(chunk.contents()), b"ABCDEFGH".to_owned());
+            }
+
+            assert!(root.next().is_none());
+        }
     }
 }
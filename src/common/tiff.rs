@@ -1,11 +1,11 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Cursor};
 use std::cell::{RefCell, Cell};
 use std::marker::PhantomData;
 
 use byteorder;
 
 use types::Result;
-use utils::{ByteOrder, ByteOrderReadExt};
+use utils::{self, ByteOrder, ByteOrderReadExt};
 
 /// A TIFF document reader.
 ///
@@ -66,16 +66,36 @@ impl<R: Read + Seek> TiffReader<R> {
             source: RefCell::new(self.source),
             byte_order: byte_order,
             next_ifd_offset: Cell::new(next_ifd_offset as u64),
+            buffer: RefCell::new(Vec::new()),
         })
     }
 }
 
+impl TiffReader<Cursor<Vec<u8>>> {
+    /// Reads a TIFF document from the provided forward-only stream into memory and returns
+    /// a new TIFF reader for it.
+    ///
+    /// Offsets in a TIFF document may point anywhere, so it can't be parsed without seeking.
+    /// This method buffers the whole stream instead, failing with `Error::LimitExceeded` if it
+    /// is longer than `max_size` bytes.
+    pub fn from_reader<R: Read>(r: R, max_size: u64) -> Result<TiffReader<Cursor<Vec<u8>>>> {
+        let mut data = Vec::new();
+        try!(r.take(max_size.saturating_add(1)).read_to_end(&mut data));
+        if data.len() as u64 > max_size {
+            return Err(limit_exceeded!("TIFF document is larger than {} bytes", max_size));
+        }
+        Ok(TiffReader::new(Cursor::new(data)))
+    }
+}
+
 /// An intermediate structure, a reference to which can be converted to an iterator
 /// of IFDs.
 pub struct LazyIfds<R: Read + Seek> {
     source: RefCell<R>,
     byte_order: ByteOrder,
     next_ifd_offset: Cell<u64>,
+    // scratch buffer for reading referenced entry values, shared by all entries
+    buffer: RefCell<Vec<u8>>,
 }
 
 impl<'a, R: Read + Seek> IntoIterator for &'a LazyIfds<R> {
@@ -110,40 +130,60 @@ impl<'a, R: Read + Seek> Ifds<'a, R> {
             return Ok(None);
         }
 
-        // seek to the beginning of the next IFD
+        // update the next IFD offset for further calls to `next()`
+        let (ifd, next_ifd_offset) = try!(self.0.read_ifd_at(next_ifd_offset));
+        self.0.next_ifd_offset.set(next_ifd_offset);
+
+        Ok(Some(ifd))
+    }
+}
+
+impl<R: Read + Seek> LazyIfds<R> {
+    /// Reads the IFD located at the given offset from the beginning of the TIFF document.
+    ///
+    /// This is intended for sub-IFDs which are not a part of the main IFD chain, e.g. the
+    /// EXIF IFD referenced by the `ExifIFDPointer` entry. Iteration over the main IFD chain
+    /// is not affected.
+    pub fn ifd_at(&self, offset: u64) -> Result<Ifd<R>> {
+        self.read_ifd_at(offset).map(|(ifd, _)| ifd)
+    }
+
+    /// Reads the IFD at the given offset, returning it together with the next IFD offset.
+    fn read_ifd_at(&self, current_ifd_offset: u64) -> Result<(Ifd<R>, u64)> {
+        // seek to the beginning of the IFD
         try_if_eof!(std,
-            self.0.source.borrow_mut().seek(SeekFrom::Start(next_ifd_offset as u64)),
+            self.source.borrow_mut().seek(SeekFrom::Start(current_ifd_offset)),
             "when seeking to the beginning of the next IFD"
         );
-        let current_ifd_offset = next_ifd_offset;
 
         // read the length of this IFD
         let current_ifd_size = try_if_eof!(
-            self.0.source.borrow_mut().read_u16(self.0.byte_order), "when reading number of entries in an IFD"
+            self.source.borrow_mut().read_u16(self.byte_order), "when reading number of entries in an IFD"
         );
+        log_trace!("TIFF IFD at offset {} with {} entries", current_ifd_offset, current_ifd_size);
+
         // it is an error for an IFD to be empty
         if current_ifd_size == 0 {
             return Err(invalid_format!("number of entries in an IFD is zero"));
         }
 
-        // compute the offset of the next IFD offset and seek to it
-        let next_ifd_offset_offset = current_ifd_offset + 2 + current_ifd_size as u64 * 12;
-        try_if_eof!(std,
-            self.0.source.borrow_mut().seek(SeekFrom::Start(next_ifd_offset_offset as u64)),
-            "when seeking to the next IFD offset"
+        // read the whole entry table followed by the next IFD offset at once, so entries
+        // are parsed from memory
+        let table_size = current_ifd_size as u64 * 12 + 4;
+        let mut entries = try_if_eof!(std,
+            self.source.borrow_mut().read_exact_vec(table_size),
+            "when reading TIFF IFD entries"
         );
 
-        // read and update the next IFD offset for further calls to `next()`
-        self.0.next_ifd_offset.set(try_if_eof!(
-            self.0.source.borrow_mut().read_u32(self.0.byte_order), "when reading the next IFD offset"
-        ) as u64);
+        let next_offset_data = entries.split_off(table_size as usize - 4);
+        let next_ifd_offset = try!((&next_offset_data[..]).read_u32(self.byte_order)) as u64;
 
-        Ok(Some(Ifd {
-            ifds: self.0,
-            ifd_offset: current_ifd_offset,
+        Ok((Ifd {
+            ifds: self,
+            entries: entries,
             current_entry: 0,
             total_entries: current_ifd_size,
-        }))
+        }, next_ifd_offset))
     }
 }
 
@@ -152,7 +192,8 @@ impl<'a, R: Read + Seek> Ifds<'a, R> {
 /// A TIFF IFD consists of entries, so this structure is an iterator yielding IFD entries.
 pub struct Ifd<'a, R: Read + Seek + 'a> {
     ifds: &'a LazyIfds<R>,
-    ifd_offset: u64,
+    // raw entry table, 12 bytes per entry
+    entries: Vec<u8>,
     current_entry: u16,
     total_entries: u16,
 }
@@ -176,37 +217,17 @@ impl<'a, R: Read + Seek + 'a> Ifd<'a, R> {
     }
 
     fn read_entry(&mut self) -> Result<Entry<'a, R>> {
-        let mut source = self.ifds.source.borrow_mut();
-
-        // seek to the beginning of the next entry (ifd offset + 2 + next_entry * 12)
-        try!(source.seek(SeekFrom::Start(self.ifd_offset + 2 + self.current_entry as u64 * 12)));
+        let byte_order = self.ifds.byte_order;
+        let start = self.current_entry as usize * 12;
+        let mut data = &self.entries[start..start + 12];
 
-        // read the tag
-        let tag = try_if_eof!(
-            source.read_u16(self.ifds.byte_order), "when reading TIFF IFD entry tag"
-        );
+        let tag = try!(data.read_u16(byte_order));
+        let entry_type = try!(data.read_u16(byte_order));
+        let count = try!(data.read_u32(byte_order));
+        let offset = try!(data.read_u32(byte_order));
 
-        // read the entry type
-        let entry_type = try_if_eof!(
-            source.read_u16(self.ifds.byte_order), "when reading TIFF IFD entry type"
-        );
-
-        // read the count
-        let count = try_if_eof!(
-            source.read_u32(self.ifds.byte_order), "when reading TIFF IFD entry data count"
-        );
-
-        // read the offset/value
-        let offset = try_if_eof!(
-            source.read_u32(self.ifds.byte_order), "when reading TIFF IFD entry data offset"
-        );
-
-        println!("---------------------------------");
-        println!("Entry tag:                   {:04X}, {}", tag, tag);
-        println!("Entry type:                  {:04X}, {}", entry_type, entry_type);
-        println!("Entry items count:       {:08X}, {}", count, count);
-        println!("Entry data offset/value: {:08X}, {}", offset, offset);
-        println!("---------------------------------");
+        log_trace!("TIFF IFD entry: tag 0x{:04X}, type {}, count {}, offset/value 0x{:08X}",
+                   tag, entry_type, count, offset);
 
         self.current_entry += 1;
 
@@ -319,7 +340,7 @@ impl<'a, R: Read + Seek + 'a> Entry<'a, R> {
             if let Some(entry_type_size) = T::entry_type().size() {
                 // if the total entry data size is smaller than 4 bytes (u32 value length)
                 // the the data is embedded into the offset u32
-                if entry_type_size as u32 * self.count <= 4 {
+                if utils::saturating_size(self.count as u64, entry_type_size as u64) <= 4 {
                     let mut data = [0u8; 4];
                     self.ifds.byte_order.write_u32(&mut data, self.offset);
                     Some(EntryValues::Embedded(EmbeddedValues {
@@ -334,7 +355,7 @@ impl<'a, R: Read + Seek + 'a> Entry<'a, R> {
                     Some(EntryValues::Referenced(ReferencedValues {
                         ifds: self.ifds,
                         count: self.count,
-                        next_offset: self.offset,
+                        next_offset: self.offset as u64,
                         bytes_read: 0,
                         _entry_type_repr: PhantomData,
                     }))
@@ -355,6 +376,22 @@ impl<'a, R: Read + Seek + 'a> Entry<'a, R> {
     /// unknown.
     #[inline]
     pub fn all_values<T: EntryTypeRepr>(&self) -> Option<Result<Vec<T::Repr>>> {
+        let mut result = Vec::new();
+        self.all_values_into::<T>(&mut result).map(|r| r.map(|_| result))
+    }
+
+    /// Loads all of the items of this entry with the specified representation type into
+    /// the provided vector.
+    ///
+    /// The vector is cleared first, so it can be reused for reading several entries without
+    /// reallocating it every time. Its contents are unspecified if an error is returned.
+    ///
+    /// This method returns `None` if the requested representation type does not correspond
+    /// to the actual type of the entry. Also it returns `None` if the entry type is
+    /// unknown.
+    pub fn all_values_into<T: EntryTypeRepr>(&self, target: &mut Vec<T::Repr>) -> Option<Result<()>> {
+        target.clear();
+
         // compare the requested repr type with the actual entry type
         if self.entry_type == T::entry_type() {
             // then try to get the size and ignore the data in the entry if it is unknown
@@ -362,8 +399,14 @@ impl<'a, R: Read + Seek + 'a> Entry<'a, R> {
                 // if the total entry data size is smaller than 4 bytes (u32 value length)
                 // the the data is embedded into the offset u32, and we just delegate to the
                 // iterator
-                if entry_type_size as u32 * self.count <= 4 {
-                    Some(self.values::<T>().unwrap().collect())
+                if utils::saturating_size(self.count as u64, entry_type_size as u64) <= 4 {
+                    for value in self.values::<T>().unwrap() {
+                        match value {
+                            Ok(value) => target.push(value),
+                            Err(e) => return Some(Err(e))
+                        }
+                    }
+                    Some(Ok(()))
                 // othewise the data is stored at that offset, load it all at once
                 } else {
                     match self.ifds.source.borrow_mut().seek(SeekFrom::Start(self.offset as u64))
@@ -373,14 +416,10 @@ impl<'a, R: Read + Seek + 'a> Entry<'a, R> {
                         Err(e) => return Some(Err(e))
                     }
 
-                    let mut result = Vec::new();
-                    match T::read_many_from(&mut *self.ifds.source.borrow_mut(),
-                                            self.ifds.byte_order, self.count, &mut result)
-                        .map_err(if_eof!("when reading TIFF IFD entry values"))
-                    {
-                        Ok(_) => Some(Ok(result)),
-                        Err(e) => Some(Err(e))
-                    }
+                    Some(T::read_many_from_with_buffer(&mut *self.ifds.source.borrow_mut(),
+                                                       self.ifds.byte_order, self.count, target,
+                                                       &mut *self.ifds.buffer.borrow_mut())
+                        .map_err(if_eof!("when reading TIFF IFD entry values")))
                 }
 
             } else {
@@ -413,6 +452,19 @@ pub trait EntryTypeRepr {
     /// may be modified even if this method returns an error.
     fn read_many_from<R: Read>(source: &mut R, byte_order: ByteOrder, n: u32, target: &mut Vec<Self::Repr>) -> byteorder::Result<()>;
 
+    /// Attempts to read a number of the represented values from the given stream with the given
+    /// byte order, using the provided vector as an intermediate buffer.
+    ///
+    /// This is the same as `read_many_from()`, except that the buffer holding the raw data
+    /// read from the stream may be reused between calls. The data is read in blocks of up
+    /// to several kilobytes rather than value by value.
+    fn read_many_from_with_buffer<R: Read>(source: &mut R, byte_order: ByteOrder, n: u32,
+                                           target: &mut Vec<Self::Repr>, buffer: &mut Vec<u8>) -> byteorder::Result<()>
+        where Self: Sized
+    {
+        entry_types::read_many::<Self, R>(source, byte_order, n, target, buffer)
+    }
+
     /// Reads the `n`th represented value inside `source`.
     ///
     /// If the value can be read successfully (`n` < `count`, the represented type is smaller
@@ -422,14 +474,13 @@ pub trait EntryTypeRepr {
 
 /// Contains representation types for all of defined TIFF entry types.
 pub mod entry_types {
-    use std::io::Read;
-    use std::str;
+    use std::io::{Read, ErrorKind};
+    use std::cmp;
 
     use byteorder;
-    use arrayvec::ArrayVec;
 
     use super::{EntryType, EntryTypeRepr};
-    use utils::{ByteOrder, ByteOrderReadExt};
+    use utils::{self, ByteOrder, ByteOrderReadExt};
 
     macro_rules! gen_entry_types {
         (
@@ -456,18 +507,7 @@ pub mod entry_types {
 
                     fn read_many_from<R: Read>(source: &mut R, byte_order: ByteOrder,
                                                n: u32, target: &mut Vec<Self::Repr>) -> byteorder::Result<()> {
-                        // This logic is necessary to handle variable-size items (Ascii strings)
-                        // We read item by item, increasing the read bytes counter until we read
-                        // all expected items (whose size can be calculated)
-                        let item_size = EntryType::$tpe.size().expect("reading unknown data type");
-                        let max_bytes = n * item_size as u32;
-                        let mut bytes_read = 0;
-                        while bytes_read < max_bytes {
-                            let (c, v) = try!(Self::read_from(source, byte_order));
-                            bytes_read += c;
-                            target.push(v);
-                        }
-                        Ok(())
+                        read_many::<Self, R>(source, byte_order, n, target, &mut Vec::new())
                     }
 
                     fn read_from_u32($u32_source: [u8; 4], $u32_byte_order: ByteOrder, $n: usize, $count: usize) -> Option<$repr> {
@@ -478,6 +518,71 @@ pub mod entry_types {
         }
     }
 
+    /// The number of values for which space is reserved before reading them.
+    ///
+    /// Counts come from the input, so larger arrays grow as they are read.
+    const MAX_RESERVED_VALUES: u32 = 4096;
+
+    /// The maximum number of bytes read from the stream at once.
+    const READ_BLOCK_SIZE: u64 = 8192;
+
+    /// Reads `n` values of the given type, which occupy `n * item_size` bytes, in blocks.
+    pub(super) fn read_many<T: EntryTypeRepr, R: Read>(source: &mut R, byte_order: ByteOrder, n: u32,
+                                                       target: &mut Vec<T::Repr>, buffer: &mut Vec<u8>)
+        -> byteorder::Result<()>
+    {
+        let item_size = T::entry_type().size().expect("reading unknown data type");
+        let total = utils::saturating_size(n as u64, item_size as u64);
+        target.reserve(cmp::min(n, MAX_RESERVED_VALUES) as usize);
+        buffer.clear();
+
+        // Ascii strings have variable size, so the values are parsed from the buffer until
+        // an incomplete one is found, and the rest of the buffer is kept for the next block
+        let (mut filled, mut parsed) = (0, 0);
+        while parsed < total {
+            if filled < total {
+                let start = buffer.len();
+                let block = cmp::min(total - filled, READ_BLOCK_SIZE);
+                buffer.resize(start + block as usize, 0);
+                try!(source.read_exact(&mut buffer[start..]).map_err(|e| match e.kind() {
+                    ErrorKind::UnexpectedEof => byteorder::Error::UnexpectedEOF,
+                    _ => byteorder::Error::Io(e)
+                }));
+                filled += block;
+            }
+
+            let consumed = {
+                let mut data = &buffer[..];
+                while parsed < total {
+                    let mut value_data = data;
+                    match T::read_from(&mut value_data, byte_order) {
+                        Ok((c, v)) => {
+                            parsed += c as u64;
+                            target.push(v);
+                            data = value_data;
+                        }
+                        Err(byteorder::Error::UnexpectedEOF) => break,
+                        Err(e) => return Err(e)
+                    }
+                }
+                buffer.len() - data.len()
+            };
+            buffer.drain(..consumed);
+
+            if filled == total && parsed < total {
+                // the last string is not terminated within the entry data; it continues
+                // in the stream, like it did when values were read one by one
+                let mut rest = (&buffer[..]).chain(&mut *source);
+                while parsed < total {
+                    let (c, v) = try!(T::read_from(&mut rest, byte_order));
+                    parsed += c as u64;
+                    target.push(v);
+                }
+            }
+        }
+        Ok(())
+    }
+
     gen_entry_types! {
         Byte, u8,
             |source, _| byteorder::ReadBytesExt::read_u8(source).map(|v| (1, v)),
@@ -514,10 +619,11 @@ pub mod entry_types {
                         i += 1;
                     }
                 }
-                let mut substrings = ArrayVec::<[_; 4]>::new();
+                let mut substrings = Vec::with_capacity(4);
                 find_substrings(&bs[..count as usize], &mut substrings);
+                // bytes are mapped to chars the same way as in values stored at an offset
                 substrings.get(n as usize)
-                    .map(|&(s, e)| unsafe { str::from_utf8_unchecked(&bs[s..e]).to_owned() })
+                    .map(|&(s, e)| bs[s..e].iter().map(|&b| b as char).collect())
             };
         Short, u16,
             |source, byte_order| source.read_u16(byte_order).map(|v| (2, v)),
@@ -613,25 +719,25 @@ impl<T: EntryTypeRepr> EmbeddedValues<T> {
 pub struct ReferencedValues<'a, T: EntryTypeRepr, R: Read + Seek + 'a> {
     ifds: &'a LazyIfds<R>,
     count: u32,
-    bytes_read: u32,
-    next_offset: u32,
+    bytes_read: u64,
+    next_offset: u64,
     _entry_type_repr: PhantomData<T>,
 }
 
 impl<'a, T: EntryTypeRepr, R: Read + Seek + 'a> ReferencedValues<'a, T, R> {
     fn read_value(&mut self) -> Result<Option<T::Repr>> {
-        if self.bytes_read >= self.count * T::entry_type().size().unwrap() as u32 {
+        if self.bytes_read >= utils::saturating_size(self.count as u64, T::entry_type().size().unwrap() as u64) {
             return Ok(None);
         }
 
-        try!(self.ifds.source.borrow_mut().seek(SeekFrom::Start(self.next_offset as u64)));
+        try!(self.ifds.source.borrow_mut().seek(SeekFrom::Start(self.next_offset)));
 
         let (bytes_read, value) = try_if_eof!(
             T::read_from(&mut *self.ifds.source.borrow_mut(), self.ifds.byte_order),
             "when reading TIFF entry value"
         );
-        self.next_offset += bytes_read;
-        self.bytes_read += bytes_read;
+        self.next_offset = try!(utils::checked_add(self.next_offset, bytes_read as u64));
+        self.bytes_read += bytes_read as u64;
 
         Ok(Some(value))
     }
@@ -643,6 +749,8 @@ mod tests {
 
     use byteorder::{self, ByteOrder, BigEndian, LittleEndian};
 
+    use types::Error;
+
     use super::{TiffReader, EntryType, entry_types};
 
     trait Writable {
@@ -732,6 +840,23 @@ mod tests {
         assert!(ifds_iter.next().is_none());
     }
 
+    #[test]
+    fn test_from_reader() {
+        let data = build! { LittleEndian,
+            b"II", 42u16, 0u32
+        };
+
+        match TiffReader::from_reader(&data[..], 7) {
+            Err(Error::LimitExceeded(_)) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("TIFF document loaded")
+        }
+
+        let reader = TiffReader::from_reader(&data[..], 8).unwrap();
+        let ifds = reader.ifds().unwrap();
+        assert!((&ifds).into_iter().next().is_none());
+    }
+
     #[test]
     fn test_one_ifd() {
         let data = build! { BigEndian,
@@ -995,4 +1120,88 @@ mod tests {
     // reading IFD entries
     //   all types
     //   all embeddable types
+
+    #[test]
+    fn test_large_entries() {
+        let mut data = build! { LittleEndian,
+            b"II", 42u16, 8u32,
+
+            2u16,
+
+            // Short x5000 @38, 10000 bytes
+            1u16, 3u16, 5000u32, 38u32,
+
+            // Ascii, 9005 bytes @10038; the last string is not terminated within the entry
+            2u16, 2u16, 9005u32, 10038u32,
+
+            0u32
+        };
+        for i in 0..5000u16 {
+            i.write_to::<_, LittleEndian>(&mut data);
+        }
+        data.extend(vec![b'a'; 9000]);
+        data.extend_from_slice(b"\0tailend\0");
+
+        let reader = TiffReader::new(Cursor::new(data));
+        let ifds = reader.ifds().unwrap();
+        let ifd = (&ifds).into_iter().next().unwrap().unwrap();
+        let entries: Vec<_> = ifd.map(|e| e.unwrap()).collect();
+
+        let mut shorts = vec![1, 2, 3];
+        entries[0].all_values_into::<entry_types::Short>(&mut shorts).unwrap().unwrap();
+        assert_eq!(shorts, (0..5000).collect::<Vec<_>>());
+
+        let strings = entries[1].all_values::<entry_types::Ascii>().unwrap().unwrap();
+        assert_eq!(strings, vec!["a".repeat(9000), "tailend".to_owned()]);
+
+        // the buffer is reused, and the result is the same
+        entries[0].all_values_into::<entry_types::Short>(&mut shorts).unwrap().unwrap();
+        assert_eq!(shorts.len(), 5000);
+        assert!(entries[1].all_values_into::<entry_types::Short>(&mut shorts).is_none());
+    }
+
+    #[test]
+    fn test_truncated_ifd() {
+        let data = build! { BigEndian,
+            b"MM", 42u16, 8u32,
+
+            // two entries are declared, but only one is present
+            2u16,
+            4u16, 1u16, 4u32, b"abcd"
+        };
+
+        let reader = TiffReader::new(Cursor::new(data));
+        let ifds = reader.ifds().unwrap();
+        match (&ifds).into_iter().next() {
+            Some(Err(Error::UnexpectedEndOfFile(_))) => {}
+            Some(Err(e)) => panic!("unexpected error: {}", e),
+            Some(Ok(_)) => panic!("truncated IFD loaded"),
+            None => panic!("no IFDs")
+        }
+    }
+
+    #[test]
+    fn test_huge_entry_count() {
+        let data = build! { LittleEndian,
+            b"II", 42u16, 8u32,
+
+            1u16,
+
+            // Long entry whose total size overflows 32 bits, with data at the end of the file
+            16u16, 4u16, 0xffff_ffffu32, 0xffff_fffcu32,
+
+            0u32
+        };
+
+        let reader = TiffReader::new(Cursor::new(data));
+        for ifd in &reader.ifds().unwrap() {
+            for e in ifd.unwrap() {
+                let e = e.unwrap();
+                assert_eq!(e.count(), 0xffff_ffff);
+                assert!(e.all_values::<entry_types::Long>().unwrap().is_err());
+                let mut values = e.values::<entry_types::Long>().unwrap();
+                assert!(values.next().unwrap().is_err());
+            }
+        }
+    }
 }
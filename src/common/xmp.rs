@@ -0,0 +1,135 @@
+//! XMP packets.
+//!
+//! XMP metadata is stored as an XML document embedded into various image containers. This
+//! module does not implement a full XML parser; instead, it provides access to the raw packet
+//! and simple lookups of namespaces and properties which are sufficient for most of the
+//! well-known XMP properties.
+
+use std::str;
+
+use types::Result;
+
+/// Namespace URI of the Adobe/Google HDR gain map XMP schema (`hdrgm` prefix).
+pub const NS_HDR_GAIN_MAP: &'static str = "http://ns.adobe.com/hdr-gain-map/1.0/";
+
+/// An XMP packet.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Xmp {
+    packet: String,
+}
+
+impl Xmp {
+    /// Creates an XMP packet from a serialized XML document, e.g. for writing it into an image.
+    ///
+    /// The document is not validated.
+    #[inline]
+    pub fn new<S: Into<String>>(packet: S) -> Xmp {
+        Xmp { packet: packet.into() }
+    }
+
+    /// Creates an XMP packet from its serialized form.
+    ///
+    /// Fails if the data is not valid UTF-8. Trailing NUL bytes, which some writers add for
+    /// padding, are removed.
+    pub fn from_bytes(data: &[u8]) -> Result<Xmp> {
+        let end = data.iter().rposition(|&b| b != 0).map(|i| i + 1).unwrap_or(0);
+        match str::from_utf8(&data[..end]) {
+            Ok(s) => Ok(Xmp { packet: s.to_owned() }),
+            Err(e) => Err(invalid_format!("XMP packet is not valid UTF-8: {}", e))
+        }
+    }
+
+    /// Returns the serialized XMP packet.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.packet
+    }
+
+    /// Returns `true` if the packet declares the namespace with the given URI.
+    pub fn has_namespace(&self, uri: &str) -> bool {
+        self.packet.match_indices(uri).any(|(i, _)| {
+            let before = self.packet[..i].chars().next_back();
+            let after = self.packet[i + uri.len()..].chars().next();
+            (before == Some('"') && after == Some('"')) || (before == Some('\'') && after == Some('\''))
+        })
+    }
+
+    /// Returns the value of a simple property with the given qualified name, e.g.
+    /// `xmp:Rating`.
+    ///
+    /// Both attribute (`xmp:Rating="5"`) and element (`<xmp:Rating>5</xmp:Rating>`) forms are
+    /// supported. XML entities in the value are not decoded. Returns `None` if the property
+    /// is not found.
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.attribute_property(name).or_else(|| self.element_property(name))
+    }
+
+    fn attribute_property(&self, name: &str) -> Option<&str> {
+        for (i, _) in self.packet.match_indices(name) {
+            // the name must not be a suffix of another name
+            match self.packet[..i].chars().next_back() {
+                Some(c) if c.is_whitespace() => {}
+                _ => continue
+            }
+            let rest = self.packet[i + name.len()..].trim_start();
+            if !rest.starts_with('=') {
+                continue;
+            }
+            let rest = rest[1..].trim_start();
+            let quote = match rest.chars().next() {
+                Some(q) if q == '"' || q == '\'' => q,
+                _ => continue
+            };
+            if let Some(end) = rest[1..].find(quote) {
+                return Some(&rest[1..end + 1]);
+            }
+        }
+        None
+    }
+
+    fn element_property(&self, name: &str) -> Option<&str> {
+        let open = format!("<{}>", name);
+        let close = format!("</{}>", name);
+        self.packet.find(&open).and_then(|start| {
+            let start = start + open.len();
+            self.packet[start..].find(&close).map(|end| &self.packet[start..start + end])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Xmp;
+
+    const PACKET: &'static str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+        xmlns:hdrgm='http://ns.adobe.com/hdr-gain-map/1.0/'
+        hdrgm:Version="1.0" xmp:Rating = '4'>
+      <xmp:CreatorTool>immeta</xmp:CreatorTool>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn test_namespaces() {
+        let xmp = Xmp::from_bytes(PACKET.as_bytes()).unwrap();
+        assert!(xmp.has_namespace("http://ns.adobe.com/hdr-gain-map/1.0/"));
+        assert!(xmp.has_namespace("http://ns.adobe.com/xap/1.0/"));
+        assert!(!xmp.has_namespace("http://ns.adobe.com/xap/1.0"));
+    }
+
+    #[test]
+    fn test_properties() {
+        let mut data = PACKET.as_bytes().to_vec();
+        data.extend_from_slice(&[0, 0]);
+        let xmp = Xmp::from_bytes(&data).unwrap();
+        assert_eq!(xmp.as_str(), PACKET);
+        assert_eq!(xmp.property("hdrgm:Version"), Some("1.0"));
+        assert_eq!(xmp.property("xmp:Rating"), Some("4"));
+        assert_eq!(xmp.property("xmp:CreatorTool"), Some("immeta"));
+        assert_eq!(xmp.property("Version"), None);
+        assert_eq!(xmp.property("xmp:Label"), None);
+    }
+}
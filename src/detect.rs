@@ -0,0 +1,96 @@
+//! Format detection by signature.
+//!
+//! The functions in this module check whether a byte slice starts with the signature of
+//! a particular image format. They do not perform any I/O or allocations, and their signature
+//! is `fn(&[u8]) -> bool`, so they can be registered as custom matchers in file type sniffers
+//! like the [infer](https://crates.io/crates/infer) crate:
+//!
+//! ```ignore
+//! let mut info = infer::Infer::new();
+//! info.add("image/webp", "webp", immeta::detect::matches_webp);
+//! ```
+//!
+//! Only the leading magic bytes are checked, so a matching slice is not necessarily a valid
+//! image, and `immeta::load()` may still fail on it. Conversely, `immeta::load()` may accept
+//! some inputs which are not matched here, e.g. JPEG streams with garbage preceding the SOI
+//! marker when `LoadOptions::soi_search_limit` is set.
+
+use types::ImageFormat;
+
+/// Returns `true` if the data starts with the JPEG SOI marker followed by another marker.
+#[inline]
+pub fn matches_jpeg(data: &[u8]) -> bool {
+    data.starts_with(b"\xff\xd8\xff")
+}
+
+/// Returns `true` if the data starts with the PNG signature.
+#[inline]
+pub fn matches_png(data: &[u8]) -> bool {
+    data.starts_with(b"\x89PNG\r\n\x1a\n")
+}
+
+/// Returns `true` if the data starts with the GIF87a or GIF89a signature.
+#[inline]
+pub fn matches_gif(data: &[u8]) -> bool {
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+/// Returns `true` if the data starts with a RIFF header of a WebP file.
+#[inline]
+pub fn matches_webp(data: &[u8]) -> bool {
+    data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP"
+}
+
+/// Returns `true` if the data starts with the signature of the given image format.
+pub fn matches(format: ImageFormat, data: &[u8]) -> bool {
+    match format {
+        ImageFormat::Jpeg => matches_jpeg(data),
+        ImageFormat::Png => matches_png(data),
+        ImageFormat::Gif => matches_gif(data),
+        ImageFormat::Webp => matches_webp(data),
+    }
+}
+
+/// Returns the image format whose signature the data starts with, if any.
+///
+/// At most 12 bytes of the data are inspected.
+pub fn detect(data: &[u8]) -> Option<ImageFormat> {
+    ImageFormat::ALL.iter().cloned().find(|&format| matches(format, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use types::ImageFormat;
+
+    use super::{detect, matches, matches_jpeg, matches_png, matches_gif, matches_webp};
+
+    #[test]
+    fn test_sample_images() {
+        for &(path, format) in &[("tests/images/owlet.jpg", ImageFormat::Jpeg),
+                                 ("tests/images/owlet.png", ImageFormat::Png),
+                                 ("tests/images/drop.gif", ImageFormat::Gif),
+                                 ("tests/images/cherry.webp", ImageFormat::Webp)] {
+            let mut data = Vec::new();
+            File::open(path).unwrap().read_to_end(&mut data).unwrap();
+            assert_eq!(detect(&data), Some(format));
+            assert_eq!(detect(&data[..12]), Some(format));
+            for &other in &ImageFormat::ALL {
+                assert_eq!(matches(other, &data), other == format);
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_data() {
+        assert!(!matches_jpeg(b"\xff\xd8"));
+        assert!(!matches_png(b"\x89PNG\r\n\x1a"));
+        assert!(!matches_gif(b"GIF89"));
+        assert!(!matches_gif(b"GIF90a"));
+        assert!(!matches_webp(b"RIFF\0\0\0\0WEB"));
+        assert!(!matches_webp(b"RIFF\0\0\0\0WAVEfmt "));
+        assert_eq!(detect(b""), None);
+    }
+}
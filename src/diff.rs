@@ -0,0 +1,402 @@
+//! Comparison of image metadata.
+//!
+//! `diff()` function compares two metadata values field by field and returns a list of
+//! changes. This is useful, for example, to verify that an image transformation
+//! preserves all metadata which is expected to be preserved.
+//!
+//! Fields are identified by dotted paths like `dimensions.width` or `gif.blocks.3`, and
+//! their values are represented as strings. EXIF data is compared tag by tag, with fields
+//! named after the IFD and the tag number, e.g. `exif.ifd0.0x010F` for the camera make;
+//! the EXIF orientation is also reported as a separate `orientation` field. PNG text chunks
+//! are identified by their keywords, e.g. `png.text.Title`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Seek};
+
+use types::Result;
+use generic::GenericMetadata;
+use formats::{jpeg, png, gif, webp};
+use common::exif::ExifData;
+use common::tiff::{Entry, EntryType, entry_types};
+
+const TAG_JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const TAG_EXIF_IFD_POINTER: u16 = 0x8769;
+const TAG_GPS_IFD_POINTER: u16 = 0x8825;
+const TAG_INTEROPERABILITY_IFD_POINTER: u16 = 0xa005;
+
+/// Describes how a field has changed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ChangeKind {
+    /// The field is present only in the second metadata value.
+    Added(String),
+    /// The field is present only in the first metadata value.
+    Removed(String),
+    /// The field is present in both metadata values, but its values differ.
+    Modified {
+        /// The value in the first metadata value.
+        old: String,
+        /// The value in the second metadata value.
+        new: String
+    }
+}
+
+/// A single changed field.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Change {
+    /// Dotted path of the field, e.g. `dimensions.width`.
+    pub field: String,
+    /// What has happened to the field.
+    pub kind: ChangeKind
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ChangeKind::Added(ref v) => write!(f, "+ {}: {}", self.field, v),
+            ChangeKind::Removed(ref v) => write!(f, "- {}: {}", self.field, v),
+            ChangeKind::Modified { ref old, ref new } =>
+                write!(f, "~ {}: {} -> {}", self.field, old, new),
+        }
+    }
+}
+
+/// Compares two metadata values and returns the list of changed fields.
+///
+/// Changes are returned in the order of fields in the first value, followed by the fields
+/// which are only present in the second value. If the values have different image formats,
+/// the fields specific to each format are reported as removed and added, respectively.
+///
+/// # Examples
+///
+/// ```no_run
+/// let original = immeta::load_from_file("original.png").unwrap();
+/// let processed = immeta::load_from_file("processed.png").unwrap();
+/// for change in immeta::diff(&original, &processed) {
+///     println!("{}", change);
+/// }
+/// ```
+pub fn diff(a: &GenericMetadata, b: &GenericMetadata) -> Vec<Change> {
+    let (mut fa, mut fb) = (Fields::new(), Fields::new());
+    a.collect_fields(&mut fa);
+    b.collect_fields(&mut fb);
+
+    let (ia, ib) = (fa.index(), fb.index());
+    let mut changes = Vec::new();
+    for &(ref name, ref old) in &fa.0 {
+        match ib.get(&**name) {
+            Some(&new) if new == old => {}
+            Some(&new) => changes.push(Change {
+                field: name.clone(),
+                kind: ChangeKind::Modified { old: old.clone(), new: new.to_owned() }
+            }),
+            None => changes.push(Change {
+                field: name.clone(),
+                kind: ChangeKind::Removed(old.clone())
+            })
+        }
+    }
+    for &(ref name, ref new) in &fb.0 {
+        if !ia.contains_key(&**name) {
+            changes.push(Change {
+                field: name.clone(),
+                kind: ChangeKind::Added(new.clone())
+            });
+        }
+    }
+    changes
+}
+
+/// An ordered list of named field values.
+struct Fields(Vec<(String, String)>);
+
+impl Fields {
+    fn new() -> Fields {
+        Fields(Vec::new())
+    }
+
+    /// Returns a map from field names to values, keeping the first value of each name.
+    fn index(&self) -> HashMap<&str, &str> {
+        let mut index = HashMap::with_capacity(self.0.len());
+        for &(ref name, ref value) in &self.0 {
+            index.entry(&**name).or_insert(&**value);
+        }
+        index
+    }
+
+    fn add_str(&mut self, name: &str, value: &str) {
+        self.0.push((name.to_owned(), value.to_owned()));
+    }
+
+    fn add<T: fmt::Debug>(&mut self, name: &str, value: T) {
+        self.0.push((name.to_owned(), format!("{:?}", value)));
+    }
+
+    fn add_opt<T: fmt::Debug>(&mut self, name: &str, value: Option<T>) {
+        if let Some(value) = value {
+            self.add(name, value);
+        }
+    }
+}
+
+/// Adds a field for each tag in IFD0, IFD1 and the EXIF, GPS and interoperability IFDs.
+///
+/// If the data is malformed, the tags read so far are followed by an `exif.error` field.
+fn collect_exif_fields(exif: &ExifData, out: &mut Fields) {
+    if let Err(e) = try_collect_exif_fields(exif, out) {
+        out.add_str("exif.error", &e.to_string());
+    }
+}
+
+fn try_collect_exif_fields(exif: &ExifData, out: &mut Fields) -> Result<()> {
+    let ifds = try!(exif.tiff_reader().ifds());
+    let mut sub_ifds = Vec::new();
+    // only IFD0 and the thumbnail IFD1 are defined by EXIF
+    for (i, ifd) in (&ifds).into_iter().take(2).enumerate() {
+        let name = if i == 0 { "ifd0" } else { "ifd1" };
+        for entry in try!(ifd) {
+            let entry = try!(entry);
+            try!(collect_exif_entry(name, &entry, &mut sub_ifds, out));
+        }
+    }
+
+    let mut visited = Vec::new();
+    while let Some((name, offset)) = sub_ifds.pop() {
+        if visited.contains(&offset) {
+            continue;
+        }
+        visited.push(offset);
+        for entry in try!(ifds.ifd_at(offset as u64)) {
+            let entry = try!(entry);
+            try!(collect_exif_entry(name, &entry, &mut sub_ifds, out));
+        }
+    }
+    Ok(())
+}
+
+fn collect_exif_entry<R: Read + Seek>(ifd: &str, entry: &Entry<R>, sub_ifds: &mut Vec<(&'static str, u32)>,
+                                      out: &mut Fields) -> Result<()> {
+    let sub_ifd = match entry.tag() {
+        TAG_EXIF_IFD_POINTER => Some("exif"),
+        TAG_GPS_IFD_POINTER => Some("gps"),
+        TAG_INTEROPERABILITY_IFD_POINTER => Some("interop"),
+        _ => None
+    };
+    if let Some(name) = sub_ifd {
+        if let Some(offset) = entry.all_values::<entry_types::Long>() {
+            sub_ifds.extend(try!(offset).first().map(|&offset| (name, offset)));
+        }
+        // the offsets depend on the layout of the data, only the values in the IFDs matter
+        return Ok(());
+    }
+    if entry.tag() == TAG_JPEG_INTERCHANGE_FORMAT {
+        return Ok(());
+    }
+
+    let name = format!("exif.{}.0x{:04X}", ifd, entry.tag());
+    macro_rules! values {
+        ($t:ident) => { try!(entry.all_values::<entry_types::$t>().unwrap()) }
+    }
+    let value = match entry.entry_type() {
+        EntryType::Ascii => values!(Ascii).join(", "),
+        EntryType::Byte | EntryType::Undefined => {
+            let bytes = if entry.entry_type() == EntryType::Byte { values!(Byte) } else { values!(Undefined) };
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+        EntryType::Short => format_values(&values!(Short)),
+        EntryType::Long => format_values(&values!(Long)),
+        EntryType::Rational => format_values(&values!(Rational).iter()
+            .map(|&(n, d)| format!("{}/{}", n, d)).collect::<Vec<_>>()),
+        EntryType::SignedByte => format_values(&values!(SignedByte)),
+        EntryType::SignedShort => format_values(&values!(SignedShort)),
+        EntryType::SignedLong => format_values(&values!(SignedLong)),
+        EntryType::SignedRational => format_values(&values!(SignedRational).iter()
+            .map(|&(n, d)| format!("{}/{}", n, d)).collect::<Vec<_>>()),
+        EntryType::Float => format_values(&values!(Float)),
+        EntryType::Double => format_values(&values!(Double)),
+        EntryType::Unknown(t) => format!("<{} values of unknown type {}>", entry.count(), t),
+    };
+    out.add_str(&name, &value);
+    Ok(())
+}
+
+/// Formats TIFF entry values as a single value or a space-separated list.
+fn format_values<T: fmt::Display>(values: &[T]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+trait CollectFields {
+    fn collect_fields(&self, out: &mut Fields);
+}
+
+impl CollectFields for GenericMetadata {
+    fn collect_fields(&self, out: &mut Fields) {
+        out.add_str("mime_type", self.mime_type());
+        let dimensions = self.dimensions();
+        out.add("dimensions.width", dimensions.width);
+        out.add("dimensions.height", dimensions.height);
+
+        let exif = match *self {
+            GenericMetadata::Jpeg(ref md) => md.exif.as_ref(),
+            GenericMetadata::Png(ref md) => md.exif.as_ref(),
+            _ => None
+        };
+        if let Some(exif) = exif {
+            match exif.orientation() {
+                Ok(orientation) => out.add_opt("orientation", orientation),
+                Err(e) => out.add_str("orientation", &format!("invalid: {}", e)),
+            }
+            collect_exif_fields(exif, out);
+        }
+
+        match *self {
+            GenericMetadata::Jpeg(ref md) => md.collect_fields(out),
+            GenericMetadata::Png(ref md) => md.collect_fields(out),
+            GenericMetadata::Gif(ref md) => md.collect_fields(out),
+            GenericMetadata::Webp(ref md) => md.collect_fields(out),
+        }
+    }
+}
+
+impl CollectFields for jpeg::Metadata {
+    fn collect_fields(&self, out: &mut Fields) {
+        out.add("jpeg.coding_process", self.coding_process);
+        out.add("jpeg.entropy_coding", self.entropy_coding);
+        out.add("jpeg.arithmetic_conditioning", self.arithmetic_conditioning);
+        out.add("jpeg.differential", self.differential);
+        out.add("jpeg.precision", self.precision);
+        out.add("jpeg.components", self.components);
+        out.add("jpeg.color_space", self.color_space);
+        out.add_opt("jpeg.density", self.density);
+        for table in &self.quantization_tables {
+            out.add(&format!("jpeg.quantization_tables.{}", table.id), &table.values);
+        }
+        for (i, image) in self.mpf_images.iter().enumerate() {
+            out.add(&format!("jpeg.mpf_images.{}", i), image);
+        }
+        out.add_opt("jpeg.restart_interval", self.restart_interval);
+        out.add("jpeg.scan_count", self.scan_count);
+        for (i, preview) in self.previews.iter().enumerate() {
+            out.add(&format!("jpeg.previews.{}", i), preview);
+        }
+        out.add_opt("jpeg.flir", self.flir.as_ref());
+        out.add_opt("jpeg.xmp", self.xmp.as_ref().map(|xmp| xmp.as_str()));
+        out.add_opt("jpeg.gain_map", self.gain_map.as_ref());
+        for (i, segment) in self.application_segments.iter().enumerate() {
+            out.add(&format!("jpeg.application_segments.{}", i), segment);
+        }
+        out.add_opt("jpeg.pending_xmp", self.pending_xmp);
+        out.add_opt("jpeg.pending_exif", self.pending_exif);
+    }
+}
+
+impl CollectFields for png::Metadata {
+    fn collect_fields(&self, out: &mut Fields) {
+        out.add("png.color_type", self.color_type);
+        out.add("png.color_depth", self.color_depth);
+        out.add("png.compression_method", self.compression_method);
+        out.add("png.filter_method", self.filter_method);
+        out.add("png.interlace_method", self.interlace_method);
+        out.add_opt("png.gamma", self.gamma);
+        out.add_opt("png.chromaticities", self.chromaticities);
+        out.add_opt("png.srgb_intent", self.srgb_intent);
+        out.add_opt("png.cicp", self.cicp);
+        out.add_opt("png.mastering_display", self.mastering_display);
+        out.add_opt("png.content_light_level", self.content_light_level);
+        out.add_opt("png.background_color", self.background_color);
+        out.add_opt("png.significant_bits", self.significant_bits);
+        out.add("png.truncated", self.truncated);
+        out.add("png.image_data_size", self.image_data_size);
+        out.add_opt("png.zlib_header", self.zlib_header);
+        out.add_opt("png.offset", self.offset);
+        out.add_opt("png.physical_scale", self.physical_scale.as_ref());
+        out.add_opt("png.animation_control", self.animation_control);
+        for (i, fc) in self.frame_controls.iter().enumerate() {
+            out.add(&format!("png.frame_controls.{}", i), fc);
+        }
+        let mut keywords: HashMap<&str, usize> = HashMap::new();
+        for text in &self.text_chunks {
+            // repeated keywords are numbered in the order of appearance
+            let n = keywords.entry(&text.keyword).or_insert(0);
+            let name = match *n {
+                0 => format!("png.text.{}", text.keyword),
+                n => format!("png.text.{}.{}", text.keyword, n),
+            };
+            *n += 1;
+            match text.text {
+                Some(ref value) => out.add_str(&name, value),
+                None => out.add_str(&name, "<compressed>"),
+            }
+            out.add_opt(&format!("{}.language_tag", name), text.language_tag.as_ref());
+            out.add_opt(&format!("{}.translated_keyword", name), text.translated_keyword.as_ref());
+        }
+        for (i, location) in self.pending_text_chunks.iter().enumerate() {
+            out.add(&format!("png.pending_text_chunks.{}", i), location);
+        }
+        out.add_opt("png.modification_time", self.modification_time);
+        out.add_opt("png.pending_exif", self.pending_exif);
+    }
+}
+
+impl CollectFields for gif::Metadata {
+    fn collect_fields(&self, out: &mut Fields) {
+        out.add("gif.version", self.version);
+        out.add_opt("gif.global_color_table", self.global_color_table.as_ref());
+        out.add("gif.color_resolution", self.color_resolution);
+        out.add("gif.background_color_index", self.background_color_index);
+        out.add("gif.pixel_aspect_ratio", self.pixel_aspect_ratio);
+        out.add("gif.frames_number", self.frames_number());
+        out.add_opt("gif.loop_count", self.loop_count);
+        out.add_opt("gif.xmp", self.xmp.as_ref().map(|xmp| xmp.as_str()));
+        out.add_opt("gif.pending_xmp", self.pending_xmp);
+        for (i, block) in self.blocks.iter().enumerate() {
+            out.add(&format!("gif.blocks.{}", i), block);
+        }
+    }
+}
+
+impl CollectFields for webp::Metadata {
+    fn collect_fields(&self, out: &mut Fields) {
+        match *self {
+            webp::Metadata::VP8(ref md) => {
+                out.add("webp.vp8.version_number", md.version_number);
+                out.add("webp.vp8.show_frame", md.show_frame);
+                out.add("webp.vp8.first_partition_len", md.first_partition_len);
+                out.add("webp.vp8.frame", &md.frame);
+            }
+            webp::Metadata::VP8L(ref md) => out.add("webp.vp8l", md),
+            webp::Metadata::VP8X(ref md) => out.add("webp.vp8x", md),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use generic;
+
+    use super::{diff, Change, ChangeKind};
+
+    #[test]
+    fn test_same_metadata() {
+        let md = generic::load_from_file("tests/images/drop.gif").unwrap();
+        assert_eq!(diff(&md, &md), vec![]);
+    }
+
+    #[test]
+    fn test_different_formats() {
+        let png = generic::load_from_file("tests/images/owlet.png").unwrap();
+        let gif = generic::load_from_file("tests/images/owlet.gif").unwrap();
+        let changes = diff(&png, &gif);
+
+        assert_eq!(changes[0], Change {
+            field: "mime_type".into(),
+            kind: ChangeKind::Modified { old: "image/png".into(), new: "image/gif".into() }
+        });
+        // dimensions are the same
+        assert!(!changes.iter().any(|c| c.field.starts_with("dimensions")));
+        assert!(changes.iter().any(|c| c.field == "png.color_type" &&
+                                       c.kind == ChangeKind::Removed("Rgb".into())));
+        assert!(changes.iter().any(|c| c.field == "gif.version" &&
+                                       c.kind == ChangeKind::Added("V89a".into())));
+    }
+}
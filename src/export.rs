@@ -0,0 +1,343 @@
+//! Stable serializable representation of image metadata.
+//!
+//! This module is only available when `serde` feature is enabled.
+//!
+//! Metadata types in `immeta::formats` closely follow the structure of the respective
+//! image formats and may change between versions of this library. Values of these types
+//! can be converted to a `Document`, whose serialized form is a documented contract:
+//!
+//! * the document always contains a `schema_version` field, currently equal to
+//!   `SCHEMA_VERSION`;
+//! * within the same schema version, fields are never removed, renamed or changed in meaning,
+//!   but new optional fields may be added;
+//! * enumeration values are represented as lowercase `snake_case` strings.
+//!
+//! When serialized to JSON, a document looks like this:
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "format": "png",
+//!   "mime_type": "image/png",
+//!   "width": 1280,
+//!   "height": 857,
+//!   "png": {
+//!     "color_type": "rgb",
+//!     "color_depth": 24,
+//!     "interlaced": false
+//!   }
+//! }
+//! ```
+//!
+//! Exactly one of `jpeg`, `png`, `gif` and `webp` fields is present, depending on `format`.
+
+use generic::GenericMetadata;
+use formats::{jpeg, png, gif, webp};
+
+/// Current version of the export schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Top-level exported metadata document.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Document {
+    /// Version of the schema, see `SCHEMA_VERSION`.
+    pub schema_version: u32,
+    /// Image format name: `jpeg`, `png`, `gif` or `webp`.
+    pub format: String,
+    /// MIME type of the image.
+    pub mime_type: String,
+    /// Image width in pixels.
+    pub width: u32,
+    /// Image height in pixels.
+    pub height: u32,
+    /// JPEG-specific metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jpeg: Option<JpegDocument>,
+    /// PNG-specific metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub png: Option<PngDocument>,
+    /// GIF-specific metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gif: Option<GifDocument>,
+    /// WebP-specific metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webp: Option<WebpDocument>,
+}
+
+/// Exported JPEG metadata.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct JpegDocument {
+    /// One of `baseline`, `extended_sequential`, `progressive`, `lossless`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coding_process: Option<String>,
+    /// Whether arithmetic entropy coding is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arithmetic_coding: Option<bool>,
+    /// Sample precision in bits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<u8>,
+    /// Number of color components.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub components: Option<u8>,
+    /// One of `grayscale`, `ycbcr`, `rgb`, `cmyk`, `ycck`, `unknown`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_space: Option<String>,
+    /// Horizontal and vertical density in dots per inch, if it is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dpi: Option<(f64, f64)>,
+    /// Number of scans in the image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan_count: Option<u32>,
+    /// Estimated libjpeg quality setting, from 1 to 100.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+}
+
+/// Exported PNG metadata.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PngDocument {
+    /// One of `grayscale`, `rgb`, `indexed`, `grayscale_alpha`, `rgb_alpha`.
+    pub color_type: String,
+    /// Number of bits per pixel.
+    pub color_depth: u8,
+    /// Whether Adam7 interlacing is used.
+    pub interlaced: bool,
+}
+
+/// Exported GIF metadata.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GifDocument {
+    /// Either `87a` or `89a`.
+    pub version: String,
+    /// Number of frames.
+    pub frames: usize,
+    /// Whether the image is animated.
+    pub animated: bool,
+    /// Total duration of the animation in milliseconds, as played by web browsers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Size of the global color table, if it is present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_color_table_size: Option<u16>,
+    /// Whether any frame has a transparent color.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transparency: Option<bool>,
+    /// Number of frames with a local color table.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_color_tables: Option<usize>,
+    /// Number of bits per primary color available to the original image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_resolution_bits: Option<u8>,
+    /// Index of the background color in the global color table.
+    pub background_color_index: u8,
+    /// Raw pixel aspect ratio value from the logical screen descriptor.
+    pub pixel_aspect_ratio: u8,
+}
+
+/// Exported WebP metadata.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct WebpDocument {
+    /// Bitstream kind of the image: `vp8`, `vp8l` or `vp8x`.
+    pub kind: String,
+}
+
+impl Document {
+    /// Converts the given generic metadata to an export document.
+    pub fn from_metadata(md: &GenericMetadata) -> Document {
+        let dimensions = md.dimensions();
+        let mut doc = Document {
+            schema_version: SCHEMA_VERSION,
+            format: String::new(),
+            mime_type: md.mime_type().to_owned(),
+            width: dimensions.width,
+            height: dimensions.height,
+            jpeg: None,
+            png: None,
+            gif: None,
+            webp: None,
+        };
+
+        match *md {
+            GenericMetadata::Jpeg(ref md) => {
+                doc.format = "jpeg".into();
+                doc.jpeg = Some(JpegDocument::from_metadata(md));
+            }
+            GenericMetadata::Png(ref md) => {
+                doc.format = "png".into();
+                doc.png = Some(PngDocument::from_metadata(md));
+            }
+            GenericMetadata::Gif(ref md) => {
+                doc.format = "gif".into();
+                doc.gif = Some(GifDocument::from_metadata(md));
+            }
+            GenericMetadata::Webp(ref md) => {
+                doc.format = "webp".into();
+                doc.webp = Some(WebpDocument::from_metadata(md));
+            }
+        }
+
+        doc
+    }
+}
+
+impl JpegDocument {
+    fn from_metadata(md: &jpeg::Metadata) -> JpegDocument {
+        JpegDocument {
+            coding_process: Some(match md.coding_process {
+                jpeg::CodingProcess::Baseline => "baseline",
+                jpeg::CodingProcess::ExtendedSequential => "extended_sequential",
+                jpeg::CodingProcess::Progressive => "progressive",
+                jpeg::CodingProcess::Lossless => "lossless",
+            }.into()),
+            arithmetic_coding: Some(md.is_arithmetic_coded()),
+            precision: Some(md.precision),
+            components: Some(md.components),
+            color_space: Some(match md.color_space {
+                jpeg::ColorSpace::Grayscale => "grayscale",
+                jpeg::ColorSpace::YCbCr => "ycbcr",
+                jpeg::ColorSpace::Rgb => "rgb",
+                jpeg::ColorSpace::Cmyk => "cmyk",
+                jpeg::ColorSpace::Ycck => "ycck",
+                jpeg::ColorSpace::Unknown => "unknown",
+            }.into()),
+            dpi: md.dpi(),
+            scan_count: Some(md.scan_count),
+            quality: md.estimated_quality(),
+        }
+    }
+}
+
+impl PngDocument {
+    fn from_metadata(md: &png::Metadata) -> PngDocument {
+        PngDocument {
+            color_type: match md.color_type {
+                png::ColorType::Grayscale => "grayscale",
+                png::ColorType::Rgb => "rgb",
+                png::ColorType::Indexed => "indexed",
+                png::ColorType::GrayscaleAlpha => "grayscale_alpha",
+                png::ColorType::RgbAlpha => "rgb_alpha",
+            }.into(),
+            color_depth: md.color_depth,
+            interlaced: md.interlace_method == png::InterlaceMethod::Adam7,
+        }
+    }
+}
+
+impl GifDocument {
+    fn from_metadata(md: &gif::Metadata) -> GifDocument {
+        GifDocument {
+            version: md.version.as_str().into(),
+            frames: md.frames_number(),
+            animated: md.is_animated(),
+            duration_ms: if md.is_animated() { Some(md.normalized_duration_ms()) } else { None },
+            global_color_table_size: md.global_color_table.as_ref().map(|t| t.size),
+            transparency: Some(md.has_transparency()),
+            local_color_tables: Some(md.local_color_tables_number()),
+            color_resolution_bits: Some(md.color_resolution_bits()),
+            background_color_index: md.background_color_index,
+            pixel_aspect_ratio: md.pixel_aspect_ratio,
+        }
+    }
+}
+
+impl WebpDocument {
+    fn from_metadata(md: &webp::Metadata) -> WebpDocument {
+        WebpDocument {
+            kind: match *md {
+                webp::Metadata::VP8(_) => "vp8",
+                webp::Metadata::VP8L(_) => "vp8l",
+                webp::Metadata::VP8X(_) => "vp8x",
+            }.into(),
+        }
+    }
+}
+
+/// Converts the given generic metadata to a JSON value following the export schema.
+///
+/// This function is only available when `serde_json` feature is enabled.
+#[cfg(feature = "serde_json")]
+pub fn to_json_value(md: &GenericMetadata) -> ::serde_json::Value {
+    ::serde_json::to_value(Document::from_metadata(md))
+        .expect("export document is always serializable")
+}
+
+/// Converts the given generic metadata to a compact JSON string following the export schema.
+///
+/// This function is only available when `serde_json` feature is enabled.
+#[cfg(feature = "serde_json")]
+pub fn to_json_string(md: &GenericMetadata) -> String {
+    ::serde_json::to_string(&Document::from_metadata(md))
+        .expect("export document is always serializable")
+}
+
+#[cfg(feature = "serde_json")]
+impl GenericMetadata {
+    /// Converts this metadata to a JSON value following the export schema.
+    ///
+    /// See `immeta::export` for the description of the schema. This method is only available
+    /// when `serde_json` feature is enabled.
+    #[inline]
+    pub fn to_json_value(&self) -> ::serde_json::Value {
+        to_json_value(self)
+    }
+
+    /// Converts this metadata to a compact JSON string following the export schema.
+    ///
+    /// See `immeta::export` for the description of the schema. This method is only available
+    /// when `serde_json` feature is enabled.
+    #[inline]
+    pub fn to_json_string(&self) -> String {
+        to_json_string(self)
+    }
+}
+
+#[cfg(all(test, feature = "serde_json"))]
+mod tests {
+    use generic;
+
+    use super::{to_json_value, to_json_string};
+
+    #[test]
+    fn test_png_document() {
+        let md = generic::load_from_file("tests/images/owlet.png").unwrap();
+        let value = to_json_value(&md);
+        assert_eq!(value, ::serde_json::json!({
+            "schema_version": 1,
+            "format": "png",
+            "mime_type": "image/png",
+            "width": 1280,
+            "height": 857,
+            "png": {
+                "color_type": "rgb",
+                "color_depth": 24,
+                "interlaced": false
+            }
+        }));
+    }
+
+    #[test]
+    fn test_gif_document() {
+        let md = generic::load_from_file("tests/images/drop.gif").unwrap();
+        let value = to_json_value(&md);
+        assert_eq!(value["format"], "gif");
+        assert_eq!(value["gif"]["version"], "89a");
+        assert_eq!(value["gif"]["frames"], 30);
+        assert_eq!(value["gif"]["animated"], true);
+        assert_eq!(value["gif"]["duration_ms"], 2100);
+        assert_eq!(value["gif"]["local_color_tables"], 0);
+        assert_eq!(value["gif"]["transparency"], true);
+        assert_eq!(value["gif"]["color_resolution_bits"], 7);
+        assert!(value.get("png").is_none());
+    }
+
+    #[test]
+    fn test_json_methods() {
+        let md = generic::load_from_file("tests/images/cherry.webp").unwrap();
+        assert_eq!(md.to_json_value(), to_json_value(&md));
+        assert_eq!(md.to_json_string(), to_json_string(&md));
+
+        let parsed: ::serde_json::Value = ::serde_json::from_str(&md.to_json_string()).unwrap();
+        assert_eq!(parsed, md.to_json_value());
+        assert_eq!(parsed["webp"]["kind"], "vp8");
+    }
+}
@@ -1,17 +1,25 @@
 //! Metadata of GIF images.
 
-use std::io::BufRead;
+use std::io::{self, Read, BufRead, Write, Seek, SeekFrom};
 use std::borrow::Cow;
 use std::str;
+use std::cmp;
+use std::fmt;
+use std::slice;
 
 use byteorder::{ReadBytesExt, LittleEndian};
 
-use types::{Result, Dimensions};
+use types::{Result, PartialResult, Error, ErrorWithPartial, Completeness, Dimensions, ByteRange};
 use traits::LoadableMetadata;
-use utils::BufReadExt;
+use loader::LoadOptions;
+use animation::{self, Animated, Blend, FrameInfo, LoopCount};
+use utils::{self, BufReadExt, Tracked};
+use common::xmp::Xmp;
+use transplant::EmbeddedMetadata;
 
 /// GIF file version number.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Version {
     V87a,
     V89a
@@ -25,10 +33,76 @@ impl Version {
             _      => None
         }
     }
+
+    /// Returns the version as it is written in the file header, i.e. `87a` or `89a`.
+    #[inline]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Version::V87a => "87a",
+            Version::V89a => "89a",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GIF{}", self.as_str())
+    }
+}
+
+/// Contents of the GIF header and the logical screen descriptor.
+///
+/// These are the image-wide parameters which precede all blocks of a GIF image. They are
+/// also available as fields of `Metadata`, but `read_screen_descriptor()` reads them without
+/// reading the rest of the image.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScreenDescriptor {
+    /// GIF format version from the file header.
+    pub version: Version,
+    /// Logical screen dimensions of the image.
+    pub dimensions: Dimensions,
+    /// Information about global color table, if it is present.
+    pub global_color_table: Option<ColorTable>,
+    /// Number of colors available to the original image; see `Metadata::color_resolution`.
+    pub color_resolution: u16,
+    /// Index of the default background color in the global color table.
+    pub background_color_index: u8,
+    /// Raw pixel aspect ratio factor; see `Metadata::pixel_aspect_ratio`.
+    pub pixel_aspect_ratio: u8,
+}
+
+impl ScreenDescriptor {
+    /// Computes pixel aspect ratio approximation, if it is available.
+    ///
+    /// See `Metadata::pixel_aspect_ratio` field documentation.
+    #[inline]
+    pub fn pixel_aspect_ratio_approx(&self) -> Option<f64> {
+        pixel_aspect_ratio_approx(self.pixel_aspect_ratio)
+    }
+
+    /// Returns the index of the background color, if there is a global color table which it
+    /// refers to.
+    ///
+    /// Without the global color table the background color index is meaningless, and
+    /// encoders usually set it to zero.
+    #[inline]
+    pub fn background_color(&self) -> Option<u8> {
+        self.global_color_table.as_ref().map(|_| self.background_color_index)
+    }
+}
+
+fn pixel_aspect_ratio_approx(pixel_aspect_ratio: u8) -> Option<f64> {
+    if pixel_aspect_ratio == 0 {
+        None
+    } else {
+        Some((pixel_aspect_ratio as f64 + 15.0)/64.0)
+    }
 }
 
 /// Represents various kinds of blocks which can be used in a GIF image.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Block {
     /// An image descriptor (image contents for one frame).
     ImageDescriptor(ImageDescriptor),
@@ -46,17 +120,100 @@ pub enum Block {
 fn skip_blocks<R: ?Sized + BufRead, F>(r: &mut R, on_eof: F) -> Result<()>
     where F: Fn() -> Cow<'static, str>
 {
+    // sub-block sizes are read directly from the buffer of the reader, which is much faster
+    // than reading and skipping each sub-block separately for image data with its 255-byte
+    // sub-blocks; `pending` is the number of bytes of the current sub-block which are still
+    // to be skipped when it spans the buffer boundary
+    let mut pending = 0usize;
     loop {
-        let n = try_if_eof!(r.read_u8(), on_eof()) as u64;
-        if n == 0 { return Ok(()); }
-        if try!(r.skip_exact(n)) != n {
-            return Err(unexpected_eof!(on_eof()));
+        let (consumed, done) = {
+            let buf = try!(r.fill_buf());
+            if buf.is_empty() {
+                return Err(unexpected_eof!(on_eof()));
+            }
+            if pending >= buf.len() {
+                pending -= buf.len();
+                (buf.len(), false)
+            } else {
+                let mut pos = pending;
+                pending = 0;
+                loop {
+                    if pos >= buf.len() {
+                        break (buf.len(), false);
+                    }
+                    let n = buf[pos] as usize;
+                    if n == 0 {
+                        break (pos + 1, true);
+                    }
+                    pos += n + 1;
+                    if pos > buf.len() {
+                        pending = pos - buf.len();
+                        break (buf.len(), false);
+                    }
+                }
+            }
+        };
+        r.consume(consumed);
+        if done {
+            return Ok(());
         }
     }
 }
 
+/// Reads data sub-blocks up to the block terminator, keeping the sub-block size bytes.
+fn read_blocks_raw<R: ?Sized + BufRead, F>(r: &mut R, on_eof: F) -> Result<Vec<u8>>
+    where F: Fn() -> Cow<'static, str>
+{
+    let mut data = Vec::new();
+    loop {
+        let n = try_if_eof!(r.read_u8(), on_eof());
+        if n == 0 { return Ok(data); }
+        data.push(n);
+        let start = data.len();
+        data.resize(start + n as usize, 0);
+        try!(r.read_exact(&mut data[start..]).map_err(if_eof!(std, on_eof())));
+    }
+}
+
+/// Returns the number of bytes taken by data sub-blocks at the start of `data`, including the
+/// block terminator, or `None` if the block terminator is missing.
+fn sub_blocks_len(data: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while pos < data.len() {
+        match data[pos] as usize {
+            0 => return Some(pos + 1),
+            n => pos += n + 1
+        }
+    }
+    None
+}
+
+/// Finds the XMP packet stored in the data of an XMP application extension, returning it
+/// together with whether the block terminator still follows the data.
+///
+/// The packet is stored as is, without splitting it into sub-blocks; instead, it is followed
+/// by a "magic trailer" of bytes 0x01, 0xFF, 0xFE, ..., 0x01, 0x00, so that a decoder which
+/// interprets the packet bytes as sub-block sizes always ends up at the block terminator.
+/// `data` are the sub-blocks following the first one, as read with `read_blocks_raw()`.
+fn xmp_packet(data: &[u8]) -> Result<(&[u8], bool)> {
+    match find_xmp_trailer(data) {
+        Some((start, terminator_pending)) => Ok((&data[..start], terminator_pending)),
+        None => Err(invalid_format!("XMP data does not have a magic trailer"))
+    }
+}
+
+/// Finds the magic trailer in XMP data read with `read_blocks_raw()`, returning its offset
+/// and whether the block terminator is still unread.
+///
+/// The sub-block size chain may end at the last byte of the trailer instead of the block
+/// terminator following it; in this case the terminator has not been consumed.
+fn find_xmp_trailer(data: &[u8]) -> Option<(usize, bool)> {
+    data.windows(3).rposition(|w| w == [0x01, 0xff, 0xfe]).map(|start| (start, data.len() - start == 256))
+}
+
 /// Contains information about a color table (global or local).
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ColorTable {
     /// Color table size, between 2 and 256.
     pub size: u16,
@@ -72,6 +229,7 @@ pub struct ColorTable {
 
 /// Contains metadata about an image block, i.e. a single frame of a GIF image.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ImageDescriptor {
     /// Offset of the image data from the left boundary of the logical screen.
     pub left: u16,
@@ -90,7 +248,9 @@ pub struct ImageDescriptor {
 }
 
 impl ImageDescriptor {
-    fn load<R: ?Sized + BufRead>(index: usize, r: &mut R) -> Result<ImageDescriptor> {
+    /// Parses the fields following the image separator; the color table and image data are
+    /// skipped by the callers.
+    fn parse(index: usize, mut r: &[u8]) -> Result<ImageDescriptor> {
         let left = try_if_eof!(
             r.read_u16::<LittleEndian>(), 
             "when reading left offset of image block {}", index
@@ -120,16 +280,6 @@ impl ImageDescriptor {
             0
         };
 
-        if local_color_table {
-            let skip_size = local_color_table_size as u64 * 3;
-            if try!(r.skip_exact(skip_size)) != skip_size {
-                return Err(unexpected_eof!("when reading color table of image block {}", index));
-            }
-        }
-
-        let _ = try_if_eof!(r.read_u8(), "when reading LZW minimum code size of image block {}", index);
-        try!(skip_blocks(r, || format!("when reading image data of image block {}", index).into()));
-
         Ok(ImageDescriptor {
             left: left,
             top: top,
@@ -154,6 +304,7 @@ impl ImageDescriptor {
 /// image should be displayed. It is especially important for animated GIF images because
 /// it contains delay and disposal method flags.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GraphicControlExtension {
     /// Indicates how the graphic should be treated after it is displayed.
     ///
@@ -187,7 +338,8 @@ impl GraphicControlExtension {
         self.delay_time as u32 * 10
     }
 
-    fn load<R: ?Sized + BufRead>(index: usize, r: &mut R) -> Result<GraphicControlExtension> {
+    /// Parses the first data sub-block of the extension, including its size.
+    fn parse(index: usize, mut r: &[u8]) -> Result<GraphicControlExtension> {
         const NAME: &'static str = "graphics control extension block";
 
         let block_size = try_if_eof!(r.read_u8(), "when reading block size of {} {}", NAME, index);
@@ -210,8 +362,6 @@ impl GraphicControlExtension {
             "when reading transparent color index of {} {}", NAME, index
         );
 
-        try!(skip_blocks(r, || format!("when reading block terminator of {} {}", NAME, index).into()));
-
         Ok(GraphicControlExtension {
             disposal_method: try!(
                 DisposalMethod::from_u8(disposal_method)
@@ -234,6 +384,7 @@ impl GraphicControlExtension {
 /// Disposal method defines how the graphic should be treated after being displayed. Descriptions
 /// of enum variants come from GIF spec.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DisposalMethod {
     /// The decoder is not required to take any action.
     None,
@@ -267,6 +418,7 @@ impl DisposalMethod {
 /// opposed to pre-rendered rasterized text. However, it seems that these blocks are not
 /// well supported by the existing software.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PlainTextExtension {
     /// Column number, in pixels, of the left edge of the text grid, with respect to 
     /// the left edge of the logical screen.
@@ -286,11 +438,34 @@ pub struct PlainTextExtension {
     /// Index of a foreground color in the global color table.
     pub foreground_color_index: u8,
     /// Index of a background color in the global color table.
-    pub background_color_index: u8
+    pub background_color_index: u8,
+
+    /// Text data to be rendered in the grid.
+    ///
+    /// The GIF spec requires it to consist of printable 7-bit ASCII characters.
+    pub text: Vec<u8>
 }
 
 impl PlainTextExtension {
-    fn load<R: ?Sized + BufRead>(index: usize, r: &mut R) -> Result<PlainTextExtension> {
+    /// Returns text data as a UTF-8 string, if possible.
+    pub fn text_str(&self) -> Option<&str> {
+        str::from_utf8(&self.text).ok()
+    }
+
+    /// Returns the number of columns and rows in the text grid.
+    ///
+    /// Returns zeros if the cell dimensions are zero.
+    pub fn grid_size(&self) -> (u16, u16) {
+        if self.cell_width == 0 || self.cell_height == 0 {
+            (0, 0)
+        } else {
+            (self.width / self.cell_width as u16, self.height / self.cell_height as u16)
+        }
+    }
+
+    /// Parses the extension given its first data sub-block, including its size, and the
+    /// remaining sub-blocks, which contain the text.
+    fn parse(index: usize, mut r: &[u8], raw: &[u8]) -> Result<PlainTextExtension> {
         const NAME: &'static str = "plain text extension block";
 
         let block_size = try_if_eof!(r.read_u8(), "when reading block size of {} {}", NAME, index);
@@ -333,7 +508,14 @@ impl PlainTextExtension {
             "when reading background color index of {} {}", NAME, index
         );
 
-        try!(skip_blocks(r, || format!("when reading text data of {} {}", NAME, index).into()));
+        // sub-block sizes are not part of the text
+        let mut text = Vec::with_capacity(raw.len());
+        let mut i = 0;
+        while i < raw.len() {
+            let n = raw[i] as usize;
+            text.extend_from_slice(&raw[i + 1..i + 1 + n]);
+            i += n + 1;
+        }
 
         Ok(PlainTextExtension {
             left: left,
@@ -345,7 +527,9 @@ impl PlainTextExtension {
             cell_height: cell_height,
 
             foreground_color_index: foreground_color_index,
-            background_color_index: background_color_index
+            background_color_index: background_color_index,
+
+            text: text
         })
     }
 }
@@ -355,6 +539,7 @@ impl PlainTextExtension {
 /// These blocks usually contain information about the application which was used to create
 /// the image.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ApplicationExtension {
     /// Eight ASCII bytes of an application identifier.
     pub application_identifier: [u8; 8],
@@ -381,7 +566,20 @@ impl ApplicationExtension {
         str::from_utf8(&self.authentication_code).ok()
     }
 
-    fn load<R: ?Sized + BufRead>(index: usize, r: &mut R) -> Result<ApplicationExtension> {
+    /// Returns `true` if this is a NETSCAPE2.0 or ANIMEXTS1.0 extension which contains
+    /// the loop count of an animation.
+    pub fn is_looping_extension(&self) -> bool {
+        (&self.application_identifier == b"NETSCAPE" && &self.authentication_code == b"2.0") ||
+        (&self.application_identifier == b"ANIMEXTS" && &self.authentication_code == b"1.0")
+    }
+
+    /// Returns `true` if this is an extension which contains an XMP packet.
+    pub fn is_xmp_extension(&self) -> bool {
+        &self.application_identifier == b"XMP Data" && &self.authentication_code == b"XMP"
+    }
+
+    /// Parses the first data sub-block of the extension, including its size.
+    fn parse(index: usize, mut r: &[u8]) -> Result<ApplicationExtension> {
         const NAME: &'static str = "application extension block";
 
         let block_size = try_if_eof!(r.read_u8(), "when reading block size of {} {}", NAME, index);
@@ -397,8 +595,6 @@ impl ApplicationExtension {
         try!(r.read_exact(&mut authentication_code)
              .map_err(if_eof!(std, "while reading authentication code in {} {}", NAME, index)));
 
-        try!(skip_blocks(r, || format!("when reading application data of {} {}", NAME, index).into()));
-
         Ok(ApplicationExtension {
             application_identifier: application_identifier,
             authentication_code: authentication_code
@@ -411,19 +607,12 @@ impl ApplicationExtension {
 /// Comment block does not contain any metadata, so this struct is used for uniformity
 /// as a placeholder in the enum.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CommentExtension;
 
-impl CommentExtension {
-    fn load<R: ?Sized + BufRead>(index: usize, r: &mut R) -> Result<CommentExtension> {
-        const NAME: &'static str = "comments extension block";
-        try!(skip_blocks(r, || format!("when reading comment data of {} {}", NAME, index).into()));
-
-        Ok(CommentExtension)
-    }
-}
-
 /// Contains metadata about the whole GIF image.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Metadata {
     /// GIF format version from the file header.
     pub version: Version,
@@ -470,7 +659,24 @@ pub struct Metadata {
     pub pixel_aspect_ratio: u8,
 
     /// Metadata for each block in the GIF image.
-    pub blocks: Vec<Block>
+    pub blocks: Vec<Block>,
+
+    /// Number of times the animation is repeated after it is played for the first time,
+    /// from NETSCAPE2.0 application extension; zero means infinite looping.
+    ///
+    /// If the extension is absent, the animation is played once.
+    pub loop_count: Option<u16>,
+
+    /// XMP packet from "XMP Data" application extension.
+    pub xmp: Option<Xmp>,
+
+    /// Location of the XMP packet from "XMP Data" application extension, if it has not been
+    /// parsed yet.
+    ///
+    /// This is only set when metadata is loaded with `LoadOptions::lazy_extensions` enabled,
+    /// in which case `xmp` stays empty until `load_extensions()` is called. The packet is
+    /// still read during loading to find the end of the extension block.
+    pub pending_xmp: Option<ByteRange>
 }
 
 impl Metadata {
@@ -479,10 +685,37 @@ impl Metadata {
     /// See `pixel_aspect_ration` field documentation.
     #[inline]
     pub fn pixel_aspect_ratio_approx(&self) -> Option<f64> {
-        if self.pixel_aspect_ratio == 0 {
-            None
-        } else {
-            Some((self.pixel_aspect_ratio as f64 + 15.0)/64.0)
+        pixel_aspect_ratio_approx(self.pixel_aspect_ratio)
+    }
+
+    /// Parses the XMP packet whose parsing was deferred, moving it from `pending_xmp` to `xmp`.
+    ///
+    /// The provided stream must contain the GIF image this metadata was loaded from, starting
+    /// at its beginning. An invalid XMP packet is skipped, like it is when loading metadata
+    /// eagerly.
+    pub fn load_extensions<R: ?Sized + Read + Seek>(&mut self, r: &mut R) -> Result<()> {
+        if let Some(location) = self.pending_xmp {
+            try!(r.seek(SeekFrom::Start(location.offset)));
+            let data = try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(r, location.len),
+                                   "when reading XMP packet");
+            match Xmp::from_bytes(&data) {
+                Ok(xmp) => self.xmp = Some(xmp),
+                Err(e) => log_debug!("skipping invalid XMP packet: {}", e)
+            }
+            self.pending_xmp = None;
+        }
+        Ok(())
+    }
+
+    /// Returns the header and logical screen descriptor fields of the image.
+    pub fn screen_descriptor(&self) -> ScreenDescriptor {
+        ScreenDescriptor {
+            version: self.version,
+            dimensions: self.dimensions,
+            global_color_table: self.global_color_table.clone(),
+            color_resolution: self.color_resolution,
+            background_color_index: self.background_color_index,
+            pixel_aspect_ratio: self.pixel_aspect_ratio,
         }
     }
 
@@ -504,84 +737,1092 @@ impl Metadata {
         // TODO: is this right?
         self.frames_number() > 1
     }
+
+    /// Returns the number of bits per primary color available to the original image, from 1
+    /// to 8.
+    ///
+    /// See `color_resolution` field documentation.
+    #[inline]
+    pub fn color_resolution_bits(&self) -> u8 {
+        self.color_resolution.trailing_zeros() as u8
+    }
+
+    /// Returns `true` if any frame of the image has a transparent color.
+    ///
+    /// Note that the transparent color may still be unused in the image data.
+    pub fn has_transparency(&self) -> bool {
+        self.frame_iter().any(|f| f.is_transparent())
+    }
+
+    /// Returns the number of frames which have their own color table.
+    pub fn local_color_tables_number(&self) -> usize {
+        self.frame_iter().filter(|f| f.has_local_color_table()).count()
+    }
+
+    /// Returns the size of the color table used by each frame, that is, of its local color
+    /// table or of the global one.
+    ///
+    /// The size is `None` for frames without any color table; such frames are rendered with
+    /// a decoder-specific default palette.
+    pub fn frame_color_table_sizes(&self) -> Vec<Option<u16>> {
+        let global = self.global_color_table.as_ref().map(|t| t.size);
+        self.frame_iter().map(|f| f.local_color_table().map(|t| t.size).or(global)).collect()
+    }
+
+    /// Returns the total duration of one play of the animation in milliseconds, i.e. the sum
+    /// of all frame delays.
+    pub fn duration_ms(&self) -> u64 {
+        self.frame_iter().map(|f| f.delay_time_ms() as u64).sum()
+    }
+
+    /// Returns the total duration of one play of the animation in milliseconds, with frame
+    /// delays normalized as described in `Frame::normalized_delay_time_ms()`.
+    ///
+    /// This is how long the animation is actually played in web browsers.
+    pub fn normalized_duration_ms(&self) -> u64 {
+        self.frame_iter().map(|f| f.normalized_delay_time_ms() as u64).sum()
+    }
+
+    /// Returns `true` if the image contains plain text extension blocks.
+    ///
+    /// Plain text blocks are not supported by most decoders, so the text they contain is
+    /// usually not displayed.
+    pub fn has_plain_text(&self) -> bool {
+        self.blocks.iter().any(|b| match *b {
+            Block::PlainTextExtension(_) => true,
+            _ => false
+        })
+    }
+
+    /// Returns an iterator over the frames of the image.
+    ///
+    /// Each frame combines an image descriptor block with the graphic control extension block
+    /// preceding it, if any.
+    #[inline]
+    pub fn frame_iter(&self) -> Frames {
+        Frames {
+            blocks: self.blocks.iter(),
+            index: 0,
+        }
+    }
+
+    /// Returns an estimate of the size in bytes of the decoded image.
+    ///
+    /// Each frame is assumed to be decoded into a separate 8-bit RGBA buffer of the logical
+    /// screen size.
+    pub fn estimated_decoded_size(&self) -> u64 {
+        let frames = cmp::max(self.frames_number(), 1);
+        utils::decoded_size(self.dimensions, 4, 1, frames as u64)
+    }
 }
 
-impl LoadableMetadata for Metadata {
-    fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Metadata> {
-        let mut signature = [0u8; 6];
-        try!(r.read_exact(&mut signature).map_err(if_eof!(std, "when reading GIF signature")));
+const MAX_ABNORMAL_DELAY_MS: u32 = 10;
+const NORMALIZED_DELAY_MS: u32 = 100;
 
-        let version = try!(Version::from_bytes(&signature[3..])
-            .ok_or(invalid_format!("invalid GIF version: {:?}", &signature[3..])));
+/// A single frame of a GIF image.
+///
+/// Values of this type are obtained with `Metadata::frame_iter()`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Frame<'a> {
+    /// Index of the frame, starting from zero.
+    pub index: usize,
+    /// Image descriptor block of the frame.
+    pub descriptor: &'a ImageDescriptor,
+    /// Graphic control extension block which applies to the frame, if any.
+    pub control: Option<&'a GraphicControlExtension>,
+}
 
-        let width = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading logical width");
-        let height = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading logical height");
+impl<'a> Frame<'a> {
+    /// Returns the frame delay in milliseconds, or zero if there is no graphic control
+    /// extension.
+    #[inline]
+    pub fn delay_time_ms(&self) -> u32 {
+        self.control.map(|c| c.delay_time_ms()).unwrap_or(0)
+    }
 
-        let packed_flags = try_if_eof!(r.read_u8(), "when reading global flags");
+    /// Returns the disposal method of the frame.
+    #[inline]
+    pub fn disposal_method(&self) -> DisposalMethod {
+        self.control.map(|c| c.disposal_method).unwrap_or(DisposalMethod::None)
+    }
 
-        let global_color_table =        (packed_flags & 0b10000000) > 0;
-        let color_resolution =          (packed_flags & 0b01110000) >> 4;
-        let global_color_table_sorted = (packed_flags & 0b00001000) > 0;
-        let global_color_table_size_p = (packed_flags & 0b00000111) >> 0;
+    /// Returns the frame delay in milliseconds, normalized the way web browsers do it.
+    ///
+    /// Browsers display frames with a delay of 10 milliseconds or less for 100 milliseconds,
+    /// since such delays are usually set by mistake and would make animations too fast.
+    #[inline]
+    pub fn normalized_delay_time_ms(&self) -> u32 {
+        match self.delay_time_ms() {
+            d if d <= MAX_ABNORMAL_DELAY_MS => NORMALIZED_DELAY_MS,
+            d => d
+        }
+    }
 
-        let global_color_table_size = if global_color_table {
-            1u16 << (global_color_table_size_p + 1) 
-        } else {
-            0
-        };
-        let background_color_index = try_if_eof!(r.read_u8(), "when reading background color index");
-        let pixel_aspect_ratio = try_if_eof!(r.read_u8(), "when reading pixel aspect ration");
+    /// Returns the index of the transparent color of the frame, if it has one.
+    #[inline]
+    pub fn transparent_color_index(&self) -> Option<u8> {
+        self.control.and_then(|c| c.transparent_color_index)
+    }
 
-        if global_color_table {
-            let skip_size = global_color_table_size as u64 * 3;
-            if try!(r.skip_exact(skip_size)) != skip_size {
-                return Err(unexpected_eof!("when reading global color table"));
+    /// Returns `true` if the frame has a transparent color.
+    #[inline]
+    pub fn is_transparent(&self) -> bool {
+        self.transparent_color_index().is_some()
+    }
+
+    /// Returns `true` if the frame has its own color table.
+    #[inline]
+    pub fn has_local_color_table(&self) -> bool {
+        self.descriptor.local_color_table.is_some()
+    }
+
+    /// Returns the local color table of the frame, if it is present.
+    #[inline]
+    pub fn local_color_table(&self) -> Option<&'a ColorTable> {
+        self.descriptor.local_color_table.as_ref()
+    }
+}
+
+/// An iterator over frames of a GIF image.
+///
+/// Values of this type are obtained with `Metadata::frame_iter()`.
+#[derive(Clone)]
+pub struct Frames<'a> {
+    blocks: slice::Iter<'a, Block>,
+    index: usize,
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Frame<'a>> {
+        let mut control = None;
+        for block in &mut self.blocks {
+            match *block {
+                Block::GraphicControlExtension(ref gce) => control = Some(gce),
+                // graphic control extension applies to the next graphic rendering block,
+                // which may also be a plain text block
+                Block::PlainTextExtension(_) => control = None,
+                Block::ImageDescriptor(ref descriptor) => {
+                    let frame = Frame {
+                        index: self.index,
+                        descriptor: descriptor,
+                        control: control,
+                    };
+                    self.index += 1;
+                    return Some(frame);
+                }
+                _ => {}
             }
         }
+        None
+    }
+}
 
-        let mut blocks = Vec::new();
-        let mut index = 0usize;
-        loop {
-            let separator = try_if_eof!(r.read_u8(), "when reading separator of block {}", index);
-            let block = match separator {
-                0x2c => Block::ImageDescriptor(try!(ImageDescriptor::load(index, r))),
+impl Animated for Metadata {
+    #[inline]
+    fn is_animated(&self) -> bool {
+        Metadata::is_animated(self)
+    }
+
+    fn loop_count(&self) -> Option<LoopCount> {
+        self.loop_count.map(|n| match n {
+            0 => LoopCount::Infinite,
+            n => LoopCount::Finite(n as u32 + 1)
+        })
+    }
+
+    fn frames(&self) -> Vec<FrameInfo> {
+        self.frame_iter().map(|f| frame_info(
+            (f.descriptor.left, f.descriptor.top), (f.descriptor.width, f.descriptor.height).into(), f.control
+        )).collect()
+    }
+}
+
+fn frame_info((left, top): (u16, u16), dimensions: Dimensions,
+              control: Option<&GraphicControlExtension>) -> FrameInfo {
+    FrameInfo {
+        left: left as u32,
+        top: top as u32,
+        dimensions: dimensions,
+        delay_ms: control.map(|c| c.delay_time_ms()).unwrap_or(0),
+        disposal: match control.map(|c| c.disposal_method) {
+            Some(DisposalMethod::RestoreToBackgroundColor) => animation::Disposal::Background,
+            Some(DisposalMethod::RestoreToPrevious) => animation::Disposal::Previous,
+            _ => animation::Disposal::None,
+        },
+        // transparent pixels leave the canvas intact
+        blend: Blend::Over,
+    }
+}
+
+/// Handling of the data sub-blocks which follow the first one in an extension block.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum ExtensionData {
+    /// The sub-blocks are skipped.
+    Skip,
+    /// The sub-blocks are parsed, keeping their size bytes.
+    Raw,
+    /// The sub-blocks are an XMP packet; see `xmp_packet()`.
+    Xmp,
+}
+
+fn check_extension_label(index: usize, label: u8) -> Result<()> {
+    match label {
+        0x01 | 0xf9 | 0xfe | 0xff => Ok(()),
+        _ => Err(invalid_format!("unknown extension type of block {}: 0x{:X}", index, label))
+    }
+}
+
+/// Describes what was being read when an extension block ended prematurely, either its first
+/// block size or its data.
+fn extension_eof(index: usize, label: u8, block_size: bool) -> Cow<'static, str> {
+    let (name, data) = match label {
+        0x01 => ("plain text extension block", "text data"),
+        0xf9 => ("graphics control extension block", "block terminator"),
+        0xfe => ("comments extension block", "comment data"),
+        _ => ("application extension block", "application data")
+    };
+    if block_size && label != 0xfe {
+        format!("when reading block size of {} {}", name, index).into()
+    } else {
+        format!("when reading {} of {} {}", data, name, index).into()
+    }
+}
+
+impl Metadata {
+    fn new(sd: ScreenDescriptor) -> Metadata {
+        Metadata {
+            version: sd.version,
+            dimensions: sd.dimensions,
+            global_color_table: sd.global_color_table,
+            color_resolution: sd.color_resolution,
+            background_color_index: sd.background_color_index,
+            pixel_aspect_ratio: sd.pixel_aspect_ratio,
+
+            blocks: Vec::new(),
+
+            loop_count: None,
+
+            xmp: None,
+            pending_xmp: None
+        }
+    }
+
+    /// Checks `first`, the first data sub-block of an extension including its size, and
+    /// returns how the sub-blocks following it are handled.
+    fn extension_data(index: usize, label: u8, first: &[u8]) -> Result<ExtensionData> {
+        Ok(match label {
+            0x01 => {
+                try!(PlainTextExtension::parse(index, first, &[]));
+                ExtensionData::Raw
+            }
+            0xf9 => {
+                try!(GraphicControlExtension::parse(index, first));
+                ExtensionData::Skip
+            }
+            0xff => {
+                let ext = try!(ApplicationExtension::parse(index, first));
+                if ext.is_looping_extension() {
+                    ExtensionData::Raw
+                } else if ext.is_xmp_extension() {
+                    ExtensionData::Xmp
+                } else {
+                    ExtensionData::Skip
+                }
+            }
+            _ => ExtensionData::Skip
+        })
+    }
+
+    /// Parses an extension block given its first data sub-block, including its size, and the
+    /// remaining sub-blocks if `extension_data()` asks for them.
+    ///
+    /// If `rest_offset`, the offset of the remaining sub-blocks in the stream, is provided,
+    /// the XMP packet is not parsed and its location is recorded in `pending_xmp` instead.
+    ///
+    /// This is the parsing core shared by `parse_bytes()` and `Metadata::load()`, which only
+    /// locate the parts of blocks in a buffer or read them from a stream. Image descriptors are
+    /// parsed with `ImageDescriptor::parse()`.
+    fn parse_extension(&mut self, index: usize, label: u8, first: &[u8], rest: &[u8],
+                       rest_offset: Option<u64>) -> Result<Block> {
+        log_trace!("GIF extension block {}, label 0x{:02X}", index, label);
+        Ok(match label {
+            0x01 => Block::PlainTextExtension(try!(PlainTextExtension::parse(index, first, rest))),
+            0xf9 => Block::GraphicControlExtension(try!(GraphicControlExtension::parse(index, first))),
+            0xfe => Block::CommentExtension(CommentExtension),
+            _ => {
+                let ext = try!(ApplicationExtension::parse(index, first));
+                // looping sub-block: size 3, identifier 1, loop count
+                if ext.is_looping_extension() && rest.len() >= 4 && rest[0] >= 3 && rest[1] == 1 {
+                    self.loop_count = Some(rest[2] as u16 | (rest[3] as u16) << 8);
+                }
+                if ext.is_xmp_extension() {
+                    let packet = try!(xmp_packet(rest)).0;
+                    match rest_offset {
+                        Some(offset) => self.pending_xmp = Some(ByteRange { offset: offset, len: packet.len() as u64 }),
+                        None => match Xmp::from_bytes(packet) {
+                            Ok(xmp) => self.xmp = Some(xmp),
+                            Err(e) => log_debug!("skipping invalid XMP packet in block {}: {}", index, e)
+                        }
+                    }
+                }
+                Block::ApplicationExtension(ext)
+            }
+        })
+    }
+}
+
+/// Reads the first data sub-block of an extension, including its size, into `buf`.
+///
+/// The sub-block is truncated if the stream ends inside of it.
+fn read_first_sub_block<R: ?Sized + BufRead>(index: usize, label: u8, r: &mut R, buf: &mut Vec<u8>) -> Result<()> {
+    let n = try_if_eof!(r.read_u8(), extension_eof(index, label, true));
+    buf.clear();
+    buf.push(n);
+    try!(Read::take(r, n as u64).read_to_end(buf));
+    Ok(())
+}
+
+/// Reads the next block from a stream into `md`, returning `false` after the trailer.
+///
+/// If `lazy` is set, the XMP packet is not parsed; see `Metadata::parse_extension()`.
+fn read_block<R: BufRead>(index: usize, r: &mut Tracked<R>, md: &mut Metadata,
+                          first: &mut Vec<u8>, rest: &mut Vec<u8>, lazy: bool) -> Result<bool> {
+    let separator = try_if_eof!(r.read_u8(), "when reading separator of block {}", index);
+    log_trace!("GIF block {}, separator 0x{:02X}", index, separator);
+    let block = match separator {
+        0x2c => {
+            try!(r.read_up_to(9, first));
+            let descriptor = try!(ImageDescriptor::parse(index, first));
+            if let Some(ref table) = descriptor.local_color_table {
+                let skip_size = table.size as u64 * 3;
+                if try!(r.skip_exact(skip_size)) != skip_size {
+                    return Err(unexpected_eof!("when reading color table of image block {}", index));
+                }
+            }
+            let _ = try_if_eof!(r.read_u8(), "when reading LZW minimum code size of image block {}", index);
+            try!(skip_blocks(r, || format!("when reading image data of image block {}", index).into()));
+            Block::ImageDescriptor(descriptor)
+        }
+        0x21 => {
+            let label = try_if_eof!(r.read_u8(), "when reading label of block {}", index);
+            try!(check_extension_label(index, label));
+            try!(read_first_sub_block(index, label, r, first));
+            let kind = try!(Metadata::extension_data(index, label, first));
+            let rest_offset = if lazy { Some(r.position) } else { None };
+            rest.clear();
+            if first[0] != 0 {
+                let on_eof = || extension_eof(index, label, false);
+                match kind {
+                    ExtensionData::Skip => try!(skip_blocks(r, on_eof)),
+                    ExtensionData::Raw => *rest = try!(read_blocks_raw(r, on_eof)),
+                    ExtensionData::Xmp => {
+                        *rest = try!(read_blocks_raw(r, &on_eof));
+                        if try!(xmp_packet(rest)).1 {
+                            let _ = try_if_eof!(r.read_u8(), on_eof());
+                        }
+                    }
+                }
+            }
+            try!(md.parse_extension(index, label, first, rest, rest_offset))
+        }
+        0x3b => return Ok(false),
+        _ => return Err(invalid_format!("unknown block type of block {}: 0x{:X}", index, separator))
+    };
+    md.blocks.push(block);
+    Ok(true)
+}
+
+/// Counts frames of a GIF image without collecting any other metadata.
+///
+/// This is considerably faster than loading `Metadata` and calling `frames_number()` on it,
+/// since blocks are skipped without being parsed and no memory is allocated. The result is
+/// the number of image descriptor blocks, the same as `frames_number()` returns.
+pub fn count_frames<R: ?Sized + BufRead>(r: &mut R) -> Result<usize> {
+    let mut header = [0u8; 13];
+    try!(r.read_exact(&mut header).map_err(if_eof!(std, "when reading GIF header")));
+    if &header[..3] != b"GIF" || Version::from_bytes(&header[3..6]).is_none() {
+        return Err(invalid_format!("invalid GIF signature: {:?}", &header[..6]));
+    }
+    try!(skip_color_table(r, header[10], || "when reading global color table".into()));
+
+    let mut frames = 0;
+    loop {
+        let separator = try_if_eof!(r.read_u8(), "when reading separator of a block after frame {}", frames);
+        match separator {
+            0x2c => {
+                let mut descriptor = [0u8; 9];
+                try!(r.read_exact(&mut descriptor)
+                     .map_err(if_eof!(std, "when reading image descriptor of frame {}", frames)));
+                try!(skip_color_table(r, descriptor[8], || format!("when reading color table of frame {}", frames).into()));
+                // LZW minimum code size precedes image data sub-blocks
+                let _ = try_if_eof!(r.read_u8(), "when reading LZW minimum code size of frame {}", frames);
+                try!(skip_blocks(r, || format!("when reading image data of frame {}", frames).into()));
+                frames += 1;
+            }
+            0x21 => {
+                let _ = try_if_eof!(r.read_u8(), "when reading extension label after frame {}", frames);
+                try!(skip_blocks(r, || format!("when reading extension block after frame {}", frames).into()));
+            }
+            0x3b => return Ok(frames),
+            _ => return Err(invalid_format!("unknown block type after frame {}: 0x{:X}", frames, separator))
+        }
+    }
+}
+
+/// Visits frames of a GIF image in display order as they are read from the stream.
+///
+/// The callback is called with information about each frame right after its image descriptor
+/// is read, before its image data, and returns whether reading should continue. No metadata
+/// is collected, so probing the first few frames of a long animation neither allocates nor
+/// reads the rest of the stream. Frame information is the same as `Animated::frames()`
+/// returns for the loaded `Metadata`.
+///
+/// Returns the number of frames visited, including the one for which the callback returned
+/// `false`.
+pub fn for_each_frame<R: ?Sized + BufRead, F>(r: &mut R, mut f: F) -> Result<usize>
+    where F: FnMut(FrameInfo) -> bool
+{
+    let mut header = [0u8; 13];
+    try!(r.read_exact(&mut header).map_err(if_eof!(std, "when reading GIF header")));
+    if &header[..3] != b"GIF" || Version::from_bytes(&header[3..6]).is_none() {
+        return Err(invalid_format!("invalid GIF signature: {:?}", &header[..6]));
+    }
+    try!(skip_color_table(r, header[10], || "when reading global color table".into()));
+
+    let mut frames = 0;
+    let mut control = None;
+    let mut first = Vec::new();
+    for index in 0.. {
+        let separator = try_if_eof!(r.read_u8(), "when reading separator of block {}", index);
+        match separator {
+            0x2c => {
+                let mut descriptor = [0u8; 9];
+                try!(r.read_exact(&mut descriptor)
+                     .map_err(if_eof!(std, "when reading image descriptor of block {}", index)));
+                let u16 = |i: usize| descriptor[i] as u16 | (descriptor[i + 1] as u16) << 8;
+                let info = frame_info((u16(0), u16(2)), (u16(4), u16(6)).into(), control.take().as_ref());
+                frames += 1;
+                if !f(info) {
+                    break;
+                }
+                try!(skip_color_table(r, descriptor[8], || format!("when reading color table of block {}", index).into()));
+                let _ = try_if_eof!(r.read_u8(), "when reading LZW minimum code size of block {}", index);
+                try!(skip_blocks(r, || format!("when reading image data of block {}", index).into()));
+            }
+            0x21 => {
+                let label = try_if_eof!(r.read_u8(), "when reading label of block {}", index);
+                match label {
+                    0xf9 => {
+                        try!(read_first_sub_block(index, 0xf9, r, &mut first));
+                        control = Some(try!(GraphicControlExtension::parse(index, &first)));
+                        if first[0] != 0 {
+                            try!(skip_blocks(r, || extension_eof(index, 0xf9, false)));
+                        }
+                    }
+                    _ => {
+                        // graphic control extension applies to the next graphic rendering block,
+                        // which may also be a plain text block
+                        if label == 0x01 {
+                            control = None;
+                        }
+                        try!(skip_blocks(r, || format!("when reading extension block {}", index).into()));
+                    }
+                }
+            }
+            0x3b => break,
+            _ => return Err(invalid_format!("unknown block type of block {}: 0x{:X}", index, separator))
+        }
+    }
+    Ok(frames)
+}
+
+/// Finds the image data of a GIF stream starting at the current position.
+///
+/// Each range covers the compressed data of one frame, in display order: the LZW minimum code
+/// size followed by data sub-blocks, up to and including the block terminator. Image
+/// descriptors, color tables and extension blocks lie outside the ranges. Blocks are skipped
+/// the same way as in `count_frames()`.
+pub fn image_data_ranges<R: ?Sized + BufRead>(r: &mut R) -> Result<Vec<ByteRange>> {
+    let r = &mut Tracked::new(r);
+    let mut header = [0u8; 13];
+    try!(r.read_exact(&mut header).map_err(if_eof!(std, "when reading GIF header")));
+    if &header[..3] != b"GIF" || Version::from_bytes(&header[3..6]).is_none() {
+        return Err(invalid_format!("invalid GIF signature: {:?}", &header[..6]));
+    }
+    try!(skip_color_table(r, header[10], || "when reading global color table".into()));
+
+    let mut ranges = Vec::new();
+    loop {
+        let frames = ranges.len();
+        let separator = try_if_eof!(r.read_u8(), "when reading separator of a block after frame {}", frames);
+        match separator {
+            0x2c => {
+                let mut descriptor = [0u8; 9];
+                try!(r.read_exact(&mut descriptor)
+                     .map_err(if_eof!(std, "when reading image descriptor of frame {}", frames)));
+                try!(skip_color_table(r, descriptor[8], || format!("when reading color table of frame {}", frames).into()));
+                let start = r.position;
+                let _ = try_if_eof!(r.read_u8(), "when reading LZW minimum code size of frame {}", frames);
+                try!(skip_blocks(r, || format!("when reading image data of frame {}", frames).into()));
+                ranges.push(ByteRange { offset: start, len: r.position - start });
+            }
+            0x21 => {
+                let _ = try_if_eof!(r.read_u8(), "when reading extension label after frame {}", frames);
+                try!(skip_blocks(r, || format!("when reading extension block after frame {}", frames).into()));
+            }
+            0x3b => return Ok(ranges),
+            _ => return Err(invalid_format!("unknown block type after frame {}: 0x{:X}", frames, separator))
+        }
+    }
+}
+
+/// Checks whether a GIF stream starting at the current position is complete, i.e. whether
+/// its trailer appears before or at the end of the stream.
+///
+/// All blocks are skipped the same way as in `count_frames()`. The position of the stream is
+/// restored afterwards.
+pub fn verify_complete<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Completeness> {
+    utils::verify_length(r, |r| {
+        let mut tracked = Tracked::new(&mut *r);
+        try!(count_frames(&mut tracked));
+        Ok(tracked.position)
+    })
+}
+
+/// Skips a color table described by the given packed flags of a screen or image descriptor.
+fn skip_color_table<R: ?Sized + BufRead, F>(r: &mut R, packed_flags: u8, on_eof: F) -> Result<()>
+    where F: Fn() -> Cow<'static, str>
+{
+    if packed_flags & 0b10000000 != 0 {
+        let size = 3u64 << ((packed_flags & 0b00000111) + 1);
+        if try!(r.skip_exact(size)) != size {
+            return Err(unexpected_eof!(on_eof()));
+        }
+    }
+    Ok(())
+}
+
+/// Extracts the XMP packet from a GIF stream.
+///
+/// GIF can't store EXIF data and ICC profiles in a standard way, so only the `xmp` field of
+/// the result is filled. The whole stream is read, since the XMP extension may follow any
+/// frame.
+pub fn extract_metadata<R: ?Sized + BufRead>(r: &mut R) -> Result<EmbeddedMetadata> {
+    let md = try!(Metadata::load(r));
+    Ok(EmbeddedMetadata { xmp: md.xmp, ..EmbeddedMetadata::default() })
+}
+
+/// Copies a GIF stream while setting the loop count of the animation, returning the previous
+/// loop count.
+///
+/// The loop count is stored in the NETSCAPE2.0 application extension, where zero means
+/// infinite looping and any other value is the number of repetitions after the first play,
+/// the same as `Metadata::loop_count`. Existing looping extensions are modified in place;
+/// if there is none before the first frame, a new one is inserted right after the global
+/// color table, and GIF87a streams are upgraded to GIF89a since they can't have extensions.
+/// All other blocks, including frame data, and any data following the trailer are copied
+/// unchanged.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::{BufReader, BufWriter};
+/// use immeta::formats::gif;
+///
+/// let mut r = BufReader::new(File::open("drop.gif").unwrap());
+/// let mut w = BufWriter::new(File::create("drop-looped.gif").unwrap());
+/// gif::set_loop_count(&mut r, &mut w, 0).unwrap();
+/// ```
+pub fn set_loop_count<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W,
+                                                               loop_count: u16) -> Result<Option<u16>> {
+    let mut header = [0u8; 13];
+    try!(r.read_exact(&mut header).map_err(if_eof!(std, "when reading GIF header")));
+    if &header[..3] != b"GIF" || Version::from_bytes(&header[3..6]).is_none() {
+        return Err(invalid_format!("invalid GIF signature: {:?}", &header[..6]));
+    }
+
+    // blocks preceding the first frame are buffered, so that the looping extension can be
+    // inserted if none of them is one
+    let mut prefix = header.to_vec();
+    try!(copy_color_table(r, &mut prefix, header[10], || "when reading global color table".into()));
+    let screen_end = prefix.len();
+    let mut prefix = Some(prefix);
+    let mut previous = None;
+    let mut found = false;
+
+    loop {
+        let separator = try_if_eof!(r.read_u8(), "when reading block separator");
+        if separator == 0x2c || separator == 0x3b {
+            if let Some(mut prefix) = prefix.take() {
+                if !found {
+                    log_debug!("inserting NETSCAPE2.0 extension with loop count {}", loop_count);
+                    if &prefix[3..6] == b"87a" {
+                        prefix[3..6].copy_from_slice(b"89a");
+                    }
+                    try!(w.write_all(&prefix[..screen_end]));
+                    try!(w.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01"));
+                    try!(w.write_all(&[loop_count as u8, (loop_count >> 8) as u8, 0]));
+                    try!(w.write_all(&prefix[screen_end..]));
+                } else {
+                    try!(w.write_all(&prefix));
+                }
+            }
+        }
+
+        match separator {
+            0x2c => {
+                let mut descriptor = [0u8; 9];
+                try!(r.read_exact(&mut descriptor).map_err(if_eof!(std, "when reading image descriptor")));
+                try!(w.write_all(&[separator]));
+                try!(w.write_all(&descriptor));
+                try!(copy_color_table(r, w, descriptor[8], || "when reading local color table".into()));
+                let code_size = try_if_eof!(r.read_u8(), "when reading LZW minimum code size");
+                try!(w.write_all(&[code_size]));
+                try!(copy_blocks(r, w, || "when reading image data".into()));
+            }
+            0x21 => {
+                let label = try_if_eof!(r.read_u8(), "when reading extension label");
+                let looping = match prefix {
+                    Some(ref mut prefix) => try!(copy_extension(r, prefix, label, loop_count, &mut previous)),
+                    None => try!(copy_extension(r, w, label, loop_count, &mut previous))
+                };
+                found |= looping;
+            }
+            0x3b => {
+                try!(w.write_all(&[separator]));
+                try!(io::copy(r, w));
+                return Ok(previous);
+            }
+            _ => return Err(invalid_format!("unknown block type: 0x{:X}", separator))
+        }
+    }
+}
+
+/// Copies an extension block following its label, setting the loop count if it is a looping
+/// extension; returns `true` if it is.
+fn copy_extension<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W, label: u8, loop_count: u16,
+                                                          previous: &mut Option<u16>) -> Result<bool> {
+    try!(w.write_all(&[0x21, label]));
+    if label != 0xff {
+        try!(copy_blocks(r, w, || "when reading extension data".into()));
+        return Ok(false);
+    }
+
+    let on_eof = || "when reading application extension".into();
+    let mut data = try!(read_blocks_raw(r, &on_eof));
+    // the first sub-block contains the application identifier and authentication code
+    let looping = data.len() >= 12 && data[0] == 0x0b &&
+        ((&data[1..9] == b"NETSCAPE" && &data[9..12] == b"2.0") ||
+         (&data[1..9] == b"ANIMEXTS" && &data[9..12] == b"1.0"));
+    let mut patched = false;
+    if looping {
+        let mut pos = 12;
+        while pos < data.len() {
+            let n = data[pos] as usize;
+            // looping sub-block: size 3, identifier 1, loop count
+            if n >= 3 && data[pos + 1] == 1 {
+                *previous = Some(data[pos + 2] as u16 | (data[pos + 3] as u16) << 8);
+                data[pos + 2] = loop_count as u8;
+                data[pos + 3] = (loop_count >> 8) as u8;
+                patched = true;
+                break;
+            }
+            pos += n + 1;
+        }
+    }
+    try!(w.write_all(&data));
+    try!(w.write_all(&[0]));
+    if data.len() >= 12 && &data[..12] == b"\x0bXMP DataXMP" {
+        if let Some((_, true)) = find_xmp_trailer(&data[12..]) {
+            let terminator = try_if_eof!(r.read_u8(), on_eof());
+            try!(w.write_all(&[terminator]));
+        }
+    }
+    Ok(patched)
+}
+
+/// Copies data sub-blocks up to and including the block terminator.
+fn copy_blocks<R: ?Sized + BufRead, W: ?Sized + Write, F>(r: &mut R, w: &mut W, on_eof: F) -> Result<()>
+    where F: Fn() -> Cow<'static, str>
+{
+    loop {
+        let n = try_if_eof!(r.read_u8(), on_eof());
+        try!(w.write_all(&[n]));
+        if n == 0 {
+            return Ok(());
+        }
+        if try!(io::copy(&mut r.take(n as u64), w)) != n as u64 {
+            return Err(unexpected_eof!(on_eof()));
+        }
+    }
+}
+
+/// Copies a color table described by the given packed flags of a screen or image descriptor.
+fn copy_color_table<R: ?Sized + BufRead, W: ?Sized + Write, F>(r: &mut R, w: &mut W, packed_flags: u8,
+                                                               on_eof: F) -> Result<()>
+    where F: Fn() -> Cow<'static, str>
+{
+    if packed_flags & 0b10000000 != 0 {
+        let size = 3u64 << ((packed_flags & 0b00000111) + 1);
+        if try!(io::copy(&mut r.take(size), w)) != size {
+            return Err(unexpected_eof!(on_eof()));
+        }
+    }
+    Ok(())
+}
+
+/// Kind of a structural violation found by `validate()`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ViolationKind {
+    /// A block or its sub-block chain is cut off by the end of the stream.
+    TruncatedBlock,
+    /// The stream ends without the trailer byte.
+    MissingTrailer,
+    /// The stream contains data after the trailer byte.
+    DataAfterTrailer,
+    /// A block starts with an unknown separator byte; the rest of the stream can't be read.
+    UnknownBlock,
+    /// An extension block has an unknown label.
+    UnknownExtension,
+    /// An extension block has an invalid size of its fixed part.
+    InvalidBlockSize,
+    /// A frame rectangle exceeds the logical screen.
+    FrameOutsideScreen,
+    /// A frame has zero width or height.
+    EmptyFrame,
+    /// Neither the frame nor the image has a color table.
+    MissingColorTable,
+    /// LZW minimum code size of a frame is out of the range supported by decoders.
+    InvalidLzwCodeSize,
+}
+
+/// A structural violation found by `validate()`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Violation {
+    /// Kind of the violation.
+    pub kind: ViolationKind,
+    /// Offset of the offending block or, if there is no such block, of the place where
+    /// the problem is found, from the beginning of the GIF stream.
+    pub offset: u64,
+}
+
+impl ViolationKind {
+    /// Returns a short human-readable description of the violation.
+    pub fn description(self) -> &'static str {
+        match self {
+            ViolationKind::TruncatedBlock => "truncated block",
+            ViolationKind::MissingTrailer => "no trailer",
+            ViolationKind::DataAfterTrailer => "data after trailer",
+            ViolationKind::UnknownBlock => "unknown block type",
+            ViolationKind::UnknownExtension => "unknown extension type",
+            ViolationKind::InvalidBlockSize => "invalid extension block size",
+            ViolationKind::FrameOutsideScreen => "frame exceeds logical screen",
+            ViolationKind::EmptyFrame => "frame is empty",
+            ViolationKind::MissingColorTable => "no color table for frame",
+            ViolationKind::InvalidLzwCodeSize => "invalid LZW minimum code size",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = self.kind.description();
+        write!(f, "{} (at offset {})", description, self.offset)
+    }
+}
+
+/// Checks the block structure of a GIF stream without decoding it.
+///
+/// The stream is read until the end. All found violations are returned in the order of their
+/// offsets; an empty vector means that the structure is valid. Violations which make reading
+/// the rest of the stream impossible, like a truncated or unknown block, are reported last.
+/// An error is returned if the stream does not have a valid GIF header or if an I/O error
+/// occurs.
+pub fn validate<R: BufRead>(r: R) -> Result<Vec<Violation>> {
+    let mut r = Tracked::new(r);
+    let mut violations = Vec::new();
+
+    let mut signature = [0u8; 6];
+    try!(r.read_exact(&mut signature).map_err(if_eof!(std, "when reading GIF signature")));
+    if &signature[..3] != b"GIF" || Version::from_bytes(&signature[3..]).is_none() {
+        return Err(invalid_format!("invalid GIF signature: {:?}", signature));
+    }
+    let screen_width = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading logical screen width") as u32;
+    let screen_height = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading logical screen height") as u32;
+    let screen_flags = try_if_eof!(r.read_u8(), "when reading logical screen descriptor flags");
+    try!(r.read_exact(&mut [0u8; 2]).map_err(if_eof!(std, "when reading logical screen descriptor")));
+    let global_color_table = screen_flags & 0b10000000 != 0;
+
+    let mut offset = r.position;
+    let result = skip_color_table(&mut r, screen_flags, || "when reading global color table".into())
+        .and_then(|_| loop {
+            offset = r.position;
+            if try!(r.fill_buf()).is_empty() {
+                violations.push(Violation { kind: ViolationKind::MissingTrailer, offset: offset });
+                return Ok(());
+            }
+            let separator = try!(r.read_u8());
+            let mut violation = |kind| violations.push(Violation { kind: kind, offset: offset });
+            match separator {
+                0x2c => {
+                    let left = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading frame left offset") as u32;
+                    let top = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading frame top offset") as u32;
+                    let width = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading frame width") as u32;
+                    let height = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading frame height") as u32;
+                    let flags = try_if_eof!(r.read_u8(), "when reading frame flags");
+                    if width == 0 || height == 0 {
+                        violation(ViolationKind::EmptyFrame);
+                    }
+                    if left + width > screen_width || top + height > screen_height {
+                        violation(ViolationKind::FrameOutsideScreen);
+                    }
+                    if !global_color_table && flags & 0b10000000 == 0 {
+                        violation(ViolationKind::MissingColorTable);
+                    }
+                    try!(skip_color_table(&mut r, flags, || "when reading local color table".into()));
+                    let code_size = try_if_eof!(r.read_u8(), "when reading LZW minimum code size");
+                    if code_size < 2 || code_size > 11 {
+                        violation(ViolationKind::InvalidLzwCodeSize);
+                    }
+                    try!(skip_blocks(&mut r, || "when reading image data".into()));
+                }
                 0x21 => {
-                    let label = try_if_eof!(r.read_u8(), "when reading label of block {}", index);
-                    match label {
-                        0x01 => Block::PlainTextExtension(try!(PlainTextExtension::load(index, r))),
-                        0xf9 => Block::GraphicControlExtension(try!(GraphicControlExtension::load(index, r))),
-                        0xfe => Block::CommentExtension(try!(CommentExtension::load(index, r))),
-                        0xff => Block::ApplicationExtension(try!(ApplicationExtension::load(index, r))),
-                        _ => return Err(invalid_format!("unknown extension type of block {}: 0x{:X}", index, label))
+                    let label = try_if_eof!(r.read_u8(), "when reading extension label");
+                    let expected_size = match label {
+                        0x01 => Some(0x0c),
+                        0xf9 => Some(0x04),
+                        0xff => Some(0x0b),
+                        0xfe => None,
+                        _ => {
+                            violation(ViolationKind::UnknownExtension);
+                            None
+                        }
+                    };
+                    if let Some(expected_size) = expected_size {
+                        let size = try!(r.fill_buf()).first().cloned();
+                        if size.is_some() && size != Some(expected_size) {
+                            violation(ViolationKind::InvalidBlockSize);
+                        }
                     }
-                },
-                0x3b => break,
-                _ => return Err(invalid_format!("unknown block type of block {}: 0x{:X}", index, separator))
-            };
-            blocks.push(block);
-            index += 1;
+                    try!(skip_blocks(&mut r, || "when reading extension data".into()));
+                }
+                0x3b => return Ok(()),
+                _ => {
+                    violation(ViolationKind::UnknownBlock);
+                    return Ok(());
+                }
+            }
+        });
+
+    match result {
+        Ok(()) => {}
+        Err(Error::UnexpectedEndOfFile(_)) => {
+            violations.push(Violation { kind: ViolationKind::TruncatedBlock, offset: offset });
         }
+        Err(e) => return Err(e)
+    }
 
-        Ok(Metadata {
-            version: version,
+    let ended = violations.last().map(|v| match v.kind {
+        ViolationKind::TruncatedBlock | ViolationKind::MissingTrailer | ViolationKind::UnknownBlock => true,
+        _ => false
+    }).unwrap_or(false);
+    if !ended && !try!(r.fill_buf()).is_empty() {
+        violations.push(Violation { kind: ViolationKind::DataAfterTrailer, offset: r.position });
+    }
 
-            dimensions: (width, height).into(),
+    Ok(violations)
+}
 
-            global_color_table: if global_color_table {
-                Some(ColorTable {
-                    size: global_color_table_size,
-                    sorted: global_color_table_sorted
-                })
-            } else {
-                None
-            },
+/// Reads logical screen dimensions from a GIF stream, stopping right after them.
+///
+/// This reads exactly 10 bytes, unlike `Metadata::load()` which reads the whole stream.
+pub fn read_dimensions<R: ?Sized + BufRead>(r: &mut R) -> Result<Dimensions> {
+    let mut signature = [0u8; 6];
+    try!(r.read_exact(&mut signature).map_err(if_eof!(std, "when reading GIF signature")));
+    if &signature[..3] != b"GIF" || Version::from_bytes(&signature[3..]).is_none() {
+        return Err(invalid_format!("invalid GIF signature: {:?}", signature));
+    }
 
-            color_resolution: 1u16 << (color_resolution + 1),
+    let width = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading logical width");
+    let height = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading logical height");
+    Ok((width, height).into())
+}
 
-            background_color_index: background_color_index,
-            pixel_aspect_ratio: pixel_aspect_ratio,
+/// Reads the GIF header and the logical screen descriptor, stopping right after them.
+///
+/// This reads exactly 13 bytes, so it is a cheap way to get the version, pixel aspect ratio
+/// and background color of an image with many frames. The global color table is not read.
+pub fn read_screen_descriptor<R: ?Sized + BufRead>(r: &mut R) -> Result<ScreenDescriptor> {
+    let mut header = Vec::new();
+    try!(r.read_up_to(13, &mut header));
+    parse_screen_descriptor(&header)
+}
 
-            blocks: blocks
-        })
+fn parse_screen_descriptor(mut r: &[u8]) -> Result<ScreenDescriptor> {
+    let mut signature = [0u8; 6];
+    try!(r.read_exact(&mut signature).map_err(if_eof!(std, "when reading GIF signature")));
+    if &signature[..3] != b"GIF" {
+        return Err(invalid_format!("invalid GIF signature: {:?}", signature));
+    }
+    let version = try!(Version::from_bytes(&signature[3..])
+        .ok_or(invalid_format!("invalid GIF version: {:?}", &signature[3..])));
+
+    let width = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading logical width");
+    let height = try_if_eof!(r.read_u16::<LittleEndian>(), "when reading logical height");
+
+    let packed_flags = try_if_eof!(r.read_u8(), "when reading global flags");
+
+    let global_color_table =        (packed_flags & 0b10000000) > 0;
+    let color_resolution =          (packed_flags & 0b01110000) >> 4;
+    let global_color_table_sorted = (packed_flags & 0b00001000) > 0;
+    let global_color_table_size_p = (packed_flags & 0b00000111) >> 0;
+
+    let background_color_index = try_if_eof!(r.read_u8(), "when reading background color index");
+    let pixel_aspect_ratio = try_if_eof!(r.read_u8(), "when reading pixel aspect ration");
+
+    Ok(ScreenDescriptor {
+        version: version,
+        dimensions: (width, height).into(),
+        global_color_table: if global_color_table {
+            Some(ColorTable {
+                size: 1u16 << (global_color_table_size_p + 1),
+                sorted: global_color_table_sorted
+            })
+        } else {
+            None
+        },
+        color_resolution: 1u16 << (color_resolution + 1),
+        background_color_index: background_color_index,
+        pixel_aspect_ratio: pixel_aspect_ratio,
+    })
+}
+
+/// Parses GIF metadata directly from an in-memory buffer.
+///
+/// This is equivalent to `Metadata::load_from_buf()`. Blocks are located in the buffer
+/// directly and parsed in place, without going through a reader or any intermediate
+/// buffers; `Metadata::load()` parses blocks read from a stream with the same code.
+///
+/// This function can be used as a fuzz target; see `immeta::fuzz`.
+pub fn parse_bytes(data: &[u8]) -> Result<Metadata> {
+    let mut md = Metadata::new(try!(parse_screen_descriptor(data)));
+    let mut pos = 13;
+    if let Some(ref table) = md.global_color_table {
+        pos += table.size as usize * 3;
+        if pos > data.len() {
+            return Err(unexpected_eof!("when reading global color table"));
+        }
+    }
+
+    loop {
+        let index = md.blocks.len();
+        let separator = try!(data.get(pos).cloned()
+            .ok_or_else(|| unexpected_eof!("when reading separator of block {}", index)));
+        pos += 1;
+        log_trace!("GIF block {}, separator 0x{:02X}", index, separator);
+        let block = match separator {
+            0x2c => {
+                let descriptor = try!(ImageDescriptor::parse(index, &data[pos..cmp::min(pos + 9, data.len())]));
+                pos += 9;
+                if let Some(ref table) = descriptor.local_color_table {
+                    pos += table.size as usize * 3;
+                    if pos > data.len() {
+                        return Err(unexpected_eof!("when reading color table of image block {}", index));
+                    }
+                }
+                if pos >= data.len() {
+                    return Err(unexpected_eof!("when reading LZW minimum code size of image block {}", index));
+                }
+                pos += 1;
+                pos += try!(sub_blocks_len(&data[pos..])
+                    .ok_or_else(|| unexpected_eof!("when reading image data of image block {}", index)));
+                Block::ImageDescriptor(descriptor)
+            }
+            0x21 => {
+                let label = try!(data.get(pos).cloned()
+                    .ok_or_else(|| unexpected_eof!("when reading label of block {}", index)));
+                pos += 1;
+                try!(check_extension_label(index, label));
+                let n = try!(data.get(pos).cloned()
+                    .ok_or_else(|| unexpected_eof!(extension_eof(index, label, true))));
+                let first = &data[pos..cmp::min(pos + 1 + n as usize, data.len())];
+                pos += 1 + n as usize;
+                let kind = try!(Metadata::extension_data(index, label, first));
+                let mut rest: &[u8] = &[];
+                if n != 0 {
+                    let on_eof = || unexpected_eof!(extension_eof(index, label, false));
+                    let len = try!(data.get(pos..).and_then(sub_blocks_len).ok_or_else(&on_eof));
+                    if kind != ExtensionData::Skip {
+                        // the block terminator is not a part of the data
+                        rest = &data[pos..pos + len - 1];
+                    }
+                    pos += len;
+                    if kind == ExtensionData::Xmp && try!(xmp_packet(rest)).1 {
+                        if pos >= data.len() {
+                            return Err(on_eof());
+                        }
+                        pos += 1;
+                    }
+                }
+                try!(md.parse_extension(index, label, first, rest, None))
+            }
+            0x3b => break,
+            _ => return Err(invalid_format!("unknown block type of block {}: 0x{:X}", index, separator))
+        };
+        md.blocks.push(block);
+    }
+
+    Ok(md)
+}
+
+impl LoadableMetadata for Metadata {
+    fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Metadata> {
+        Metadata::load_partial(r).map_err(|e| e.error)
+    }
+
+    fn load_from_buf(buf: &[u8]) -> Result<Metadata> {
+        parse_bytes(buf)
+    }
+
+    /// Loads GIF metadata, returning the metadata with the blocks read so far if an error
+    /// occurs after the logical screen descriptor has been read.
+    fn load_partial<R: ?Sized + BufRead>(r: &mut R) -> PartialResult<Metadata> {
+        Metadata::load_impl(r, false)
+    }
+
+    fn load_partial_with_options<R: ?Sized + BufRead + Seek>(r: &mut R, options: &LoadOptions) -> PartialResult<Metadata> {
+        Metadata::load_impl(r, options.lazy_extensions)
+    }
+}
+
+impl Metadata {
+    fn load_impl<R: ?Sized + BufRead>(r: &mut R, lazy: bool) -> PartialResult<Metadata> {
+        let r = &mut Tracked::new(r);
+        let mut md = Metadata::new(try!(read_screen_descriptor(r)));
+
+        if let Some(ref table) = md.global_color_table {
+            let skip_size = table.size as u64 * 3;
+            if try!(r.skip_exact(skip_size)) != skip_size {
+                return Err(unexpected_eof!("when reading global color table").into());
+            }
+        }
+
+        let (mut first, mut rest) = (Vec::new(), Vec::new());
+        loop {
+            let index = md.blocks.len();
+            match read_block(index, r, &mut md, &mut first, &mut rest, lazy) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => return Err(ErrorWithPartial {
+                    error: e,
+                    partial: Some(md)
+                })
+            }
+        }
+
+        Ok(md)
     }
 }
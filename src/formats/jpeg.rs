@@ -1,63 +1,2157 @@
 //! Metadata of JPEG images.
 
-use std::io::BufRead;
+use std::io::{self, Read, BufRead, Write, Seek, SeekFrom, Take, Cursor};
+use std::cmp;
 
 use byteorder::{ReadBytesExt, BigEndian};
 
-use types::{Result, Dimensions};
+use types::{Result, Error, PartialResult, ErrorWithPartial, Completeness, Dimensions, ByteRange};
 use traits::LoadableMetadata;
-use utils::BufReadExt;
+use loader::LoadOptions;
+use utils::{self, BufReadExt, Tracked, SubReader};
+use common::tiff::{TiffReader, entry_types};
+use common::xmp::{self, Xmp};
+use common::flir::{self, FlirMetadata};
+use common::exif::{self, ExifData};
+use transplant::EmbeddedMetadata;
 
-/// Represents metadata of a JPEG image.
+/// JPEG coding process, as defined by the start of frame (SOFn) marker.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CodingProcess {
+    /// Baseline sequential DCT (SOF0).
+    Baseline,
+    /// Extended sequential DCT (SOF1, SOF5, SOF9, SOF13).
+    ExtendedSequential,
+    /// Progressive DCT (SOF2, SOF6, SOF10, SOF14).
+    Progressive,
+    /// Lossless sequential (SOF3, SOF7, SOF11, SOF15).
+    Lossless
+}
+
+/// Type of a JPEG frame, i.e. the particular SOFn marker which starts it.
+///
+/// The frame type is fully determined by the coding process, the entropy coding method
+/// and whether the frame is differential; see `Metadata::frame_type()`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FrameType {
+    /// SOF0, baseline DCT.
+    Sof0,
+    /// SOF1, extended sequential DCT, Huffman coding.
+    Sof1,
+    /// SOF2, progressive DCT, Huffman coding.
+    Sof2,
+    /// SOF3, lossless (sequential), Huffman coding.
+    Sof3,
+    /// SOF5, differential sequential DCT, Huffman coding.
+    Sof5,
+    /// SOF6, differential progressive DCT, Huffman coding.
+    Sof6,
+    /// SOF7, differential lossless (sequential), Huffman coding.
+    Sof7,
+    /// SOF9, extended sequential DCT, arithmetic coding.
+    Sof9,
+    /// SOF10, progressive DCT, arithmetic coding.
+    Sof10,
+    /// SOF11, lossless (sequential), arithmetic coding.
+    Sof11,
+    /// SOF13, differential sequential DCT, arithmetic coding.
+    Sof13,
+    /// SOF14, differential progressive DCT, arithmetic coding.
+    Sof14,
+    /// SOF15, differential lossless (sequential), arithmetic coding.
+    Sof15
+}
+
+impl FrameType {
+    /// Returns the frame type corresponding to the given marker type, e.g. `0xC2` for SOF2.
+    ///
+    /// Returns `None` if the marker is not a start of frame marker.
+    pub fn from_marker(marker_type: u8) -> Option<FrameType> {
+        match marker_type {
+            0xc0 => Some(FrameType::Sof0),
+            0xc1 => Some(FrameType::Sof1),
+            0xc2 => Some(FrameType::Sof2),
+            0xc3 => Some(FrameType::Sof3),
+            0xc5 => Some(FrameType::Sof5),
+            0xc6 => Some(FrameType::Sof6),
+            0xc7 => Some(FrameType::Sof7),
+            0xc9 => Some(FrameType::Sof9),
+            0xca => Some(FrameType::Sof10),
+            0xcb => Some(FrameType::Sof11),
+            0xcd => Some(FrameType::Sof13),
+            0xce => Some(FrameType::Sof14),
+            0xcf => Some(FrameType::Sof15),
+            _ => None
+        }
+    }
+
+    /// Returns the marker type of this frame type, e.g. `0xC2` for SOF2.
+    pub fn marker(self) -> u8 {
+        match self {
+            FrameType::Sof0 => 0xc0,
+            FrameType::Sof1 => 0xc1,
+            FrameType::Sof2 => 0xc2,
+            FrameType::Sof3 => 0xc3,
+            FrameType::Sof5 => 0xc5,
+            FrameType::Sof6 => 0xc6,
+            FrameType::Sof7 => 0xc7,
+            FrameType::Sof9 => 0xc9,
+            FrameType::Sof10 => 0xca,
+            FrameType::Sof11 => 0xcb,
+            FrameType::Sof13 => 0xcd,
+            FrameType::Sof14 => 0xce,
+            FrameType::Sof15 => 0xcf,
+        }
+    }
+}
+
+/// Entropy coding method used in a JPEG image.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EntropyCoding {
+    /// Huffman coding.
+    Huffman,
+    /// Arithmetic coding.
+    Arithmetic
+}
+
+/// Color space of a JPEG image.
+///
+/// JPEG files do not store the color space explicitly, so it is inferred from the number of
+/// components, the presence of a JFIF header and the transform flag of an Adobe APP14 segment,
+/// in the same way as libjpeg does.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ColorSpace {
+    /// Single luminance component.
+    Grayscale,
+    /// Luminance and two chrominance components.
+    YCbCr,
+    /// Untransformed red, green and blue components.
+    Rgb,
+    /// Untransformed cyan, magenta, yellow and black components.
+    Cmyk,
+    /// YCbCr-transformed CMY components plus black component.
+    Ycck,
+    /// The color space can't be inferred, e.g. because of an unusual number of components.
+    Unknown
+}
+
+impl ColorSpace {
+    fn infer(components: &[u8], jfif: bool, adobe_transform: Option<u8>) -> ColorSpace {
+        match components.len() {
+            1 => ColorSpace::Grayscale,
+            3 => if jfif {
+                ColorSpace::YCbCr
+            } else {
+                match adobe_transform {
+                    Some(0) => ColorSpace::Rgb,
+                    Some(_) => ColorSpace::YCbCr,
+                    // some encoders mark RGB components with their ASCII names
+                    None if components == b"RGB" => ColorSpace::Rgb,
+                    None => ColorSpace::YCbCr
+                }
+            },
+            4 => match adobe_transform {
+                Some(2) => ColorSpace::Ycck,
+                _ => ColorSpace::Cmyk
+            },
+            _ => ColorSpace::Unknown
+        }
+    }
+}
+
+/// Units of JFIF pixel density.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DensityUnit {
+    /// No units; density values only specify the pixel aspect ratio.
+    AspectRatio,
+    /// Dots per inch.
+    DotsPerInch,
+    /// Dots per centimeter.
+    DotsPerCentimeter
+}
+
+/// Pixel density of a JPEG image, as stored in its JFIF header.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Density {
+    /// Units of density values.
+    pub unit: DensityUnit,
+    /// Horizontal pixel density.
+    pub x: u16,
+    /// Vertical pixel density.
+    pub y: u16,
+}
+
+impl Density {
+    /// Returns horizontal and vertical density in dots per inch.
+    ///
+    /// Returns `None` if the density only specifies the pixel aspect ratio.
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        match self.unit {
+            DensityUnit::AspectRatio => None,
+            DensityUnit::DotsPerInch => Some((self.x as f64, self.y as f64)),
+            DensityUnit::DotsPerCentimeter => Some((self.x as f64 * 2.54, self.y as f64 * 2.54)),
+        }
+    }
+
+    fn parse_jfif(payload: &[u8]) -> Option<Density> {
+        // "JFIF\0", version, units, X density, Y density
+        if payload.len() < 12 {
+            return None;
+        }
+        let unit = match payload[7] {
+            0 => DensityUnit::AspectRatio,
+            1 => DensityUnit::DotsPerInch,
+            2 => DensityUnit::DotsPerCentimeter,
+            _ => return None
+        };
+        Some(Density {
+            unit: unit,
+            x: utils::ByteOrder::Big.read_u16(&payload[8..10]),
+            y: utils::ByteOrder::Big.read_u16(&payload[10..12]),
+        })
+    }
+}
+
+/// A quantization table defined in a DQT segment.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QuantizationTable {
+    /// Table identifier, from 0 to 3.
+    pub id: u8,
+    /// Precision of table values in bits, either 8 or 16.
+    pub precision: u8,
+    /// 64 table values in zig-zag order, as they are stored in the file.
+    pub values: Vec<u16>,
+}
+
+impl QuantizationTable {
+    fn parse_dqt(mut payload: &[u8], tables: &mut Vec<QuantizationTable>) -> Result<()> {
+        while !payload.is_empty() {
+            let (precision, id) = (payload[0] >> 4, payload[0] & 0x0f);
+            let value_size = match precision {
+                0 => 1,
+                1 => 2,
+                _ => return Err(invalid_format!("invalid precision of JPEG quantization table: {}", precision))
+            };
+            if payload.len() < 1 + 64 * value_size {
+                return Err(invalid_format!("JPEG quantization table {} is truncated", id));
+            }
+
+            let values = payload[1..1 + 64 * value_size].chunks(value_size)
+                .map(|v| if value_size == 1 { v[0] as u16 } else { utils::ByteOrder::Big.read_u16(v) })
+                .collect();
+            let table = QuantizationTable {
+                id: id,
+                precision: value_size as u8 * 8,
+                values: values,
+            };
+            // a table may be redefined by a later DQT segment
+            match tables.iter().position(|t| t.id == id) {
+                Some(i) => tables[i] = table,
+                None => tables.push(table)
+            }
+
+            payload = &payload[1 + 64 * value_size..];
+        }
+        Ok(())
+    }
+}
+
+/// Type of an image in a Multi-Picture Format (MPF) file.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MpImageType {
+    /// Baseline MP primary image.
+    BaselinePrimary,
+    /// Large thumbnail of VGA resolution.
+    LargeThumbnailVga,
+    /// Large thumbnail of Full HD resolution.
+    LargeThumbnailFullHd,
+    /// Multi-frame panorama image.
+    Panorama,
+    /// Disparity image, e.g. one of a stereo pair.
+    Disparity,
+    /// Multi-angle image.
+    MultiAngle,
+    /// Undefined type; for example, HDR gain maps are stored with this type.
+    Undefined,
+    /// Unknown type code.
+    Unknown(u32)
+}
+
+impl From<u32> for MpImageType {
+    fn from(n: u32) -> MpImageType {
+        match n {
+            0x030000 => MpImageType::BaselinePrimary,
+            0x010001 => MpImageType::LargeThumbnailVga,
+            0x010002 => MpImageType::LargeThumbnailFullHd,
+            0x020001 => MpImageType::Panorama,
+            0x020002 => MpImageType::Disparity,
+            0x020003 => MpImageType::MultiAngle,
+            0x000000 => MpImageType::Undefined,
+            n => MpImageType::Unknown(n),
+        }
+    }
+}
+
+/// An entry of the Multi-Picture Format (MPF) index, describing one of the images stored
+/// in the file.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MpImage {
+    /// Type of the image.
+    pub image_type: MpImageType,
+    /// Image data format; 0 means JPEG.
+    pub format: u8,
+    /// Whether this image is the representative image of the file.
+    pub representative: bool,
+    /// Whether this image is a parent of dependent images.
+    pub dependent_parent: bool,
+    /// Whether this image is dependent on another image.
+    pub dependent_child: bool,
+    /// Size of the image data in bytes.
+    pub size: u32,
+    /// Offset of the image data from the beginning of the stream.
+    pub offset: u64,
+    /// Numbers (starting from 1) of the entries for dependent images, or 0 if absent.
+    pub dependent_images: [u16; 2],
+}
+
+impl MpImage {
+    /// Parses the MP index IFD from APP2 payload starting with "MPF\0".
+    ///
+    /// `header_offset` is the offset of the MP header (i.e. the TIFF header following
+    /// the "MPF\0" identifier) in the stream, and `soi_offset` is the offset of the SOI
+    /// marker of the first image.
+    fn parse_mpf(payload: &[u8], header_offset: u64, soi_offset: u64) -> Result<Vec<MpImage>> {
+        let reader = TiffReader::new(SubReader::new(Cursor::new(payload), 4, payload.len() as u64 - 4));
+        let ifds = try!(reader.ifds());
+        let index_ifd = match (&ifds).into_iter().next() {
+            Some(ifd) => try!(ifd),
+            None => return Ok(Vec::new())
+        };
+
+        let mut entries = Vec::new();
+        for entry in index_ifd {
+            let entry = try!(entry);
+            // MP Entry tag
+            if entry.tag() == 0xb002 {
+                entries = match entry.all_values::<entry_types::Undefined>() {
+                    Some(values) => try!(values),
+                    None => return Err(invalid_format!("invalid type of MP Entry: {:?}", entry.entry_type()))
+                };
+            }
+        }
+
+        // MP Entry values are stored in the byte order of the MP header
+        let byte_order = if payload[4..].starts_with(b"II") { utils::ByteOrder::Little } else { utils::ByteOrder::Big };
+        Ok(entries.chunks(16).filter(|e| e.len() == 16).enumerate().map(|(i, e)| {
+            let attributes = byte_order.read_u32(&e[0..4]);
+            let offset = byte_order.read_u32(&e[8..12]);
+            MpImage {
+                image_type: (attributes & 0x00ff_ffff).into(),
+                format: ((attributes >> 24) & 0x07) as u8,
+                representative: attributes & 0x2000_0000 != 0,
+                dependent_parent: attributes & 0x8000_0000 != 0,
+                dependent_child: attributes & 0x4000_0000 != 0,
+                size: byte_order.read_u32(&e[4..8]),
+                // the first image always starts at the beginning of the file
+                offset: if i == 0 { soi_offset } else { header_offset + offset as u64 },
+                dependent_images: [byte_order.read_u16(&e[12..14]), byte_order.read_u16(&e[14..16])],
+            }
+        }).collect())
+    }
+}
+
+/// Location where a preview image is stored.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PreviewSource {
+    /// Thumbnail in the IFD1 of the EXIF segment.
+    ExifThumbnail,
+    /// Large thumbnail image listed in the MPF index.
+    Mpf,
+    /// JPEG image appended after the end of the main image which is not listed in the MPF index.
+    Trailing
+}
+
+/// A preview JPEG image embedded into a JPEG file.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PreviewImage {
+    /// Where the preview is stored.
+    pub source: PreviewSource,
+    /// Offset of the preview data from the beginning of the stream.
+    pub offset: u64,
+    /// Size of the preview data in bytes.
+    pub size: u64,
+    /// Dimensions of the preview, if its frame header could be read.
+    pub dimensions: Option<Dimensions>,
+}
+
+/// Reads the dimensions from the first frame header of a JPEG stream, consuming the stream
+/// up to and including its EOI marker.
+///
+/// Returns `None` for the dimensions if there is no frame header, and `false` as the second
+/// element if the stream does not end with EOI.
+fn read_image_extent<R: BufRead>(r: R) -> Result<(Option<Dimensions>, bool)> {
+    let mut segments = Segments::new(r);
+    let mut dimensions = None;
+    while let Some(segment) = segments.next() {
+        let mut segment = try!(segment);
+        match segment.marker() {
+            0xd9 => return Ok((dimensions, true)),
+            m if dimensions.is_none() && frame_kind(m).is_some() => {
+                let p = segment.payload();
+                let _ = try_if_eof!(p.read_u8(), "when reading sample precision");
+                let h = try_if_eof!(p.read_u16::<BigEndian>(), "when reading height");
+                let w = try_if_eof!(p.read_u16::<BigEndian>(), "when reading width");
+                dimensions = Some((w, h).into());
+            }
+            _ => {}
+        }
+    }
+    Ok((dimensions, false))
+}
+
+/// Same as `read_image_extent()`, but for an image at the beginning of `data`; also returns
+/// the number of bytes the image occupies.
+fn image_extent(data: &[u8]) -> Result<(Option<Dimensions>, bool, u64)> {
+    let mut segments = SliceSegments::new(data);
+    let mut dimensions = None;
+    while let Some(segment) = segments.next() {
+        let segment = try!(segment);
+        match segment.marker {
+            0xd9 => return Ok((dimensions, true, segments.position() as u64)),
+            m if dimensions.is_none() && frame_kind(m).is_some() => {
+                let mut p = segment.payload;
+                let _ = try_if_eof!(p.read_u8(), "when reading sample precision");
+                let h = try_if_eof!(p.read_u16::<BigEndian>(), "when reading height");
+                let w = try_if_eof!(p.read_u16::<BigEndian>(), "when reading width");
+                dimensions = Some((w, h).into());
+            }
+            _ => {}
+        }
+    }
+    Ok((dimensions, false, segments.position() as u64))
+}
+
+/// Reads image dimensions from a JPEG stream, stopping right after the frame header.
+///
+/// Unlike `Metadata::load()`, which reads the stream up to its end to count scans, this only
+/// reads the segments preceding the frame header, which usually takes no more than several
+/// kilobytes unless the image has large EXIF or ICC data.
+pub fn read_dimensions<R: ?Sized + BufRead>(r: &mut R) -> Result<Dimensions> {
+    read_dimensions_impl(r, 0)
+}
+
+pub(crate) fn read_dimensions_impl<R: ?Sized + BufRead>(r: &mut R, soi_search_limit: u64) -> Result<Dimensions> {
+    let mut segments = Segments::with_soi_search_limit(r, soi_search_limit);
+    while let Some(segment) = segments.next() {
+        let mut segment = try!(segment);
+        match segment.marker() {
+            0xda | 0xd9 => break,
+            m if frame_kind(m).is_some() => {
+                let p = segment.payload();
+                let _ = try_if_eof!(p.read_u8(), "when reading sample precision");
+                let h = try_if_eof!(p.read_u16::<BigEndian>(), "when reading height");
+                let w = try_if_eof!(p.read_u16::<BigEndian>(), "when reading width");
+                return Ok((w, h).into());
+            }
+            _ => {}
+        }
+    }
+    Err(invalid_format!("JPEG frame header not found"))
+}
+
+/// Parses the EXIF segment payload (starting with "Exif\0\0") and returns the location of
+/// the thumbnail image, relative to the beginning of the payload, if it is present.
+fn exif_thumbnail(payload: &[u8]) -> Result<Option<(u64, u64)>> {
+    let reader = TiffReader::new(SubReader::new(Cursor::new(payload), 6, payload.len() as u64 - 6));
+    let ifds = try!(reader.ifds());
+    // the thumbnail is described by IFD1
+    let ifd1 = match (&ifds).into_iter().nth(1) {
+        Some(ifd) => try!(ifd),
+        None => return Ok(None)
+    };
+
+    let (mut offset, mut length) = (None, None);
+    for entry in ifd1 {
+        let entry = try!(entry);
+        let value = match entry.all_values::<entry_types::Long>() {
+            Some(values) => try!(values).first().cloned(),
+            None => None
+        };
+        match entry.tag() {
+            0x0201 => offset = value,  // JPEGInterchangeFormat
+            0x0202 => length = value,  // JPEGInterchangeFormatLength
+            _ => {}
+        }
+    }
+    match (offset, length) {
+        (Some(offset), Some(length)) if length > 0 => Ok(Some((offset as u64 + 6, length as u64))),
+        _ => Ok(None)
+    }
+}
+
+/// Describes an HDR gain map (Ultra HDR or Adobe gain map JPEG) associated with the image.
+///
+/// The base image is stored as a regular JPEG, and the gain map is stored as a secondary
+/// JPEG image listed in the MPF index.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GainMap {
+    /// Version of the gain map metadata (`hdrgm:Version` XMP property), if it is known.
+    pub version: Option<String>,
+    /// Index of the gain map image in `Metadata::mpf_images`, if it is known.
+    pub mpf_index: Option<usize>,
+    /// Dimensions of the gain map image.
+    ///
+    /// They are only known when metadata is loaded from a seekable stream, since the gain map
+    /// image is located after the base image data.
+    pub dimensions: Option<Dimensions>,
+}
+
+/// Maximum length of an application segment identifier.
+const MAX_APP_IDENTIFIER_LEN: usize = 64;
+
+/// Location and identifier of an application (APPn) segment.
 ///
-/// Currently it is very basic and only provides access to image dimensions.
+/// Payloads of application segments are not kept in `Metadata`, since they can be large and
+/// most of them are vendor-specific; they can be read from the original stream with
+/// `read_payload()`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AppSegment {
+    /// Number of the application marker, from 0 for APP0 to 15 for APP15.
+    pub n: u8,
+    /// Offset of the marker from the beginning of the JPEG stream.
+    pub offset: u64,
+    /// Size of the payload, not including the marker and the size field.
+    pub len: u16,
+    /// Identifier of the segment, i.e. the NUL-terminated ASCII string at the beginning of
+    /// the payload, like `Exif` or `ICC_PROFILE`.
+    ///
+    /// It is empty if the payload does not start with such string, e.g. for Ducky APP12
+    /// segments, whose identifier is not NUL-terminated.
+    pub identifier: String,
+}
+
+impl AppSegment {
+    fn new(marker: u8, offset: u64, payload: &[u8]) -> AppSegment {
+        let prefix = &payload[..payload.len().min(MAX_APP_IDENTIFIER_LEN + 1)];
+        let identifier = match prefix.iter().position(|&b| b == 0) {
+            Some(end) if prefix[..end].iter().all(|&b| b >= 0x20 && b < 0x7f) =>
+                String::from_utf8_lossy(&prefix[..end]).into_owned(),
+            _ => String::new()
+        };
+        AppSegment {
+            n: marker - 0xe0,
+            offset: offset,
+            len: payload.len() as u16,
+            identifier: identifier,
+        }
+    }
+
+    /// Returns the offset of the payload from the beginning of the JPEG stream.
+    #[inline]
+    pub fn payload_offset(&self) -> u64 {
+        // marker and payload size precede the payload
+        self.offset + 4
+    }
+
+    /// Reads the payload of the segment from the stream the metadata was loaded from.
+    ///
+    /// The JPEG image must start at the beginning of the stream.
+    pub fn read_payload<R: ?Sized + Read + Seek>(&self, r: &mut R) -> Result<Vec<u8>> {
+        try!(r.seek(SeekFrom::Start(self.payload_offset())));
+        let mut data = Vec::with_capacity(self.len as usize);
+        try!(r.take(self.len as u64).read_to_end(&mut data));
+        if data.len() < self.len as usize {
+            return Err(unexpected_eof!("when reading payload of APP{} segment", self.n));
+        }
+        Ok(data)
+    }
+}
+
+/// Signature of the APP1 segment containing an XMP packet.
+const XMP_SIGNATURE: &'static [u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const XMP_EXTENSION_SIGNATURE: &'static [u8] = b"http://ns.adobe.com/xmp/extension/\0";
+const ICC_SIGNATURE: &'static [u8] = b"ICC_PROFILE\0";
+/// Maximum size of ICC profile data in a single APP2 segment, excluding its header.
+const MAX_ICC_CHUNK_SIZE: usize = 0xffff - 2 - 14;
+
+/// Luminance quantization table from the JPEG specification (Annex K), used by libjpeg
+/// as the base for its quality scaling.
+static STD_LUMINANCE_TABLE: [u16; 64] = [
+    16,  11,  10,  16,  24,  40,  51,  61,
+    12,  12,  14,  19,  26,  58,  60,  55,
+    14,  13,  16,  24,  40,  57,  69,  56,
+    14,  17,  22,  29,  51,  87,  80,  62,
+    18,  22,  37,  56,  68, 109, 103,  77,
+    24,  35,  55,  64,  81, 104, 113,  92,
+    49,  64,  78,  87, 103, 121, 120, 101,
+    72,  92,  95,  98, 112, 100, 103,  99,
+];
+
+/// Represents metadata of a JPEG image.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Metadata {
     /// Image size.
     pub dimensions: Dimensions,
-    // TODO: something else?
+    /// Coding process of the image.
+    pub coding_process: CodingProcess,
+    /// Entropy coding method of the image.
+    pub entropy_coding: EntropyCoding,
+    /// Whether the frame is a part of hierarchical (differential) coding.
+    pub differential: bool,
+    /// Sample precision in bits: 8 or 12 for DCT-based images, from 2 to 16 for lossless ones.
+    pub precision: u8,
+    /// Number of color components in the frame.
+    pub components: u8,
+    /// Inferred color space of the image.
+    pub color_space: ColorSpace,
+    /// Pixel density from the JFIF header, if it is present and valid.
+    pub density: Option<Density>,
+    /// Quantization tables defined before the first scan.
+    pub quantization_tables: Vec<QuantizationTable>,
+    /// Whether a DAC (define arithmetic coding conditioning) segment is present before
+    /// the first scan.
+    pub arithmetic_conditioning: bool,
+    /// Images listed in the Multi-Picture Format (MPF) index, including this one.
+    ///
+    /// Empty if the file does not contain an MPF index.
+    pub mpf_images: Vec<MpImage>,
+    /// Restart interval in MCUs in effect for the first scan, if restart markers are enabled.
+    pub restart_interval: Option<u16>,
+    /// Number of scans (SOS segments) in the image.
+    ///
+    /// Counting scans requires reading the whole image data. If the image is truncated, only
+    /// the scans which start before the end of the data are counted.
+    pub scan_count: u32,
+    /// Preview images embedded into the file.
+    ///
+    /// Previews are looked up in the EXIF thumbnail, large thumbnails listed in the MPF index
+    /// and JPEG images directly following the end of the main image. Vendor-specific
+    /// MakerNote previews are not supported.
+    pub previews: Vec<PreviewImage>,
+    /// FLIR radiometric metadata, if the image was produced by a FLIR thermal camera.
+    pub flir: Option<FlirMetadata>,
+    /// XMP packet from the APP1 segment, if it is present.
+    pub xmp: Option<Xmp>,
+    /// EXIF data from the APP1 segment, if it is present.
+    pub exif: Option<ExifData>,
+    /// Location of the payload of the APP1 segment with XMP packet, without the XMP
+    /// identifier, if it has not been parsed yet.
+    ///
+    /// This is only set when metadata is loaded with `LoadOptions::lazy_extensions` enabled,
+    /// in which case `xmp` stays empty until `load_extensions()` is called.
+    pub pending_xmp: Option<ByteRange>,
+    /// Location of the payload of the APP1 segment with EXIF data, including the `Exif`
+    /// identifier, if it has not been parsed yet.
+    ///
+    /// This is only set when metadata is loaded with `LoadOptions::lazy_extensions` enabled,
+    /// in which case `exif` and the EXIF thumbnail in `previews` stay empty until
+    /// `load_extensions()` is called.
+    pub pending_exif: Option<ByteRange>,
+    /// Information about the HDR gain map, if the image has one.
+    ///
+    /// If parsing of XMP is deferred, a gain map declared only in the XMP packet of the main
+    /// image is found by `load_extensions()`.
+    pub gain_map: Option<GainMap>,
+    /// Application (APPn) segments preceding the first scan, in the order of appearance.
+    ///
+    /// See also `app_segments()` and `app_segments_with_identifier()` methods.
+    pub application_segments: Vec<AppSegment>,
 }
 
-impl LoadableMetadata for Metadata {
-    fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Metadata> {
-        loop {
-            if try!(r.skip_until(0xff)) == 0 {
-                return Err(unexpected_eof!("when searching for a marker"));
+impl Metadata {
+    /// Returns an estimate of the size in bytes of the decoded image.
+    ///
+    /// Decoded JPEG images are assumed to have one channel per component, with samples of more
+    /// than 8 bits stored in two bytes.
+    pub fn estimated_decoded_size(&self) -> u64 {
+        let bytes_per_channel = if self.precision > 8 { 2 } else { 1 };
+        utils::decoded_size(self.dimensions, self.components as u64, bytes_per_channel, 1)
+    }
+
+    /// Returns the APPn segments with the given marker number, e.g. 4 for APP4.
+    pub fn app_segments(&self, n: u8) -> Vec<&AppSegment> {
+        self.application_segments.iter().filter(|s| s.n == n).collect()
+    }
+
+    /// Returns the application segments with the given identifier, e.g. `ICC_PROFILE`.
+    pub fn app_segments_with_identifier(&self, identifier: &str) -> Vec<&AppSegment> {
+        self.application_segments.iter().filter(|s| s.identifier == identifier).collect()
+    }
+
+    /// Returns `true` if the image uses progressive coding process.
+    #[inline]
+    pub fn is_progressive(&self) -> bool {
+        self.coding_process == CodingProcess::Progressive
+    }
+
+    /// Returns `true` if the image uses lossless coding process.
+    #[inline]
+    pub fn is_lossless(&self) -> bool {
+        self.coding_process == CodingProcess::Lossless
+    }
+
+    /// Returns `true` if the image uses hierarchical coding, i.e. it consists of
+    /// differential frames.
+    #[inline]
+    pub fn is_hierarchical(&self) -> bool {
+        self.differential
+    }
+
+    /// Returns the type of the frame, i.e. which SOFn marker was encountered.
+    pub fn frame_type(&self) -> FrameType {
+        let mut marker = 0xc0 | match self.coding_process {
+            CodingProcess::Baseline => 0,
+            CodingProcess::ExtendedSequential => 1,
+            CodingProcess::Progressive => 2,
+            CodingProcess::Lossless => 3,
+        };
+        if self.differential {
+            marker |= 0x04;
+        }
+        if self.entropy_coding == EntropyCoding::Arithmetic {
+            marker |= 0x08;
+        }
+        // differential and arithmetic-coded frames are never baseline, so they use the
+        // extended sequential process instead
+        if marker & 0x03 == 0 && marker != 0xc0 {
+            marker |= 0x01;
+        }
+        FrameType::from_marker(marker).expect("invalid combination of frame parameters")
+    }
+
+    /// Returns `true` if the image uses arithmetic entropy coding.
+    ///
+    /// Arithmetic coding is indicated either by the SOFn marker or by the presence of a DAC
+    /// segment. Many decoders do not support such images.
+    #[inline]
+    pub fn is_arithmetic_coded(&self) -> bool {
+        self.entropy_coding == EntropyCoding::Arithmetic || self.arithmetic_conditioning
+    }
+
+    /// Returns the preview image with the largest number of pixels, if there are any previews
+    /// with known dimensions.
+    pub fn largest_preview(&self) -> Option<&PreviewImage> {
+        self.previews.iter()
+            .filter(|p| p.dimensions.is_some())
+            .max_by_key(|p| p.dimensions.map(|d| d.width as u64 * d.height as u64))
+    }
+
+    /// Returns `true` if the image contains an HDR gain map. See `GainMap`.
+    #[inline]
+    pub fn has_gain_map(&self) -> bool {
+        self.gain_map.is_some()
+    }
+
+    /// Returns the index of the first MPF image which may contain a gain map, that is,
+    /// a secondary image of undefined type.
+    fn gain_map_candidate(&self) -> Option<usize> {
+        self.mpf_images.iter().skip(1)
+            .position(|image| image.image_type == MpImageType::Undefined)
+            .map(|i| i + 1)
+    }
+
+    /// Returns the gain map declared in the XMP packet of this image, if there is one.
+    fn declared_gain_map(&self) -> Option<GainMap> {
+        self.xmp.as_ref()
+            .filter(|xmp| xmp.has_namespace(xmp::NS_HDR_GAIN_MAP))
+            .map(|xmp| GainMap {
+                version: xmp.property("hdrgm:Version").map(|v| v.to_owned()),
+                mpf_index: self.gain_map_candidate(),
+                dimensions: None,
+            })
+    }
+
+    /// Parses EXIF data and XMP packet whose parsing was deferred.
+    ///
+    /// The provided stream must contain the JPEG image this metadata was loaded from, starting
+    /// at its beginning. Invalid EXIF data and XMP packets are skipped, like they are when
+    /// loading metadata eagerly. If the XMP packet declares a gain map, `gain_map` is updated,
+    /// reading the gain map image from the stream if necessary.
+    pub fn load_extensions<R: ?Sized + Read + Seek>(&mut self, r: &mut R) -> Result<()> {
+        if let Some(location) = self.pending_exif {
+            try!(r.seek(SeekFrom::Start(location.offset)));
+            let payload = try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(r, location.len),
+                                      "when reading payload of JPEG marker 0xE1");
+            match exif_thumbnail(&payload) {
+                Ok(Some((offset, size))) if offset + size <= payload.len() as u64 => {
+                    let data = &payload[offset as usize..(offset + size) as usize];
+                    self.previews.insert(0, PreviewImage {
+                        source: PreviewSource::ExifThumbnail,
+                        offset: location.offset + offset,
+                        size: size,
+                        dimensions: image_extent(data).ok().and_then(|(d, _, _)| d),
+                    });
+                }
+                Ok(_) => {}
+                Err(e) => log_debug!("ignoring invalid EXIF segment: {}", e)
             }
-            
-            let marker_type = try_if_eof!(r.read_u8(), "when reading marker type");
-            if marker_type == 0 { continue; }  // skip "stuffed" byte
+            match ExifData::new(payload[6..].to_vec(), location.offset + 6) {
+                Ok(data) => self.exif = Some(data),
+                Err(e) => log_debug!("ignoring invalid EXIF data: {}", e)
+            }
+            self.pending_exif = None;
+        }
 
-            let has_size = match marker_type {
-                0xd0...0xd9 => false,
-                _ => true
-            };
+        if let Some(location) = self.pending_xmp {
+            try!(r.seek(SeekFrom::Start(location.offset)));
+            let data = try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(r, location.len),
+                                   "when reading payload of JPEG marker 0xE1");
+            match Xmp::from_bytes(&data) {
+                Ok(xmp) => self.xmp = Some(xmp),
+                Err(e) => log_debug!("ignoring invalid XMP packet: {}", e)
+            }
+            self.pending_xmp = None;
+
+            if let Some(mut gain_map) = self.declared_gain_map() {
+                gain_map.dimensions = self.gain_map.as_ref().and_then(|g| g.dimensions);
+                let dimensions_known = gain_map.dimensions.is_some();
+                self.gain_map = Some(gain_map);
+                if !dimensions_known {
+                    try!(self.load_gain_map(&mut io::BufReader::new(&mut *r), 0));
+                }
+            }
+        }
+        Ok(())
+    }
 
-            let size = if has_size {
-                try_if_eof!(r.read_u16::<BigEndian>(), "when reading marker payload size") - 2
-            } else { 0 };
+    /// Looks for the gain map image in the stream and updates `gain_map` accordingly.
+    ///
+    /// `start` is the offset of the JPEG stream in the reader.
+    fn load_gain_map<R: ?Sized + BufRead + Seek>(&mut self, r: &mut R, start: u64) -> Result<()> {
+        let index = match self.gain_map_candidate() {
+            Some(index) => index,
+            None => return Ok(())
+        };
 
-            let dimensions = match marker_type {
-                0xc0 | 0xc2 => {  // maybe others?
-                    // skip one byte
-                    let _ = try_if_eof!(r.read_u8(), "when skipping to dimensions data");
-                    let h = try_if_eof!(r.read_u16::<BigEndian>(), "when reading height");
-                    let w = try_if_eof!(r.read_u16::<BigEndian>(), "when reading width");
-                    Some((w, h))
+        // the image may be unreachable in a forward-only stream
+        if let Err(e) = r.seek(SeekFrom::Start(start + self.mpf_images[index].offset)) {
+            return match Error::from(e) {
+                e @ Error::LimitExceeded(_) => Err(e),
+                e => {
+                    log_debug!("failed to seek to MPF image {}: {}", index, e);
+                    Ok(())
                 }
-                _ => None
             };
+        }
+        self.apply_gain_map_image(index, <Metadata as LoadableMetadata>::load(r));
+        Ok(())
+    }
 
-            if let Some(dimensions) = dimensions {
-                return Ok(Metadata {
-                    dimensions: dimensions.into()
+    /// Updates `gain_map` with the result of loading the MPF image with the given index.
+    fn apply_gain_map_image(&mut self, index: usize, image: Result<Metadata>) {
+        let image = match image {
+            Ok(image) => image,
+            Err(e) => {
+                log_debug!("failed to load MPF image {}: {}", index, e);
+                return;
+            }
+        };
+
+        // Ultra HDR declares the gain map in the primary image XMP; the Adobe gain map
+        // specification only requires it in the gain map image itself
+        let image_version = image.xmp.as_ref()
+            .filter(|xmp| xmp.has_namespace(xmp::NS_HDR_GAIN_MAP))
+            .map(|xmp| xmp.property("hdrgm:Version").map(|v| v.to_owned()));
+        match self.gain_map {
+            Some(ref mut gain_map) => gain_map.dimensions = Some(image.dimensions),
+            None => if let Some(version) = image_version {
+                self.gain_map = Some(GainMap {
+                    version: version,
+                    mpf_index: Some(index),
+                    dimensions: Some(image.dimensions),
                 });
             }
-            
-            let size = size as u64;
-            if try!(r.skip_exact(size)) != size {
+        }
+    }
+
+    /// Returns horizontal and vertical density of the image in dots per inch, if it is known.
+    ///
+    /// See `Density::dpi()`.
+    #[inline]
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        self.density.and_then(|d| d.dpi())
+    }
+
+    /// Returns an estimate of the quality setting the image was encoded with, on the 1-100
+    /// scale used by libjpeg.
+    ///
+    /// The estimate is obtained by comparing the luminance quantization table (table 0) with
+    /// the standard one, as if the image was produced by libjpeg. It is only an approximation
+    /// for images produced by encoders with custom tables. Returns `None` if there is no
+    /// luminance table.
+    pub fn estimated_quality(&self) -> Option<u8> {
+        let table = match self.quantization_tables.iter().find(|t| t.id == 0) {
+            Some(table) => table,
+            None => return None
+        };
+        let sum: u64 = table.values.iter().map(|&v| v as u64).sum();
+        let std_sum: u64 = STD_LUMINANCE_TABLE.iter().map(|&v| v as u64).sum();
+
+        // libjpeg scales the standard table by `scale` percent, where
+        // scale = 5000 / quality for quality < 50 and scale = 200 - 2 * quality otherwise
+        let scale = sum as f64 * 100.0 / std_sum as f64;
+        let quality = if scale <= 100.0 { (200.0 - scale) / 2.0 } else { 5000.0 / scale };
+        Some(quality.round().max(1.0).min(100.0) as u8)
+    }
+}
+
+/// Decodes the kind of frame from the SOFn marker type.
+///
+/// Returns `None` if the marker is not a start of frame marker.
+fn frame_kind(marker_type: u8) -> Option<(CodingProcess, EntropyCoding, bool)> {
+    FrameType::from_marker(marker_type).map(|_| {
+        let coding_process = match marker_type & 0x03 {
+            0 => CodingProcess::Baseline,
+            1 => CodingProcess::ExtendedSequential,
+            2 => CodingProcess::Progressive,
+            _ => CodingProcess::Lossless
+        };
+        let entropy_coding = if marker_type & 0x08 == 0 {
+            EntropyCoding::Huffman
+        } else {
+            EntropyCoding::Arithmetic
+        };
+        let differential = marker_type & 0x04 != 0;
+        (coding_process, entropy_coding, differential)
+    })
+}
+
+/// A low-level reader of JPEG marker segments.
+///
+/// JPEG stream is a sequence of segments, each starting with a marker. `Segments` reads
+/// these segments one by one, providing access to their payload; the payload which is not
+/// read explicitly is skipped automatically. High-level `Metadata` is loaded with this reader,
+/// but it can also be used directly to access segments this library does not interpret, e.g.
+/// vendor-specific application segments.
+///
+/// Entropy-coded data following SOS segments is skipped, as are restart markers inside it.
+/// Iteration stops after the EOI marker or at the end of the stream.
+///
+/// Since segments borrow the reader, `Segments` can't implement `Iterator`. It should be
+/// used like this instead:
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use immeta::formats::jpeg::Segments;
+///
+/// let mut segments = Segments::new(BufReader::new(File::open("kitty.jpg").unwrap()));
+/// while let Some(segment) = segments.next() {
+///     let segment = segment.unwrap();
+///     println!("marker 0x{:02X} at {}, {} bytes", segment.marker(), segment.offset(), segment.len());
+/// }
+/// ```
+pub struct Segments<R: BufRead> {
+    source: Tracked<R>,
+    payload_end: u64,
+    started: bool,
+    finished: bool,
+    soi_search_limit: u64,
+    soi_offset: u64,
+}
+
+impl<R: BufRead> Segments<R> {
+    /// Creates a new segment reader for a JPEG stream which starts at the current position
+    /// of the provided reader.
+    pub fn new(source: R) -> Segments<R> {
+        Segments {
+            source: Tracked::new(source),
+            payload_end: 0,
+            started: false,
+            finished: false,
+            soi_search_limit: 0,
+            soi_offset: 0,
+        }
+    }
+
+    /// Creates a new segment reader which skips up to `limit` bytes preceding the SOI marker.
+    ///
+    /// Offsets of segments are still counted from the current position of the provided
+    /// reader, and not from the SOI marker.
+    pub fn with_soi_search_limit(source: R, limit: u64) -> Segments<R> {
+        let mut segments = Segments::new(source);
+        segments.soi_search_limit = limit;
+        segments
+    }
+
+    /// Returns the offset of the SOI marker.
+    ///
+    /// It is zero unless the reader was created with `with_soi_search_limit()` and there
+    /// were bytes preceding the SOI marker. Before the first segment is read, returns zero.
+    #[inline]
+    pub fn soi_offset(&self) -> u64 {
+        self.soi_offset
+    }
+
+    /// Returns the number of bytes consumed from the underlying reader so far.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.source.position
+    }
+
+    /// Unwraps this segment reader, returning the underlying reader.
+    ///
+    /// Unread payload of the last segment is not skipped.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.source.inner
+    }
+
+    /// Reads the next segment.
+    ///
+    /// Returns `None` when there are no more segments. The first segment is always the SOI
+    /// marker; if the stream does not start with it, an error is returned.
+    pub fn next(&mut self) -> Option<Result<Segment<R>>> {
+        if self.finished {
+            return None;
+        }
+        match self.read_header() {
+            Ok(Some((offset, marker, len))) => Some(Ok(self.segment(offset, marker, len))),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn read_marker(&mut self) -> Result<Option<(u64, u8)>> {
+        if !self.started {
+            self.started = true;
+            let mut soi = [0u8; 2];
+            try!(self.source.read_exact(&mut soi).map_err(if_eof!(std, "when reading JPEG SOI marker")));
+            while soi != [0xff, 0xd8] {
+                if self.soi_offset >= self.soi_search_limit {
+                    return Err(invalid_format!("invalid JPEG SOI marker: {:?}", soi));
+                }
+                soi[0] = soi[1];
+                soi[1] = try_if_eof!(self.source.read_u8(), "when searching for JPEG SOI marker");
+                self.soi_offset += 1;
+            }
+            if self.soi_offset > 0 {
+                log_debug!("skipped {} bytes before JPEG SOI marker", self.soi_offset);
+            }
+            return Ok(Some((self.soi_offset, 0xd8)));
+        }
+
+        if self.source.position < self.payload_end {
+            let n = self.payload_end - self.source.position;
+            log_trace!("skipping {} bytes of JPEG segment payload", n);
+            if try!(self.source.skip_exact(n)) != n {
                 return Err(unexpected_eof!("when skipping marker payload"));
             }
         }
+
+        loop {
+            if try!(BufReadExt::skip_until(&mut self.source, 0xff)) == 0 {
+                return Ok(None);
+            }
+
+            // any number of 0xFF fill bytes may precede a marker
+            let marker = loop {
+                match self.source.read_u8() {
+                    Ok(0xff) => continue,
+                    Ok(m) => break m,
+                    Err(::byteorder::Error::UnexpectedEOF) => return Ok(None),
+                    Err(e) => return Err(e.into())
+                }
+            };
+
+            match marker {
+                // "stuffed" byte or restart marker inside entropy-coded data
+                0x00 | 0xd0...0xd7 => continue,
+                m => return Ok(Some((self.source.position - 2, m)))
+            }
+        }
+    }
+
+    fn read_header(&mut self) -> Result<Option<(u64, u8, u16)>> {
+        let (offset, marker) = match try!(self.read_marker()) {
+            Some(t) => t,
+            None => return Ok(None)
+        };
+
+        let len = match marker {
+            // SOI, EOI and TEM markers do not have payload
+            0xd8 | 0xd9 | 0x01 => 0,
+            _ => {
+                let len = try_if_eof!(self.source.read_u16::<BigEndian>(), "when reading marker payload size");
+                if len < 2 {
+                    return Err(invalid_format!("invalid payload size of JPEG marker 0x{:02X}: {}", marker, len));
+                }
+                len - 2
+            }
+        };
+        log_trace!("JPEG marker 0x{:02X} at offset {}, payload size {}", marker, offset, len);
+
+        Ok(Some((offset, marker, len)))
+    }
+
+    fn segment(&mut self, offset: u64, marker: u8, len: u16) -> Segment<R> {
+        if marker == 0xd9 {
+            self.finished = true;
+        }
+        self.payload_end = self.source.position + len as u64;
+
+        Segment {
+            marker: marker,
+            offset: offset,
+            len: len,
+            payload: (&mut self.source).take(len as u64),
+        }
+    }
+}
+
+/// A single JPEG marker segment.
+///
+/// Values of this type are obtained with `Segments::next()`.
+pub struct Segment<'a, R: BufRead + 'a> {
+    marker: u8,
+    offset: u64,
+    len: u16,
+    payload: Take<&'a mut Tracked<R>>,
+}
+
+impl<'a, R: BufRead + 'a> Segment<'a, R> {
+    /// Returns the marker type, i.e. the second byte of the marker, e.g. `0xC0` for SOF0.
+    #[inline]
+    pub fn marker(&self) -> u8 { self.marker }
+
+    /// Returns the offset of the marker from the beginning of the JPEG stream.
+    #[inline]
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// Returns the size of the segment payload, not including the marker and the size field.
+    #[inline]
+    pub fn len(&self) -> u16 { self.len }
+
+    /// Returns a reader of the segment payload.
+    #[inline]
+    pub fn payload(&mut self) -> &mut BufRead {
+        &mut self.payload
+    }
+
+    /// Reads the remaining segment payload into a vector.
+    pub fn read_payload(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.len as usize);
+        try!(self.payload.read_to_end(&mut data));
+        if self.payload.limit() != 0 {
+            return Err(unexpected_eof!("when reading payload of JPEG marker 0x{:02X}", self.marker));
+        }
+        Ok(data)
+    }
+}
+
+/// A marker segment located by `SliceSegments`.
+struct SliceSegment<'a> {
+    marker: u8,
+    offset: u64,
+    len: u16,
+    /// The payload, which is truncated if the buffer ends inside of it.
+    payload: &'a [u8],
+}
+
+/// Locates marker segments in a buffer like `Segments` does in a stream, failing in the same
+/// cases.
+///
+/// The SOI marker must be at the beginning of the buffer.
+struct SliceSegments<'a> {
+    data: &'a [u8],
+    pos: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl<'a> SliceSegments<'a> {
+    fn new(data: &'a [u8]) -> SliceSegments<'a> {
+        SliceSegments {
+            data: data,
+            pos: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Returns the number of bytes of the buffer consumed so far.
+    #[inline]
+    fn position(&self) -> usize {
+        cmp::min(self.pos, self.data.len())
+    }
+
+    fn next(&mut self) -> Option<Result<SliceSegment<'a>>> {
+        if self.finished {
+            return None;
+        }
+        match self.read_segment() {
+            Ok(Some(segment)) => Some(Ok(segment)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn read_marker(&mut self) -> Result<Option<(u64, u8)>> {
+        if !self.started {
+            self.started = true;
+            if self.data.len() < 2 {
+                return Err(unexpected_eof!("when reading JPEG SOI marker"));
+            }
+            if self.data[..2] != [0xff, 0xd8] {
+                return Err(invalid_format!("invalid JPEG SOI marker: {:?}", [self.data[0], self.data[1]]));
+            }
+            self.pos = 2;
+            return Ok(Some((0, 0xd8)));
+        }
+
+        if self.pos > self.data.len() {
+            return Err(unexpected_eof!("when skipping marker payload"));
+        }
+
+        loop {
+            let rest = &self.data[self.pos..];
+            // any number of 0xFF fill bytes may precede a marker
+            let marker_pos = match rest.iter().position(|&b| b == 0xff)
+                .and_then(|start| rest[start..].iter().position(|&b| b != 0xff).map(|n| start + n)) {
+                Some(i) => self.pos + i,
+                None => {
+                    self.pos = self.data.len();
+                    return Ok(None);
+                }
+            };
+            self.pos = marker_pos + 1;
+
+            match self.data[marker_pos] {
+                // "stuffed" byte or restart marker inside entropy-coded data
+                0x00 | 0xd0...0xd7 => continue,
+                m => return Ok(Some((marker_pos as u64 - 1, m)))
+            }
+        }
+    }
+
+    fn read_segment(&mut self) -> Result<Option<SliceSegment<'a>>> {
+        let (offset, marker) = match try!(self.read_marker()) {
+            Some(t) => t,
+            None => return Ok(None)
+        };
+
+        let len = match marker {
+            // SOI, EOI and TEM markers do not have payload
+            0xd8 | 0xd9 | 0x01 => 0,
+            _ => {
+                if self.data.len() - self.pos < 2 {
+                    return Err(unexpected_eof!("when reading marker payload size"));
+                }
+                let len = (self.data[self.pos] as u16) << 8 | self.data[self.pos + 1] as u16;
+                self.pos += 2;
+                if len < 2 {
+                    return Err(invalid_format!("invalid payload size of JPEG marker 0x{:02X}: {}", marker, len));
+                }
+                len - 2
+            }
+        };
+        log_trace!("JPEG marker 0x{:02X} at offset {}, payload size {}", marker, offset, len);
+
+        if marker == 0xd9 {
+            self.finished = true;
+        }
+        let payload = &self.data[self.pos..cmp::min(self.pos + len as usize, self.data.len())];
+        self.pos += len as usize;
+        Ok(Some(SliceSegment {
+            marker: marker,
+            offset: offset,
+            len: len,
+            payload: payload,
+        }))
+    }
+}
+
+/// Kinds of metadata segments removed by `strip_metadata()`.
+///
+/// APP0 (JFIF) and APP14 (Adobe) segments are never removed because decoders need them to
+/// interpret colors correctly, and neither is the MPF segment which indexes additional
+/// images stored after the main one.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct StripOptions {
+    /// Remove EXIF data (APP1 segments starting with `Exif\0\0`).
+    ///
+    /// This also removes the EXIF thumbnail.
+    pub exif: bool,
+    /// Remove XMP packets, including extended XMP (APP1 segments).
+    pub xmp: bool,
+    /// Remove ICC color profiles (APP2 segments starting with `ICC_PROFILE\0`).
+    pub icc: bool,
+    /// Remove comments (COM segments).
+    pub comments: bool,
+    /// Remove all other application segments, e.g. vendor-specific APP1-APP13 and APP15 data
+    /// and FLIR radiometric data.
+    pub other_app: bool,
+    /// Remove all data following the EOI marker of the main image, e.g. preview and gain map
+    /// images.
+    pub trailing_data: bool,
+}
+
+impl StripOptions {
+    /// Options which remove all metadata segments and trailing data.
+    pub fn all() -> StripOptions {
+        StripOptions {
+            exif: true,
+            xmp: true,
+            icc: true,
+            comments: true,
+            other_app: true,
+            trailing_data: true,
+        }
+    }
+
+    /// Options which remove nothing; individual fields can then be enabled.
+    pub fn none() -> StripOptions {
+        StripOptions {
+            exif: false,
+            xmp: false,
+            icc: false,
+            comments: false,
+            other_app: false,
+            trailing_data: false,
+        }
+    }
+
+    fn strips(&self, marker: u8, payload: &[u8]) -> bool {
+        match marker {
+            0xe1 if payload.starts_with(b"Exif\0\0") => self.exif,
+            0xe1 if payload.starts_with(XMP_SIGNATURE) || payload.starts_with(XMP_EXTENSION_SIGNATURE) => self.xmp,
+            0xe2 if payload.starts_with(ICC_SIGNATURE) => self.icc,
+            0xe2 if payload.starts_with(b"MPF\0") => false,
+            0xfe => self.comments,
+            0xe0 | 0xee => false,
+            0xe1...0xef => self.other_app,
+            _ => false
+        }
+    }
+}
+
+/// Copies a JPEG stream while removing the selected metadata segments.
+///
+/// All other segments and the entropy-coded image data are copied unchanged, so the image
+/// is not recompressed. Returns the number of removed segments.
+///
+/// Note that size and offset fields of the MPF index are not updated. Removing segments
+/// preceding the MPF segment keeps the offsets of additional images valid, which is the
+/// usual case since EXIF, XMP and ICC segments are written first, but the recorded size of
+/// the main image becomes inaccurate.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::{BufReader, BufWriter};
+/// use immeta::formats::jpeg::{self, StripOptions};
+///
+/// let mut r = BufReader::new(File::open("kitty.jpg").unwrap());
+/// let mut w = BufWriter::new(File::create("kitty-clean.jpg").unwrap());
+/// jpeg::strip_metadata(&mut r, &mut w, &StripOptions::all()).unwrap();
+/// ```
+pub fn strip_metadata<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W,
+                                                               options: &StripOptions) -> Result<usize> {
+    let mut removed = 0;
+    try!(rewrite_segments(r, w, options.trailing_data, |_, marker, payload| {
+        if options.strips(marker, payload) {
+            log_debug!("removing JPEG marker 0x{:02X} with {} bytes of payload", marker, payload.len());
+            removed += 1;
+            Ok(false)
+        } else {
+            Ok(true)
+        }
+    }));
+    Ok(removed)
+}
+
+/// Copies a JPEG stream while inserting the provided XMP packet, replacing the existing one.
+///
+/// The packet is written into an APP1 segment in place of the existing XMP segment, or, if
+/// there is none, after the JFIF and EXIF segments which must come first. Extended XMP
+/// segments belong to the replaced packet and are removed as well. Everything else is copied
+/// unchanged; the caveat about the MPF index described for `strip_metadata()` applies here
+/// too.
+///
+/// A single APP1 segment can hold at most 65504 bytes of XMP. Larger packets must be split
+/// into extended XMP, which is not supported, so an error is returned for them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::{BufReader, BufWriter};
+/// use immeta::common::xmp::Xmp;
+/// use immeta::formats::jpeg;
+///
+/// let xmp = Xmp::new(r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">...</x:xmpmeta>"#);
+/// let mut r = BufReader::new(File::open("kitty.jpg").unwrap());
+/// let mut w = BufWriter::new(File::create("kitty-tagged.jpg").unwrap());
+/// jpeg::write_xmp(&mut r, &mut w, &xmp).unwrap();
+/// ```
+pub fn write_xmp<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W, xmp: &Xmp) -> Result<()> {
+    embed_metadata(r, w, &EmbeddedMetadata { xmp: Some(xmp.clone()), ..EmbeddedMetadata::default() })
+}
+
+/// Checks whether a JPEG stream starting at the current position is complete, i.e. whether
+/// its EOI marker appears before or at the end of the stream.
+///
+/// The whole stream up to EOI marker has to be read, since entropy-coded data does not
+/// declare its length, but nothing is decoded. Additional images stored after the primary
+/// one, e.g. MPF images and HDR gain maps, are reported as trailing data. The position of
+/// the stream is restored afterwards.
+pub fn verify_complete<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Completeness> {
+    utils::verify_length(r, |r| {
+        let mut segments = Segments::new(&mut *r);
+        while let Some(segment) = segments.next() {
+            let segment = try!(segment);
+            if segment.marker() == 0xd9 {
+                return Ok(segment.offset() + 2);
+            }
+        }
+        Err(unexpected_eof!("when searching for JPEG EOI marker"))
+    })
+}
+
+/// Finds the entropy-coded image data of a JPEG stream starting at the current position.
+///
+/// Each range covers the data of one scan, from the end of its SOS segment up to the marker
+/// which follows the data, with restart markers included. Everything outside the ranges is
+/// marker segments, i.e. tables, frame and scan headers and application data. Like in
+/// `verify_complete()`, images stored after the EOI marker of the primary one are not
+/// considered. If the stream ends inside a scan, its range extends to the end of the stream.
+pub fn image_data_ranges<R: ?Sized + BufRead>(r: &mut R) -> Result<Vec<ByteRange>> {
+    let mut ranges = Vec::new();
+    let mut scan_start = None;
+    let mut segments = Segments::new(&mut *r);
+    while let Some(segment) = segments.next() {
+        let segment = try!(segment);
+        if let Some(start) = scan_start.take() {
+            ranges.push(ByteRange { offset: start, len: segment.offset() - start });
+        }
+        if segment.marker() == 0xda {
+            // marker and payload size precede the payload
+            scan_start = Some(segment.offset() + 4 + segment.len() as u64);
+        }
+    }
+    if let Some(start) = scan_start {
+        ranges.push(ByteRange { offset: start, len: segments.position().saturating_sub(start) });
+    }
+    Ok(ranges)
+}
+
+/// Extracts EXIF data, the XMP packet and the ICC profile from a JPEG stream.
+///
+/// Only the segments preceding the first scan are read. ICC profiles split into several
+/// APP2 segments are reassembled. Invalid EXIF data and XMP packets are ignored.
+pub fn extract_metadata<R: ?Sized + BufRead>(r: &mut R) -> Result<EmbeddedMetadata> {
+    let mut md = EmbeddedMetadata::default();
+    let mut icc_chunks = Vec::new();
+    let mut segments = Segments::new(&mut *r);
+    while let Some(segment) = segments.next() {
+        let mut segment = try!(segment);
+        let marker = segment.marker();
+        match marker {
+            0xda | 0xd9 => break,
+            0xe1 | 0xe2 => {
+                let offset = segment.offset();
+                let payload = try!(segment.read_payload());
+                if marker == 0xe1 && payload.starts_with(b"Exif\0\0") && md.exif.is_none() {
+                    // marker, payload size and EXIF header precede the TIFF document
+                    match ExifData::new(payload[6..].to_vec(), offset + 4 + 6) {
+                        Ok(exif) => md.exif = Some(exif),
+                        Err(e) => log_debug!("skipping invalid EXIF data: {}", e)
+                    }
+                } else if marker == 0xe1 && payload.starts_with(XMP_SIGNATURE) && md.xmp.is_none() {
+                    match Xmp::from_bytes(&payload[XMP_SIGNATURE.len()..]) {
+                        Ok(xmp) => md.xmp = Some(xmp),
+                        Err(e) => log_debug!("skipping invalid XMP packet: {}", e)
+                    }
+                } else if marker == 0xe2 && payload.starts_with(ICC_SIGNATURE) && payload.len() >= 14 {
+                    // sequence number and number of chunks follow the signature
+                    icc_chunks.push((payload[12], payload[14..].to_vec()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !icc_chunks.is_empty() {
+        icc_chunks.sort_by_key(|&(sequence_number, _)| sequence_number);
+        md.icc_profile = Some(icc_chunks.into_iter().flat_map(|(_, data)| data).collect());
+    }
+    Ok(md)
+}
+
+/// Copies a JPEG stream while embedding the provided metadata.
+///
+/// Each kind of metadata which is present replaces all segments of the same kind; new
+/// segments are written in place of the first replaced segment or after the JFIF and the
+/// kept EXIF segments, in the order EXIF, XMP, ICC profile. The ICC profile is split into
+/// several APP2 segments if necessary. Everything else is copied unchanged, as described
+/// for `write_xmp()`.
+///
+/// EXIF data larger than 65526 bytes and XMP packets larger than 65504 bytes do not fit into
+/// a segment, so an error is returned for them before anything is written.
+pub fn embed_metadata<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W,
+                                                              md: &EmbeddedMetadata) -> Result<()> {
+    let mut new_segments = Vec::new();
+    if let Some(ref exif) = md.exif {
+        new_segments.push((0xe1, "EXIF data", [&b"Exif\0\0"[..], exif.as_bytes()].concat()));
+    }
+    if let Some(ref xmp) = md.xmp {
+        new_segments.push((0xe1, "XMP packet", [XMP_SIGNATURE, xmp.as_str().as_bytes()].concat()));
+    }
+    if let Some(ref profile) = md.icc_profile {
+        let chunks: Vec<_> = profile.chunks(MAX_ICC_CHUNK_SIZE).collect();
+        if chunks.len() > 255 {
+            return Err(limit_exceeded!("ICC profile of {} bytes does not fit into 255 JPEG segments", profile.len()));
+        }
+        for (i, chunk) in chunks.iter().enumerate() {
+            let header = [ICC_SIGNATURE, &[i as u8 + 1, chunks.len() as u8]].concat();
+            new_segments.push((0xe2, "ICC profile", [&header[..], chunk].concat()));
+        }
+    }
+    for &(_, what, ref payload) in &new_segments {
+        if payload.len() + 2 > 0xffff {
+            return Err(limit_exceeded!("{} of {} bytes does not fit into a JPEG segment", what, payload.len()));
+        }
+    }
+
+    let mut written = new_segments.is_empty();
+    try!(rewrite_segments(r, w, false, |w, marker, payload| {
+        let is_exif = marker == 0xe1 && payload.starts_with(b"Exif\0\0");
+        let is_xmp = marker == 0xe1 &&
+            (payload.starts_with(XMP_SIGNATURE) || payload.starts_with(XMP_EXTENSION_SIGNATURE));
+        let is_icc = marker == 0xe2 && payload.starts_with(ICC_SIGNATURE);
+        let replaced = (is_exif && md.exif.is_some()) || (is_xmp && md.xmp.is_some()) ||
+                       (is_icc && md.icc_profile.is_some());
+        let precedes = marker == 0xe0 || (is_exif && !replaced);
+
+        if !written && !precedes {
+            for &(marker, _, ref payload) in &new_segments {
+                let len = payload.len() + 2;
+                try!(w.write_all(&[0xff, marker, (len >> 8) as u8, len as u8]));
+                try!(w.write_all(payload));
+            }
+            written = true;
+        }
+        if replaced {
+            log_debug!("removing JPEG marker 0x{:02X} with {} bytes of payload", marker, payload.len());
+        }
+        Ok(!replaced)
+    }));
+
+    if !written {
+        return Err(invalid_format!("JPEG stream does not contain a frame header"));
+    }
+    Ok(())
+}
+
+/// Copies a JPEG stream segment by segment.
+///
+/// For each marker segment the filter is called with the writer, the marker and the payload
+/// before the segment is written; it may write additional data and returns whether the
+/// segment should be copied. Entropy-coded data is copied unchanged, and so is the data
+/// following the EOI marker unless `skip_trailing_data` is set.
+fn rewrite_segments<R, W, F>(r: &mut R, w: &mut W, skip_trailing_data: bool, mut filter: F) -> Result<()>
+    where R: ?Sized + BufRead, W: ?Sized + Write, F: FnMut(&mut W, u8, &[u8]) -> Result<bool>
+{
+    let mut soi = [0u8; 2];
+    try_if_eof!(std, r.read_exact(&mut soi), "when reading JPEG SOI marker");
+    if soi != [0xff, 0xd8] {
+        return Err(invalid_format!("invalid JPEG SOI marker: {:?}", soi));
+    }
+    try!(w.write_all(&soi));
+
+    let mut next_marker = None;
+    loop {
+        let marker = match next_marker.take() {
+            Some(m) => m,
+            None => match try!(read_next_marker(r)) {
+                Some(m) => m,
+                None => {
+                    log_debug!("JPEG stream ended without EOI marker");
+                    return Ok(());
+                }
+            }
+        };
+
+        match marker {
+            0xd9 => {  // EOI
+                try!(w.write_all(&[0xff, 0xd9]));
+                if !skip_trailing_data {
+                    try!(io::copy(r, w));
+                }
+                return Ok(());
+            }
+            // markers without payload
+            0x01 | 0xd0...0xd7 => {
+                try!(w.write_all(&[0xff, marker]));
+                continue;
+            }
+            _ => {}
+        }
+
+        let len = try_if_eof!(r.read_u16::<BigEndian>(), "when reading marker payload size");
+        if len < 2 {
+            return Err(invalid_format!("invalid payload size of JPEG marker 0x{:02X}: {}", marker, len));
+        }
+        let payload = try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(r, len as u64 - 2),
+                                  "when reading payload of JPEG marker 0x{:02X}", marker);
+
+        if !try!(filter(w, marker, &payload)) {
+            continue;
+        }
+
+        try!(w.write_all(&[0xff, marker, (len >> 8) as u8, len as u8]));
+        try!(w.write_all(&payload));
+
+        if marker == 0xda {  // SOS
+            next_marker = try!(copy_scan_data(r, w));
+            if next_marker.is_none() {
+                log_debug!("JPEG stream ended inside entropy-coded data");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Rewrites the EXIF orientation of a JPEG image in place, returning the previous value.
+///
+/// The Orientation tag in IFD0 of the EXIF segment is located and its value is overwritten;
+/// nothing else in the stream is changed. This is typically used to reset the orientation
+/// to 1 after the image has been physically rotated. Returns `None` and leaves the stream
+/// untouched if the image has no EXIF segment or no Orientation tag, since adding one would
+/// require rewriting the whole file.
+///
+/// The JPEG stream must start at the current position of the provided stream.
+///
+/// # Panics
+///
+/// Panics if `orientation` is not between 1 and 8.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::OpenOptions;
+/// use immeta::formats::jpeg;
+///
+/// let mut f = OpenOptions::new().read(true).write(true).open("kitty.jpg").unwrap();
+/// jpeg::set_orientation(&mut f, 1).unwrap();
+/// ```
+pub fn set_orientation<F: ?Sized + Read + Write + Seek>(f: &mut F, orientation: u16) -> Result<Option<u16>> {
+    assert!(orientation >= 1 && orientation <= 8, "invalid EXIF orientation: {}", orientation);
+
+    let start = try!(f.seek(SeekFrom::Current(0)));
+    let mut location = None;
+    {
+        let mut segments = Segments::new(io::BufReader::new(&mut *f));
+        while let Some(segment) = segments.next() {
+            let mut segment = try!(segment);
+            match segment.marker() {
+                0xda | 0xd9 => break,
+                0xe1 => {
+                    let payload = try!(segment.read_payload());
+                    if payload.starts_with(b"Exif\0\0") {
+                        // marker, payload size and EXIF header precede the TIFF document
+                        let tiff_offset = segment.offset() + 4 + 6;
+                        location = try!(exif::find_orientation(&payload[6..]))
+                            .map(|(offset, byte_order, value)| (tiff_offset + offset as u64, byte_order, value));
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    match location {
+        Some((offset, byte_order, value)) => {
+            let data = match byte_order {
+                utils::ByteOrder::Little => [orientation as u8, (orientation >> 8) as u8],
+                utils::ByteOrder::Big => [(orientation >> 8) as u8, orientation as u8],
+            };
+            try!(f.seek(SeekFrom::Start(start + offset)));
+            try!(f.write_all(&data));
+            Ok(Some(value))
+        }
+        None => Ok(None)
+    }
+}
+
+/// Reads the next marker, skipping fill bytes and any garbage preceding it.
+fn read_next_marker<R: ?Sized + BufRead>(r: &mut R) -> Result<Option<u8>> {
+    let skipped = try!(BufReadExt::skip_until(r, 0xff));
+    if skipped == 0 {
+        return Ok(None);
+    }
+    if skipped > 1 {
+        log_debug!("skipped {} bytes of garbage before JPEG marker", skipped - 1);
+    }
+    loop {
+        match r.read_u8() {
+            Ok(0xff) => continue,
+            Ok(m) => return Ok(Some(m)),
+            Err(::byteorder::Error::UnexpectedEOF) => return Ok(None),
+            Err(e) => return Err(e.into())
+        }
+    }
+}
+
+/// Copies entropy-coded data following an SOS segment, including stuffed bytes and restart
+/// markers, and returns the marker which ends it, or `None` at the end of the stream.
+fn copy_scan_data<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W) -> Result<Option<u8>> {
+    loop {
+        let (n, found) = {
+            let buf = try!(r.fill_buf());
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            match buf.iter().position(|&b| b == 0xff) {
+                Some(i) => {
+                    try!(w.write_all(&buf[..i]));
+                    (i + 1, true)
+                }
+                None => {
+                    try!(w.write_all(buf));
+                    (buf.len(), false)
+                }
+            }
+        };
+        r.consume(n);
+        if !found {
+            continue;
+        }
+
+        let marker = loop {
+            match r.read_u8() {
+                Ok(0xff) => continue,
+                Ok(m) => break m,
+                Err(::byteorder::Error::UnexpectedEOF) => {
+                    try!(w.write_all(&[0xff]));
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into())
+            }
+        };
+        match marker {
+            // "stuffed" byte or restart marker inside entropy-coded data
+            0x00 | 0xd0...0xd7 => try!(w.write_all(&[0xff, marker])),
+            m => return Ok(Some(m))
+        }
+    }
+}
+
+/// Frame header parameters.
+struct Frame {
+    precision: u8,
+    dimensions: Dimensions,
+    coding_process: CodingProcess,
+    entropy_coding: EntropyCoding,
+    differential: bool,
+    component_ids: Vec<u8>,
+}
+
+impl Frame {
+    fn parse(mut p: &[u8], kind: (CodingProcess, EntropyCoding, bool)) -> Result<Frame> {
+        let precision = try_if_eof!(p.read_u8(), "when reading sample precision");
+        let h = try_if_eof!(p.read_u16::<BigEndian>(), "when reading height");
+        let w = try_if_eof!(p.read_u16::<BigEndian>(), "when reading width");
+        let n = try_if_eof!(p.read_u8(), "when reading number of components");
+        let mut component_ids = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+            // component identifier, sampling factors, quantization table selector
+            let mut component = [0u8; 3];
+            try!(p.read_exact(&mut component).map_err(if_eof!(std, "when reading frame component")));
+            component_ids.push(component[0]);
+        }
+        Ok(Frame {
+            precision: precision,
+            dimensions: (w, h).into(),
+            coding_process: kind.0,
+            entropy_coding: kind.1,
+            differential: kind.2,
+            component_ids: component_ids,
+        })
+    }
+}
+
+/// Metadata collected from the segments of a JPEG image.
+///
+/// This is the parsing core shared by `parse_bytes()` and `Metadata::load()`, which only
+/// locate segments in a buffer or read them from a stream and pass their payloads to
+/// `segment()`.
+#[derive(Default)]
+struct Parser {
+    frame: Option<Frame>,
+    jfif: bool,
+    density: Option<Density>,
+    adobe_transform: Option<u8>,
+    quantization_tables: Vec<QuantizationTable>,
+    arithmetic_conditioning: bool,
+    mpf_images: Vec<MpImage>,
+    xmp: Option<Xmp>,
+    exif: Option<ExifData>,
+    restart_interval: Option<u16>,
+    scan_count: u32,
+    soi_offset: u64,
+    previews: Vec<PreviewImage>,
+    flir_chunks: Vec<(u8, Vec<u8>)>,
+    application_segments: Vec<AppSegment>,
+    complete: bool,
+    lazy: bool,
+    pending_xmp: Option<ByteRange>,
+    pending_exif: Option<ByteRange>,
+}
+
+impl Parser {
+    /// Returns how many bytes of the payload of a segment `segment()` needs.
+    fn payload_len(&self, marker_type: u8, len: u16) -> u16 {
+        match marker_type {
+            // all segments up to the first scan are parsed, and then the remaining scans are counted
+            _ if self.scan_count > 0 => 0,
+            // payloads of application segments are read in full to find their identifiers
+            0xe0...0xef | 0xdb => len,
+            0xdd => cmp::min(2, len),
+            m if frame_kind(m).is_some() && self.frame.is_none() => len,
+            _ => 0
+        }
+    }
+
+    /// Parses a segment, given at least `payload_len()` bytes of its payload; the payload is
+    /// shorter if the image ends inside of it.
+    fn segment(&mut self, marker_type: u8, offset: u64, len: u16, payload: &[u8]) -> Result<()> {
+        if marker_type == 0xd9 {
+            self.complete = true;
+        }
+
+        if self.scan_count > 0 {
+            if marker_type == 0xda {
+                self.scan_count += 1;
+            }
+            return Ok(());
+        }
+
+        if ((marker_type >= 0xe0 && marker_type <= 0xef) || marker_type == 0xdb) && payload.len() < len as usize {
+            return Err(unexpected_eof!("when reading payload of JPEG marker 0x{:02X}", marker_type));
+        }
+        if marker_type >= 0xe0 && marker_type <= 0xef {
+            self.application_segments.push(AppSegment::new(marker_type, offset, payload));
+        }
+
+        match marker_type {
+            0xd8 => self.soi_offset = offset,  // SOI
+            0xda => self.scan_count += 1,  // SOS
+            0xcc => self.arithmetic_conditioning = true,  // DAC
+            0xdd => {  // DRI
+                let mut p = payload;
+                let interval = try_if_eof!(p.read_u16::<BigEndian>(), "when reading restart interval");
+                self.restart_interval = if interval == 0 { None } else { Some(interval) };
+            }
+            0xdb => try!(QuantizationTable::parse_dqt(payload, &mut self.quantization_tables)),  // DQT
+            0xe2 => {  // APP2
+                if payload.starts_with(b"MPF\0") && self.mpf_images.is_empty() {
+                    // marker, payload size and "MPF\0" identifier precede the MP header
+                    let header_offset = offset + 8;
+                    self.mpf_images = try!(MpImage::parse_mpf(payload, header_offset, self.soi_offset));
+                }
+            }
+            0xe1 => {  // APP1
+                // marker and payload size precede the payload
+                let payload_offset = offset + 4;
+                if payload.starts_with(b"Exif\0\0") && self.lazy {
+                    if self.pending_exif.is_none() {
+                        self.pending_exif = Some(ByteRange { offset: payload_offset, len: payload.len() as u64 });
+                    }
+                } else if payload.starts_with(b"Exif\0\0") {
+                    match exif_thumbnail(payload) {
+                        Ok(Some((offset, size))) if offset + size <= payload.len() as u64 => {
+                            let data = &payload[offset as usize..(offset + size) as usize];
+                            self.previews.push(PreviewImage {
+                                source: PreviewSource::ExifThumbnail,
+                                offset: payload_offset + offset,
+                                size: size,
+                                dimensions: image_extent(data).ok().and_then(|(d, _, _)| d),
+                            });
+                        }
+                        Ok(_) => {}
+                        Err(e) => log_debug!("ignoring invalid EXIF segment: {}", e)
+                    }
+                    if self.exif.is_none() {
+                        // the TIFF header follows the "Exif\0\0" identifier
+                        match ExifData::new(payload[6..].to_vec(), payload_offset + 6) {
+                            Ok(data) => self.exif = Some(data),
+                            Err(e) => log_debug!("ignoring invalid EXIF data: {}", e)
+                        }
+                    }
+                } else if payload.starts_with(b"FLIR\0") && payload.len() >= 8 {
+                    // identifier, format version, chunk index and the index of the last chunk
+                    self.flir_chunks.push((payload[6], payload[8..].to_vec()));
+                } else if payload.starts_with(XMP_SIGNATURE) && self.lazy {
+                    if self.pending_xmp.is_none() {
+                        let signature_len = XMP_SIGNATURE.len() as u64;
+                        self.pending_xmp = Some(ByteRange {
+                            offset: payload_offset + signature_len,
+                            len: payload.len() as u64 - signature_len,
+                        });
+                    }
+                } else if payload.starts_with(XMP_SIGNATURE) && self.xmp.is_none() {
+                    match Xmp::from_bytes(&payload[XMP_SIGNATURE.len()..]) {
+                        Ok(xmp) => self.xmp = Some(xmp),
+                        Err(e) => log_debug!("ignoring invalid XMP packet: {}", e)
+                    }
+                }
+            }
+            0xe0 | 0xee => {  // APP0 or APP14
+                if marker_type == 0xe0 && payload.starts_with(b"JFIF\0") {
+                    self.jfif = true;
+                    self.density = Density::parse_jfif(payload);
+                } else if marker_type == 0xee && payload.starts_with(b"Adobe") && payload.len() >= 12 {
+                    self.adobe_transform = Some(payload[11]);
+                }
+            }
+            _ => if let Some(kind) = frame_kind(marker_type) {
+                if self.frame.is_none() {
+                    self.frame = Some(try!(Frame::parse(payload, kind)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a JPEG image directly following the end of the main one, or following another
+    /// such image.
+    fn trailing_image(&mut self, offset: u64, size: u64, dimensions: Option<Dimensions>) {
+        let mpf_image = self.mpf_images.iter().skip(1).find(|image| image.offset == offset);
+        let source = match mpf_image.map(|image| image.image_type) {
+            Some(MpImageType::LargeThumbnailVga) | Some(MpImageType::LargeThumbnailFullHd) =>
+                Some(PreviewSource::Mpf),
+            Some(_) => None,
+            None => Some(PreviewSource::Trailing),
+        };
+        if let Some(source) = source {
+            self.previews.push(PreviewImage {
+                source: source,
+                offset: offset,
+                size: size,
+                dimensions: dimensions,
+            });
+        }
+    }
+
+    fn finish(self) -> Result<Metadata> {
+        let frame = match self.frame {
+            Some(frame) => frame,
+            None => return Err(unexpected_eof!("when searching for a marker"))
+        };
+
+        let mut flir_chunks = self.flir_chunks;
+        let flir = if flir_chunks.is_empty() {
+            None
+        } else {
+            flir_chunks.sort_by_key(|&(index, _)| index);
+            let data: Vec<u8> = flir_chunks.into_iter().flat_map(|(_, chunk)| chunk).collect();
+            match flir::parse_fff(&data) {
+                Ok(md) => Some(md),
+                Err(e) => {
+                    log_debug!("ignoring invalid FLIR data: {}", e);
+                    None
+                }
+            }
+        };
+
+        let mut md = Metadata {
+            dimensions: frame.dimensions,
+            coding_process: frame.coding_process,
+            entropy_coding: frame.entropy_coding,
+            differential: frame.differential,
+            precision: frame.precision,
+            components: frame.component_ids.len() as u8,
+            color_space: ColorSpace::infer(&frame.component_ids, self.jfif, self.adobe_transform),
+            density: self.density,
+            quantization_tables: self.quantization_tables,
+            arithmetic_conditioning: self.arithmetic_conditioning,
+            mpf_images: self.mpf_images,
+            restart_interval: self.restart_interval,
+            scan_count: self.scan_count,
+            previews: self.previews,
+            flir: flir,
+            xmp: self.xmp,
+            exif: self.exif,
+            pending_xmp: self.pending_xmp,
+            pending_exif: self.pending_exif,
+            gain_map: None,
+            application_segments: self.application_segments,
+        };
+        md.gain_map = md.declared_gain_map();
+        Ok(md)
+    }
+}
+
+impl Metadata {
+    fn load_impl<R: ?Sized + BufRead>(r: &mut R, soi_search_limit: u64, lazy: bool) -> Result<Metadata> {
+        let mut parser = Parser { lazy: lazy, ..Parser::default() };
+        let mut payload = Vec::new();
+        let mut segments = Segments::with_soi_search_limit(r, soi_search_limit);
+        loop {
+            let mut segment = match segments.next() {
+                Some(Ok(segment)) => segment,
+                // the essential metadata is already known, so it is returned as is
+                Some(Err(ref e)) if parser.frame.is_some() => {
+                    log_debug!("ignoring an error after JPEG frame header: {}", e);
+                    break;
+                }
+                Some(Err(e)) => return Err(e),
+                None => break
+            };
+            let n = parser.payload_len(segment.marker(), segment.len());
+            try!(segment.payload().read_up_to(n as u64, &mut payload));
+            try!(parser.segment(segment.marker(), segment.offset(), segment.len(), &payload));
+        }
+
+        // look for JPEG images directly following the main one
+        if parser.complete {
+            let mut offset = segments.position();
+            let r = segments.into_inner();
+            loop {
+                match r.fill_buf() {
+                    Ok(buf) if buf.starts_with(&[0xff, 0xd8]) => {}
+                    _ => break
+                }
+                let mut tracked = Tracked::new(&mut *r);
+                let (dimensions, complete) = match read_image_extent(&mut tracked) {
+                    Ok(t) => t,
+                    Err(e) => {
+                        log_debug!("failed to read JPEG image at offset {}: {}", offset, e);
+                        break;
+                    }
+                };
+                parser.trailing_image(offset, tracked.position, dimensions);
+                offset += tracked.position;
+                if !complete {
+                    break;
+                }
+            }
+        }
+
+        parser.finish()
+    }
+
+    /// Parses an image at the beginning of `data` with the same code as `load_impl()`,
+    /// borrowing segment payloads from the buffer.
+    fn parse_impl(data: &[u8]) -> Result<Metadata> {
+        let mut parser = Parser::default();
+        let mut segments = SliceSegments::new(data);
+        loop {
+            let segment = match segments.next() {
+                Some(Ok(segment)) => segment,
+                // the essential metadata is already known, so it is returned as is
+                Some(Err(ref e)) if parser.frame.is_some() => {
+                    log_debug!("ignoring an error after JPEG frame header: {}", e);
+                    break;
+                }
+                Some(Err(e)) => return Err(e),
+                None => break
+            };
+            let n = cmp::min(parser.payload_len(segment.marker, segment.len) as usize, segment.payload.len());
+            try!(parser.segment(segment.marker, segment.offset, segment.len, &segment.payload[..n]));
+        }
+
+        // look for JPEG images directly following the main one
+        if parser.complete {
+            let mut offset = segments.position();
+            while data[offset..].starts_with(&[0xff, 0xd8]) {
+                let (dimensions, complete, size) = match image_extent(&data[offset..]) {
+                    Ok(t) => t,
+                    Err(e) => {
+                        log_debug!("failed to read JPEG image at offset {}: {}", offset, e);
+                        break;
+                    }
+                };
+                parser.trailing_image(offset as u64, size, dimensions);
+                offset += size as usize;
+                if !complete {
+                    break;
+                }
+            }
+        }
+
+        parser.finish()
+    }
+
+    fn load_from_seek_impl<R: ?Sized + BufRead + Seek>(r: &mut R, soi_search_limit: u64,
+                                                       lazy: bool) -> Result<Metadata> {
+        let start = try!(r.seek(SeekFrom::Current(0)));
+        let mut md = try!(Metadata::load_impl(r, soi_search_limit, lazy));
+        try!(md.load_gain_map(r, start));
+        Ok(md)
+    }
+}
+
+/// Parses JPEG metadata directly from an in-memory buffer.
+///
+/// This is equivalent to `Metadata::load_from_buf()`. Segments are located in the buffer
+/// directly and their payloads are parsed in place, without going through a reader; scans are
+/// skipped by searching the buffer for markers. `Metadata::load()` parses segments read from
+/// a stream with the same code. The gain map image referenced by the MPF index is found by
+/// slicing the buffer instead of seeking.
+///
+/// This function can be used as a fuzz target; see `immeta::fuzz`.
+pub fn parse_bytes(data: &[u8]) -> Result<Metadata> {
+    let mut md = try!(Metadata::parse_impl(data));
+    if let Some(index) = md.gain_map_candidate() {
+        let offset = md.mpf_images[index].offset;
+        let image = if offset < data.len() as u64 {
+            Metadata::parse_impl(&data[offset as usize..])
+        } else {
+            Err(unexpected_eof!("when looking for MPF image at offset {}", offset))
+        };
+        md.apply_gain_map_image(index, image);
+    }
+    Ok(md)
+}
+
+impl LoadableMetadata for Metadata {
+    fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Metadata> {
+        Metadata::load_impl(r, 0, false)
+    }
+
+    fn load_from_buf(buf: &[u8]) -> Result<Metadata> {
+        parse_bytes(buf)
+    }
+
+    fn load_from_seek<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Metadata> {
+        Metadata::load_from_seek_impl(r, 0, false)
+    }
+
+    fn load_partial_from_seek<R: ?Sized + BufRead + Seek>(r: &mut R) -> PartialResult<Metadata> {
+        Metadata::load_from_seek_impl(r, 0, false).map_err(ErrorWithPartial::from)
+    }
+
+    fn load_partial_with_options<R: ?Sized + BufRead + Seek>(r: &mut R, options: &LoadOptions) -> PartialResult<Metadata> {
+        Metadata::load_from_seek_impl(r, options.soi_search_limit, options.lazy_extensions).map_err(ErrorWithPartial::from)
     }
 }
@@ -4,3 +4,4 @@ pub mod jpeg;
 pub mod png;
 pub mod gif;
 pub mod webp;
+pub mod pdf;
@@ -0,0 +1,1018 @@
+//! Metadata of PDF documents.
+//!
+//! PDF is not an image format, but single-page documents are often handled like images, e.g.
+//! by preview services. This module reads just enough of a document to find the size of its
+//! first page: the header, the trailer, the cross-reference sections, and the objects of the
+//! page tree on the way from the document catalog to the first page. Page contents are
+//! never read.
+//!
+//! PDF documents are not detected by `immeta::load()`, because they have no pixel dimensions;
+//! `Metadata` must be loaded explicitly:
+//!
+//! ```no_run
+//! use immeta::LoadableMetadata;
+//! use immeta::formats::pdf;
+//!
+//! let md = pdf::Metadata::load_from_file("document.pdf").unwrap();
+//! println!("{} pages, first one is {}x{} pt", md.page_count,
+//!          md.dimensions.width, md.dimensions.height);
+//! ```
+//!
+//! Cross-reference streams and object streams, which are used by most documents written by
+//! PDF 1.5 and later, are compressed, so they are only supported when `zlib` feature is
+//! enabled. Encrypted documents are loaded as long as the page tree is not stored in object
+//! streams, and they are flagged with `Metadata::encrypted`. Damaged documents whose
+//! cross-reference data is not valid are not reconstructed.
+
+use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::borrow::Cow;
+use std::cmp;
+use std::str;
+
+use types::{Result, Dimensions};
+use traits::LoadableMetadata;
+use utils;
+
+/// Maximum number of bytes read at once when parsing an object or a cross-reference section.
+const CHUNK_LEN: u64 = 64 * 1024;
+/// Number of bytes at the beginning of a document which are searched for the header.
+const HEADER_SEARCH_LEN: u64 = 1024;
+/// Number of bytes at the end of a document which are searched for `startxref` keyword.
+const TRAILER_SEARCH_LEN: u64 = 1024;
+/// Maximum number of cross-reference sections chained by `Prev` entries.
+const MAX_XREF_SECTIONS: usize = 256;
+/// Maximum depth of the page tree and of nested arrays and dictionaries.
+const MAX_DEPTH: usize = 64;
+/// Maximum size of decompressed stream data.
+#[cfg(feature = "zlib")]
+const MAX_STREAM_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A rectangle in PDF user space units.
+///
+/// The coordinates are normalized, so `left <= right` and `bottom <= top`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rectangle {
+    pub left: f64,
+    pub bottom: f64,
+    pub right: f64,
+    pub top: f64,
+}
+
+impl Rectangle {
+    /// Returns the width of the rectangle.
+    #[inline]
+    pub fn width(&self) -> f64 {
+        self.right - self.left
+    }
+
+    /// Returns the height of the rectangle.
+    #[inline]
+    pub fn height(&self) -> f64 {
+        self.top - self.bottom
+    }
+}
+
+/// Represents metadata of a PDF document.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Metadata {
+    /// PDF version from the file header as a pair of major and minor numbers, e.g. `(1, 7)`.
+    ///
+    /// The document catalog may declare a later version, which is not checked.
+    pub version: (u8, u8),
+    /// Whether the document is encrypted, i.e. whether its trailer has an `Encrypt` entry.
+    ///
+    /// Page dimensions of encrypted documents are readable, but their contents are not.
+    pub encrypted: bool,
+    /// Number of pages, as declared by the root of the page tree.
+    pub page_count: u32,
+    /// Media box of the first page, i.e. the boundaries of the physical medium, in user
+    /// space units.
+    pub media_box: Rectangle,
+    /// Clockwise rotation of the first page in degrees when it is displayed: 0, 90, 180
+    /// or 270.
+    pub rotation: u16,
+    /// Size of a user space unit of the first page in points, which is 1 unless the page
+    /// defines `UserUnit` entry.
+    pub user_unit: f64,
+    /// Size of the first page as it is displayed, in points (1/72 inch) rounded to the
+    /// nearest integer.
+    ///
+    /// Width and height of the media box are swapped for pages rotated by 90 or 270 degrees.
+    pub dimensions: Dimensions,
+}
+
+impl Metadata {
+    /// Returns `true` if the document consists of a single page and can be handled like
+    /// an image.
+    #[inline]
+    pub fn is_single_page(&self) -> bool {
+        self.page_count == 1
+    }
+}
+
+/// Parses PDF metadata from an in-memory buffer.
+///
+/// This is equivalent to `Metadata::load_from_buf()`. The objects needed to find the first
+/// page are parsed in place, without copying them out of the buffer; `load_from_seek()` reads
+/// the same objects from a stream and parses them with the same code.
+///
+/// This function can be used as a fuzz target; see `immeta::fuzz`.
+pub fn parse_bytes(data: &[u8]) -> Result<Metadata> {
+    let mut doc = try!(Document::open(data));
+    doc.first_page()
+}
+
+impl LoadableMetadata for Metadata {
+    /// Loads PDF metadata from a non-seekable stream.
+    ///
+    /// Because the cross-reference data is located at the end of a document, the whole
+    /// stream is read into memory first. Use `load_from_seek()` whenever possible.
+    fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Metadata> {
+        let mut data = Vec::new();
+        try!(Read::take(&mut *r, utils::MAX_EXACT_VEC_LEN + 1).read_to_end(&mut data));
+        if data.len() as u64 > utils::MAX_EXACT_VEC_LEN {
+            return Err(limit_exceeded!("PDF document is larger than {} bytes", utils::MAX_EXACT_VEC_LEN));
+        }
+        parse_bytes(&data)
+    }
+
+    fn load_from_seek<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Metadata> {
+        let mut doc = try!(Document::open(Stream(r)));
+        doc.first_page()
+    }
+}
+
+/// A PDF object. Strings are kept undecoded since they are never needed.
+#[derive(Clone, PartialEq, Debug)]
+enum Object {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String,
+    Name(Vec<u8>),
+    Array(Vec<Object>),
+    Dictionary(Dictionary),
+    Reference(u32),
+}
+
+impl Object {
+    fn describe(&self) -> &'static str {
+        match *self {
+            Object::Null => "null",
+            Object::Boolean(_) => "boolean",
+            Object::Number(_) => "number",
+            Object::String => "string",
+            Object::Name(_) => "name",
+            Object::Array(_) => "array",
+            Object::Dictionary(_) => "dictionary",
+            Object::Reference(_) => "reference",
+        }
+    }
+
+    fn into_dictionary(self, what: &str) -> Result<Dictionary> {
+        match self {
+            Object::Dictionary(d) => Ok(d),
+            o => Err(invalid_format!("{} is a {}, expected a dictionary", what, o.describe()))
+        }
+    }
+
+    fn into_array(self, what: &str) -> Result<Vec<Object>> {
+        match self {
+            Object::Array(a) => Ok(a),
+            o => Err(invalid_format!("{} is a {}, expected an array", what, o.describe()))
+        }
+    }
+
+    fn as_number(&self, what: &str) -> Result<f64> {
+        match *self {
+            Object::Number(n) => Ok(n),
+            ref o => Err(invalid_format!("{} is a {}, expected a number", what, o.describe()))
+        }
+    }
+
+    fn as_integer(&self, what: &str) -> Result<u64> {
+        match *self {
+            Object::Number(n) if n >= 0.0 && n.fract() == 0.0 && n < (1u64 << 53) as f64 => Ok(n as u64),
+            ref o => Err(invalid_format!("{} is a {}, expected a non-negative integer", what, o.describe()))
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Dictionary(Vec<(Vec<u8>, Object)>);
+
+impl Dictionary {
+    fn get(&self, key: &[u8]) -> Option<&Object> {
+        self.0.iter().find(|&&(ref k, _)| &k[..] == key).map(|&(_, ref v)| v)
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    match b {
+        0 | b'\t' | b'\n' | 0x0c | b'\r' | b' ' => true,
+        _ => false
+    }
+}
+
+fn is_delimiter(b: u8) -> bool {
+    match b {
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%' => true,
+        _ => false
+    }
+}
+
+fn is_regular(b: u8) -> bool {
+    !is_whitespace(b) && !is_delimiter(b)
+}
+
+/// A parser of PDF objects in a byte buffer.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8]) -> Parser<'a> {
+        Parser { data: data, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).cloned()
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if is_whitespace(b) {
+                self.pos += 1;
+            } else if b == b'%' {
+                while self.peek().map_or(false, |b| b != b'\r' && b != b'\n') {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Reads a sequence of regular characters, which is a keyword or a number.
+    fn token(&mut self) -> &'a [u8] {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.peek().map_or(false, is_regular) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        let token = self.token();
+        if token == keyword.as_bytes() {
+            Ok(())
+        } else if token.is_empty() && self.pos == self.data.len() {
+            Err(unexpected_eof!("when reading PDF keyword {}", keyword))
+        } else {
+            Err(invalid_format!("expected PDF keyword {}, found {:?}", keyword, String::from_utf8_lossy(token)))
+        }
+    }
+
+    /// Reads an unsigned integer, restoring the position if there is none.
+    fn integer(&mut self) -> Option<u64> {
+        let start = self.pos;
+        let token = self.token();
+        match parse_integer(token) {
+            Some(n) => Some(n),
+            None => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    /// Reads an indirect object header, i.e. the object number, the generation number and
+    /// `obj` keyword, and returns the object number.
+    fn object_header(&mut self) -> Result<u32> {
+        let number = match self.integer() {
+            Some(n) if n <= u32::max_value() as u64 => n as u32,
+            _ => return Err(invalid_format!("invalid PDF object header"))
+        };
+        if self.integer().is_none() {
+            return Err(invalid_format!("invalid PDF object header"));
+        }
+        try!(self.expect_keyword("obj"));
+        Ok(number)
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Object> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_format!("PDF objects are nested too deeply"));
+        }
+
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(unexpected_eof!("when reading PDF object")),
+            Some(b'/') => {
+                self.pos += 1;
+                Ok(Object::Name(decode_name(self.token())))
+            }
+            Some(b'<') if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                let mut entries = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b'>') if self.data.get(self.pos + 1) == Some(&b'>') => {
+                            self.pos += 2;
+                            return Ok(Object::Dictionary(Dictionary(entries)));
+                        }
+                        None => return Err(unexpected_eof!("when reading PDF dictionary")),
+                        _ => {}
+                    }
+                    let key = match try!(self.object(depth + 1)) {
+                        Object::Name(key) => key,
+                        o => return Err(invalid_format!("PDF dictionary key is a {}, expected a name", o.describe()))
+                    };
+                    let value = try!(self.object(depth + 1));
+                    entries.push((key, value));
+                }
+            }
+            Some(b'<') => {
+                match self.data[self.pos..].iter().position(|&b| b == b'>') {
+                    Some(n) => {
+                        self.pos += n + 1;
+                        Ok(Object::String)
+                    }
+                    None => Err(unexpected_eof!("when reading PDF hexadecimal string"))
+                }
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                let mut nesting = 0;
+                loop {
+                    match self.peek() {
+                        None => return Err(unexpected_eof!("when reading PDF string")),
+                        Some(b'\\') => self.pos += 1,
+                        Some(b'(') => nesting += 1,
+                        Some(b')') if nesting == 0 => {
+                            self.pos += 1;
+                            return Ok(Object::String);
+                        }
+                        Some(b')') => nesting -= 1,
+                        _ => {}
+                    }
+                    self.pos += 1;
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Object::Array(items));
+                        }
+                        None => return Err(unexpected_eof!("when reading PDF array")),
+                        _ => items.push(try!(self.object(depth + 1)))
+                    }
+                }
+            }
+            Some(_) => {
+                let token = self.token();
+                match token {
+                    b"null" => Ok(Object::Null),
+                    b"true" => Ok(Object::Boolean(true)),
+                    b"false" => Ok(Object::Boolean(false)),
+                    _ if token.is_empty() => {
+                        Err(invalid_format!("unexpected PDF delimiter {:?}", self.peek().unwrap_or(0) as char))
+                    }
+                    _ => {
+                        // an unsigned integer may start an indirect reference, e.g. `12 0 R`
+                        if let Some(number) = parse_integer(token) {
+                            let start = self.pos;
+                            if self.integer().is_some() && self.token() == b"R" && number <= u32::max_value() as u64 {
+                                return Ok(Object::Reference(number as u32));
+                            }
+                            self.pos = start;
+                        }
+                        parse_number(token)
+                            .map(Object::Number)
+                            .ok_or_else(|| invalid_format!("unexpected PDF token {:?}", String::from_utf8_lossy(token)))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_integer(token: &[u8]) -> Option<u64> {
+    if token.is_empty() || token.len() > 19 || !token.iter().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    str::from_utf8(token).ok().and_then(|s| s.parse().ok())
+}
+
+fn parse_number(token: &[u8]) -> Option<f64> {
+    if !token.iter().all(|&b| b.is_ascii_digit() || b == b'.' || b == b'-' || b == b'+') {
+        return None;
+    }
+    str::from_utf8(token).ok().and_then(|s| s.parse().ok())
+}
+
+/// Decodes `#xx` escape sequences in a name.
+fn decode_name(token: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(token.len());
+    let mut i = 0;
+    while i < token.len() {
+        let escaped = if token[i] == b'#' && i + 2 < token.len() {
+            str::from_utf8(&token[i + 1..i + 3]).ok().and_then(|s| u8::from_str_radix(s, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(b) => {
+                result.push(b);
+                i += 3;
+            }
+            None => {
+                result.push(token[i]);
+                i += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Location of an object as recorded in the cross-reference data.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Location {
+    Free,
+    /// Offset of the object from the beginning of the document.
+    Offset(u64),
+    /// Object number of the object stream containing the object, and the index of the object
+    /// in the stream.
+    Compressed(u32, u64),
+}
+
+/// A cross-reference section.
+enum Section {
+    /// A cross-reference table, as a list of subsections consisting of the number of the first
+    /// object, the number of entries, and the offset of the first entry in the document.
+    Table(Vec<(u32, u32, u64)>),
+    /// A decoded cross-reference stream with its field widths and subsections.
+    #[cfg_attr(not(feature = "zlib"), allow(dead_code))]
+    Stream {
+        widths: [usize; 3],
+        index: Vec<(u32, u32)>,
+        data: Vec<u8>,
+    },
+}
+
+/// Random access to the bytes of a document.
+///
+/// Documents in memory are parsed in place, while seekable streams are read in chunks, so
+/// that only the parts of a document which are needed are read.
+trait Source<'s> {
+    /// Returns the current position and the length of the source.
+    fn bounds(&mut self) -> Result<(u64, u64)>;
+
+    /// Returns at most `len` bytes at the given position.
+    fn read_at(&mut self, position: u64, len: u64) -> Result<Cow<'s, [u8]>>;
+
+    /// Returns `len` bytes of stream data at the given position.
+    #[cfg(feature = "zlib")]
+    fn stream_data(&mut self, position: u64, len: u64) -> Result<Cow<'s, [u8]>>;
+}
+
+impl<'s> Source<'s> for &'s [u8] {
+    fn bounds(&mut self) -> Result<(u64, u64)> {
+        Ok((0, self.len() as u64))
+    }
+
+    fn read_at(&mut self, position: u64, len: u64) -> Result<Cow<'s, [u8]>> {
+        let data: &'s [u8] = *self;
+        let start = cmp::min(position, data.len() as u64) as usize;
+        let end = cmp::min(position.saturating_add(len), data.len() as u64) as usize;
+        Ok(Cow::Borrowed(&data[start..end]))
+    }
+
+    #[cfg(feature = "zlib")]
+    fn stream_data(&mut self, position: u64, len: u64) -> Result<Cow<'s, [u8]>> {
+        let data = try!(self.read_at(position, len));
+        if (data.len() as u64) < len {
+            return Err(unexpected_eof!("when reading PDF stream data"));
+        }
+        Ok(data)
+    }
+}
+
+/// A seekable stream as a `Source`.
+struct Stream<'a, R: ?Sized + 'a>(&'a mut R);
+
+impl<'a, 's, R: ?Sized + BufRead + Seek> Source<'s> for Stream<'a, R> {
+    fn bounds(&mut self) -> Result<(u64, u64)> {
+        let start = try!(self.0.seek(SeekFrom::Current(0)));
+        let end = try!(self.0.seek(SeekFrom::End(0)));
+        Ok((start, end))
+    }
+
+    fn read_at(&mut self, position: u64, len: u64) -> Result<Cow<'s, [u8]>> {
+        try!(self.0.seek(SeekFrom::Start(position)));
+        let mut buf = Vec::new();
+        try!(Read::take(&mut *self.0, len).read_to_end(&mut buf));
+        Ok(Cow::Owned(buf))
+    }
+
+    #[cfg(feature = "zlib")]
+    fn stream_data(&mut self, position: u64, len: u64) -> Result<Cow<'s, [u8]>> {
+        try!(self.0.seek(SeekFrom::Start(position)));
+        let data = try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(&mut *self.0, len),
+                               "when reading PDF stream data");
+        Ok(Cow::Owned(data))
+    }
+}
+
+/// The cross-reference data of a document, which is used to fetch objects from a `Source`.
+///
+/// Objects are parsed from the source in place, so that documents in memory are not copied.
+struct Document<S> {
+    source: S,
+    /// Offset of the header from the beginning of the stream; all offsets in the document are
+    /// relative to it.
+    base: u64,
+    version: (u8, u8),
+    sections: Vec<Section>,
+    trailer: Dictionary,
+    /// Numbers of the objects which are being fetched, to detect objects which depend on
+    /// themselves, e.g. an object stream whose length is stored in that stream.
+    fetching: Vec<u32>,
+}
+
+impl<'s, S: Source<'s>> Document<S> {
+    /// Reads the header, the trailer and the cross-reference sections of a document.
+    fn open(mut source: S) -> Result<Document<S>> {
+        let (start, end) = try!(source.bounds());
+        let head = try!(source.read_at(start, HEADER_SEARCH_LEN));
+        let i = try!(find_subslice(&head, b"%PDF-").ok_or_else(|| invalid_format!("PDF header not found")));
+        let version = match head.get(i + 5..i + 8) {
+            Some(v) if v[0].is_ascii_digit() && v[1] == b'.' && v[2].is_ascii_digit() => (v[0] - b'0', v[2] - b'0'),
+            _ => return Err(invalid_format!("invalid PDF version"))
+        };
+        let base = start + i as u64;
+
+        let tail_start = if end - base > TRAILER_SEARCH_LEN { end - TRAILER_SEARCH_LEN } else { base };
+        let tail = try!(source.read_at(tail_start, TRAILER_SEARCH_LEN));
+        let startxref = match rfind_subslice(&tail, b"startxref") {
+            Some(i) => {
+                let mut p = Parser::new(&tail[i + 9..]);
+                try!(p.integer().ok_or_else(|| invalid_format!("invalid PDF startxref offset")))
+            }
+            None => return Err(invalid_format!("PDF startxref keyword not found"))
+        };
+
+        let mut doc = Document {
+            source: source,
+            base: base,
+            version: version,
+            sections: Vec::new(),
+            trailer: Dictionary(Vec::new()),
+            fetching: Vec::new(),
+        };
+
+        let mut pending = vec![startxref];
+        while let Some(offset) = pending.pop() {
+            if doc.sections.len() >= MAX_XREF_SECTIONS {
+                return Err(invalid_format!("too many PDF cross-reference sections"));
+            }
+            log_trace!("PDF cross-reference section at offset {}", offset);
+            let trailer = try!(doc.read_xref_section(offset));
+            // hybrid files have a cross-reference stream which is read before previous sections
+            if let Some(prev) = trailer.get(b"Prev") {
+                pending.push(try!(prev.as_integer("PDF Prev entry")));
+            }
+            if let Some(stream) = trailer.get(b"XRefStm") {
+                pending.push(try!(stream.as_integer("PDF XRefStm entry")));
+            }
+            if doc.trailer.0.is_empty() {
+                doc.trailer = trailer;
+            }
+        }
+        Ok(doc)
+    }
+
+    fn chunk(&mut self, offset: u64) -> Result<Cow<'s, [u8]>> {
+        let position = try!(self.base.checked_add(offset).ok_or_else(|| invalid_format!("invalid PDF offset")));
+        self.source.read_at(position, CHUNK_LEN)
+    }
+
+    /// Reads a cross-reference section and returns its trailer dictionary.
+    fn read_xref_section(&mut self, offset: u64) -> Result<Dictionary> {
+        let pos = {
+            let buf = try!(self.chunk(offset));
+            let mut p = Parser::new(&buf);
+            if p.token() != b"xref" {
+                return self.read_xref_stream(offset);
+            }
+            p.pos
+        };
+
+        let mut subsections = Vec::new();
+        let mut offset = offset + pos as u64;
+        let mut buf = try!(self.chunk(offset));
+        loop {
+            let mut p = Parser::new(&buf);
+            let first = match p.integer() {
+                Some(n) => n,
+                None => {
+                    try!(p.expect_keyword("trailer"));
+                    let trailer = try!(p.object(0)).into_dictionary("PDF trailer");
+                    self.sections.push(Section::Table(subsections));
+                    return trailer;
+                }
+            };
+            let count = try!(p.integer().ok_or_else(|| invalid_format!("invalid PDF cross-reference subsection")));
+            if first.saturating_add(count) > u32::max_value() as u64 {
+                return Err(invalid_format!("invalid PDF cross-reference subsection"));
+            }
+            p.skip_whitespace();
+            let entries = offset + p.pos as u64;
+            subsections.push((first as u32, count as u32, entries));
+            offset = entries + count * 20;
+            buf = try!(self.chunk(offset));
+        }
+    }
+
+    #[cfg(feature = "zlib")]
+    fn read_xref_stream(&mut self, offset: u64) -> Result<Dictionary> {
+        let (dict, data) = try!(self.read_stream(offset));
+        let mut widths = [0; 3];
+        let w = try!(try!(dict.get(b"W").cloned().unwrap_or(Object::Null).into_array("PDF W entry"))
+            .iter().map(|o| o.as_integer("PDF W entry")).collect::<Result<Vec<_>>>());
+        if w.len() != 3 || w.iter().any(|&w| w > 8) {
+            return Err(invalid_format!("invalid PDF cross-reference stream field widths"));
+        }
+        for (w, &v) in widths.iter_mut().zip(&w) {
+            *w = v as usize;
+        }
+
+        let index = match dict.get(b"Index") {
+            Some(index) => {
+                let values = try!(try!(index.clone().into_array("PDF Index entry"))
+                    .iter().map(|o| o.as_integer("PDF Index entry")).collect::<Result<Vec<_>>>());
+                values.chunks(2).filter(|c| c.len() == 2).map(|c| (c[0] as u32, c[1] as u32)).collect()
+            }
+            None => {
+                let size = try!(dict.get(b"Size").unwrap_or(&Object::Null).as_integer("PDF Size entry"));
+                vec![(0, size as u32)]
+            }
+        };
+
+        self.sections.push(Section::Stream { widths: widths, index: index, data: data });
+        Ok(dict)
+    }
+
+    #[cfg(not(feature = "zlib"))]
+    fn read_xref_stream(&mut self, _: u64) -> Result<Dictionary> {
+        Err(invalid_format!("PDF cross-reference streams are only supported when zlib feature is enabled"))
+    }
+
+    /// Reads a stream object at the given offset, returning its dictionary and decoded data.
+    #[cfg(feature = "zlib")]
+    fn read_stream(&mut self, offset: u64) -> Result<(Dictionary, Vec<u8>)> {
+        let buf = try!(self.chunk(offset));
+        let mut p = Parser::new(&buf);
+        try!(p.object_header());
+        let dict = try!(try!(p.object(0)).into_dictionary("PDF stream dictionary"));
+        try!(p.expect_keyword("stream"));
+        // the keyword is followed by CRLF or LF
+        if p.peek() == Some(b'\r') {
+            p.pos += 1;
+        }
+        if p.peek() == Some(b'\n') {
+            p.pos += 1;
+        }
+        let data_offset = offset + p.pos as u64;
+
+        let length = match dict.get(b"Length") {
+            Some(length) => try!(try!(self.resolve(length.clone())).as_integer("PDF stream length")),
+            None => return Err(invalid_format!("PDF stream length is missing"))
+        };
+        let data = try!(self.source.stream_data(self.base + data_offset, length));
+        let data = try!(decode_stream(&dict, data));
+        Ok((dict, data))
+    }
+
+    fn locate(&mut self, number: u32) -> Result<Location> {
+        for section in &self.sections {
+            match *section {
+                Section::Table(ref subsections) => {
+                    for &(first, count, entries) in subsections {
+                        if number >= first && number - first < count {
+                            let position = self.base + entries + (number - first) as u64 * 20;
+                            let entry = try!(self.source.read_at(position, 20));
+                            return parse_xref_entry(&entry);
+                        }
+                    }
+                }
+                Section::Stream { widths, ref index, ref data } => {
+                    let row_len = widths[0] + widths[1] + widths[2];
+                    let mut row = 0;
+                    for &(first, count) in index {
+                        if number >= first && number - first < count {
+                            let start = (row + (number - first) as usize) * row_len;
+                            return match data.get(start..start + row_len) {
+                                Some(entry) => Ok(parse_xref_stream_entry(entry, widths)),
+                                None => Err(unexpected_eof!("when reading PDF cross-reference stream entry"))
+                            };
+                        }
+                        row += count as usize;
+                    }
+                }
+            }
+        }
+        Ok(Location::Free)
+    }
+
+    /// Reads an indirect object.
+    fn fetch(&mut self, number: u32) -> Result<Object> {
+        if self.fetching.contains(&number) {
+            return Err(invalid_format!("PDF object {} refers to itself", number));
+        }
+        self.fetching.push(number);
+        let result = self.read_object(number);
+        self.fetching.pop();
+        result
+    }
+
+    fn read_object(&mut self, number: u32) -> Result<Object> {
+        match try!(self.locate(number)) {
+            // references to free or missing objects are treated as references to null
+            Location::Free => Ok(Object::Null),
+            Location::Offset(offset) => {
+                let buf = try!(self.chunk(offset));
+                let mut p = Parser::new(&buf);
+                if try!(p.object_header()) != number {
+                    return Err(invalid_format!("PDF object {} is not found at its offset", number));
+                }
+                p.object(0)
+            }
+            Location::Compressed(stream, index) => self.fetch_compressed(number, stream, index),
+        }
+    }
+
+    #[cfg(feature = "zlib")]
+    fn fetch_compressed(&mut self, number: u32, stream: u32, index: u64) -> Result<Object> {
+        if self.trailer.get(b"Encrypt").is_some() {
+            return Err(invalid_format!("object streams of encrypted PDF documents are not supported"));
+        }
+        let offset = match try!(self.locate(stream)) {
+            Location::Offset(offset) => offset,
+            _ => return Err(invalid_format!("PDF object stream {} is not found", stream))
+        };
+        let (dict, data) = try!(self.read_stream(offset));
+        let first = try!(dict.get(b"First").unwrap_or(&Object::Null).as_integer("PDF First entry")) as usize;
+
+        // the stream starts with pairs of object numbers and offsets relative to `First`
+        let mut p = Parser::new(&data);
+        for _ in 0..index {
+            if p.integer().and(p.integer()).is_none() {
+                break;
+            }
+        }
+        match (p.integer(), p.integer()) {
+            (Some(n), Some(offset)) if n == number as u64 => {
+                let start = first.saturating_add(offset as usize);
+                match data.get(start..) {
+                    Some(object) => Parser::new(object).object(0),
+                    None => Err(unexpected_eof!("when reading PDF object {}", number))
+                }
+            }
+            _ => Err(invalid_format!("PDF object {} is not found in object stream {}", number, stream))
+        }
+    }
+
+    #[cfg(not(feature = "zlib"))]
+    fn fetch_compressed(&mut self, number: u32, _: u32, _: u64) -> Result<Object> {
+        Err(invalid_format!("PDF object {} is in an object stream, which is only supported when zlib \
+                             feature is enabled", number))
+    }
+
+    /// Replaces an indirect reference with the referenced object.
+    fn resolve(&mut self, object: Object) -> Result<Object> {
+        let mut object = object;
+        for _ in 0..MAX_DEPTH {
+            match object {
+                Object::Reference(number) => object = try!(self.fetch(number)),
+                object => return Ok(object)
+            }
+        }
+        Err(invalid_format!("PDF references are nested too deeply"))
+    }
+
+    fn resolve_entry(&mut self, dict: &Dictionary, key: &[u8]) -> Result<Option<Object>> {
+        match dict.get(key) {
+            Some(o) => self.resolve(o.clone()).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn read_rectangle(&mut self, object: Object) -> Result<Rectangle> {
+        let items = try!(object.into_array("PDF rectangle"));
+        if items.len() != 4 {
+            return Err(invalid_format!("PDF rectangle has {} elements", items.len()));
+        }
+        let mut values = [0.0; 4];
+        for (v, item) in values.iter_mut().zip(items) {
+            *v = try!(try!(self.resolve(item)).as_number("PDF rectangle element"));
+        }
+        Ok(Rectangle {
+            left: values[0].min(values[2]),
+            bottom: values[1].min(values[3]),
+            right: values[0].max(values[2]),
+            top: values[1].max(values[3]),
+        })
+    }
+
+    /// Walks the page tree to the first page and collects its metadata.
+    fn first_page(&mut self) -> Result<Metadata> {
+        let trailer = self.trailer.clone();
+        let catalog = match try!(self.resolve_entry(&trailer, b"Root")) {
+            Some(root) => try!(root.into_dictionary("PDF document catalog")),
+            None => return Err(invalid_format!("PDF document catalog is missing"))
+        };
+        let mut node = match try!(self.resolve_entry(&catalog, b"Pages")) {
+            Some(pages) => try!(pages.into_dictionary("PDF page tree")),
+            None => return Err(invalid_format!("PDF page tree is missing"))
+        };
+        let page_count = match try!(self.resolve_entry(&node, b"Count")) {
+            Some(count) => try!(count.as_integer("PDF page count")) as u32,
+            None => return Err(invalid_format!("PDF page count is missing"))
+        };
+
+        // media box and rotation are inherited from the ancestors of a page
+        let mut media_box = None;
+        let mut rotation = None;
+        for depth in 0.. {
+            if let Some(o) = try!(self.resolve_entry(&node, b"MediaBox")) {
+                media_box = Some(try!(self.read_rectangle(o)));
+            }
+            if let Some(o) = try!(self.resolve_entry(&node, b"Rotate")) {
+                rotation = Some(try!(o.as_number("PDF page rotation")));
+            }
+            let kids = match try!(self.resolve_entry(&node, b"Kids")) {
+                Some(kids) => try!(kids.into_array("PDF page tree node kids")),
+                None => break
+            };
+            if depth >= MAX_DEPTH {
+                return Err(invalid_format!("PDF page tree is too deep"));
+            }
+            node = match kids.into_iter().next() {
+                Some(kid) => try!(try!(self.resolve(kid)).into_dictionary("PDF page tree node")),
+                None => return Err(invalid_format!("PDF document has no pages"))
+            };
+        }
+
+        let media_box = try!(media_box.ok_or_else(|| invalid_format!("PDF page media box is missing")));
+        let rotation = (rotation.unwrap_or(0.0) as i64).rem_euclid(360) as u16 / 90 * 90;
+        let user_unit = match try!(self.resolve_entry(&node, b"UserUnit")) {
+            Some(o) => try!(o.as_number("PDF page user unit")),
+            None => 1.0
+        };
+        let (width, height) = (media_box.width() * user_unit, media_box.height() * user_unit);
+        let (width, height) = if rotation % 180 == 0 { (width, height) } else { (height, width) };
+
+        Ok(Metadata {
+            version: self.version,
+            encrypted: self.trailer.get(b"Encrypt").is_some(),
+            page_count: page_count,
+            media_box: media_box,
+            rotation: rotation,
+            user_unit: user_unit,
+            dimensions: Dimensions { width: width.round() as u32, height: height.round() as u32 },
+        })
+    }
+}
+
+fn find_subslice(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+fn rfind_subslice(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).rposition(|w| w == needle)
+}
+
+/// Parses a 20-byte cross-reference table entry, e.g. `0000012345 00000 n\r\n`.
+fn parse_xref_entry(entry: &[u8]) -> Result<Location> {
+    if entry.len() < 18 {
+        return Err(unexpected_eof!("when reading PDF cross-reference entry"));
+    }
+    match (parse_integer(&entry[..10]), entry[17]) {
+        (Some(offset), b'n') => Ok(Location::Offset(offset)),
+        (Some(_), b'f') => Ok(Location::Free),
+        _ => Err(invalid_format!("invalid PDF cross-reference entry"))
+    }
+}
+
+/// Parses a cross-reference stream entry consisting of three big-endian fields.
+fn parse_xref_stream_entry(entry: &[u8], widths: [usize; 3]) -> Location {
+    let mut fields = [0u64; 3];
+    let mut pos = 0;
+    for (field, &width) in fields.iter_mut().zip(&widths) {
+        *field = entry[pos..pos + width].iter().fold(0, |n, &b| (n << 8) | b as u64);
+        pos += width;
+    }
+    // the type field defaults to 1 when it is omitted
+    let kind = if widths[0] == 0 { 1 } else { fields[0] };
+    match kind {
+        1 => Location::Offset(fields[1]),
+        2 if fields[1] <= u32::max_value() as u64 => Location::Compressed(fields[1] as u32, fields[2]),
+        _ => Location::Free
+    }
+}
+
+/// Decompresses stream data and reverses PNG predictors, which are commonly applied to
+/// cross-reference streams.
+#[cfg(feature = "zlib")]
+fn decode_stream(dict: &Dictionary, data: Cow<[u8]>) -> Result<Vec<u8>> {
+    use flate2::bufread::ZlibDecoder;
+
+    let filters = match dict.get(b"Filter") {
+        None => Vec::new(),
+        Some(&Object::Array(ref filters)) => filters.clone(),
+        Some(filter) => vec![filter.clone()],
+    };
+    let data = match filters.get(0) {
+        None => return Ok(data.into_owned()),
+        Some(&Object::Name(ref name)) if filters.len() == 1 && name == b"FlateDecode" => {
+            let mut result = Vec::new();
+            try!(ZlibDecoder::new(&data[..]).take(MAX_STREAM_SIZE + 1).read_to_end(&mut result)
+                 .map_err(if_eof!(std, "when decompressing PDF stream")));
+            if result.len() as u64 > MAX_STREAM_SIZE {
+                return Err(limit_exceeded!("decompressed PDF stream is larger than {} bytes", MAX_STREAM_SIZE));
+            }
+            result
+        }
+        Some(_) => return Err(invalid_format!("unsupported PDF stream filter"))
+    };
+
+    let params = match dict.get(b"DecodeParms") {
+        Some(&Object::Dictionary(ref params)) => params.clone(),
+        Some(&Object::Array(ref params)) => match params.get(0) {
+            Some(&Object::Dictionary(ref params)) => params.clone(),
+            _ => Dictionary(Vec::new())
+        },
+        _ => Dictionary(Vec::new())
+    };
+    let predictor = try!(params.get(b"Predictor").unwrap_or(&Object::Number(1.0)).as_integer("PDF predictor"));
+    let columns = try!(params.get(b"Columns").unwrap_or(&Object::Number(1.0)).as_integer("PDF predictor columns"));
+    match predictor {
+        1 => Ok(data),
+        10...15 => unpredict_png(&data, columns),
+        _ => Err(invalid_format!("unsupported PDF stream predictor {}", predictor))
+    }
+}
+
+/// Reverses PNG predictors applied to rows of `columns` bytes, each preceded by a filter
+/// type byte.
+#[cfg(feature = "zlib")]
+fn unpredict_png(data: &[u8], columns: u64) -> Result<Vec<u8>> {
+    // each row contains at least one byte besides the filter type, so a valid number of
+    // columns never exceeds the size of the data; checking it also bounds the row buffers
+    if columns == 0 || columns > data.len() as u64 {
+        return Err(invalid_format!("invalid PDF predictor columns {}", columns));
+    }
+    let columns = columns as usize;
+    let row_len = try!(columns.checked_add(1).ok_or_else(|| invalid_format!("invalid PDF predictor columns")));
+    let mut result = Vec::with_capacity(data.len());
+    let mut prev = vec![0u8; columns];
+    for row in data.chunks(row_len) {
+        if row.len() != row_len {
+            return Err(unexpected_eof!("when reading PDF predicted stream row"));
+        }
+        let mut current = row[1..].to_vec();
+        for i in 0..columns {
+            let left = if i > 0 { current[i - 1] } else { 0 };
+            let up = prev[i];
+            let up_left = if i > 0 { prev[i - 1] } else { 0 };
+            let predicted = match row[0] {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => {
+                    let p = left as i16 + up as i16 - up_left as i16;
+                    let (pa, pb, pc) = ((p - left as i16).abs(), (p - up as i16).abs(), (p - up_left as i16).abs());
+                    if pa <= pb && pa <= pc { left } else if pb <= pc { up } else { up_left }
+                }
+                f => return Err(invalid_format!("invalid PNG predictor filter type {}", f))
+            };
+            current[i] = current[i].wrapping_add(predicted);
+        }
+        result.extend_from_slice(&current);
+        prev = current;
+    }
+    Ok(result)
+}
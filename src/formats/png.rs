@@ -1,16 +1,33 @@
 //! Metadata for PNG images.
 
-use std::io::BufRead;
+use std::cmp;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, BufRead, Write, Seek, SeekFrom, Take};
 
 use byteorder::{ReadBytesExt, BigEndian};
 
-use types::{Result, Dimensions};
+use types::{Result, PartialResult, Error, ErrorWithPartial, Completeness, Dimensions, ByteRange};
 use traits::LoadableMetadata;
+use loader::LoadOptions;
+use animation::{Animated, Blend, Disposal, FrameInfo, LoopCount};
+use utils::{self, BufReadExt, Tracked};
+use common::exif::ExifData;
+use common::datetime::DateTime;
+use common::xmp::Xmp;
+use transplant::EmbeddedMetadata;
+
+const SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Maximum size of decompressed text in zTXt and iTXt chunks.
+#[cfg(feature = "zlib")]
+const MAX_TEXT_SIZE: u64 = 16 * 1024 * 1024;
 
 /// Color type used in an image.
 ///
 /// These color types directly corresponds to those defined in PNG spec.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ColorType {
     Grayscale,
     Rgb,
@@ -26,6 +43,56 @@ const CT_GRAYSCALE_ALPHA: u8 = 4;
 const CT_RGB_ALPHA: u8 = 6;
 
 impl ColorType {
+    /// Returns the number of channels in decoded pixels of this color type.
+    ///
+    /// Indexed colors are assumed to be expanded to RGBA by a decoder.
+    pub fn channels(self) -> u8 {
+        match self {
+            ColorType::Grayscale => 1,
+            ColorType::Rgb => 3,
+            ColorType::Indexed => 4,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::RgbAlpha => 4,
+        }
+    }
+
+    /// Returns the number of samples in each pixel as it is stored in the image data.
+    ///
+    /// Unlike `channels()`, this is 1 for indexed colors, whose pixels are palette indices.
+    pub fn samples(self) -> u8 {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::RgbAlpha => 4,
+        }
+    }
+
+    /// Returns `true` if pixels of this color type have an alpha channel.
+    ///
+    /// Note that images of other color types may still be transparent because of a tRNS
+    /// chunk.
+    pub fn has_alpha(self) -> bool {
+        match self {
+            ColorType::GrayscaleAlpha | ColorType::RgbAlpha => true,
+            _ => false
+        }
+    }
+
+    /// Returns the number of bits per pixel for the given bit depth, i.e. the number of bits
+    /// per sample or per palette index.
+    ///
+    /// Returns `None` if the bit depth is not allowed for this color type.
+    pub fn bits_per_pixel(self, bit_depth: u8) -> Option<u8> {
+        let allowed = match self {
+            ColorType::Indexed => bit_depth == 1 || bit_depth == 2 || bit_depth == 4 || bit_depth == 8,
+            ColorType::Grayscale => bit_depth == 1 || bit_depth == 2 || bit_depth == 4 ||
+                                    bit_depth == 8 || bit_depth == 16,
+            _ => bit_depth == 8 || bit_depth == 16,
+        };
+        if allowed { Some(bit_depth * self.samples()) } else { None }
+    }
+
     fn from_u8(n: u8) -> Option<ColorType> {
         match n {
             CT_GRAYSCALE       => Some(ColorType::Grayscale),
@@ -38,32 +105,32 @@ impl ColorType {
     }
 }
 
-fn compute_color_depth(bit_depth: u8, color_type: u8) -> Option<u8> {
-    match color_type {
-        CT_INDEXED => match bit_depth {
-            1 | 2 | 4 | 8 => Some(bit_depth),
-            _ => None
-        },
-        CT_GRAYSCALE => match bit_depth {
-            1 | 2 | 4 | 8 | 16 => Some(bit_depth),
-            _ => None,
-        },
-        CT_GRAYSCALE_ALPHA => match bit_depth {
-            8 | 16 => Some(bit_depth*2),
-            _ => None
-        },
-        CT_RGB => match bit_depth {
-            8 | 16 => Some(bit_depth*3),
-            _ => None
-        },
-        CT_RGB_ALPHA => match bit_depth {
-            8 | 16 => Some(bit_depth*4),
-            _ => None
-        },
-        _ => None
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    /// Converts the color type value of the IHDR chunk.
+    fn try_from(n: u8) -> Result<ColorType> {
+        ColorType::from_u8(n).ok_or(invalid_format!("invalid color type: {}", n))
     }
 }
 
+impl From<ColorType> for u8 {
+    /// Returns the color type value of the IHDR chunk.
+    fn from(color_type: ColorType) -> u8 {
+        match color_type {
+            ColorType::Grayscale      => CT_GRAYSCALE,
+            ColorType::Rgb            => CT_RGB,
+            ColorType::Indexed        => CT_INDEXED,
+            ColorType::GrayscaleAlpha => CT_GRAYSCALE_ALPHA,
+            ColorType::RgbAlpha       => CT_RGB_ALPHA,
+        }
+    }
+}
+
+fn compute_color_depth(bit_depth: u8, color_type: u8) -> Option<u8> {
+    ColorType::from_u8(color_type).and_then(|ct| ct.bits_per_pixel(bit_depth))
+}
+
 /// Compression method used in an image.
 ///
 /// PNG spec currently defines only one compression method:
@@ -71,6 +138,7 @@ fn compute_color_depth(bit_depth: u8, color_type: u8) -> Option<u8> {
 /// > At present, only compression method 0 (deflate/inflate compression with a sliding window of
 /// at most 32768 bytes) is defined.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum CompressionMethod {
     DeflateInflate
 }
@@ -91,6 +159,7 @@ impl CompressionMethod {
 /// > At present, only filter method 0 (adaptive filtering with five basic filter types) is
 /// defined.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum FilterMethod {
     AdaptiveFiltering
 }
@@ -108,6 +177,7 @@ impl FilterMethod {
 ///
 /// PNG spec says that interlacing can be disabled or Adam7 interlace method can be used.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum InterlaceMethod {
     Disabled,
     Adam7
@@ -125,6 +195,7 @@ impl InterlaceMethod {
 
 /// Represents metadata of a PNG image.
 #[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Metadata {
     /// Width and height.
     pub dimensions: Dimensions,
@@ -137,28 +208,1943 @@ pub struct Metadata {
     /// Preprocessing method used in the image.
     pub filter_method: FilterMethod,
     /// Transmission order used in the image.
-    pub interlace_method: InterlaceMethod
+    pub interlace_method: InterlaceMethod,
+    /// Textual information stored in tEXt, zTXt and iTXt chunks, in the order of appearance.
+    pub text_chunks: Vec<TextChunk>,
+    /// Locations of text chunks which have not been parsed yet.
+    ///
+    /// This is only filled when metadata is loaded with `LoadOptions::lazy_extensions`
+    /// enabled, in which case `text_chunks` stays empty until `load_text_chunks()` is called.
+    pub pending_text_chunks: Vec<TextChunkLocation>,
+    /// Image gamma multiplied by 100000, from gAMA chunk.
+    pub gamma: Option<u32>,
+    /// Chromaticities of the primaries and the white point, from cHRM chunk.
+    pub chromaticities: Option<Chromaticities>,
+    /// Rendering intent, from sRGB chunk.
+    ///
+    /// When present, the image samples conform to the sRGB color space, and gAMA and cHRM
+    /// chunks, if any, only provide compatible approximations of it.
+    pub srgb_intent: Option<RenderingIntent>,
+    /// Color space signaling, from cICP chunk.
+    pub cicp: Option<Cicp>,
+    /// Color volume of the display used to master the content, from mDCv chunk.
+    pub mastering_display: Option<MasteringDisplayColorVolume>,
+    /// Content light level information, from cLLi chunk.
+    pub content_light_level: Option<ContentLightLevel>,
+    /// Suggested background color, from bKGD chunk.
+    pub background_color: Option<BackgroundColor>,
+    /// Number of significant bits in each channel of the original image, from sBIT chunk.
+    pub significant_bits: Option<SignificantBits>,
+    /// Whether the stream ends before the end of a complete IEND chunk, e.g. because the file
+    /// was transferred partially.
+    ///
+    /// Truncated images can still be loaded, but they can't be decoded completely.
+    pub truncated: bool,
+    /// Total size of the compressed image data, i.e. of the payload of all IDAT chunks.
+    ///
+    /// Frames of animated images stored in fdAT chunks are not counted.
+    pub image_data_size: u64,
+    /// Parameters from the zlib header at the beginning of the image data.
+    pub zlib_header: Option<ZlibHeader>,
+    /// Position of the image on the page, from oFFs chunk.
+    pub offset: Option<ImageOffset>,
+    /// Physical size of a pixel, from sCAL chunk.
+    pub physical_scale: Option<PhysicalScale>,
+    /// Animation parameters from acTL chunk; only present in animated PNG (APNG) images.
+    pub animation_control: Option<AnimationControl>,
+    /// Parameters of animation frames from fcTL chunks, in the order of appearance.
+    pub frame_controls: Vec<FrameControl>,
+    /// Whether the default image, i.e. the one stored in IDAT chunks, is the first frame of
+    /// the animation.
+    ///
+    /// When it is `false`, the default image is only displayed by decoders which do not
+    /// support APNG, and the animation consists of frames stored in fdAT chunks.
+    pub default_image_is_first_frame: bool,
+    /// EXIF data from eXIf chunk.
+    pub exif: Option<ExifData>,
+    /// Location of the payload of the eXIf chunk, if it has not been parsed yet.
+    ///
+    /// This is only set when metadata is loaded with `LoadOptions::lazy_extensions` enabled,
+    /// in which case `exif` stays empty until `load_extensions()` is called.
+    pub pending_exif: Option<ByteRange>,
+    /// Time of the last modification of the image, in UTC, from tIME chunk.
+    pub modification_time: Option<DateTime>,
 }
 
-impl LoadableMetadata for Metadata {
-    fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Metadata> {
+/// CIE 1931 chromaticities of the primaries and the white point of an image.
+///
+/// All values are x and y coordinates multiplied by 100000.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Chromaticities {
+    pub white_point: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+impl Chromaticities {
+    fn read(r: &mut BufRead) -> Result<Chromaticities> {
+        let mut values = [0u32; 8];
+        for v in &mut values {
+            *v = try_if_eof!(r.read_u32::<BigEndian>(), "when reading chromaticities");
+        }
+        Ok(Chromaticities {
+            white_point: (values[0], values[1]),
+            red: (values[2], values[3]),
+            green: (values[4], values[5]),
+            blue: (values[6], values[7]),
+        })
+    }
+}
+
+/// Rendering intent, as defined by the ICC.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric
+}
+
+impl RenderingIntent {
+    fn from_u8(n: u8) -> Option<RenderingIntent> {
+        match n {
+            0 => Some(RenderingIntent::Perceptual),
+            1 => Some(RenderingIntent::RelativeColorimetric),
+            2 => Some(RenderingIntent::Saturation),
+            3 => Some(RenderingIntent::AbsoluteColorimetric),
+            _ => None
+        }
+    }
+}
+
+/// Coding-independent code points identifying the color space of an image.
+///
+/// The values are defined in ITU-T H.273. When present, this information overrides gAMA,
+/// cHRM, sRGB and iCCP chunks.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Cicp {
+    /// Color primaries, e.g. 1 for BT.709 or 9 for BT.2020.
+    pub color_primaries: u8,
+    /// Transfer function, e.g. 13 for sRGB, 16 for PQ or 18 for HLG.
+    pub transfer_function: u8,
+    /// Matrix coefficients; always 0 (RGB) in PNG images.
+    pub matrix_coefficients: u8,
+    /// Whether the samples use the full range rather than the narrow "video" range.
+    pub full_range: bool,
+}
+
+impl Cicp {
+    /// Returns `true` if the transfer function is an HDR one, i.e. PQ or HLG.
+    #[inline]
+    pub fn is_hdr(&self) -> bool {
+        self.transfer_function == 16 || self.transfer_function == 18
+    }
+}
+
+/// Color volume of a mastering display, as defined in SMPTE ST 2086.
+///
+/// Chromaticities are in units of 0.00002, luminance values are in units of 0.0001 cd/m².
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MasteringDisplayColorVolume {
+    /// Chromaticities of red, green and blue primaries.
+    pub primaries: [(u16, u16); 3],
+    /// Chromaticity of the white point.
+    pub white_point: (u16, u16),
+    /// Maximum luminance of the display.
+    pub max_luminance: u32,
+    /// Minimum luminance of the display.
+    pub min_luminance: u32,
+}
+
+impl MasteringDisplayColorVolume {
+    fn read(r: &mut BufRead) -> Result<MasteringDisplayColorVolume> {
+        let mut values = [0u16; 8];
+        for v in &mut values {
+            *v = try_if_eof!(r.read_u16::<BigEndian>(), "when reading mastering display chromaticities");
+        }
+        let max_luminance = try_if_eof!(r.read_u32::<BigEndian>(), "when reading mastering display luminance");
+        let min_luminance = try_if_eof!(r.read_u32::<BigEndian>(), "when reading mastering display luminance");
+        Ok(MasteringDisplayColorVolume {
+            primaries: [(values[0], values[1]), (values[2], values[3]), (values[4], values[5])],
+            white_point: (values[6], values[7]),
+            max_luminance: max_luminance,
+            min_luminance: min_luminance,
+        })
+    }
+}
+
+/// Content light level information, as defined in CTA-861.3.
+///
+/// Values are in units of 0.0001 cd/m²; zero means that the value is unknown.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ContentLightLevel {
+    /// Maximum content light level of any pixel.
+    pub max_content_light_level: u32,
+    /// Maximum frame-average light level.
+    pub max_frame_average_light_level: u32,
+}
+
+/// Suggested background color of an image.
+///
+/// Viewers may use this color when there is no better choice, e.g. as the matte color when
+/// flattening transparent images. Samples have the bit depth of the image.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BackgroundColor {
+    /// Index in the palette; used for indexed images.
+    PaletteIndex(u8),
+    /// Gray level; used for grayscale images with or without alpha.
+    Gray(u16),
+    /// Red, green and blue samples; used for RGB images with or without alpha.
+    Rgb(u16, u16, u16),
+}
+
+impl BackgroundColor {
+    fn read(r: &mut BufRead, color_type: ColorType) -> Result<BackgroundColor> {
+        Ok(match color_type {
+            ColorType::Indexed => BackgroundColor::PaletteIndex(
+                try_if_eof!(r.read_u8(), "when reading background palette index")
+            ),
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => BackgroundColor::Gray(
+                try_if_eof!(r.read_u16::<BigEndian>(), "when reading background gray level")
+            ),
+            ColorType::Rgb | ColorType::RgbAlpha => {
+                let mut rgb = [0u16; 3];
+                for v in &mut rgb {
+                    *v = try_if_eof!(r.read_u16::<BigEndian>(), "when reading background color");
+                }
+                BackgroundColor::Rgb(rgb[0], rgb[1], rgb[2])
+            }
+        })
+    }
+}
+
+/// Number of significant bits in each channel of an image.
+///
+/// Images whose sample depth is not supported by PNG are stored with a larger bit depth;
+/// this information allows recovering the original samples. For indexed images the bits
+/// relate to the palette entries.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SignificantBits {
+    /// Gray channel.
+    Gray(u8),
+    /// Gray and alpha channels.
+    GrayAlpha(u8, u8),
+    /// Red, green and blue channels.
+    Rgb(u8, u8, u8),
+    /// Red, green, blue and alpha channels.
+    RgbAlpha(u8, u8, u8, u8),
+}
+
+impl SignificantBits {
+    fn read(r: &mut BufRead, color_type: ColorType) -> Result<SignificantBits> {
+        let mut bits = [0u8; 4];
+        let n = match color_type {
+            ColorType::Grayscale => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb | ColorType::Indexed => 3,
+            ColorType::RgbAlpha => 4,
+        };
+        try!(r.read_exact(&mut bits[..n]).map_err(if_eof!(std, "when reading significant bits")));
+        if bits[..n].iter().any(|&b| b == 0 || b > 16) {
+            return Err(invalid_format!("invalid number of significant bits: {:?}", &bits[..n]));
+        }
+        Ok(match n {
+            1 => SignificantBits::Gray(bits[0]),
+            2 => SignificantBits::GrayAlpha(bits[0], bits[1]),
+            3 => SignificantBits::Rgb(bits[0], bits[1], bits[2]),
+            _ => SignificantBits::RgbAlpha(bits[0], bits[1], bits[2], bits[3]),
+        })
+    }
+}
+
+/// Compression level hint stored in a zlib header.
+///
+/// The hint is informational only; it is set by the compressor and is not needed for
+/// decompression.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CompressionLevel {
+    Fastest,
+    Fast,
+    Default,
+    Maximum
+}
+
+/// Parameters of a zlib stream, as stored in its header.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ZlibHeader {
+    /// Size of the LZ77 window in bytes, from 256 to 32768.
+    pub window_size: u32,
+    /// Compression level used by the compressor.
+    pub compression_level: CompressionLevel,
+    /// Whether a preset dictionary is used; it is not allowed in PNG images.
+    pub preset_dictionary: bool,
+}
+
+impl ZlibHeader {
+    fn parse(cmf: u8, flg: u8) -> Result<ZlibHeader> {
+        if cmf & 0x0f != 8 || cmf >> 4 > 7 {
+            return Err(invalid_format!("invalid zlib compression method: 0x{:02X}", cmf));
+        }
+        if (cmf as u16 * 256 + flg as u16) % 31 != 0 {
+            return Err(invalid_format!("invalid zlib header check bits: 0x{:02X}{:02X}", cmf, flg));
+        }
+        Ok(ZlibHeader {
+            window_size: 1 << ((cmf >> 4) + 8),
+            compression_level: match flg >> 6 {
+                0 => CompressionLevel::Fastest,
+                1 => CompressionLevel::Fast,
+                2 => CompressionLevel::Default,
+                _ => CompressionLevel::Maximum,
+            },
+            preset_dictionary: flg & 0x20 != 0,
+        })
+    }
+}
+
+/// Unit of an image offset.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OffsetUnit {
+    Pixel,
+    Micrometer
+}
+
+/// Position of an image on the page, e.g. for printing.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ImageOffset {
+    /// Offset from the left edge of the page.
+    pub x: i32,
+    /// Offset from the top edge of the page.
+    pub y: i32,
+    /// Unit of the offsets.
+    pub unit: OffsetUnit,
+}
+
+impl ImageOffset {
+    fn read(r: &mut BufRead) -> Result<ImageOffset> {
+        let x = try_if_eof!(r.read_i32::<BigEndian>(), "when reading image x offset");
+        let y = try_if_eof!(r.read_i32::<BigEndian>(), "when reading image y offset");
+        let unit = try_if_eof!(r.read_u8(), "when reading image offset unit");
+        Ok(ImageOffset {
+            x: x,
+            y: y,
+            unit: match unit {
+                0 => OffsetUnit::Pixel,
+                1 => OffsetUnit::Micrometer,
+                n => return Err(invalid_format!("invalid image offset unit: {}", n))
+            },
+        })
+    }
+}
+
+/// Unit of a physical scale.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScaleUnit {
+    Meter,
+    Radian
+}
+
+/// Physical size of a pixel, used e.g. in maps and scientific images.
+///
+/// The values are stored as decimal floating-point strings in the image, and they are kept
+/// as is to avoid losing precision; use `width_value()` and `height_value()` to parse them.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PhysicalScale {
+    /// Unit of the values.
+    pub unit: ScaleUnit,
+    /// Width of a pixel.
+    pub width: String,
+    /// Height of a pixel.
+    pub height: String,
+}
+
+impl PhysicalScale {
+    /// Returns the width of a pixel as a number, if it is valid.
+    #[inline]
+    pub fn width_value(&self) -> Option<f64> {
+        self.width.parse().ok()
+    }
+
+    /// Returns the height of a pixel as a number, if it is valid.
+    #[inline]
+    pub fn height_value(&self) -> Option<f64> {
+        self.height.parse().ok()
+    }
+
+    fn parse(data: &[u8]) -> Result<PhysicalScale> {
+        if data.is_empty() {
+            return Err(unexpected_eof!("when reading physical scale unit"));
+        }
+        let unit = match data[0] {
+            1 => ScaleUnit::Meter,
+            2 => ScaleUnit::Radian,
+            n => return Err(invalid_format!("invalid physical scale unit: {}", n))
+        };
+        let (width, height) = try!(split_nul(&data[1..], "physical scale width"));
+        if width.is_empty() || height.is_empty() {
+            return Err(invalid_format!("empty physical scale value"));
+        }
+        Ok(PhysicalScale {
+            unit: unit,
+            width: latin1(width),
+            height: latin1(height),
+        })
+    }
+}
+
+/// Animation parameters of an APNG image.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AnimationControl {
+    /// Number of frames in the animation.
+    pub num_frames: u32,
+    /// Number of times the animation is played; zero means infinite looping.
+    pub num_plays: u32,
+}
+
+/// Parameters of a single APNG animation frame.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FrameControl {
+    /// Sequence number of the fcTL chunk, shared with fdAT chunks.
+    pub sequence_number: u32,
+    /// Width and height of the frame.
+    pub dimensions: Dimensions,
+    /// Horizontal offset of the frame on the canvas.
+    pub x_offset: u32,
+    /// Vertical offset of the frame on the canvas.
+    pub y_offset: u32,
+    /// Numerator of the frame delay in seconds.
+    pub delay_num: u16,
+    /// Denominator of the frame delay in seconds; zero means 100.
+    pub delay_den: u16,
+    /// What happens to the frame area after the frame is displayed.
+    pub dispose_op: Disposal,
+    /// How the frame is composed with the canvas.
+    pub blend_op: Blend,
+}
+
+impl FrameControl {
+    /// Returns the frame delay in milliseconds.
+    pub fn delay_ms(&self) -> u32 {
+        let den = if self.delay_den == 0 { 100 } else { self.delay_den as u32 };
+        self.delay_num as u32 * 1000 / den
+    }
+
+    fn frame_info(&self) -> FrameInfo {
+        FrameInfo {
+            left: self.x_offset,
+            top: self.y_offset,
+            dimensions: self.dimensions,
+            delay_ms: self.delay_ms(),
+            disposal: self.dispose_op,
+            blend: self.blend_op,
+        }
+    }
+
+    fn read(r: &mut BufRead) -> Result<FrameControl> {
+        let sequence_number = try_if_eof!(r.read_u32::<BigEndian>(), "when reading frame sequence number");
+        let width = try_if_eof!(r.read_u32::<BigEndian>(), "when reading frame width");
+        let height = try_if_eof!(r.read_u32::<BigEndian>(), "when reading frame height");
+        let x_offset = try_if_eof!(r.read_u32::<BigEndian>(), "when reading frame x offset");
+        let y_offset = try_if_eof!(r.read_u32::<BigEndian>(), "when reading frame y offset");
+        let delay_num = try_if_eof!(r.read_u16::<BigEndian>(), "when reading frame delay numerator");
+        let delay_den = try_if_eof!(r.read_u16::<BigEndian>(), "when reading frame delay denominator");
+        let dispose_op = try_if_eof!(r.read_u8(), "when reading frame dispose operation");
+        let blend_op = try_if_eof!(r.read_u8(), "when reading frame blend operation");
+
+        Ok(FrameControl {
+            sequence_number: sequence_number,
+            dimensions: (width, height).into(),
+            x_offset: x_offset,
+            y_offset: y_offset,
+            delay_num: delay_num,
+            delay_den: delay_den,
+            dispose_op: match dispose_op {
+                0 => Disposal::None,
+                1 => Disposal::Background,
+                2 => Disposal::Previous,
+                n => return Err(invalid_format!("invalid frame dispose operation: {}", n))
+            },
+            blend_op: match blend_op {
+                0 => Blend::Source,
+                1 => Blend::Over,
+                n => return Err(invalid_format!("invalid frame blend operation: {}", n))
+            },
+        })
+    }
+}
+
+/// Type of a textual chunk.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TextChunkKind {
+    /// tEXt chunk with uncompressed Latin-1 text.
+    Text,
+    /// zTXt chunk with compressed Latin-1 text.
+    CompressedText,
+    /// iTXt chunk with optionally compressed UTF-8 text.
+    InternationalText,
+}
+
+/// Textual information stored in a tEXt, zTXt or iTXt chunk.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TextChunk {
+    /// Type of the chunk.
+    pub kind: TextChunkKind,
+    /// Keyword identifying the kind of the text, e.g. `Title`, `Author` or `Software`.
+    pub keyword: String,
+    /// Whether the text is stored compressed.
+    pub compressed: bool,
+    /// Language of the text, e.g. `en-US`; only present in iTXt chunks.
+    pub language_tag: Option<String>,
+    /// Keyword translated to the language of the text; only present in iTXt chunks.
+    pub translated_keyword: Option<String>,
+    /// The text itself.
+    ///
+    /// Compressed text is only available when `zlib` feature is enabled; otherwise, and also
+    /// when decompression fails, this field is `None`.
+    pub text: Option<String>,
+}
+
+/// Location of a text chunk in the stream, recorded instead of parsing the chunk when text
+/// chunks are loaded lazily.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TextChunkLocation {
+    /// Type of the chunk, i.e. `tEXt`, `zTXt` or `iTXt`.
+    pub chunk_type: [u8; 4],
+    /// Offset of the chunk payload from the beginning of the PNG stream.
+    pub offset: u64,
+    /// Length of the chunk payload.
+    pub len: u32,
+}
+
+/// Decodes Latin-1 text.
+fn latin1(data: &[u8]) -> String {
+    data.iter().map(|&b| b as char).collect()
+}
+
+/// Splits the data at the first NUL byte, returning parts before and after it.
+fn split_nul<'a>(data: &'a [u8], what: &str) -> Result<(&'a [u8], &'a [u8])> {
+    match data.iter().position(|&b| b == 0) {
+        Some(i) => Ok((&data[..i], &data[i + 1..])),
+        None => Err(unexpected_eof!("when reading {}", what))
+    }
+}
+
+#[cfg(feature = "zlib")]
+fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    use flate2::bufread::ZlibDecoder;
+
+    let mut result = Vec::new();
+    match ZlibDecoder::new(data).take(MAX_TEXT_SIZE + 1).read_to_end(&mut result) {
+        Ok(n) if n as u64 > MAX_TEXT_SIZE => {
+            log_debug!("compressed PNG text is larger than {} bytes", MAX_TEXT_SIZE);
+            None
+        }
+        Ok(_) => Some(result),
+        Err(e) => {
+            log_debug!("failed to decompress PNG text: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "zlib"))]
+fn decompress(_: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(feature = "zlib")]
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    try!(encoder.write_all(data));
+    Ok(try!(encoder.finish()))
+}
+
+#[cfg(not(feature = "zlib"))]
+fn compress(_: &[u8]) -> Result<Vec<u8>> {
+    Err(invalid_format!("compressed PNG chunks can only be written when zlib feature is enabled"))
+}
+
+/// Encodes text in Latin-1, failing if it contains other characters.
+fn to_latin1(s: &str, what: &str) -> Result<Vec<u8>> {
+    s.chars()
+        .map(|c| if (c as u32) < 0x100 { Ok(c as u8) } else {
+            Err(invalid_format!("{} can't be encoded in Latin-1: {:?}", what, s))
+        })
+        .collect()
+}
+
+impl TextChunk {
+    /// Creates an uncompressed tEXt chunk with Latin-1 text.
+    pub fn new<K: Into<String>, T: Into<String>>(keyword: K, text: T) -> TextChunk {
+        TextChunk {
+            kind: TextChunkKind::Text,
+            keyword: keyword.into(),
+            compressed: false,
+            language_tag: None,
+            translated_keyword: None,
+            text: Some(text.into()),
+        }
+    }
+
+    /// Creates an uncompressed iTXt chunk with UTF-8 text and without a language tag.
+    pub fn international<K: Into<String>, T: Into<String>>(keyword: K, text: T) -> TextChunk {
+        TextChunk {
+            kind: TextChunkKind::InternationalText,
+            keyword: keyword.into(),
+            compressed: false,
+            language_tag: Some(String::new()),
+            translated_keyword: Some(String::new()),
+            text: Some(text.into()),
+        }
+    }
+
+    /// Serializes the chunk, returning its type and payload.
+    ///
+    /// The `compressed` flag is only used for iTXt chunks; zTXt chunks are always compressed
+    /// and tEXt chunks never are.
+    fn to_chunk(&self) -> Result<([u8; 4], Vec<u8>)> {
+        let text = match self.text {
+            Some(ref text) => text,
+            None => return Err(invalid_format!("text of {} chunk is missing", self.keyword))
+        };
+        let mut payload = try!(to_latin1(&self.keyword, "text chunk keyword"));
+        if payload.is_empty() || payload.len() > 79 || payload.contains(&0) {
+            return Err(invalid_format!("invalid text chunk keyword: {:?}", self.keyword));
+        }
+        payload.push(0);
+
+        match self.kind {
+            TextChunkKind::Text => {
+                payload.extend(try!(to_latin1(text, "tEXt chunk text")));
+                Ok((*b"tEXt", payload))
+            }
+            TextChunkKind::CompressedText => {
+                payload.push(0);
+                payload.extend(try!(compress(&try!(to_latin1(text, "zTXt chunk text")))));
+                Ok((*b"zTXt", payload))
+            }
+            TextChunkKind::InternationalText => {
+                payload.extend_from_slice(&[self.compressed as u8, 0]);
+                let language_tag = self.language_tag.as_ref().map(|s| &**s).unwrap_or("");
+                payload.extend(try!(to_latin1(language_tag, "iTXt language tag")));
+                payload.push(0);
+                payload.extend_from_slice(self.translated_keyword.as_ref().map(|s| s.as_bytes()).unwrap_or(b""));
+                payload.push(0);
+                if self.compressed {
+                    payload.extend(try!(compress(text.as_bytes())));
+                } else {
+                    payload.extend_from_slice(text.as_bytes());
+                }
+                Ok((*b"iTXt", payload))
+            }
+        }
+    }
+
+    fn parse(chunk_type: &[u8; 4], data: &[u8]) -> Result<TextChunk> {
+        let (keyword, rest) = try!(split_nul(data, "text chunk keyword"));
+        if keyword.is_empty() || keyword.len() > 79 {
+            return Err(invalid_format!("invalid text chunk keyword length: {}", keyword.len()));
+        }
+        let keyword = latin1(keyword);
+
+        match chunk_type {
+            b"tEXt" => Ok(TextChunk {
+                kind: TextChunkKind::Text,
+                keyword: keyword,
+                compressed: false,
+                language_tag: None,
+                translated_keyword: None,
+                text: Some(latin1(rest)),
+            }),
+            b"zTXt" => {
+                if rest.is_empty() {
+                    return Err(unexpected_eof!("when reading zTXt compression method"));
+                }
+                if rest[0] != 0 {
+                    return Err(invalid_format!("invalid zTXt compression method: {}", rest[0]));
+                }
+                Ok(TextChunk {
+                    kind: TextChunkKind::CompressedText,
+                    keyword: keyword,
+                    compressed: true,
+                    language_tag: None,
+                    translated_keyword: None,
+                    text: decompress(&rest[1..]).map(|text| latin1(&text)),
+                })
+            }
+            _ => {
+                if rest.len() < 2 {
+                    return Err(unexpected_eof!("when reading iTXt compression flag"));
+                }
+                let compressed = match rest[0] {
+                    0 => false,
+                    1 => true,
+                    n => return Err(invalid_format!("invalid iTXt compression flag: {}", n))
+                };
+                if compressed && rest[1] != 0 {
+                    return Err(invalid_format!("invalid iTXt compression method: {}", rest[1]));
+                }
+                let (language_tag, rest) = try!(split_nul(&rest[2..], "iTXt language tag"));
+                let (translated_keyword, text) = try!(split_nul(rest, "iTXt translated keyword"));
+                let text = if compressed { decompress(text) } else { Some(text.to_vec()) };
+                Ok(TextChunk {
+                    kind: TextChunkKind::InternationalText,
+                    keyword: keyword,
+                    compressed: compressed,
+                    language_tag: Some(latin1(language_tag)),
+                    translated_keyword: Some(String::from_utf8_lossy(translated_keyword).into_owned()),
+                    text: text.map(|text| String::from_utf8_lossy(&text).into_owned()),
+                })
+            }
+        }
+    }
+}
+
+impl Metadata {
+    /// Returns the text associated with the given keyword in the first textual chunk
+    /// which has it, if any.
+    pub fn text(&self, keyword: &str) -> Option<&str> {
+        self.text_chunks.iter()
+            .filter(|chunk| chunk.keyword == keyword)
+            .filter_map(|chunk| chunk.text.as_ref())
+            .map(|text| &text[..])
+            .next()
+    }
+
+    /// Returns the XMP packet stored in the iTXt chunk with `XML:com.adobe.xmp` keyword, if
+    /// it is present and valid.
+    pub fn xmp(&self) -> Option<Xmp> {
+        self.text("XML:com.adobe.xmp").and_then(|text| Xmp::from_bytes(text.as_bytes()).ok())
+    }
+
+    /// Returns the image gamma stored in gAMA chunk as a floating-point number.
+    ///
+    /// This is the exponent relating image samples to the intensity of the original scene,
+    /// e.g. 0.45455 for images with sRGB-like encoding.
+    pub fn gamma_value(&self) -> Option<f64> {
+        self.gamma.map(|g| g as f64 / 100000.0)
+    }
+
+    /// Returns `true` if the image declares an HDR transfer function in cICP chunk.
+    #[inline]
+    pub fn is_hdr(&self) -> bool {
+        self.cicp.map(|c| c.is_hdr()).unwrap_or(false)
+    }
+
+    /// Returns `true` if the image declares that it is in the sRGB color space.
+    #[inline]
+    pub fn is_srgb(&self) -> bool {
+        self.srgb_intent.is_some()
+    }
+
+    /// Returns the size of the uncompressed image data, i.e. of the filtered scanlines which
+    /// are compressed into IDAT chunks.
+    pub fn raw_data_size(&self) -> u64 {
+        const ADAM7_PASSES: [(u64, u64, u64, u64); 7] = [
+            (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)
+        ];
+
+        let width = self.dimensions.width as u64;
+        let height = self.dimensions.height as u64;
+        let bpp = self.color_depth as u64;
+        // each scanline is preceded by a filter type byte
+        let size = |w: u64, h: u64| if w == 0 { 0 } else { h * ((w * bpp + 7) / 8 + 1) };
+
+        match self.interlace_method {
+            InterlaceMethod::Disabled => size(width, height),
+            InterlaceMethod::Adam7 => ADAM7_PASSES.iter().map(|&(x0, y0, dx, dy)| {
+                let w = if width > x0 { (width - x0 + dx - 1) / dx } else { 0 };
+                let h = if height > y0 { (height - y0 + dy - 1) / dy } else { 0 };
+                size(w, h)
+            }).sum()
+        }
+    }
+
+    /// Returns the ratio of the uncompressed image data size to the compressed one, if there
+    /// is any image data.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        if self.image_data_size == 0 {
+            None
+        } else {
+            Some(self.raw_data_size() as f64 / self.image_data_size as f64)
+        }
+    }
+
+    /// Returns the bit depth of the image, i.e. the number of bits per sample or per palette
+    /// index, as it is stored in the IHDR chunk.
+    #[inline]
+    pub fn bit_depth(&self) -> u8 {
+        self.color_depth / self.color_type.samples()
+    }
+
+    /// Returns an estimate of the size in bytes of the decoded image.
+    ///
+    /// Samples of less than 8 bits are assumed to be expanded to 8 bits, and indexed colors
+    /// are assumed to be expanded to 8-bit RGBA. Each frame of an animated image is assumed
+    /// to be decoded into a separate buffer of the canvas size.
+    pub fn estimated_decoded_size(&self) -> u64 {
+        let bytes_per_channel = if self.bit_depth() > 8 { 2 } else { 1 };
+        let frames = self.animation_control.map(|ac| cmp::max(ac.num_frames, 1)).unwrap_or(1);
+        utils::decoded_size(self.dimensions, self.color_type.channels() as u64, bytes_per_channel, frames as u64)
+    }
+}
+
+/// A low-level reader of PNG chunks.
+///
+/// PNG stream is a signature followed by a sequence of chunks. `Chunks` reads these chunks one
+/// by one, providing access to their payload; the payload which is not read explicitly is
+/// skipped automatically, together with the CRC. High-level `Metadata` is loaded with this
+/// reader, but it can also be used directly to access ancillary and private chunks this library
+/// does not interpret.
+///
+/// Iteration stops after the IEND chunk or at the end of the stream.
+///
+/// Since chunks borrow the reader, `Chunks` can't implement `Iterator`. It should be used like
+/// this instead:
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use immeta::formats::png::Chunks;
+///
+/// let mut chunks = Chunks::new(BufReader::new(File::open("kitty.png").unwrap()));
+/// while let Some(chunk) = chunks.next() {
+///     let chunk = chunk.unwrap();
+///     println!("{} at {}, {} bytes", chunk.type_str(), chunk.offset(), chunk.len());
+/// }
+/// ```
+pub struct Chunks<R: BufRead> {
+    source: Tracked<R>,
+    chunk_end: u64,
+    started: bool,
+    finished: bool,
+}
+
+impl<R: BufRead> Chunks<R> {
+    /// Creates a new chunk reader for a PNG stream which starts at the current position of
+    /// the provided reader.
+    pub fn new(source: R) -> Chunks<R> {
+        Chunks {
+            source: Tracked::new(source),
+            chunk_end: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Returns the number of bytes consumed from the underlying reader so far.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.source.position
+    }
+
+    /// Unwraps this chunk reader, returning the underlying reader.
+    ///
+    /// Unread payload of the last chunk is not skipped.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.source.inner
+    }
+
+    /// Reads the next chunk.
+    ///
+    /// Returns `None` when there are no more chunks. The PNG signature is checked before the
+    /// first chunk is read; if it is invalid, an error is returned.
+    pub fn next(&mut self) -> Option<Result<Chunk<R>>> {
+        if self.finished {
+            return None;
+        }
+        match self.read_header() {
+            Ok(Some((offset, chunk_type, len))) => Some(Ok(self.chunk(offset, chunk_type, len))),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn read_header(&mut self) -> Result<Option<(u64, [u8; 4], u32)>> {
+        if !self.started {
+            self.started = true;
+            let mut signature = [0u8; 8];
+            try!(self.source.read_exact(&mut signature).map_err(if_eof!(std, "when reading PNG signature")));
+            if &signature != SIGNATURE {
+                return Err(invalid_format!("invalid PNG header: {:?}", signature));
+            }
+        }
+
+        if self.source.position < self.chunk_end {
+            let n = self.chunk_end - self.source.position;
+            log_trace!("skipping {} bytes of PNG chunk payload and CRC", n);
+            if try!(self.source.skip_exact(n)) != n {
+                return Err(unexpected_eof!("when skipping chunk payload"));
+            }
+        }
+
+        // the end of the stream is only allowed at a chunk boundary
+        if try!(self.source.fill_buf()).is_empty() {
+            return Ok(None);
+        }
+
+        let offset = self.source.position;
+        let len = try_if_eof!(self.source.read_u32::<BigEndian>(), "when reading chunk length");
+        if len > 0x7fff_ffff {
+            return Err(invalid_format!("invalid PNG chunk length: {}", len));
+        }
+        let mut chunk_type = [0u8; 4];
+        try!(self.source.read_exact(&mut chunk_type).map_err(if_eof!(std, "when reading chunk type")));
+        log_trace!("PNG chunk {:?} at offset {}, length {}", chunk_type, offset, len);
+
+        Ok(Some((offset, chunk_type, len)))
+    }
+
+    fn chunk(&mut self, offset: u64, chunk_type: [u8; 4], len: u32) -> Chunk<R> {
+        if &chunk_type == b"IEND" {
+            self.finished = true;
+        }
+        self.chunk_end = self.source.position + len as u64 + 4;
+
+        Chunk {
+            chunk_type: chunk_type,
+            offset: offset,
+            len: len,
+            crc: None,
+            payload: (&mut self.source).take(len as u64),
+        }
+    }
+}
+
+/// A single PNG chunk.
+///
+/// Values of this type are obtained with `Chunks::next()`.
+pub struct Chunk<'a, R: BufRead + 'a> {
+    chunk_type: [u8; 4],
+    offset: u64,
+    len: u32,
+    crc: Option<u32>,
+    payload: Take<&'a mut Tracked<R>>,
+}
+
+impl<'a, R: BufRead + 'a> Chunk<'a, R> {
+    /// Returns the chunk type, e.g. `*b"IHDR"`.
+    #[inline]
+    pub fn chunk_type(&self) -> [u8; 4] { self.chunk_type }
+
+    /// Returns the chunk type as a string.
+    ///
+    /// Valid chunk types consist of ASCII letters only; other bytes are replaced with `?`.
+    pub fn type_str(&self) -> String {
+        self.chunk_type.iter()
+            .map(|&b| if (b as char).is_ascii_alphabetic() { b as char } else { '?' })
+            .collect()
+    }
+
+    /// Returns `true` if this chunk is critical, i.e. a decoder must understand it.
+    #[inline]
+    pub fn is_critical(&self) -> bool { self.chunk_type[0] & 0x20 == 0 }
+
+    /// Returns `true` if this chunk type is private, i.e. not defined by the PNG specification
+    /// or registered.
+    #[inline]
+    pub fn is_private(&self) -> bool { self.chunk_type[1] & 0x20 != 0 }
+
+    /// Returns `true` if this chunk may be copied by editors which do not understand it.
+    #[inline]
+    pub fn is_safe_to_copy(&self) -> bool { self.chunk_type[3] & 0x20 != 0 }
+
+    /// Returns the offset of the chunk, i.e. of its length field, from the beginning of the
+    /// PNG stream.
+    #[inline]
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// Returns the size of the chunk payload, not including the length, type and CRC fields.
+    #[inline]
+    pub fn len(&self) -> u32 { self.len }
+
+    /// Returns a reader of the chunk payload.
+    #[inline]
+    pub fn payload(&mut self) -> &mut BufRead {
+        &mut self.payload
+    }
+
+    /// Reads the remaining chunk payload into a vector.
+    pub fn read_payload(&mut self) -> Result<Vec<u8>> {
+        let n = self.payload.limit();
+        Ok(try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(&mut self.payload, n),
+                       "when reading payload of PNG chunk {}", self.type_str()))
+    }
+
+    /// Returns the CRC stored after the chunk payload.
+    ///
+    /// The remaining payload is skipped, so it can't be read after this method is called.
+    /// The CRC is not verified.
+    pub fn crc(&mut self) -> Result<u32> {
+        if let Some(crc) = self.crc {
+            return Ok(crc);
+        }
+        let n = self.payload.limit();
+        if try!(io::copy(&mut self.payload, &mut io::sink())) != n {
+            return Err(unexpected_eof!("when skipping payload of PNG chunk {}", self.type_str()));
+        }
+        let crc = try_if_eof!(self.payload.get_mut().read_u32::<BigEndian>(), "when reading chunk CRC");
+        self.crc = Some(crc);
+        Ok(crc)
+    }
+}
+
+/// Checks that the stream ends with a complete IEND chunk by seeking to its end.
+///
+/// This is much faster than reading all chunks, so it can be used to detect truncated files
+/// quickly, but it also rejects files with trailing data after IEND chunk. The position of the
+/// stream is restored afterwards.
+pub fn ends_with_iend<R: ?Sized + Read + Seek>(r: &mut R) -> Result<bool> {
+    const IEND: &'static [u8; 12] = b"\0\0\0\0IEND\xae\x42\x60\x82";
+
+    let position = try!(r.seek(SeekFrom::Current(0)));
+    let end = try!(r.seek(SeekFrom::End(0)));
+    let result = if end < IEND.len() as u64 {
+        Ok(false)
+    } else {
+        let mut trailer = [0u8; 12];
+        r.seek(SeekFrom::End(-(IEND.len() as i64)))
+            .and_then(|_| r.read_exact(&mut trailer))
+            .map(|_| &trailer == IEND)
+            .map_err(From::from)
+    };
+    try!(r.seek(SeekFrom::Start(position)));
+    result
+}
+
+/// Finds the image data of a PNG stream starting at the current position.
+///
+/// Compressed image data may be split between IDAT chunks arbitrarily, so each range covers
+/// a whole run of consecutive IDAT chunks, including their headers and CRCs. Valid images
+/// have exactly one such run. Frame data of APNG images stored in fdAT chunks is not
+/// included.
+pub fn image_data_ranges<R: ?Sized + BufRead>(r: &mut R) -> Result<Vec<ByteRange>> {
+    let mut ranges: Vec<ByteRange> = Vec::new();
+    let mut previous_idat = false;
+    let mut chunks = Chunks::new(r);
+    while let Some(chunk) = chunks.next() {
+        let chunk = try!(chunk);
+        let idat = &chunk.chunk_type() == b"IDAT";
+        if idat {
+            // length, type and CRC surround the payload
+            let len = chunk.len() as u64 + 12;
+            match ranges.last_mut() {
+                Some(range) if previous_idat => range.len += len,
+                _ => ranges.push(ByteRange { offset: chunk.offset(), len: len })
+            }
+        }
+        previous_idat = idat;
+    }
+    Ok(ranges)
+}
+
+/// Checks whether a PNG stream starting at the current position is complete, i.e. whether
+/// its IEND chunk ends before or at the end of the stream.
+///
+/// Only chunk headers are read, and payloads are skipped by seeking, so this is almost as
+/// fast as `ends_with_iend()`, but data following IEND chunk is reported instead of being
+/// treated as an error. The position of the stream is restored afterwards.
+pub fn verify_complete<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Completeness> {
+    utils::verify_length(r, |r| {
         let mut signature = [0u8; 8];
         try!(r.read_exact(&mut signature).map_err(if_eof!(std, "when reading PNG signature")));
+        if &signature != SIGNATURE {
+            return Err(invalid_format!("invalid PNG signature: {:?}", signature));
+        }
 
-        if &signature != b"\x89PNG\r\n\x1a\n" {
-            return Err(invalid_format!("invalid PNG header: {:?}", signature));
+        let mut len = SIGNATURE.len() as u64;
+        loop {
+            let mut header = [0u8; 8];
+            try!(r.read_exact(&mut header).map_err(if_eof!(std, "when reading PNG chunk header")));
+            let payload_len = try!((&header[..4]).read_u32::<BigEndian>());
+            // length, type, payload and CRC
+            len += 12 + payload_len as u64;
+            if &header[4..] == b"IEND" {
+                return Ok(len);
+            }
+            try!(r.seek(SeekFrom::Current(payload_len as i64 + 4)));
         }
+    })
+}
 
-        // chunk length
-        let _ = try!(r.read_u32::<BigEndian>().map_err(if_eof!("when reading chunk length")));
-        
-        let mut chunk_type = [0u8; 4];
-        try!(r.read_exact(&mut chunk_type).map_err(if_eof!(std, "when reading chunk type")));
+/// Keyword of the iTXt chunk which contains an XMP packet.
+const XMP_KEYWORD: &'static str = "XML:com.adobe.xmp";
+
+/// Ancillary chunks which affect how the image is displayed, kept by
+/// `ChunkEditor::strip_ancillary()`.
+const RENDERING_CHUNKS: &'static [&'static [u8; 4]] = &[
+    b"tRNS", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT", b"cICP", b"mDCv", b"cLLi",
+    b"acTL", b"fcTL", b"fdAT",
+];
+
+#[derive(Clone, Debug)]
+enum Insertion {
+    Chunk([u8; 4], Vec<u8>),
+    Text(TextChunk),
+}
 
-        if &chunk_type != b"IHDR" {
-            return Err(invalid_format!("invalid PNG chunk: {:?}", chunk_type));
+/// An editor which copies a PNG stream while removing and inserting chunks.
+///
+/// Chunks which are not removed are copied byte for byte, including their CRCs, and so is
+/// any data following the IEND chunk, so the image data is never recompressed. Inserted
+/// chunks are written with freshly computed CRCs in the order they were added: bKGD, hIST
+/// and tRNS chunks right before the first IDAT chunk and all other chunks right before
+/// the PLTE chunk, or before the first IDAT chunk if there is no palette. Critical chunks
+/// are never removed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::{BufReader, BufWriter};
+/// use immeta::formats::png::{ChunkEditor, TextChunk};
+///
+/// let mut r = BufReader::new(File::open("kitty.png").unwrap());
+/// let mut w = BufWriter::new(File::create("kitty-clean.png").unwrap());
+/// ChunkEditor::new()
+///     .strip_ancillary(true)
+///     .insert_text(TextChunk::new("Software", "kittycat 1.0"))
+///     .copy(&mut r, &mut w)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChunkEditor {
+    strip_ancillary: bool,
+    keep: Vec<[u8; 4]>,
+    remove: Vec<[u8; 4]>,
+    remove_text: Vec<String>,
+    insert: Vec<Insertion>,
+}
+
+impl Default for ChunkEditor {
+    fn default() -> ChunkEditor {
+        ChunkEditor::new()
+    }
+}
+
+impl ChunkEditor {
+    /// Creates an editor which copies the stream unchanged.
+    pub fn new() -> ChunkEditor {
+        ChunkEditor {
+            strip_ancillary: false,
+            keep: Vec::new(),
+            remove: Vec::new(),
+            remove_text: Vec::new(),
+            insert: Vec::new(),
         }
+    }
+
+    /// Sets whether all ancillary chunks should be removed.
+    ///
+    /// Chunks which affect how the image is displayed are kept: transparency (tRNS), color
+    /// space information (gAMA, cHRM, sRGB, iCCP, sBIT, cICP, mDCv, cLLi) and animation
+    /// (acTL, fcTL, fdAT). Other chunks can be kept with `keep()`, and these ones can still
+    /// be removed with `remove()`.
+    #[inline]
+    pub fn strip_ancillary(mut self, strip: bool) -> ChunkEditor {
+        self.strip_ancillary = strip;
+        self
+    }
+
+    /// Keeps chunks of the given type when ancillary chunks are stripped.
+    #[inline]
+    pub fn keep(mut self, chunk_type: [u8; 4]) -> ChunkEditor {
+        self.keep.push(chunk_type);
+        self
+    }
+
+    /// Removes all chunks of the given type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunk type is critical.
+    pub fn remove(mut self, chunk_type: [u8; 4]) -> ChunkEditor {
+        assert!(chunk_type[0] & 0x20 != 0, "critical PNG chunks can't be removed: {:?}", chunk_type);
+        self.remove.push(chunk_type);
+        self
+    }
+
+    /// Removes all textual chunks (tEXt, zTXt and iTXt) with the given keyword.
+    #[inline]
+    pub fn remove_text<S: Into<String>>(mut self, keyword: S) -> ChunkEditor {
+        self.remove_text.push(keyword.into());
+        self
+    }
 
+    /// Inserts a textual chunk.
+    ///
+    /// The chunk type is determined by `TextChunk::kind`. Invalid chunks, e.g. ones with
+    /// an empty keyword or tEXt chunks with text which can't be represented in Latin-1, cause
+    /// an error when the stream is copied.
+    #[inline]
+    pub fn insert_text(mut self, chunk: TextChunk) -> ChunkEditor {
+        self.insert.push(Insertion::Text(chunk));
+        self
+    }
+
+    /// Inserts a chunk with the given type and payload.
+    ///
+    /// The payload is not validated, so it is up to the caller to make sure that the chunk
+    /// is valid and allowed to appear before the image data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunk type does not consist of ASCII letters.
+    pub fn insert_chunk(mut self, chunk_type: [u8; 4], payload: Vec<u8>) -> ChunkEditor {
+        assert!(chunk_type.iter().all(|&b| (b as char).is_ascii_alphabetic()),
+                "invalid PNG chunk type: {:?}", chunk_type);
+        self.insert.push(Insertion::Chunk(chunk_type, payload));
+        self
+    }
+
+    /// Copies the PNG stream, applying the edits, and returns the number of removed chunks.
+    pub fn copy<R: ?Sized + BufRead, W: ?Sized + Write>(&self, r: &mut R, w: &mut W) -> Result<usize> {
+        let mut insertions = Vec::with_capacity(self.insert.len());
+        for insertion in &self.insert {
+            insertions.push(match *insertion {
+                Insertion::Chunk(chunk_type, ref payload) => (chunk_type, payload.clone()),
+                Insertion::Text(ref chunk) => try!(chunk.to_chunk())
+            });
+        }
+
+        let (late, early): (Vec<_>, Vec<_>) = insertions.into_iter()
+            .partition(|&(ref chunk_type, _)| AFTER_PLTE_CHUNKS.contains(&chunk_type));
+
+        try!(w.write_all(SIGNATURE));
+        let mut chunks = Chunks::new(&mut *r);
+        let mut first = true;
+        let mut inserted_early = false;
+        let mut inserted = false;
+        let mut removed = 0;
+        while let Some(chunk) = chunks.next() {
+            let mut chunk = try!(chunk);
+            let chunk_type = chunk.chunk_type();
+            if first && &chunk_type != b"IHDR" {
+                return Err(invalid_format!("first PNG chunk is not IHDR: {}", chunk.type_str()));
+            }
+            first = false;
+
+            let is_data = &chunk_type == b"IDAT" || &chunk_type == b"IEND";
+            if !inserted_early && (is_data || &chunk_type == b"PLTE") {
+                for &(ref chunk_type, ref payload) in &early {
+                    try!(write_chunk(w, chunk_type, payload));
+                }
+                inserted_early = true;
+            }
+            if !inserted && is_data {
+                for &(ref chunk_type, ref payload) in &late {
+                    try!(write_chunk(w, chunk_type, payload));
+                }
+                inserted = true;
+            }
+
+            // the keyword of textual chunks is only read when it is needed
+            let is_text = match &chunk_type {
+                b"tEXt" | b"zTXt" | b"iTXt" => true,
+                _ => false
+            };
+            let head = if is_text && !self.remove_text.is_empty() {
+                try!(chunk.read_payload())
+            } else {
+                Vec::new()
+            };
+
+            if self.removes(&chunk_type, if is_text { Some(&head) } else { None }) {
+                log_debug!("removing PNG chunk {} with {} bytes of payload", chunk.type_str(), chunk.len());
+                try!(chunk.crc());
+                removed += 1;
+            } else {
+                try!(copy_chunk(&mut chunk, &head, w));
+            }
+        }
+        if first {
+            return Err(unexpected_eof!("when reading IHDR chunk"));
+        }
+        if !inserted {
+            log_debug!("PNG stream ended without IDAT and IEND chunks");
+            let early = if inserted_early { &early[..0] } else { &early[..] };
+            for &(ref chunk_type, ref payload) in early.iter().chain(&late) {
+                try!(write_chunk(w, chunk_type, payload));
+            }
+        }
+
+        try!(io::copy(chunks.into_inner(), w));
+        Ok(removed)
+    }
+
+    fn removes(&self, chunk_type: &[u8; 4], text_payload: Option<&[u8]>) -> bool {
+        if chunk_type[0] & 0x20 == 0 {
+            return false;
+        }
+        if self.remove.contains(chunk_type) {
+            return true;
+        }
+        if let Some(payload) = text_payload {
+            let keyword = payload.split(|&b| b == 0).next().unwrap_or(payload);
+            if self.remove_text.iter().any(|k| k.chars().map(|c| c as u32).eq(keyword.iter().map(|&b| b as u32))) {
+                return true;
+            }
+        }
+        self.strip_ancillary && !self.keep.contains(chunk_type) && !RENDERING_CHUNKS.contains(&chunk_type)
+    }
+}
+
+/// Copies a PNG stream while inserting the provided XMP packet, replacing the existing one.
+///
+/// The packet is written into an uncompressed iTXt chunk with `XML:com.adobe.xmp` keyword
+/// before the image data, and existing XMP chunks are removed. This is a shortcut for
+/// a `ChunkEditor` with the respective edits, so all other chunks are copied unchanged.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::{BufReader, BufWriter};
+/// use immeta::common::xmp::Xmp;
+/// use immeta::formats::png;
+///
+/// let xmp = Xmp::new(r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">...</x:xmpmeta>"#);
+/// let mut r = BufReader::new(File::open("kitty.png").unwrap());
+/// let mut w = BufWriter::new(File::create("kitty-tagged.png").unwrap());
+/// png::write_xmp(&mut r, &mut w, &xmp).unwrap();
+/// ```
+pub fn write_xmp<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W, xmp: &Xmp) -> Result<()> {
+    try!(ChunkEditor::new()
+        .remove_text(XMP_KEYWORD)
+        .insert_text(TextChunk::international(XMP_KEYWORD, xmp.as_str()))
+        .copy(r, w));
+    Ok(())
+}
+
+/// Extracts EXIF data, the XMP packet and the ICC profile from a PNG stream.
+///
+/// Only the chunks preceding the image data are read. The ICC profile is compressed, so it
+/// is only extracted when `zlib` feature is enabled. Invalid chunks are ignored.
+pub fn extract_metadata<R: ?Sized + BufRead>(r: &mut R) -> Result<EmbeddedMetadata> {
+    let mut md = EmbeddedMetadata::default();
+    let mut chunks = Chunks::new(&mut *r);
+    while let Some(chunk) = chunks.next() {
+        let mut chunk = try!(chunk);
+        match &chunk.chunk_type() {
+            b"IDAT" | b"IEND" => break,
+            b"eXIf" if md.exif.is_none() => {
+                let offset = chunk.offset() + 8;
+                match chunk.read_payload().and_then(|data| ExifData::new(data, offset)) {
+                    Ok(exif) => md.exif = Some(exif),
+                    Err(e) => log_debug!("skipping invalid eXIf chunk: {}", e)
+                }
+            }
+            b"iCCP" if md.icc_profile.is_none() => {
+                let data = try!(chunk.read_payload());
+                // profile name and compression method precede the compressed profile
+                match split_nul(&data, "iCCP profile name") {
+                    Ok((_, rest)) if rest.len() > 1 => match decompress(&rest[1..]) {
+                        Some(profile) => md.icc_profile = Some(profile),
+                        None => log_debug!("skipping iCCP chunk which can't be decompressed")
+                    },
+                    Ok(_) => log_debug!("skipping iCCP chunk without compressed profile"),
+                    Err(e) => log_debug!("skipping invalid iCCP chunk: {}", e)
+                }
+            }
+            b"iTXt" if md.xmp.is_none() => {
+                let chunk_type = chunk.chunk_type();
+                match chunk.read_payload().and_then(|data| TextChunk::parse(&chunk_type, &data)) {
+                    Ok(ref text) if text.keyword == XMP_KEYWORD => {
+                        md.xmp = text.text.as_ref().and_then(|text| Xmp::from_bytes(text.as_bytes()).ok());
+                    }
+                    Ok(_) => {}
+                    Err(e) => log_debug!("skipping invalid iTXt chunk: {}", e)
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(md)
+}
+
+/// Copies a PNG stream while embedding the provided metadata.
+///
+/// Each kind of metadata which is present replaces the respective chunks: eXIf for EXIF
+/// data, the iTXt chunk described in `write_xmp()` for the XMP packet, and iCCP for the ICC
+/// profile, which also replaces sRGB chunk since the two can't be used together. New chunks
+/// are inserted as described for `ChunkEditor`, which this function is a shortcut for.
+///
+/// The ICC profile is compressed, so it can only be embedded when `zlib` feature is enabled;
+/// otherwise an error is returned.
+pub fn embed_metadata<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W,
+                                                              md: &EmbeddedMetadata) -> Result<()> {
+    let mut editor = ChunkEditor::new();
+    if let Some(ref exif) = md.exif {
+        editor = editor.remove(*b"eXIf").insert_chunk(*b"eXIf", exif.as_bytes().to_vec());
+    }
+    if let Some(ref profile) = md.icc_profile {
+        let mut payload = b"ICC profile\0\0".to_vec();
+        payload.extend(try!(compress(profile)));
+        editor = editor.remove(*b"iCCP").remove(*b"sRGB").insert_chunk(*b"iCCP", payload);
+    }
+    if let Some(ref xmp) = md.xmp {
+        editor = editor
+            .remove_text(XMP_KEYWORD)
+            .insert_text(TextChunk::international(XMP_KEYWORD, xmp.as_str()));
+    }
+    try!(editor.copy(r, w));
+    Ok(())
+}
+
+/// Copies a PNG stream while setting its physical pixel density in dots per inch.
+///
+/// The density is written into a pHYs chunk in pixels per meter, rounded to the nearest
+/// integer, e.g. 72 DPI becomes 2835 pixels per meter. An existing pHYs chunk is replaced.
+/// This is a shortcut for a `ChunkEditor` with the respective edits, so all other chunks are
+/// copied unchanged.
+///
+/// # Panics
+///
+/// Panics if a density is not positive or does not fit into a pHYs chunk, i.e. exceeds
+/// 2<sup>31</sup> - 1 pixels per meter.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::{BufReader, BufWriter};
+/// use immeta::formats::png;
+///
+/// let mut r = BufReader::new(File::open("kitty.png").unwrap());
+/// let mut w = BufWriter::new(File::create("kitty-print.png").unwrap());
+/// png::set_dpi(&mut r, &mut w, 300.0, 300.0).unwrap();
+/// ```
+pub fn set_dpi<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W, x_dpi: f64, y_dpi: f64) -> Result<()> {
+    let to_ppm = |dpi: f64| {
+        let ppm = (dpi / 0.0254).round();
+        assert!(ppm >= 1.0 && ppm <= 0x7fff_ffff as f64, "invalid pixel density: {} DPI", dpi);
+        ppm as u32
+    };
+    let (x, y) = (to_ppm(x_dpi), to_ppm(y_dpi));
+    let payload = vec![
+        (x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8,
+        (y >> 24) as u8, (y >> 16) as u8, (y >> 8) as u8, y as u8,
+        1,  // unit is the meter
+    ];
+    try!(ChunkEditor::new().remove(*b"pHYs").insert_chunk(*b"pHYs", payload).copy(r, w));
+    Ok(())
+}
+
+/// Copies a chunk whose payload has been partially read into `head`, together with the
+/// original CRC.
+fn copy_chunk<R: BufRead, W: ?Sized + Write>(chunk: &mut Chunk<R>, head: &[u8], w: &mut W) -> Result<()> {
+    let len = chunk.len();
+    try!(w.write_all(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]));
+    try!(w.write_all(&chunk.chunk_type()));
+    try!(w.write_all(head));
+    let rest = chunk.payload.limit();
+    if try!(io::copy(&mut chunk.payload, w)) != rest {
+        return Err(unexpected_eof!("when reading payload of PNG chunk {}", chunk.type_str()));
+    }
+    let crc = try!(chunk.crc());
+    try!(w.write_all(&[(crc >> 24) as u8, (crc >> 16) as u8, (crc >> 8) as u8, crc as u8]));
+    Ok(())
+}
+
+/// Writes a new chunk, computing its CRC.
+fn write_chunk<W: ?Sized + Write>(w: &mut W, chunk_type: &[u8; 4], payload: &[u8]) -> Result<()> {
+    if payload.len() > 0x7fff_ffff {
+        return Err(limit_exceeded!("PNG chunk payload of {} bytes is too large", payload.len()));
+    }
+    let len = payload.len() as u32;
+    let crc = crc32(chunk_type, payload);
+    try!(w.write_all(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]));
+    try!(w.write_all(chunk_type));
+    try!(w.write_all(payload));
+    try!(w.write_all(&[(crc >> 24) as u8, (crc >> 16) as u8, (crc >> 8) as u8, crc as u8]));
+    Ok(())
+}
+
+/// Computes the CRC of a chunk, which covers its type and payload.
+///
+/// Chunks written by this library are small, so the CRC is computed bit by bit without
+/// a lookup table.
+fn crc32(chunk_type: &[u8; 4], payload: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in chunk_type.iter().chain(payload) {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Kind of a structural violation found by `validate()`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ViolationKind {
+    /// The first chunk is not IHDR.
+    IhdrNotFirst,
+    /// A chunk which may only appear once is repeated.
+    DuplicateChunk,
+    /// A critical chunk is not known to this library, so decoders are unlikely to know it too.
+    UnknownCriticalChunk,
+    /// The reserved bit of the chunk type, i.e. the case of its third letter, is set.
+    ReservedBitSet,
+    /// A chunk which must precede PLTE follows it.
+    ChunkAfterPlte,
+    /// A chunk which must follow PLTE precedes it.
+    ChunkBeforePlte,
+    /// A chunk which must precede the image data follows it.
+    ChunkAfterIdat,
+    /// IDAT chunks are interleaved with other chunks.
+    NonConsecutiveIdat,
+    /// An indexed image does not have PLTE chunk.
+    MissingPlte,
+    /// A grayscale image has PLTE chunk.
+    UnexpectedPlte,
+    /// Both sRGB and iCCP chunks are present.
+    ConflictingColorProfiles,
+    /// There are no IDAT chunks.
+    MissingIdat,
+    /// The stream ends without IEND chunk.
+    MissingIend,
+    /// The stream contains data after IEND chunk.
+    DataAfterIend,
+}
+
+/// A structural violation found by `validate()`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Violation {
+    /// Kind of the violation.
+    pub kind: ViolationKind,
+    /// Offset of the offending chunk or, if there is no such chunk, of the place where a chunk
+    /// is expected, from the beginning of the PNG stream.
+    pub offset: u64,
+    /// Type of the offending chunk, if any.
+    pub chunk_type: Option<[u8; 4]>,
+}
+
+impl ViolationKind {
+    /// Returns a short human-readable description of the violation.
+    pub fn description(self) -> &'static str {
+        match self {
+            ViolationKind::IhdrNotFirst => "the first chunk is not IHDR",
+            ViolationKind::DuplicateChunk => "chunk must not be repeated",
+            ViolationKind::UnknownCriticalChunk => "unknown critical chunk",
+            ViolationKind::ReservedBitSet => "reserved bit of chunk type is set",
+            ViolationKind::ChunkAfterPlte => "chunk must precede PLTE",
+            ViolationKind::ChunkBeforePlte => "chunk must follow PLTE",
+            ViolationKind::ChunkAfterIdat => "chunk must precede IDAT",
+            ViolationKind::NonConsecutiveIdat => "IDAT chunks are not consecutive",
+            ViolationKind::MissingPlte => "indexed image does not have PLTE",
+            ViolationKind::UnexpectedPlte => "grayscale image has PLTE",
+            ViolationKind::ConflictingColorProfiles => "both sRGB and iCCP are present",
+            ViolationKind::MissingIdat => "no IDAT chunks",
+            ViolationKind::MissingIend => "no IEND chunk",
+            ViolationKind::DataAfterIend => "data after IEND",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = self.kind.description();
+        match self.chunk_type {
+            Some(ref t) => write!(f, "{} ({} at offset {})", description, String::from_utf8_lossy(t), self.offset),
+            None => write!(f, "{} (at offset {})", description, self.offset),
+        }
+    }
+}
+
+const KNOWN_CRITICAL_CHUNKS: &'static [&'static [u8; 4]] = &[b"IHDR", b"PLTE", b"IDAT", b"IEND"];
+
+const UNIQUE_CHUNKS: &'static [&'static [u8; 4]] = &[
+    b"IHDR", b"PLTE", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCv", b"cLLi",
+    b"bKGD", b"hIST", b"tRNS", b"eXIf", b"pHYs", b"oFFs", b"sCAL", b"pCAL", b"tIME", b"acTL",
+];
+
+const BEFORE_PLTE_CHUNKS: &'static [&'static [u8; 4]] = &[
+    b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCv", b"cLLi",
+];
+
+const AFTER_PLTE_CHUNKS: &'static [&'static [u8; 4]] = &[b"bKGD", b"hIST", b"tRNS"];
+
+const BEFORE_IDAT_CHUNKS: &'static [&'static [u8; 4]] = &[
+    b"PLTE", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCv", b"cLLi", b"bKGD",
+    b"hIST", b"tRNS", b"eXIf", b"pHYs", b"sPLT", b"oFFs", b"sCAL", b"pCAL", b"acTL",
+];
+
+/// Checks chunk ordering and other structural constraints of a PNG stream without decoding it.
+///
+/// The stream is read until the end. All found violations are returned in the order of their
+/// offsets; an empty vector means that the structure is valid. Note that chunk payloads and
+/// CRCs are not checked. An error is returned if the stream can't be read as a sequence of
+/// chunks, e.g. if the signature is invalid or the stream is truncated.
+pub fn validate<R: BufRead>(r: R) -> Result<Vec<Violation>> {
+    let mut chunks = Chunks::new(r);
+    let mut violations = Vec::new();
+    let mut seen: Vec<[u8; 4]> = Vec::new();
+    let mut color_type = None;
+    let mut plte_offset = None;
+    let mut after_plte = Vec::new();
+    let mut idat_ended = false;
+    let mut last_type = None;
+    let mut end = 8;
+
+    {
+        let mut violation = |kind, offset, chunk_type| violations.push(Violation {
+            kind: kind,
+            offset: offset,
+            chunk_type: chunk_type,
+        });
+
+        while let Some(chunk) = chunks.next() {
+            let mut chunk = try!(chunk);
+            let t = chunk.chunk_type();
+            let offset = chunk.offset();
+            end = offset + chunk.len() as u64 + 12;
+
+            if seen.is_empty() {
+                if &t == b"IHDR" {
+                    let data = try!(chunk.read_payload());
+                    color_type = data.get(9).cloned().and_then(ColorType::from_u8);
+                } else {
+                    violation(ViolationKind::IhdrNotFirst, offset, Some(t));
+                }
+            }
+
+            if t[2] & 0x20 != 0 {
+                violation(ViolationKind::ReservedBitSet, offset, Some(t));
+            }
+            if chunk.is_critical() && !KNOWN_CRITICAL_CHUNKS.contains(&&t) {
+                violation(ViolationKind::UnknownCriticalChunk, offset, Some(t));
+            }
+            if seen.contains(&t) {
+                if UNIQUE_CHUNKS.contains(&&t) || &t == b"IEND" {
+                    violation(ViolationKind::DuplicateChunk, offset, Some(t));
+                }
+                if &t == b"IDAT" && idat_ended {
+                    violation(ViolationKind::NonConsecutiveIdat, offset, Some(t));
+                }
+            }
+            if plte_offset.is_some() && BEFORE_PLTE_CHUNKS.contains(&&t) {
+                violation(ViolationKind::ChunkAfterPlte, offset, Some(t));
+            }
+            if seen.contains(b"IDAT") && BEFORE_IDAT_CHUNKS.contains(&&t) {
+                violation(ViolationKind::ChunkAfterIdat, offset, Some(t));
+            }
+            if plte_offset.is_none() && AFTER_PLTE_CHUNKS.contains(&&t) {
+                after_plte.push((offset, t));
+            }
+
+            if &t == b"PLTE" {
+                if plte_offset.is_none() {
+                    for &(offset, t) in &after_plte {
+                        violation(ViolationKind::ChunkBeforePlte, offset, Some(t));
+                    }
+                    plte_offset = Some(offset);
+                }
+                match color_type {
+                    Some(ColorType::Grayscale) | Some(ColorType::GrayscaleAlpha) =>
+                        violation(ViolationKind::UnexpectedPlte, offset, Some(t)),
+                    _ => {}
+                }
+            }
+            if (&t == b"sRGB" && seen.contains(b"iCCP")) || (&t == b"iCCP" && seen.contains(b"sRGB")) {
+                violation(ViolationKind::ConflictingColorProfiles, offset, Some(t));
+            }
+            if last_type == Some(*b"IDAT") && &t != b"IDAT" {
+                idat_ended = true;
+            }
+
+            last_type = Some(t);
+            if !seen.contains(&t) {
+                seen.push(t);
+            }
+        }
+
+        if color_type == Some(ColorType::Indexed) && plte_offset.is_none() {
+            violation(ViolationKind::MissingPlte, end, None);
+        }
+        if !seen.contains(b"IDAT") {
+            violation(ViolationKind::MissingIdat, end, None);
+        }
+        if last_type != Some(*b"IEND") {
+            violation(ViolationKind::MissingIend, end, None);
+        }
+    }
+
+    if last_type == Some(*b"IEND") {
+        // the chunk reader stops right after IEND header, so skip the rest of the chunk
+        let rest = end - chunks.position();
+        let mut r = chunks.into_inner();
+        try!(r.skip_exact(rest));
+        if !try!(r.fill_buf()).is_empty() {
+            violations.push(Violation {
+                kind: ViolationKind::DataAfterIend,
+                offset: end,
+                chunk_type: None,
+            });
+        }
+    }
+
+    violations.sort_by_key(|v| v.offset);
+    Ok(violations)
+}
+
+impl Animated for Metadata {
+    /// Returns `true` if the image is an APNG image with more than one frame.
+    fn is_animated(&self) -> bool {
+        self.animation_control.map(|ac| ac.num_frames > 1).unwrap_or(false)
+    }
+
+    fn loop_count(&self) -> Option<LoopCount> {
+        self.animation_control.map(|ac| match ac.num_plays {
+            0 => LoopCount::Infinite,
+            n => LoopCount::Finite(n)
+        })
+    }
+
+    /// Returns frames described by fcTL chunks.
+    ///
+    /// For images which are not animated this vector is empty.
+    fn frames(&self) -> Vec<FrameInfo> {
+        self.frame_controls.iter().map(FrameControl::frame_info).collect()
+    }
+}
+
+/// Visits frames of an APNG image in display order as they are read from the stream.
+///
+/// The callback is called with information about each frame as soon as its fcTL chunk is
+/// read, and returns whether reading should continue. Other chunks are skipped without being
+/// parsed, and nothing is read after the callback returns `false`. Frame information is the
+/// same as `Animated::frames()` returns for the loaded `Metadata`; in particular, images
+/// which are not animated have no frames.
+///
+/// Returns the number of frames visited, including the one for which the callback returned
+/// `false`.
+pub fn for_each_frame<R: ?Sized + BufRead, F>(r: &mut R, mut f: F) -> Result<usize>
+    where F: FnMut(FrameInfo) -> bool
+{
+    let mut chunks = Chunks::new(r);
+    match chunks.next() {
+        Some(chunk) => match try!(chunk).chunk_type() {
+            ref t if t == b"IHDR" => {}
+            t => return Err(invalid_format!("invalid PNG chunk: {:?}", t))
+        },
+        None => return Err(unexpected_eof!("when reading IHDR chunk"))
+    }
+
+    let mut frames = 0;
+    let mut animated = false;
+    let mut seen_idat = false;
+    while let Some(chunk) = chunks.next() {
+        let mut chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e @ Error::LimitExceeded(_)) => return Err(e),
+            Err(e) => {
+                log_debug!("stopped reading PNG chunks: {}", e);
+                break;
+            }
+        };
+        match &chunk.chunk_type() {
+            b"IDAT" => seen_idat = true,
+            b"IEND" => break,
+            b"acTL" if !seen_idat && chunk.len() >= 8 => animated = true,
+            b"fcTL" if animated => match FrameControl::read(chunk.payload()) {
+                Ok(fc) => {
+                    frames += 1;
+                    if !f(fc.frame_info()) {
+                        break;
+                    }
+                }
+                Err(e) => log_debug!("skipping invalid fcTL chunk: {}", e)
+            },
+            _ => {}
+        }
+    }
+    Ok(frames)
+}
+
+/// Reads image dimensions from a PNG stream, stopping right after them.
+///
+/// This reads exactly 24 bytes, that is, the PNG signature and the beginning of the IHDR
+/// chunk, unlike `Metadata::load()` which reads the whole stream.
+pub fn read_dimensions<R: ?Sized + BufRead>(r: &mut R) -> Result<Dimensions> {
+    let mut chunks = Chunks::new(r);
+    let mut chunk = match chunks.next() {
+        Some(chunk) => try!(chunk),
+        None => return Err(unexpected_eof!("when reading IHDR chunk"))
+    };
+    if &chunk.chunk_type() != b"IHDR" {
+        return Err(invalid_format!("invalid PNG chunk: {:?}", chunk.chunk_type()));
+    }
+
+    let r = chunk.payload();
+    let width = try_if_eof!(r.read_u32::<BigEndian>(), "when reading width");
+    let height = try_if_eof!(r.read_u32::<BigEndian>(), "when reading height");
+    Ok((width, height).into())
+}
+
+/// Parses PNG metadata directly from an in-memory buffer.
+///
+/// This is equivalent to `Metadata::load_from_buf()`. Chunks are located in the buffer
+/// directly and their payloads are parsed in place, without going through `Chunks`, a
+/// reader or any intermediate buffers; `Metadata::load()` parses chunks read from a stream
+/// with the same code.
+///
+/// This function can be used as a fuzz target; see `immeta::fuzz`.
+pub fn parse_bytes(data: &[u8]) -> Result<Metadata> {
+    if data.len() < SIGNATURE.len() {
+        return Err(unexpected_eof!("when reading PNG signature"));
+    }
+    if &data[..SIGNATURE.len()] != SIGNATURE {
+        return Err(invalid_format!("invalid PNG header: {:?}", &data[..SIGNATURE.len()]));
+    }
+
+    let mut chunks = SliceChunks { data: data, pos: SIGNATURE.len() as u64, finished: false };
+    let chunk = match chunks.next() {
+        Some(chunk) => try!(chunk),
+        None => return Err(unexpected_eof!("when reading IHDR chunk"))
+    };
+    if &chunk.chunk_type != b"IHDR" {
+        return Err(invalid_format!("invalid PNG chunk: {:?}", chunk.chunk_type));
+    }
+    let mut parser = try!(Parser::new(chunk.payload, false));
+
+    while let Some(chunk) = chunks.next() {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                log_debug!("stopped reading PNG chunks: {}", e);
+                break;
+            }
+        };
+        if &chunk.chunk_type == b"IEND" {
+            if chunk.complete {
+                parser.md.truncated = false;
+            } else {
+                log_debug!("failed to read IEND chunk: unexpected end of file");
+            }
+            break;
+        }
+        let n = cmp::min(parser.payload_len(&chunk.chunk_type, chunk.len), chunk.payload.len() as u64);
+        parser.chunk(chunk.chunk_type, chunk.offset, chunk.len, &chunk.payload[..n as usize]);
+    }
+
+    Ok(parser.md)
+}
+
+/// A chunk located by `parse_bytes()`.
+struct SliceChunk<'a> {
+    chunk_type: [u8; 4],
+    offset: u64,
+    len: u32,
+    /// The payload, which is shorter than `len` if the buffer ends inside of it.
+    payload: &'a [u8],
+    /// Whether the buffer contains the whole payload and the CRC.
+    complete: bool,
+}
+
+/// Locates chunks in a buffer like `Chunks` does in a stream, failing in the same cases.
+struct SliceChunks<'a> {
+    data: &'a [u8],
+    pos: u64,
+    finished: bool,
+}
+
+impl<'a> SliceChunks<'a> {
+    fn next(&mut self) -> Option<Result<SliceChunk<'a>>> {
+        if self.finished {
+            return None;
+        }
+        let result = self.read_chunk();
+        match result {
+            Ok(Some(ref chunk)) if &chunk.chunk_type != b"IEND" => {}
+            _ => self.finished = true
+        }
+        match result {
+            Ok(Some(chunk)) => Some(Ok(chunk)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e))
+        }
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<SliceChunk<'a>>> {
+        if self.pos > self.data.len() as u64 {
+            return Err(unexpected_eof!("when skipping chunk payload"));
+        }
+        let rest = &self.data[self.pos as usize..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        if rest.len() < 4 {
+            return Err(unexpected_eof!("when reading chunk length"));
+        }
+        let len = (rest[0] as u32) << 24 | (rest[1] as u32) << 16 | (rest[2] as u32) << 8 | rest[3] as u32;
+        if len > 0x7fff_ffff {
+            return Err(invalid_format!("invalid PNG chunk length: {}", len));
+        }
+        if rest.len() < 8 {
+            return Err(unexpected_eof!("when reading chunk type"));
+        }
+        let mut chunk_type = [0u8; 4];
+        chunk_type.copy_from_slice(&rest[4..8]);
+        log_trace!("PNG chunk {:?} at offset {}, length {}", chunk_type, self.pos, len);
+
+        let chunk_len = 8 + len as u64 + 4;
+        let chunk = SliceChunk {
+            chunk_type: chunk_type,
+            offset: self.pos,
+            len: len,
+            payload: &rest[8..cmp::min(8 + len as u64, rest.len() as u64) as usize],
+            complete: rest.len() as u64 >= chunk_len,
+        };
+        self.pos += chunk_len;
+        Ok(Some(chunk))
+    }
+}
+
+/// The parsing core of `Metadata`, which interprets chunk payloads given as byte slices.
+///
+/// `parse_bytes()` passes payloads borrowed from its buffer, while `Metadata::load()` reads
+/// them from the stream into a reused buffer; only the first `payload_len()` bytes of each
+/// payload are needed, so image data is never read into memory. IHDR and IEND chunks are
+/// handled separately, by `Parser::new()` and by the callers.
+struct Parser {
+    md: Metadata,
+    lazy: bool,
+    seen_idat: bool,
+}
+
+impl Parser {
+    fn new(ihdr: &[u8], lazy: bool) -> Result<Parser> {
+        log_trace!("reading PNG IHDR chunk");
+        let mut r = ihdr;
         let width = try!(r.read_u32::<BigEndian>().map_err(if_eof!("when reading width")));
         let height = try!(r.read_u32::<BigEndian>().map_err(if_eof!("when reading height")));
         let bit_depth = try!(r.read_u8().map_err(if_eof!("when reading bit depth")));
@@ -167,7 +2153,7 @@ impl LoadableMetadata for Metadata {
         let filter_method = try!(r.read_u8().map_err(if_eof!("when reading filter method")));
         let interlace_method = try!(r.read_u8().map_err(if_eof!("when reading interlace method")));
 
-        Ok(Metadata {
+        let md = Metadata {
             dimensions: (width, height).into(),
             color_type: try!(
                 ColorType::from_u8(color_type)
@@ -188,7 +2174,330 @@ impl LoadableMetadata for Metadata {
             interlace_method: try!(
                 InterlaceMethod::from_u8(interlace_method)
                     .ok_or(invalid_format!("invalid interlace method: {}", interlace_method))
-            )
+            ),
+            text_chunks: Vec::new(),
+            pending_text_chunks: Vec::new(),
+            gamma: None,
+            chromaticities: None,
+            srgb_intent: None,
+            cicp: None,
+            mastering_display: None,
+            content_light_level: None,
+            background_color: None,
+            significant_bits: None,
+            truncated: true,
+            image_data_size: 0,
+            zlib_header: None,
+            offset: None,
+            physical_scale: None,
+            animation_control: None,
+            frame_controls: Vec::new(),
+            default_image_is_first_frame: false,
+            exif: None,
+            pending_exif: None,
+            modification_time: None,
+        };
+
+        Ok(Parser {
+            md: md,
+            lazy: lazy,
+            seen_idat: false,
         })
     }
+
+    /// Returns the number of payload bytes `chunk()` needs for a chunk of the given type.
+    fn payload_len(&self, chunk_type: &[u8; 4], len: u32) -> u64 {
+        let md = &self.md;
+        let needed = match chunk_type {
+            b"tEXt" | b"zTXt" | b"iTXt" if self.lazy => 0,
+            b"tEXt" | b"zTXt" | b"iTXt" | b"sCAL" => len,
+            b"eXIf" if self.lazy => 0,
+            b"eXIf" if md.exif.is_none() => len,
+            // only the zlib header of image data is parsed
+            b"IDAT" if !self.seen_idat => 2,
+            b"acTL" if !self.seen_idat && md.animation_control.is_none() => 8,
+            b"fcTL" if md.animation_control.is_some() => 26,
+            b"cICP" if md.cicp.is_none() => 4,
+            b"mDCv" if md.mastering_display.is_none() => 24,
+            b"cLLi" if md.content_light_level.is_none() => 8,
+            b"bKGD" if md.background_color.is_none() => 6,
+            b"sBIT" if md.significant_bits.is_none() => 4,
+            b"oFFs" if md.offset.is_none() => 9,
+            b"tIME" if md.modification_time.is_none() => 7,
+            b"gAMA" if md.gamma.is_none() => 4,
+            b"cHRM" if md.chromaticities.is_none() => 32,
+            b"sRGB" if md.srgb_intent.is_none() => 1,
+            _ => 0
+        };
+        cmp::min(needed, len) as u64
+    }
+
+    /// Parses a chunk whose payload starts with `payload`.
+    ///
+    /// The payload is shorter than `payload_len()` requested if the input ends inside of it;
+    /// ancillary chunks which can't be parsed are skipped, like invalid ones.
+    fn chunk(&mut self, chunk_type: [u8; 4], offset: u64, len: u32, payload: &[u8]) {
+        let md = &mut self.md;
+        // the complete payload of variable-sized chunks
+        let full = if payload.len() == len as usize {
+            Ok(payload)
+        } else {
+            Err(unexpected_eof!("when reading payload of PNG chunk {:?}", chunk_type))
+        };
+        let mut r = payload;
+
+        // ancillary chunks may be located anywhere, including after image data
+        match &chunk_type {
+            b"tEXt" | b"zTXt" | b"iTXt" if self.lazy => {
+                md.pending_text_chunks.push(TextChunkLocation {
+                    chunk_type: chunk_type,
+                    offset: offset + 8,
+                    len: len,
+                });
+            }
+            b"tEXt" | b"zTXt" | b"iTXt" => {
+                match full.and_then(|data| TextChunk::parse(&chunk_type, data)) {
+                    Ok(text) => md.text_chunks.push(text),
+                    Err(e) => log_debug!("skipping invalid {:?} chunk: {}", chunk_type, e)
+                }
+            }
+            b"IDAT" => {
+                if !self.seen_idat {
+                    match r.read_u8().and_then(|cmf| r.read_u8().map(|flg| (cmf, flg))) {
+                        Ok((cmf, flg)) => match ZlibHeader::parse(cmf, flg) {
+                            Ok(header) => md.zlib_header = Some(header),
+                            Err(e) => log_debug!("invalid image data zlib header: {}", e)
+                        },
+                        Err(e) => log_debug!("failed to read image data zlib header: {}", e)
+                    }
+                }
+                self.seen_idat = true;
+                md.image_data_size += len as u64;
+            }
+            b"acTL" if !self.seen_idat && md.animation_control.is_none() => {
+                match r.read_u32::<BigEndian>().and_then(|f| r.read_u32::<BigEndian>().map(|p| (f, p))) {
+                    Ok((num_frames, num_plays)) => md.animation_control = Some(AnimationControl {
+                        num_frames: num_frames,
+                        num_plays: num_plays,
+                    }),
+                    Err(e) => log_debug!("skipping invalid acTL chunk: {}", e)
+                }
+            }
+            // frame data in fdAT chunks is skipped
+            b"fcTL" if md.animation_control.is_some() => {
+                match FrameControl::read(&mut r) {
+                    Ok(fc) => {
+                        if !self.seen_idat && md.frame_controls.is_empty() {
+                            md.default_image_is_first_frame = true;
+                        }
+                        md.frame_controls.push(fc);
+                    }
+                    Err(e) => log_debug!("skipping invalid fcTL chunk: {}", e)
+                }
+            }
+            b"cICP" if md.cicp.is_none() => {
+                if r.len() == 4 {
+                    md.cicp = Some(Cicp {
+                        color_primaries: r[0],
+                        transfer_function: r[1],
+                        matrix_coefficients: r[2],
+                        full_range: r[3] != 0,
+                    });
+                } else {
+                    log_debug!("skipping invalid cICP chunk: {} bytes", r.len());
+                }
+            }
+            b"mDCv" if md.mastering_display.is_none() => {
+                match MasteringDisplayColorVolume::read(&mut r) {
+                    Ok(mdcv) => md.mastering_display = Some(mdcv),
+                    Err(e) => log_debug!("skipping invalid mDCv chunk: {}", e)
+                }
+            }
+            b"cLLi" if md.content_light_level.is_none() => {
+                match r.read_u32::<BigEndian>().and_then(|c| r.read_u32::<BigEndian>().map(|f| (c, f))) {
+                    Ok((max_cll, max_fall)) => md.content_light_level = Some(ContentLightLevel {
+                        max_content_light_level: max_cll,
+                        max_frame_average_light_level: max_fall,
+                    }),
+                    Err(e) => log_debug!("skipping invalid cLLi chunk: {}", e)
+                }
+            }
+            b"bKGD" if md.background_color.is_none() => {
+                match BackgroundColor::read(&mut r, md.color_type) {
+                    Ok(color) => md.background_color = Some(color),
+                    Err(e) => log_debug!("skipping invalid bKGD chunk: {}", e)
+                }
+            }
+            b"sBIT" if md.significant_bits.is_none() => {
+                match SignificantBits::read(&mut r, md.color_type) {
+                    Ok(bits) => md.significant_bits = Some(bits),
+                    Err(e) => log_debug!("skipping invalid sBIT chunk: {}", e)
+                }
+            }
+            b"oFFs" if md.offset.is_none() => {
+                match ImageOffset::read(&mut r) {
+                    Ok(offset) => md.offset = Some(offset),
+                    Err(e) => log_debug!("skipping invalid oFFs chunk: {}", e)
+                }
+            }
+            b"sCAL" if md.physical_scale.is_none() => {
+                match full.and_then(PhysicalScale::parse) {
+                    Ok(scale) => md.physical_scale = Some(scale),
+                    Err(e) => log_debug!("skipping invalid sCAL chunk: {}", e)
+                }
+            }
+            b"eXIf" if self.lazy => {
+                if md.exif.is_none() && md.pending_exif.is_none() {
+                    md.pending_exif = Some(ByteRange { offset: offset + 8, len: len as u64 });
+                }
+            }
+            b"eXIf" if md.exif.is_none() => {
+                match full.and_then(|data| ExifData::new(data.to_vec(), offset + 8)) {
+                    Ok(exif) => md.exif = Some(exif),
+                    Err(e) => log_debug!("skipping invalid eXIf chunk: {}", e)
+                }
+            }
+            b"tIME" if md.modification_time.is_none() => {
+                if r.len() == 7 {
+                    let year = (r[0] as u16) << 8 | r[1] as u16;
+                    md.modification_time = DateTime::new(year, r[2], r[3], r[4], r[5], r[6])
+                        .map(|dt| dt.with_utc_offset(Some(0)));
+                    if md.modification_time.is_none() {
+                        log_debug!("skipping tIME chunk with invalid time: {:?}", r);
+                    }
+                } else {
+                    log_debug!("skipping invalid tIME chunk: {} bytes", r.len());
+                }
+            }
+            b"gAMA" if md.gamma.is_none() => {
+                match r.read_u32::<BigEndian>() {
+                    Ok(gamma) => md.gamma = Some(gamma),
+                    Err(e) => log_debug!("skipping invalid gAMA chunk: {}", e)
+                }
+            }
+            b"cHRM" if md.chromaticities.is_none() => {
+                match Chromaticities::read(&mut r) {
+                    Ok(chromaticities) => md.chromaticities = Some(chromaticities),
+                    Err(e) => log_debug!("skipping invalid cHRM chunk: {}", e)
+                }
+            }
+            b"sRGB" if md.srgb_intent.is_none() => {
+                match r.read_u8() {
+                    Ok(intent) => {
+                        md.srgb_intent = RenderingIntent::from_u8(intent);
+                        if md.srgb_intent.is_none() {
+                            log_debug!("skipping sRGB chunk with invalid rendering intent: {}", intent);
+                        }
+                    }
+                    Err(e) => log_debug!("skipping invalid sRGB chunk: {}", e)
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Metadata {
+    fn load_impl<R: ?Sized + BufRead>(r: &mut R, lazy: bool) -> Result<Metadata> {
+        let mut chunks = Chunks::new(r);
+        let mut buf = Vec::new();
+        {
+            let mut chunk = match chunks.next() {
+                Some(chunk) => try!(chunk),
+                None => return Err(unexpected_eof!("when reading IHDR chunk"))
+            };
+            if &chunk.chunk_type() != b"IHDR" {
+                return Err(invalid_format!("invalid PNG chunk: {:?}", chunk.chunk_type()));
+            }
+            try!(chunk.payload().read_up_to(13, &mut buf));
+        }
+        let mut parser = try!(Parser::new(&buf, lazy));
+
+        while let Some(chunk) = chunks.next() {
+            let mut chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e @ Error::LimitExceeded(_)) => return Err(e),
+                Err(e) => {
+                    log_debug!("stopped reading PNG chunks: {}", e);
+                    break;
+                }
+            };
+            let chunk_type = chunk.chunk_type();
+            if &chunk_type == b"IEND" {
+                match chunk.crc() {
+                    Ok(_) => parser.md.truncated = false,
+                    Err(e @ Error::LimitExceeded(_)) => return Err(e),
+                    Err(e) => log_debug!("failed to read IEND chunk: {}", e)
+                }
+                break;
+            }
+            let n = parser.payload_len(&chunk_type, chunk.len());
+            match chunk.payload().read_up_to(n, &mut buf).map_err(Error::from) {
+                Ok(()) => {}
+                Err(e @ Error::LimitExceeded(_)) => return Err(e),
+                Err(e) => {
+                    log_debug!("stopped reading PNG chunks: {}", e);
+                    break;
+                }
+            }
+            parser.chunk(chunk_type, chunk.offset(), chunk.len(), &buf);
+        }
+
+        Ok(parser.md)
+    }
+
+    /// Parses text chunks whose parsing was deferred, moving them from `pending_text_chunks`
+    /// to `text_chunks`.
+    ///
+    /// The provided stream must contain the PNG image this metadata was loaded from, starting
+    /// at its beginning. Invalid text chunks are skipped, like they are when loading metadata
+    /// eagerly. If an I/O error occurs, the chunks which have not been parsed yet stay in
+    /// `pending_text_chunks`.
+    pub fn load_text_chunks<R: ?Sized + Read + Seek>(&mut self, r: &mut R) -> Result<()> {
+        while !self.pending_text_chunks.is_empty() {
+            let location = self.pending_text_chunks[0];
+            try!(r.seek(SeekFrom::Start(location.offset)));
+            let data = try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(r, location.len as u64),
+                                   "when reading payload of PNG text chunk");
+            match TextChunk::parse(&location.chunk_type, &data) {
+                Ok(text) => self.text_chunks.push(text),
+                Err(e) => log_debug!("skipping invalid text chunk at offset {}: {}", location.offset, e)
+            }
+            self.pending_text_chunks.remove(0);
+        }
+        Ok(())
+    }
+
+    /// Parses all metadata whose parsing was deferred, i.e. text chunks and EXIF data.
+    ///
+    /// The provided stream must contain the PNG image this metadata was loaded from, starting
+    /// at its beginning. See also `load_text_chunks()`.
+    pub fn load_extensions<R: ?Sized + Read + Seek>(&mut self, r: &mut R) -> Result<()> {
+        try!(self.load_text_chunks(r));
+        if let Some(location) = self.pending_exif {
+            try!(r.seek(SeekFrom::Start(location.offset)));
+            let data = try_if_eof!(std, utils::ByteOrderReadExt::read_exact_vec(r, location.len),
+                                   "when reading payload of PNG eXIf chunk");
+            match ExifData::new(data, location.offset) {
+                Ok(exif) => self.exif = Some(exif),
+                Err(e) => log_debug!("skipping invalid eXIf chunk: {}", e)
+            }
+            self.pending_exif = None;
+        }
+        Ok(())
+    }
+}
+
+impl LoadableMetadata for Metadata {
+    fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Metadata> {
+        Metadata::load_impl(r, false)
+    }
+
+    fn load_from_buf(buf: &[u8]) -> Result<Metadata> {
+        parse_bytes(buf)
+    }
+
+    fn load_partial_with_options<R: ?Sized + BufRead + Seek>(r: &mut R, options: &LoadOptions) -> PartialResult<Metadata> {
+        Metadata::load_impl(r, options.lazy_extensions).map_err(ErrorWithPartial::from)
+    }
 }
@@ -1,10 +1,20 @@
-use std::io::BufRead;
+use std::cmp;
+use std::io::{self, BufRead, Write, Seek};
+use std::fmt;
 
-use types::{Result, Dimensions};
-use common::riff::{RiffReader, RiffChunk, ChunkId};
+use types::{Result, Error, Completeness, Dimensions, ByteRange};
+use common::riff::{self, ChunkId};
 use traits::LoadableMetadata;
+use animation::{Animated, Blend, Disposal, FrameInfo, LoopCount};
+use utils::{self, BufReadExt};
+use common::exif::ExifData;
+use common::xmp::Xmp;
+use transplant::EmbeddedMetadata;
+
+pub use common::riff::Chunk;
 
 #[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Metadata {
     VP8(VP8Metadata),
     VP8L(VP8LMetadata),
@@ -12,6 +22,7 @@ pub enum Metadata {
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct VP8Metadata {
     pub version_number: u8,
     pub show_frame: bool,
@@ -20,63 +31,831 @@ pub struct VP8Metadata {
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum VP8Frame {
     Key { dimensions: Dimensions, x_scale: u8, y_scale: u8 },
     Inter
 }
 
+/// Layout of a WebP file.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Flavor {
+    /// A single lossy VP8 bitstream.
+    SimpleLossy,
+    /// A single lossless VP8L bitstream.
+    SimpleLossless,
+    /// Extended file with VP8X chunk, which may contain alpha, animation, color profile
+    /// and metadata.
+    Extended,
+}
+
+/// Header of a lossless VP8L bitstream.
 #[derive(Clone, Eq, PartialEq, Debug)]
-pub struct VP8LMetadata;
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VP8LMetadata {
+    /// Width and height of the image.
+    pub dimensions: Dimensions,
+    /// Whether the image uses its alpha channel.
+    ///
+    /// This is only a hint; an image with this flag unset must still be decoded correctly
+    /// if its alpha channel values are not all opaque.
+    pub alpha_is_used: bool,
+    /// Version of the bitstream; the only currently defined version is 0.
+    pub version_number: u8,
+}
 
+/// Contents of the VP8X chunk of an extended WebP file.
 #[derive(Clone, Eq, PartialEq, Debug)]
-pub struct VP8XMetadata;
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VP8XMetadata {
+    /// Whether the file contains an ICC profile.
+    pub has_icc_profile: bool,
+    /// Whether any of the frames of the image contains transparency information.
+    pub has_alpha: bool,
+    /// Whether the file contains EXIF metadata.
+    pub has_exif: bool,
+    /// Whether the file contains XMP metadata.
+    pub has_xmp: bool,
+    /// Whether the image is an animation.
+    pub has_animation: bool,
+    /// Width and height of the canvas.
+    pub canvas_dimensions: Dimensions,
+    /// Width and height of the first frame as coded in its bitstream, if it could be read.
+    ///
+    /// The frame may be smaller than the canvas, for example, in animations where frames
+    /// only cover changed areas.
+    pub frame_dimensions: Option<Dimensions>,
+    /// Global animation parameters from the ANIM chunk, if it is present.
+    pub animation_parameters: Option<AnimationParameters>,
+    /// Frames of the animation described by ANMF chunks, in display order.
+    ///
+    /// This vector is empty for images which are not animated.
+    pub frames: Vec<AnimationFrame>,
+}
+
+/// Contents of the ANIM chunk of an animated WebP file.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AnimationParameters {
+    /// Color used to clear the canvas, as red, green, blue and alpha components.
+    ///
+    /// Note that this color is only a hint and decoders may ignore it.
+    pub background_color: (u8, u8, u8, u8),
+    /// Number of times the animation is played; zero means infinitely.
+    pub loop_count: u16,
+}
+
+/// Contents of the header of an ANMF chunk which describes a single frame of an animation.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AnimationFrame {
+    /// Horizontal offset of the frame on the canvas.
+    pub x_offset: u32,
+    /// Vertical offset of the frame on the canvas.
+    pub y_offset: u32,
+    /// Width and height of the frame.
+    pub dimensions: Dimensions,
+    /// Time in milliseconds the frame is displayed.
+    pub duration_ms: u32,
+    /// How the frame is composed with the canvas.
+    pub blend: Blend,
+    /// What happens to the frame area after the frame is displayed.
+    pub disposal: Disposal,
+}
 
 const WEBP_CHUNK_TYPE: ChunkId = ChunkId([b'W', b'E', b'B', b'P']);
 const ALPH_CHUNK_ID: ChunkId   = ChunkId([b'A', b'L', b'P', b'H']);
 const VP8_CHUNK_ID: ChunkId    = ChunkId([b'V', b'P', b'8', b' ']);
 const VP8L_CHUNK_ID: ChunkId   = ChunkId([b'V', b'P', b'8', b'L']);
 const VP8X_CHUNK_ID: ChunkId   = ChunkId([b'V', b'P', b'8', b'X']);
+const ICCP_CHUNK_ID: ChunkId   = ChunkId([b'I', b'C', b'C', b'P']);
+const EXIF_CHUNK_ID: ChunkId   = ChunkId([b'E', b'X', b'I', b'F']);
+const XMP_CHUNK_ID: ChunkId    = ChunkId([b'X', b'M', b'P', b' ']);
+const ANIM_CHUNK_ID: ChunkId   = ChunkId([b'A', b'N', b'I', b'M']);
+const ANMF_CHUNK_ID: ChunkId   = ChunkId([b'A', b'N', b'M', b'F']);
 
 impl Metadata {
+    /// Returns dimensions of the image.
+    ///
+    /// # Panics
+    ///
+    /// Panics for simple lossy files whose frame is not a key frame. Such metadata is never
+    /// returned by `Metadata::load()`; see `frame_dimensions()` for a non-panicking variant.
     pub fn dimensions(&self) -> Dimensions {
         match *self {
             Metadata::VP8(VP8Metadata { frame: VP8Frame::Key { dimensions, .. }, .. }) => dimensions,
+            Metadata::VP8L(ref md) => md.dimensions,
+            Metadata::VP8X(ref md) => md.canvas_dimensions,
             _ => unimplemented!()
         }
     }
+
+    /// Returns the layout of the file.
+    pub fn flavor(&self) -> Flavor {
+        match *self {
+            Metadata::VP8(_) => Flavor::SimpleLossy,
+            Metadata::VP8L(_) => Flavor::SimpleLossless,
+            Metadata::VP8X(_) => Flavor::Extended
+        }
+    }
+
+    /// Returns dimensions of the canvas the image is displayed on.
+    ///
+    /// This is the same as `dimensions()`. For simple (non-extended) files the canvas
+    /// has the same size as the image bitstream.
+    #[inline]
+    pub fn canvas_dimensions(&self) -> Dimensions {
+        self.dimensions()
+    }
+
+    /// Returns coded dimensions of the first frame of the image, if they are known.
+    ///
+    /// For extended files, including animations, these dimensions may differ from
+    /// the canvas dimensions.
+    pub fn frame_dimensions(&self) -> Option<Dimensions> {
+        match *self {
+            Metadata::VP8(VP8Metadata { frame: VP8Frame::Key { dimensions, .. }, .. }) => Some(dimensions),
+            Metadata::VP8(_) => None,
+            Metadata::VP8L(ref md) => Some(md.dimensions),
+            Metadata::VP8X(ref md) => md.frame_dimensions
+        }
+    }
+
+    /// Returns an estimate of the size in bytes of the decoded image, if its dimensions are
+    /// known.
+    ///
+    /// Lossy images are assumed to be decoded to 8-bit RGB, other kinds to 8-bit RGBA. Each
+    /// frame of an animated image is assumed to be decoded into a separate buffer of the canvas
+    /// size. Simple lossy files whose frame is not a key frame have no dimensions, so `None`
+    /// is returned for them.
+    pub fn estimated_decoded_size(&self) -> Option<u64> {
+        let (dimensions, channels, frames) = match *self {
+            Metadata::VP8(VP8Metadata { frame: VP8Frame::Key { dimensions, .. }, .. }) => (dimensions, 3, 1),
+            Metadata::VP8(_) => return None,
+            Metadata::VP8L(ref md) => (md.dimensions, 4, 1),
+            Metadata::VP8X(ref md) => (md.canvas_dimensions, 4, cmp::max(md.frames.len(), 1))
+        };
+        Some(utils::decoded_size(dimensions, channels, 1, frames as u64))
+    }
 }
 
-impl LoadableMetadata for Metadata {
-    fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Metadata> {
-        let mut rr = RiffReader::new(r);
+/// A low-level reader of the chunks of a WebP file.
+///
+/// WebP file is a RIFF container whose payload is a sequence of chunks. `Chunks` reads these
+/// chunks one by one, including the ones unknown to this library, and provides access to their
+/// payload; the payload which is not read explicitly is skipped when the next chunk is requested.
+///
+/// This type is useful for diagnostic tools which need to see the structure of the file.
+pub struct Chunks<R: BufRead>(riff::Chunks<R>);
 
-        let mut root = try!(rr.root());
-        if root.chunk_type() != WEBP_CHUNK_TYPE {
-            return Err(invalid_format!("invalid WEBP signature"));
+impl<R: BufRead> Chunks<R> {
+    /// Creates a new chunk reader for a WebP file which starts at the current position of
+    /// the provided reader.
+    pub fn new(source: R) -> Chunks<R> {
+        Chunks(riff::Chunks::with_form_type(source, WEBP_CHUNK_TYPE))
+    }
+
+    /// Returns the number of bytes consumed from the underlying reader so far.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.0.position()
+    }
+
+    /// Unwraps this chunk reader, returning the underlying reader.
+    ///
+    /// Unread payload of the last chunk is not skipped.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.0.into_inner()
+    }
+
+    /// Reads the next chunk.
+    ///
+    /// Returns `None` when there are no more chunks, that is, at the end of the RIFF container
+    /// or at the end of the stream, whichever comes first. The RIFF header is checked before
+    /// the first chunk is read; if it is invalid, an error is returned.
+    #[inline]
+    pub fn next(&mut self) -> Option<Result<Chunk<R>>> {
+        self.0.next()
+    }
+}
+
+/// Kind of a structural violation found by `validate()`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ViolationKind {
+    /// The first chunk is not VP8, VP8L or VP8X.
+    InvalidFirstChunk,
+    /// VP8X chunk is not the first chunk.
+    MisplacedVp8x,
+    /// A chunk which may only appear once is repeated.
+    DuplicateChunk,
+    /// A chunk which must precede the image data follows it.
+    ChunkAfterImageData,
+    /// A chunk which must follow the image data precedes it.
+    ChunkBeforeImageData,
+    /// A simple (non-extended) file contains chunks after the image data.
+    UnexpectedChunk,
+    /// The file does not contain any image data.
+    MissingImageData,
+    /// A chunk is cut off by the end of the stream.
+    TruncatedChunk,
+    /// The size of the RIFF container declared in its header differs from the stream length.
+    RiffSizeMismatch,
+}
+
+/// A structural violation found by `validate()`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Violation {
+    /// Kind of the violation.
+    pub kind: ViolationKind,
+    /// Offset of the offending chunk or, if there is no such chunk, of the place where
+    /// the problem is found, from the beginning of the file.
+    pub offset: u64,
+    /// Identifier of the offending chunk, if any.
+    pub chunk_id: Option<ChunkId>,
+}
+
+impl ViolationKind {
+    /// Returns a short human-readable description of the violation.
+    pub fn description(self) -> &'static str {
+        match self {
+            ViolationKind::InvalidFirstChunk => "the first chunk is not VP8, VP8L or VP8X",
+            ViolationKind::MisplacedVp8x => "VP8X is not the first chunk",
+            ViolationKind::DuplicateChunk => "chunk must not be repeated",
+            ViolationKind::ChunkAfterImageData => "chunk must precede image data",
+            ViolationKind::ChunkBeforeImageData => "chunk must follow image data",
+            ViolationKind::UnexpectedChunk => "simple file has chunks after image data",
+            ViolationKind::MissingImageData => "no image data",
+            ViolationKind::TruncatedChunk => "truncated chunk",
+            ViolationKind::RiffSizeMismatch => "RIFF size does not match file length",
         }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = self.kind.description();
+        match self.chunk_id {
+            Some(id) => write!(f, "{} ({} at offset {})", description, id, self.offset),
+            None => write!(f, "{} (at offset {})", description, self.offset),
+        }
+    }
+}
+
+/// Checks chunk ordering and the RIFF container size of a WebP file without decoding it.
+///
+/// The stream is read until the end. All found violations are returned in the order of their
+/// offsets; an empty vector means that the structure is valid. Note that chunk payloads are
+/// not checked. An error is returned if the RIFF header is invalid or if an I/O error occurs.
+pub fn validate<R: BufRead>(r: R) -> Result<Vec<Violation>> {
+    let mut chunks = riff::Chunks::with_form_type(r, WEBP_CHUNK_TYPE);
+    let mut violations = Vec::new();
+    let mut seen = Vec::new();
+    let mut extended = false;
+    let mut image_data_seen = false;
+    let mut offset = 0;
+    let mut truncated = false;
+
+    loop {
+        let chunk = match chunks.next() {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(Error::UnexpectedEndOfFile(_))) if !seen.is_empty() => {
+                truncated = true;
+                break;
+            }
+            Some(Err(e)) => return Err(e),
+            None => break
+        };
+        let id = chunk.chunk_id();
+        offset = chunk.offset();
+        let mut violation = |kind| violations.push(Violation { kind: kind, offset: offset, chunk_id: Some(id) });
+
+        let is_image_data = match id {
+            VP8_CHUNK_ID | VP8L_CHUNK_ID | ANMF_CHUNK_ID => true,
+            _ => false
+        };
 
-        loop {
-            let mut chunk = match root.next() {
+        if seen.is_empty() {
+            match id {
+                VP8X_CHUNK_ID => extended = true,
+                VP8_CHUNK_ID | VP8L_CHUNK_ID => {}
+                _ => violation(ViolationKind::InvalidFirstChunk)
+            }
+        } else if id == VP8X_CHUNK_ID {
+            violation(ViolationKind::MisplacedVp8x);
+        } else if !extended {
+            violation(ViolationKind::UnexpectedChunk);
+        }
+
+        match id {
+            VP8X_CHUNK_ID | ICCP_CHUNK_ID | ANIM_CHUNK_ID | EXIF_CHUNK_ID | XMP_CHUNK_ID
+                if seen.contains(&id) => violation(ViolationKind::DuplicateChunk),
+            VP8_CHUNK_ID | VP8L_CHUNK_ID if extended && image_data_seen =>
+                violation(ViolationKind::DuplicateChunk),
+            ICCP_CHUNK_ID | ANIM_CHUNK_ID | ALPH_CHUNK_ID if extended && image_data_seen =>
+                violation(ViolationKind::ChunkAfterImageData),
+            EXIF_CHUNK_ID | XMP_CHUNK_ID if extended && !image_data_seen =>
+                violation(ViolationKind::ChunkBeforeImageData),
+            _ => {}
+        }
+
+        image_data_seen |= is_image_data;
+        seen.push(id);
+    }
+
+    if truncated {
+        violations.push(Violation { kind: ViolationKind::TruncatedChunk, offset: offset, chunk_id: seen.last().cloned() });
+        return Ok(violations);
+    }
+
+    let position = chunks.position();
+    if !image_data_seen {
+        violations.push(Violation { kind: ViolationKind::MissingImageData, offset: position, chunk_id: None });
+    }
+
+    let declared_size = chunks.declared_size().unwrap_or(position);
+    let mut r = chunks.into_inner();
+    if position < declared_size || !try!(r.fill_buf()).is_empty() {
+        violations.push(Violation { kind: ViolationKind::RiffSizeMismatch, offset: position, chunk_id: None });
+    }
+
+    Ok(violations)
+}
+
+/// Copies a WebP file while inserting the provided XMP packet, replacing the existing one.
+///
+/// The packet is written into an `XMP ` chunk following the image data and EXIF chunks, and
+/// the XMP flag of the VP8X chunk and the RIFF container size are updated. Simple lossy and
+/// lossless files are converted to the extended format, which is required for metadata, by
+/// adding a VP8X chunk. All other chunks are copied unchanged.
+///
+/// Since the container size precedes the chunks, the whole file is read into memory before
+/// it is written.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::{BufReader, BufWriter};
+/// use immeta::common::xmp::Xmp;
+/// use immeta::formats::webp;
+///
+/// let xmp = Xmp::new(r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">...</x:xmpmeta>"#);
+/// let mut r = BufReader::new(File::open("kitty.webp").unwrap());
+/// let mut w = BufWriter::new(File::create("kitty-tagged.webp").unwrap());
+/// webp::write_xmp(&mut r, &mut w, &xmp).unwrap();
+/// ```
+pub fn write_xmp<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W, xmp: &Xmp) -> Result<()> {
+    embed_metadata(r, w, &EmbeddedMetadata { xmp: Some(xmp.clone()), ..EmbeddedMetadata::default() })
+}
+
+/// Finds the image data of a WebP file starting at the current position.
+///
+/// Each range covers a whole VP8, VP8L or ALPH chunk, including its header, in the order of
+/// appearance. For animated files these are the chunks inside ANMF frames. Metadata chunks,
+/// like EXIF, XMP and ICCP, and the headers of the extended format lie outside the ranges.
+pub fn image_data_ranges<R: ?Sized + BufRead>(r: &mut R) -> Result<Vec<ByteRange>> {
+    fn collect<R: BufRead>(chunks: &mut riff::Chunks<R>, ranges: &mut Vec<ByteRange>) -> Result<()> {
+        while let Some(chunk) = chunks.next() {
+            let mut chunk = try!(chunk);
+            match chunk.chunk_id() {
+                VP8_CHUNK_ID | VP8L_CHUNK_ID | ALPH_CHUNK_ID => ranges.push(ByteRange {
+                    offset: chunk.offset(),
+                    len: chunk.len() as u64 + 8,
+                }),
+                ANMF_CHUNK_ID => {
+                    // frame data sub-chunks follow the ANMF header
+                    let mut header = Vec::new();
+                    try!(chunk.payload().read_up_to(16, &mut header));
+                    try!(read_anmf_chunk(&header));
+                    try!(collect(&mut chunk.subchunks(), ranges));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    let mut ranges = Vec::new();
+    try!(collect(&mut riff::Chunks::with_form_type(r, WEBP_CHUNK_TYPE), &mut ranges));
+    Ok(ranges)
+}
+
+/// Checks whether a WebP file starting at the current position is complete, i.e. whether
+/// the size of its RIFF container fits into the stream.
+///
+/// Only the RIFF header is read, so chunks inside the container are not checked. The
+/// position of the stream is restored afterwards.
+pub fn verify_complete<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Completeness> {
+    utils::verify_length(r, |r| {
+        let mut header = [0u8; 12];
+        try!(r.read_exact(&mut header).map_err(if_eof!(std, "when reading RIFF header")));
+        if &header[..4] != riff::RIFF_CHUNK_ID.as_bytes() || &header[8..] != WEBP_CHUNK_TYPE.as_bytes() {
+            return Err(invalid_format!("invalid WebP RIFF header: {:?}", &header[..]));
+        }
+        let size = header[4] as u64 | (header[5] as u64) << 8 | (header[6] as u64) << 16 | (header[7] as u64) << 24;
+        // RIFF chunk id and size precede the container payload, which is padded to even size
+        Ok(8 + size + size % 2)
+    })
+}
+
+/// Extracts EXIF data, the XMP packet and the ICC profile from a WebP file.
+///
+/// Invalid EXIF data and XMP packets are ignored.
+pub fn extract_metadata<R: ?Sized + BufRead>(r: &mut R) -> Result<EmbeddedMetadata> {
+    let mut md = EmbeddedMetadata::default();
+    let mut chunks = riff::Chunks::with_form_type(&mut *r, WEBP_CHUNK_TYPE);
+    while let Some(chunk) = chunks.next() {
+        let mut chunk = try!(chunk);
+        match chunk.chunk_id() {
+            ICCP_CHUNK_ID if md.icc_profile.is_none() => md.icc_profile = Some(try!(chunk.read_payload())),
+            EXIF_CHUNK_ID if md.exif.is_none() => {
+                let mut offset = chunk.offset() + 8;
+                let mut data = try!(chunk.read_payload());
+                // some writers keep the header of the JPEG APP1 segment
+                if data.starts_with(b"Exif\0\0") {
+                    data.drain(..6);
+                    offset += 6;
+                }
+                match ExifData::new(data, offset) {
+                    Ok(exif) => md.exif = Some(exif),
+                    Err(e) => log_debug!("skipping invalid EXIF chunk: {}", e)
+                }
+            }
+            XMP_CHUNK_ID if md.xmp.is_none() => match Xmp::from_bytes(&try!(chunk.read_payload())) {
+                Ok(xmp) => md.xmp = Some(xmp),
+                Err(e) => log_debug!("skipping invalid XMP chunk: {}", e)
+            },
+            _ => {}
+        }
+    }
+    Ok(md)
+}
+
+/// Copies a WebP file while embedding the provided metadata.
+///
+/// Each kind of metadata which is present replaces the respective chunk: the ICC profile is
+/// written into an `ICCP` chunk right after the VP8X chunk, and EXIF data and the XMP packet
+/// are written into `EXIF` and `XMP ` chunks following the image data. The flags of the VP8X
+/// chunk are updated accordingly; otherwise this function works like `write_xmp()`.
+pub fn embed_metadata<R: ?Sized + BufRead, W: ?Sized + Write>(r: &mut R, w: &mut W,
+                                                              md: &EmbeddedMetadata) -> Result<()> {
+    let mut chunks = riff::Chunks::with_form_type(&mut *r, WEBP_CHUNK_TYPE);
+    let mut contents = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        let mut chunk = try!(chunk);
+        let id = chunk.chunk_id();
+        let replaced = match id {
+            ICCP_CHUNK_ID => md.icc_profile.is_some(),
+            EXIF_CHUNK_ID => md.exif.is_some(),
+            XMP_CHUNK_ID => md.xmp.is_some(),
+            _ => false
+        };
+        if replaced {
+            log_debug!("removing {} chunk with {} bytes of payload", id, chunk.len());
+            continue;
+        }
+        contents.push((id, try!(chunk.read_payload())));
+    }
+
+    let mut flags = 0;
+    if md.icc_profile.is_some() {
+        flags |= 0x20;
+    }
+    if md.exif.is_some() {
+        flags |= 0x08;
+    }
+    if md.xmp.is_some() {
+        flags |= 0x04;
+    }
+
+    let vp8x = match contents.first() {
+        Some(&(VP8X_CHUNK_ID, _)) => None,
+        Some(&(VP8_CHUNK_ID, ref payload)) => match try!(read_vp8_chunk(payload)).frame {
+            VP8Frame::Key { dimensions, .. } => Some((0, dimensions)),
+            VP8Frame::Inter => return Err(invalid_format!("VP8 frame is not a key frame"))
+        },
+        Some(&(VP8L_CHUNK_ID, ref payload)) => {
+            let md = try!(read_vp8l_chunk(payload));
+            Some((if md.alpha_is_used { 0x10 } else { 0 }, md.dimensions))
+        }
+        Some(&(cid, _)) => return Err(invalid_format!("invalid WEBP chunk id: {}", cid)),
+        None => return Err(unexpected_eof!("when reading first WEBP chunk"))
+    };
+    match vp8x {
+        Some((image_flags, dimensions)) => {
+            if dimensions.width == 0 || dimensions.height == 0 {
+                return Err(invalid_format!("WebP image has zero dimension: {}x{}",
+                                           dimensions.width, dimensions.height));
+            }
+            let (width, height) = (dimensions.width - 1, dimensions.height - 1);
+            let payload = vec![
+                image_flags | flags, 0, 0, 0,
+                width as u8, (width >> 8) as u8, (width >> 16) as u8,
+                height as u8, (height >> 8) as u8, (height >> 16) as u8,
+            ];
+            contents.insert(0, (VP8X_CHUNK_ID, payload));
+        }
+        None => {
+            let payload = &mut contents[0].1;
+            if payload.is_empty() {
+                return Err(unexpected_eof!("when reading VP8X chunk"));
+            }
+            payload[0] |= flags;
+        }
+    }
+
+    if let Some(ref profile) = md.icc_profile {
+        contents.insert(1, (ICCP_CHUNK_ID, profile.clone()));
+    }
+    // EXIF and XMP follow the image data, but precede unknown chunks
+    let mut metadata = Vec::new();
+    if let Some(ref exif) = md.exif {
+        metadata.push((EXIF_CHUNK_ID, exif.as_bytes().to_vec()));
+    }
+    if let Some(ref xmp) = md.xmp {
+        metadata.push((XMP_CHUNK_ID, xmp.as_str().as_bytes().to_vec()));
+    }
+    for (id, payload) in metadata {
+        let position = contents.iter()
+            .rposition(|&(id, _)| match id {
+                VP8X_CHUNK_ID | ICCP_CHUNK_ID | ANIM_CHUNK_ID | ALPH_CHUNK_ID | VP8_CHUNK_ID |
+                VP8L_CHUNK_ID | ANMF_CHUNK_ID | EXIF_CHUNK_ID | XMP_CHUNK_ID => true,
+                _ => false
+            })
+            .unwrap();
+        contents.insert(position + 1, (id, payload));
+    }
+
+    let size = contents.iter().fold(4u64, |size, &(_, ref payload)| size + 8 + (payload.len() as u64 + 1) / 2 * 2);
+    if size > 0xffff_ffff {
+        return Err(limit_exceeded!("WebP file of {} bytes does not fit into a RIFF container", size + 8));
+    }
+    let size = size as u32;
+    try!(w.write_all(riff::RIFF_CHUNK_ID.as_bytes()));
+    try!(w.write_all(&[size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8]));
+    try!(w.write_all(WEBP_CHUNK_TYPE.as_bytes()));
+    for &(id, ref payload) in &contents {
+        let len = payload.len() as u32;
+        try!(w.write_all(id.as_bytes()));
+        try!(w.write_all(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]));
+        try!(w.write_all(payload));
+        if len % 2 == 1 {
+            try!(w.write_all(&[0]));
+        }
+    }
+
+    try!(io::copy(chunks.into_inner(), w));
+    Ok(())
+}
+
+impl Animated for Metadata {
+    /// Returns `true` if the image is an extended WebP animation with more than one frame.
+    fn is_animated(&self) -> bool {
+        match *self {
+            Metadata::VP8X(ref md) => md.has_animation && md.frames.len() > 1,
+            _ => false
+        }
+    }
+
+    fn loop_count(&self) -> Option<LoopCount> {
+        let ap = match *self {
+            Metadata::VP8X(ref md) => md.animation_parameters,
+            _ => None
+        };
+        ap.map(|ap| match ap.loop_count {
+            0 => LoopCount::Infinite,
+            n => LoopCount::Finite(n as u32)
+        })
+    }
+
+    /// Returns frames described by ANMF chunks.
+    ///
+    /// For images which are not animated this vector is empty.
+    fn frames(&self) -> Vec<FrameInfo> {
+        match *self {
+            Metadata::VP8X(ref md) => md.frames.iter().map(AnimationFrame::frame_info).collect(),
+            _ => Vec::new()
+        }
+    }
+}
+
+impl AnimationFrame {
+    fn frame_info(&self) -> FrameInfo {
+        FrameInfo {
+            left: self.x_offset,
+            top: self.y_offset,
+            dimensions: self.dimensions,
+            delay_ms: self.duration_ms,
+            disposal: self.disposal,
+            blend: self.blend,
+        }
+    }
+}
+
+/// Visits frames of an animated WebP image in display order as they are read from the stream.
+///
+/// The callback is called with information about each frame as soon as the header of its ANMF
+/// chunk is read, and returns whether reading should continue. Frame data is skipped, and
+/// nothing is read after the callback returns `false`. Frame information is the same as
+/// `Animated::frames()` returns for the loaded `Metadata`; in particular, simple and lossless
+/// files have no frames.
+///
+/// Returns the number of frames visited, including the one for which the callback returned
+/// `false`.
+pub fn for_each_frame<R: ?Sized + BufRead, F>(r: &mut R, mut f: F) -> Result<usize>
+    where F: FnMut(FrameInfo) -> bool
+{
+    let mut chunks = riff::Chunks::with_form_type(r, WEBP_CHUNK_TYPE);
+    match chunks.next() {
+        Some(chunk) => match try!(chunk).chunk_id() {
+            VP8X_CHUNK_ID => {}
+            VP8_CHUNK_ID | VP8L_CHUNK_ID => return Ok(0),
+            cid => return Err(invalid_format!("invalid WEBP chunk id: {}", cid))
+        },
+        None => return Err(unexpected_eof!("when reading first WEBP chunk"))
+    }
+
+    let mut frames = 0;
+    let mut buf = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        let mut chunk = try!(chunk);
+        if chunk.chunk_id() == ANMF_CHUNK_ID {
+            try!(chunk.payload().read_up_to(16, &mut buf));
+            let frame = try!(read_anmf_chunk(&buf));
+            frames += 1;
+            if !f(frame.frame_info()) {
+                break;
+            }
+        }
+    }
+    Ok(frames)
+}
+
+/// Parses WebP metadata directly from an in-memory buffer.
+///
+/// This is equivalent to `Metadata::load_from_buf()`. Chunks are located in the buffer
+/// directly and their payloads are parsed in place, without going through `Chunks`, a
+/// reader or any intermediate buffers; `Metadata::load()` parses chunks read from a stream
+/// with the same code.
+///
+/// This function can be used as a fuzz target; see `immeta::fuzz`.
+pub fn parse_bytes(data: &[u8]) -> Result<Metadata> {
+    let mut chunks = riff::SliceChunks::with_form_type(data, WEBP_CHUNK_TYPE);
+    let chunk = match chunks.next() {
+        Some(c) => try!(c),
+        None => return Err(unexpected_eof!("when reading first WEBP chunk"))
+    };
+    let mut md = try!(parse_first_chunk(chunk.chunk_id, chunk.payload));
+
+    if let Metadata::VP8X(ref mut md) = md {
+        while let Some(chunk) = chunks.next() {
+            let chunk = try!(chunk);
+            try!(md.parse_chunk(chunk.chunk_id, chunk.payload));
+            if chunk.chunk_id == ANMF_CHUNK_ID && md.frame_dimensions.is_none() {
+                // frame data sub-chunks follow the ANMF header
+                let mut frame_chunks = riff::SliceChunks::sequence(&chunk.payload[16..], chunk.offset + 24);
+                while let Some(frame_chunk) = frame_chunks.next() {
+                    let frame_chunk = try!(frame_chunk);
+                    if is_bitstream_chunk(frame_chunk.chunk_id) {
+                        md.frame_dimensions = try!(read_frame_dimensions(frame_chunk.chunk_id, frame_chunk.payload));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(md)
+}
+
+impl LoadableMetadata for Metadata {
+    fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Metadata> {
+        let mut chunks = riff::Chunks::with_form_type(r, WEBP_CHUNK_TYPE);
+        let mut buf = Vec::new();
+        let mut md = {
+            let mut chunk = match chunks.next() {
                 Some(c) => try!(c),
                 None => return Err(unexpected_eof!("when reading first WEBP chunk"))
             };
+            try!(chunk.payload().read_up_to(FIRST_CHUNK_HEADER_LEN, &mut buf));
+            try!(parse_first_chunk(chunk.chunk_id(), &buf))
+        };
 
-            match chunk.chunk_id() {
-                VP8_CHUNK_ID => return read_vp8_chunk(&mut chunk).map(Metadata::VP8),
-                VP8L_CHUNK_ID => return Err(invalid_format!("unsupported (yet) VP8 chunk id")),
-                VP8X_CHUNK_ID => return Err(invalid_format!("unsupported (yet) VP8 chunk id")),
-                ALPH_CHUNK_ID => return Err(invalid_format!("unsupported (yet) VP8 chunk id")),
-                cid => return Err(invalid_format!("invalid WEBP chunk id: {}", cid))
+        if let Metadata::VP8X(ref mut md) = md {
+            while let Some(chunk) = chunks.next() {
+                let mut chunk = try!(chunk);
+                let id = chunk.chunk_id();
+                try!(chunk.payload().read_up_to(md.payload_len(id), &mut buf));
+                try!(md.parse_chunk(id, &buf));
+                if id == ANMF_CHUNK_ID && md.frame_dimensions.is_none() {
+                    let mut frame_chunks = chunk.subchunks();
+                    while let Some(frame_chunk) = frame_chunks.next() {
+                        let mut frame_chunk = try!(frame_chunk);
+                        if is_bitstream_chunk(frame_chunk.chunk_id()) {
+                            try!(frame_chunk.payload().read_up_to(FIRST_CHUNK_HEADER_LEN, &mut buf));
+                            md.frame_dimensions = try!(read_frame_dimensions(frame_chunk.chunk_id(), &buf));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(md)
+    }
+
+    fn load_from_buf(buf: &[u8]) -> Result<Metadata> {
+        parse_bytes(buf)
+    }
+}
+
+/// The number of payload bytes needed to parse the first chunk of a WebP file.
+const FIRST_CHUNK_HEADER_LEN: u64 = 10;
+
+/// Parses the first chunk of a WebP file, which determines its kind, given the first
+/// `FIRST_CHUNK_HEADER_LEN` bytes of the payload.
+fn parse_first_chunk(id: ChunkId, payload: &[u8]) -> Result<Metadata> {
+    log_trace!("WebP chunk {}", id);
+    match id {
+        VP8_CHUNK_ID => {
+            let md = try!(read_vp8_chunk(payload));
+            // simple files consist of a single frame, which must be a key frame
+            match md.frame {
+                VP8Frame::Key { .. } => Ok(Metadata::VP8(md)),
+                VP8Frame::Inter => Err(invalid_format!("VP8 frame is not a key frame"))
             }
         }
+        VP8L_CHUNK_ID => read_vp8l_chunk(payload).map(Metadata::VP8L),
+        VP8X_CHUNK_ID => read_vp8x_chunk(payload).map(Metadata::VP8X),
+        ALPH_CHUNK_ID => Err(invalid_format!("unsupported (yet) VP8 chunk id")),
+        cid => Err(invalid_format!("invalid WEBP chunk id: {}", cid))
     }
 }
 
-fn read_vp8_chunk(chunk: &mut RiffChunk) -> Result<VP8Metadata> {
-    let r = chunk.contents();
+fn is_bitstream_chunk(id: ChunkId) -> bool {
+    id == VP8_CHUNK_ID || id == VP8L_CHUNK_ID
+}
+
+impl VP8XMetadata {
+    /// Returns the number of payload bytes `parse_chunk()` needs for a chunk with the given
+    /// identifier.
+    fn payload_len(&self, id: ChunkId) -> u64 {
+        match id {
+            ANIM_CHUNK_ID => 6,
+            ANMF_CHUNK_ID => 16,
+            VP8_CHUNK_ID | VP8L_CHUNK_ID if self.frame_dimensions.is_none() => FIRST_CHUNK_HEADER_LEN,
+            _ => 0
+        }
+    }
 
-    let mut hdr = [0u8; 3];
-    try!(r.read_exact(&mut hdr).map_err(if_eof!(std, "when reading VP8 frame header")));
+    /// Parses a chunk following the VP8X chunk, given the first `payload_len()` bytes of its
+    /// payload.
+    ///
+    /// The frame data of ANMF chunks is not parsed; the callers look for the frame bitstream
+    /// in it while `frame_dimensions` is unknown.
+    fn parse_chunk(&mut self, id: ChunkId, payload: &[u8]) -> Result<()> {
+        log_trace!("WebP chunk {}", id);
+        match id {
+            ANIM_CHUNK_ID => self.animation_parameters = Some(try!(read_anim_chunk(payload))),
+            ANMF_CHUNK_ID => self.frames.push(try!(read_anmf_chunk(payload))),
+            VP8_CHUNK_ID | VP8L_CHUNK_ID if self.frame_dimensions.is_none() => {
+                self.frame_dimensions = try!(read_frame_dimensions(id, payload));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Reads image dimensions from a WebP stream, stopping right after the image header.
+///
+/// This reads at most 30 bytes, unlike `Metadata::load()` which reads all chunks of
+/// extended files. For extended files the canvas dimensions are returned.
+pub fn read_dimensions<R: ?Sized + BufRead>(r: &mut R) -> Result<Dimensions> {
+    let mut chunks = riff::Chunks::with_form_type(r, WEBP_CHUNK_TYPE);
+    let mut chunk = match chunks.next() {
+        Some(c) => try!(c),
+        None => return Err(unexpected_eof!("when reading first WEBP chunk"))
+    };
+    let mut header = Vec::new();
+    try!(chunk.payload().read_up_to(FIRST_CHUNK_HEADER_LEN, &mut header));
+
+    match chunk.chunk_id() {
+        VP8_CHUNK_ID => match try!(read_vp8_chunk(&header)).frame {
+            VP8Frame::Key { dimensions, .. } => Ok(dimensions),
+            VP8Frame::Inter => Err(invalid_format!("VP8 frame is not a key frame"))
+        },
+        VP8L_CHUNK_ID => read_vp8l_chunk(&header).map(|md| md.dimensions),
+        VP8X_CHUNK_ID => read_vp8x_chunk(&header).map(|md| md.canvas_dimensions),
+        cid => Err(invalid_format!("invalid WEBP chunk id: {}", cid))
+    }
+}
+
+fn read_vp8_chunk(data: &[u8]) -> Result<VP8Metadata> {
+    if data.len() < 3 {
+        return Err(unexpected_eof!("when reading VP8 frame header"));
+    }
+    let hdr = &data[..3];
 
     let mut result = VP8Metadata {
         version_number: 0,
@@ -101,8 +880,10 @@ fn read_vp8_chunk(chunk: &mut RiffChunk) -> Result<VP8Metadata> {
                                  ((hdr[2] as u32) << 11);
 
     if key_frame {
-        let mut hdr = [0u8; 7];
-        try!(r.read_exact(&mut hdr).map_err(if_eof!(std, "when reading VP8 key frame header")));
+        if data.len() < 10 {
+            return Err(unexpected_eof!("when reading VP8 key frame header"));
+        }
+        let hdr = &data[3..10];
 
         // check magic value
         if &hdr[..3] != &[0x9d, 0x01, 0x2a] {
@@ -132,3 +913,121 @@ fn read_vp8_chunk(chunk: &mut RiffChunk) -> Result<VP8Metadata> {
     Ok(result)
 }
 
+
+fn read_vp8l_chunk(data: &[u8]) -> Result<VP8LMetadata> {
+    if data.len() < 5 {
+        return Err(unexpected_eof!("when reading VP8L header"));
+    }
+    let hdr = &data[..5];
+
+    if hdr[0] != 0x2f {
+        return Err(invalid_format!("VP8L signature is invalid: {:#04x}", hdr[0]));
+    }
+
+    // the signature is followed by a 32-bit little-endian value consisting of
+    // (from least significant bits):
+    //    14 bits  --  width minus one
+    //    14 bits  --  height minus one
+    //     1 bit   --  alpha is used flag
+    //     3 bits  --  version number
+
+    let bits = hdr[1] as u32 | (hdr[2] as u32) << 8 | (hdr[3] as u32) << 16 | (hdr[4] as u32) << 24;
+    let width = (bits & 0x3fff) + 1;
+    let height = ((bits >> 14) & 0x3fff) + 1;
+    let version_number = (bits >> 29) as u8;
+    if version_number != 0 {
+        return Err(invalid_format!("unsupported VP8L version: {}", version_number));
+    }
+
+    Ok(VP8LMetadata {
+        dimensions: (width, height).into(),
+        alpha_is_used: (bits >> 28) & 1 == 1,
+        version_number: version_number
+    })
+}
+
+fn read_vp8x_chunk(data: &[u8]) -> Result<VP8XMetadata> {
+    if data.len() < 10 {
+        return Err(unexpected_eof!("when reading VP8X chunk"));
+    }
+    let hdr = &data[..10];
+
+    // bits of the first byte:
+    //    rrilexar
+    // where
+    //    r  --  reserved
+    //    i  --  ICC profile
+    //    l  --  alpha
+    //    e  --  EXIF metadata
+    //    x  --  XMP metadata
+    //    a  --  animation
+    // the next three bytes are reserved, followed by canvas width and height minus one,
+    // 24 bits each
+
+    let flags = hdr[0];
+    let width  = (hdr[4] as u32 | (hdr[5] as u32) << 8 | (hdr[6] as u32) << 16) + 1;
+    let height = (hdr[7] as u32 | (hdr[8] as u32) << 8 | (hdr[9] as u32) << 16) + 1;
+
+    Ok(VP8XMetadata {
+        has_icc_profile: flags & 0x20 != 0,
+        has_alpha: flags & 0x10 != 0,
+        has_exif: flags & 0x08 != 0,
+        has_xmp: flags & 0x04 != 0,
+        has_animation: flags & 0x02 != 0,
+        canvas_dimensions: (width, height).into(),
+        frame_dimensions: None,
+        animation_parameters: None,
+        frames: Vec::new()
+    })
+}
+
+fn read_frame_dimensions(id: ChunkId, data: &[u8]) -> Result<Option<Dimensions>> {
+    match id {
+        VP8_CHUNK_ID => match try!(read_vp8_chunk(data)).frame {
+            VP8Frame::Key { dimensions, .. } => Ok(Some(dimensions)),
+            VP8Frame::Inter => Ok(None)
+        },
+        VP8L_CHUNK_ID => read_vp8l_chunk(data).map(|md| Some(md.dimensions)),
+        _ => Ok(None)
+    }
+}
+
+fn read_anim_chunk(data: &[u8]) -> Result<AnimationParameters> {
+    if data.len() < 6 {
+        return Err(unexpected_eof!("when reading ANIM chunk"));
+    }
+    let hdr = &data[..6];
+
+    // background color is stored in blue, green, red, alpha order, followed by 16-bit loop count
+    Ok(AnimationParameters {
+        background_color: (hdr[2], hdr[1], hdr[0], hdr[3]),
+        loop_count: hdr[4] as u16 | (hdr[5] as u16) << 8
+    })
+}
+
+fn read_anmf_chunk(data: &[u8]) -> Result<AnimationFrame> {
+    if data.len() < 16 {
+        return Err(unexpected_eof!("when reading ANMF chunk header"));
+    }
+    let hdr = &data[..16];
+
+    // ANMF header consists of five 24-bit values: frame offsets divided by two, frame width
+    // and height minus one and frame duration, followed by a flags byte:
+    //    rrrrrrbd
+    // where
+    //    r  --  reserved
+    //    b  --  blending method, 0 is alpha-blending, 1 is no blending
+    //    d  --  disposal method, 0 is none, 1 is dispose to background
+
+    let u24 = |i: usize| hdr[i] as u32 | (hdr[i+1] as u32) << 8 | (hdr[i+2] as u32) << 16;
+    let flags = hdr[15];
+
+    Ok(AnimationFrame {
+        x_offset: u24(0) * 2,
+        y_offset: u24(3) * 2,
+        dimensions: (u24(6) + 1, u24(9) + 1).into(),
+        duration_ms: u24(12),
+        blend: if flags & 0x02 != 0 { Blend::Source } else { Blend::Over },
+        disposal: if flags & 0x01 != 0 { Disposal::Background } else { Disposal::None }
+    })
+}
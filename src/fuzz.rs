@@ -0,0 +1,273 @@
+//! Entry points for fuzzing.
+//!
+//! Every format module provides a `parse_bytes()` function which parses metadata from a byte
+//! slice. These functions perform no I/O and do not depend on any global state, so they can
+//! be used as fuzz targets directly. Malformed input is supposed to result in an error, so
+//! any panic or abort found this way is a bug:
+//!
+//! ```ignore
+//! #![no_main]
+//! use libfuzzer_sys::fuzz_target;
+//!
+//! fuzz_target!(|data: &[u8]| {
+//!     let _ = immeta::formats::png::parse_bytes(data);
+//! });
+//! ```
+//!
+//! `parse_all()` runs all parsers and stream checks of this library on the same input, which
+//! is convenient for a single catch-all target.
+//!
+//! Random bytes rarely get past signature and framing checks, so this module also provides
+//! structured inputs, like `PngInput`, which are serialized into streams with valid framing
+//! but arbitrary contents. When `arbitrary` feature is enabled, they implement the `Arbitrary`
+//! trait of the [arbitrary](https://crates.io/crates/arbitrary) crate, so that fuzzers can
+//! generate them from raw fuzzer input:
+//!
+//! ```ignore
+//! fuzz_target!(|input: immeta::fuzz::PngInput| {
+//!     immeta::fuzz::parse_all(&input.to_bytes());
+//! });
+//! ```
+
+use std::io::{self, Cursor};
+
+#[cfg(feature = "arbitrary")] use arbitrary::Arbitrary;
+
+use generic;
+use loader::{Immeta, LoadOptions};
+use formats::{jpeg, png, gif, webp, pdf};
+use validation::Validator;
+use polyglot;
+use transplant;
+
+/// Runs all parsers and stream checks on the provided data, discarding the results.
+///
+/// This includes format detection, loading and probing of generic metadata, accessors of the
+/// loaded metadata and resource limit checks, `parse_bytes()` of every format module,
+/// validation, completeness checks, image data ranges, polyglot analysis and metadata
+/// extraction.
+pub fn parse_all(data: &[u8]) {
+    if let Ok(md) = generic::load_from_buf(data) {
+        let _ = md.dimensions();
+        let _ = md.estimated_decoded_size();
+        let _ = LoadOptions::hardened().limits.check(&md);
+    }
+    let _ = Immeta::hardened().load_from_buf(data);
+    let _ = generic::probe_from_buf(data);
+    let _ = jpeg::parse_bytes(data);
+    let _ = png::parse_bytes(data);
+    let _ = gif::parse_bytes(data);
+    if let Ok(md) = webp::parse_bytes(data) {
+        let _ = md.frame_dimensions();
+        let _ = md.estimated_decoded_size();
+    }
+    let _ = pdf::parse_bytes(data);
+    let _ = Validator::new().validate(&mut Cursor::new(data));
+    let _ = generic::verify_complete(&mut Cursor::new(data));
+    let _ = generic::image_data_ranges(&mut Cursor::new(data));
+    let _ = polyglot::analyze(&mut Cursor::new(data));
+    if let Ok(probe) = generic::probe_from_buf(data) {
+        let _ = transplant::extract(probe.format, &mut Cursor::new(data));
+        let _ = transplant::embed(probe.format, &mut Cursor::new(data), &mut io::sink(),
+                                  &transplant::EmbeddedMetadata::default());
+    }
+}
+
+/// A JPEG marker segment of `JpegInput`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct JpegSegment {
+    /// Marker type, i.e. the second byte of the marker.
+    pub marker: u8,
+    /// Segment payload; it is truncated to the maximum segment size when serialized.
+    pub payload: Vec<u8>,
+}
+
+/// A structured JPEG stream: the SOI marker, marker segments, entropy-coded data and the EOI
+/// marker.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct JpegInput {
+    /// Marker segments following the SOI marker.
+    pub segments: Vec<JpegSegment>,
+    /// Data following the segments, e.g. entropy-coded data of the last scan.
+    pub entropy_data: Vec<u8>,
+}
+
+impl JpegInput {
+    /// Serializes the input into a JPEG stream.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![0xff, 0xd8];
+        for segment in &self.segments {
+            let payload = &segment.payload[..segment.payload.len().min(0xffff - 2)];
+            let len = payload.len() + 2;
+            data.extend_from_slice(&[0xff, segment.marker, (len >> 8) as u8, len as u8]);
+            data.extend_from_slice(payload);
+        }
+        data.extend_from_slice(&self.entropy_data);
+        data.extend_from_slice(&[0xff, 0xd9]);
+        data
+    }
+}
+
+/// A chunk of `PngInput` or `WebpInput`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct Chunk {
+    /// Chunk type or identifier.
+    pub chunk_type: [u8; 4],
+    /// Chunk payload.
+    pub payload: Vec<u8>,
+}
+
+/// A structured PNG stream: the signature, IHDR chunk with the given fields, other chunks and
+/// IEND chunk.
+///
+/// CRCs of the chunks are zero, since this library does not verify them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct PngInput {
+    /// Image width.
+    pub width: u32,
+    /// Image height.
+    pub height: u32,
+    /// Bit depth.
+    pub bit_depth: u8,
+    /// Color type.
+    pub color_type: u8,
+    /// Interlace method.
+    pub interlace_method: u8,
+    /// Chunks between IHDR and IEND.
+    pub chunks: Vec<Chunk>,
+}
+
+impl PngInput {
+    /// Serializes the input into a PNG stream.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn write_chunk(data: &mut Vec<u8>, chunk_type: &[u8; 4], payload: &[u8]) {
+            let len = payload.len() as u32;
+            data.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+            data.extend_from_slice(chunk_type);
+            data.extend_from_slice(payload);
+            data.extend_from_slice(&[0, 0, 0, 0]);
+        }
+
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        let (w, h) = (self.width, self.height);
+        write_chunk(&mut data, b"IHDR", &[
+            (w >> 24) as u8, (w >> 16) as u8, (w >> 8) as u8, w as u8,
+            (h >> 24) as u8, (h >> 16) as u8, (h >> 8) as u8, h as u8,
+            self.bit_depth, self.color_type, 0, 0, self.interlace_method,
+        ]);
+        for chunk in &self.chunks {
+            write_chunk(&mut data, &chunk.chunk_type, &chunk.payload);
+        }
+        write_chunk(&mut data, b"IEND", &[]);
+        data
+    }
+}
+
+/// A block of `GifInput`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub enum GifBlock {
+    /// An extension block with the given label and data sub-blocks.
+    Extension {
+        /// Extension label.
+        label: u8,
+        /// Data, split into sub-blocks of at most 255 bytes when serialized.
+        data: Vec<u8>,
+    },
+    /// An image descriptor followed by the local color table, if its flags declare one, and
+    /// by image data.
+    Image {
+        /// Frame position, size and flags.
+        descriptor: [u8; 9],
+        /// LZW minimum code size.
+        lzw_code_size: u8,
+        /// Image data, split into sub-blocks of at most 255 bytes when serialized.
+        data: Vec<u8>,
+    },
+}
+
+/// A structured GIF stream: the header, the logical screen descriptor and the global color
+/// table, blocks and the trailer.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct GifInput {
+    /// Logical screen descriptor following the header.
+    pub screen_descriptor: [u8; 7],
+    /// Blocks following the global color table.
+    pub blocks: Vec<GifBlock>,
+}
+
+impl GifInput {
+    /// Serializes the input into a GIF stream.
+    ///
+    /// Color tables declared by the flags are filled with zeros.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn color_table(data: &mut Vec<u8>, flags: u8) {
+            if flags & 0x80 != 0 {
+                data.resize(data.len() + (3 << ((flags & 0x07) + 1)), 0);
+            }
+        }
+
+        fn sub_blocks(data: &mut Vec<u8>, payload: &[u8]) {
+            for block in payload.chunks(255) {
+                data.push(block.len() as u8);
+                data.extend_from_slice(block);
+            }
+            data.push(0);
+        }
+
+        let mut data = b"GIF89a".to_vec();
+        data.extend_from_slice(&self.screen_descriptor);
+        color_table(&mut data, self.screen_descriptor[4]);
+        for block in &self.blocks {
+            match *block {
+                GifBlock::Extension { label, data: ref payload } => {
+                    data.extend_from_slice(&[0x21, label]);
+                    sub_blocks(&mut data, payload);
+                }
+                GifBlock::Image { ref descriptor, lzw_code_size, data: ref payload } => {
+                    data.push(0x2c);
+                    data.extend_from_slice(descriptor);
+                    color_table(&mut data, descriptor[8]);
+                    data.push(lzw_code_size);
+                    sub_blocks(&mut data, payload);
+                }
+            }
+        }
+        data.push(0x3b);
+        data
+    }
+}
+
+/// A structured WebP file: the RIFF header with the correct size followed by chunks.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct WebpInput {
+    /// Chunks of the RIFF container.
+    pub chunks: Vec<Chunk>,
+}
+
+impl WebpInput {
+    /// Serializes the input into a WebP file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for chunk in &self.chunks {
+            let len = chunk.payload.len() as u32;
+            body.extend_from_slice(&chunk.chunk_type);
+            body.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+            body.extend_from_slice(&chunk.payload);
+            if len % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let size = body.len() as u32;
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&[size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8]);
+        data.extend(body);
+        data
+    }
+}
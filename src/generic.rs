@@ -1,12 +1,20 @@
-use std::io::{BufRead, Cursor, Seek, SeekFrom, BufReader};
+use std::io::{Read, BufRead, Cursor, Seek, SeekFrom, BufReader};
 use std::fs::File;
 use std::path::Path;
 use std::result;
+use std::convert::TryFrom;
+use std::borrow::Cow;
 
-use types::{Result, Dimensions};
+use types::{Result, PartialResult, Error, ErrorWithPartial, Completeness, Dimensions, ImageFormat, ByteRange};
+use loader::LoadOptions;
 use traits::LoadableMetadata;
+use animation::Animated;
 use formats::{jpeg, png, gif, webp};
 use generic::markers::MetadataMarker;
+use utils::{self, PeekReader, Tracked};
+use common::datetime::DateTime;
+use common::exif::ExifTimestamps;
+use common::xmp::Xmp;
 
 /// Contains metadata marker types.
 ///
@@ -227,6 +235,7 @@ pub mod markers {
 ///
 /// Values of this type are obtained via `immeta::load()` function and its derivatives.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum GenericMetadata {
     Png(png::Metadata),
     Gif(gif::Metadata),
@@ -245,16 +254,140 @@ impl GenericMetadata {
         }
     }
 
-    /// Returns a MIME type string for the image type of the contained metadata.
-    pub fn mime_type(&self) -> &'static str {
+    /// Returns an estimate of the size in bytes of the decoded image.
+    ///
+    /// The estimate is computed from image dimensions, number of channels, bytes per
+    /// channel and number of frames, as reported by the contained metadata. It can be used
+    /// to enforce memory limits before passing the image to a decoder.
+    pub fn estimated_decoded_size(&self) -> u64 {
+        match *self {
+            GenericMetadata::Png(ref md) => md.estimated_decoded_size(),
+            GenericMetadata::Gif(ref md) => md.estimated_decoded_size(),
+            GenericMetadata::Jpeg(ref md) => md.estimated_decoded_size(),
+            // metadata without dimensions is never loaded, see `webp::Metadata::load()`
+            GenericMetadata::Webp(ref md) => md.estimated_decoded_size().unwrap_or(0)
+        }
+    }
+
+    /// Returns the image format of the contained metadata.
+    pub fn format(&self) -> ImageFormat {
         match *self {
-            GenericMetadata::Png(_) => "image/png",
-            GenericMetadata::Gif(_) => "image/gif",
-            GenericMetadata::Jpeg(_) => "image/jpeg",
-            GenericMetadata::Webp(_) => "image/webp"
+            GenericMetadata::Png(_) => ImageFormat::Png,
+            GenericMetadata::Gif(_) => ImageFormat::Gif,
+            GenericMetadata::Jpeg(_) => ImageFormat::Jpeg,
+            GenericMetadata::Webp(_) => ImageFormat::Webp
         }
     }
 
+    /// Returns animation information of the contained metadata, if its image format
+    /// supports animation.
+    ///
+    /// Note that the image may still consist of a single frame; use `Animated::is_animated()`
+    /// to check it.
+    pub fn animation(&self) -> Option<&Animated> {
+        match *self {
+            GenericMetadata::Png(ref md) => Some(md),
+            GenericMetadata::Gif(ref md) => Some(md),
+            GenericMetadata::Webp(ref md) => Some(md),
+            _ => None
+        }
+    }
+
+    /// Parses extended metadata whose parsing was deferred because it was loaded with
+    /// `LoadOptions::lazy_extensions` enabled.
+    ///
+    /// The provided stream must contain the image this metadata was loaded from, starting at
+    /// its beginning; for gzip-compressed input, it must provide the decompressed data. Does
+    /// nothing if there is no deferred metadata.
+    pub fn load_extensions<R: ?Sized + Read + Seek>(&mut self, r: &mut R) -> Result<()> {
+        match *self {
+            GenericMetadata::Png(ref mut md) => md.load_extensions(r),
+            GenericMetadata::Jpeg(ref mut md) => md.load_extensions(r),
+            GenericMetadata::Gif(ref mut md) => md.load_extensions(r),
+            _ => Ok(())
+        }
+    }
+
+    /// Returns the time when the image was originally created, e.g. when the photo was taken.
+    ///
+    /// The following sources are checked in this order, and the first valid timestamp found
+    /// is returned:
+    ///
+    /// 1. EXIF `DateTimeOriginal` tag;
+    /// 2. EXIF `DateTimeDigitized` tag;
+    /// 3. XMP `exif:DateTimeOriginal` property;
+    /// 4. XMP `xmp:CreateDate` property;
+    /// 5. XMP `photoshop:DateCreated` property.
+    ///
+    /// EXIF data is available in JPEG and PNG images, XMP packets in JPEG, PNG and GIF images.
+    pub fn created_at(&self) -> Option<DateTime> {
+        let exif = self.exif_timestamps();
+        exif.original
+            .or(exif.digitized)
+            .or_else(|| self.xmp_timestamp(&["exif:DateTimeOriginal", "xmp:CreateDate", "photoshop:DateCreated"]))
+    }
+
+    /// Returns the time when the image was last modified.
+    ///
+    /// The following sources are checked in this order, and the first valid timestamp found
+    /// is returned:
+    ///
+    /// 1. PNG tIME chunk;
+    /// 2. EXIF `DateTime` tag;
+    /// 3. XMP `xmp:ModifyDate` property.
+    pub fn modified_at(&self) -> Option<DateTime> {
+        let png_time = match *self {
+            GenericMetadata::Png(ref md) => md.modification_time,
+            _ => None
+        };
+        png_time
+            .or_else(|| self.exif_timestamps().modified)
+            .or_else(|| self.xmp_timestamp(&["xmp:ModifyDate"]))
+    }
+
+    fn exif_timestamps(&self) -> ExifTimestamps {
+        let exif = match *self {
+            GenericMetadata::Jpeg(ref md) => md.exif.as_ref(),
+            GenericMetadata::Png(ref md) => md.exif.as_ref(),
+            _ => None
+        };
+        match exif.map(|exif| exif.timestamps()) {
+            Some(Ok(timestamps)) => timestamps,
+            Some(Err(e)) => {
+                log_debug!("ignoring invalid EXIF data when reading timestamps: {}", e);
+                ExifTimestamps::default()
+            }
+            None => ExifTimestamps::default()
+        }
+    }
+
+    fn xmp_timestamp(&self, properties: &[&str]) -> Option<DateTime> {
+        let xmp: Option<Cow<Xmp>> = match *self {
+            GenericMetadata::Jpeg(ref md) => md.xmp.as_ref().map(Cow::Borrowed),
+            GenericMetadata::Png(ref md) => md.xmp().map(Cow::Owned),
+            GenericMetadata::Gif(ref md) => md.xmp.as_ref().map(Cow::Borrowed),
+            GenericMetadata::Webp(_) => None
+        };
+        xmp.and_then(|xmp| properties.iter()
+            .filter_map(|&name| xmp.property(name).and_then(DateTime::parse_iso8601))
+            .next())
+    }
+
+    /// Returns a MIME type string for the image type of the contained metadata.
+    #[inline]
+    pub fn mime_type(&self) -> &'static str {
+        self.format().mime_type()
+    }
+
+    /// Returns a typed MIME value for the image type of the contained metadata.
+    ///
+    /// This method is only available when `mime` feature is enabled.
+    #[cfg(feature = "mime")]
+    #[inline]
+    pub fn mime(&self) -> ::mime::Mime {
+        self.format().mime()
+    }
+
     /// Attemts to convert this value to the specific metadata type by value.
     ///
     /// This method is needed only to provide a convenient syntax and it is not necessary
@@ -274,39 +407,230 @@ impl GenericMetadata {
     }
 }
 
+macro_rules! impl_generic_conversions {
+    ($($gvar:ident, $mtpe:ty, $as_name:ident, $name:expr);+) => {
+        $(
+        impl GenericMetadata {
+            #[doc = concat!("Returns a reference to the contained ", $name,
+                            " metadata, or `None` if this value contains metadata of another format.")]
+            #[inline]
+            pub fn $as_name(&self) -> Option<&$mtpe> {
+                match *self {
+                    GenericMetadata::$gvar(ref md) => Some(md),
+                    _ => None
+                }
+            }
+        }
+
+        impl TryFrom<GenericMetadata> for $mtpe {
+            type Error = GenericMetadata;
+
+            /// Converts the generic metadata into the concrete metadata type, returning the
+            /// original value if it contains metadata of another format.
+            #[inline]
+            fn try_from(gmd: GenericMetadata) -> result::Result<$mtpe, GenericMetadata> {
+                match gmd {
+                    GenericMetadata::$gvar(md) => Ok(md),
+                    gmd => Err(gmd)
+                }
+            }
+        }
+
+        impl<'a> TryFrom<&'a GenericMetadata> for &'a $mtpe {
+            type Error = ();
+
+            #[inline]
+            fn try_from(gmd: &'a GenericMetadata) -> result::Result<&'a $mtpe, ()> {
+                gmd.$as_name().ok_or(())
+            }
+        }
+
+        impl From<$mtpe> for GenericMetadata {
+            #[inline]
+            fn from(md: $mtpe) -> GenericMetadata {
+                GenericMetadata::$gvar(md)
+            }
+        }
+        )+
+    }
+}
+
+impl_generic_conversions! {
+    Jpeg, jpeg::Metadata, as_jpeg, "JPEG";
+    Png, png::Metadata, as_png, "PNG";
+    Gif, gif::Metadata, as_gif, "GIF";
+    Webp, webp::Metadata, as_webp, "WebP"
+}
+
 /// Attempts to load metadata for an image contained in the provided input stream.
 ///
 /// This method automatically determines the format of the contained image. Because it may
 /// need to read the stream from the beginning several times, a `Seek` bound is necessary
-/// on the input stream. This may cause problems only with network streams as they are
-/// naturally not seekable, so one would need to buffer the data from them first.
+/// on the input stream. Network streams and pipes are naturally not seekable; use
+/// `load_from_reader()` for them.
+///
+/// If `gzip` feature is enabled, gzip-compressed input is detected and decompressed
+/// transparently before the format of the contained image is determined.
 pub fn load<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<GenericMetadata> {
-    // try png
-    try!(r.seek(SeekFrom::Start(0)));
-    if let Ok(md) = png::Metadata::load(r) {
-        return Ok(GenericMetadata::Png(md));
+    load_partial(r).map_err(|e| e.error)
+}
+
+/// Attempts to load metadata for an image contained in the provided input stream, returning
+/// metadata loaded so far together with the error if parsing fails midway.
+///
+/// This method behaves like `load()`, except that when the image format has been recognized
+/// but its metadata can't be parsed completely (e.g. because the file is truncated), the
+/// returned error may contain the partially loaded metadata. See `ErrorWithPartial` for
+/// more information.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// let mut f = BufReader::new(File::open("truncated.gif").unwrap());
+/// match immeta::load_partial(&mut f) {
+///     Ok(md) => println!("complete: {:?}", md.dimensions()),
+///     Err(e) => match e.partial {
+///         Some(md) => println!("partial: {:?} ({})", md.dimensions(), e.error),
+///         None => println!("failed: {}", e.error)
+///     }
+/// }
+/// ```
+pub fn load_partial<R: ?Sized + BufRead + Seek>(r: &mut R) -> PartialResult<GenericMetadata> {
+    load_partial_with_options(r, &LoadOptions::default())
+}
+
+/// Implements format detection and loading for `load_partial()` and `Immeta` loader, trying only
+/// the formats enabled in the provided options.
+pub(crate) fn load_partial_with_options<R: ?Sized + BufRead + Seek>(r: &mut R, options: &LoadOptions)
+    -> PartialResult<GenericMetadata>
+{
+    load_partial_nested(r, options, 0)
+}
+
+/// Loads an image which is nested into `depth` containers, e.g. gzip layers.
+#[cfg_attr(not(feature = "gzip"), allow(unused_variables))]
+fn load_partial_nested<R: ?Sized + BufRead + Seek>(r: &mut R, options: &LoadOptions, depth: u32)
+    -> PartialResult<GenericMetadata>
+{
+    // try gzip-compressed input
+    #[cfg(feature = "gzip")]
+    {
+        try!(r.seek(SeekFrom::Start(0)));
+        if try!(is_gzip(r)) {
+            log_debug!("detected gzip-compressed input at nesting depth {}", depth);
+            return load_gzip(r, options, depth);
+        }
     }
 
-    // try gif
-    try!(r.seek(SeekFrom::Start(0)));
-    if let Ok(md) = gif::Metadata::load(r) {
-        return Ok(GenericMetadata::Gif(md));
+    macro_rules! try_format {
+        ($name:expr, $var:ident, $md:ty) => {
+            if options.is_format_enabled(ImageFormat::$var) {
+                try!(r.seek(SeekFrom::Start(0)));
+                match <$md as LoadableMetadata>::load_partial_with_options(r, options) {
+                    Ok(md) => {
+                        log_debug!("detected {} image, {} bytes consumed",
+                                   $name, try!(r.seek(SeekFrom::Current(0))));
+                        return Ok(GenericMetadata::$var(md));
+                    }
+                    Err(ErrorWithPartial { error, partial: Some(md) }) => {
+                        log_debug!("detected {} image, but it is broken: {}", $name, error);
+                        let error = if options.hex_dump_errors {
+                            let position = try!(r.seek(SeekFrom::Current(0)));
+                            with_hex_dump(r, position, error)
+                        } else {
+                            error
+                        };
+                        return Err(ErrorWithPartial {
+                            error: error,
+                            partial: Some(GenericMetadata::$var(md))
+                        });
+                    }
+                    Err(ErrorWithPartial { error: e @ Error::LimitExceeded(_), .. }) => return Err(e.into()),
+                    Err(e) => log_debug!("not a {} image: {}", $name, e)
+                }
+            }
+        }
     }
 
-    // try webp
-    try!(r.seek(SeekFrom::Start(0)));
-    if let Ok(md) = webp::Metadata::load(r) {
-        return Ok(GenericMetadata::Webp(md));
+    try_format!("PNG", Png, png::Metadata);
+    try_format!("GIF", Gif, gif::Metadata);
+    try_format!("WebP", Webp, webp::Metadata);
+    // JPEG should be the last because it can't be determined from its header (since it has none)
+    try_format!("JPEG", Jpeg, jpeg::Metadata);
+
+    let error = invalid_format!("unknown or unsupported file type");
+    if options.hex_dump_errors {
+        Err(with_hex_dump(r, 0, error).into())
+    } else {
+        Err(error.into())
     }
+}
 
-    // try jpeg
-    // should be the last because JPEG can't be determined from its header (since it has none)
-    try!(r.seek(SeekFrom::Start(0)));
-    if let Ok(md) = jpeg::Metadata::load(r) {
-        return Ok(GenericMetadata::Jpeg(md));
+/// The number of bytes preceding the failure position which are included in hex dumps.
+const HEX_DUMP_BEFORE: u64 = 32;
+/// The number of bytes starting at the failure position which are included in hex dumps.
+const HEX_DUMP_AFTER: u64 = 32;
+
+/// Extends the provided error with a hex dump of the input around the given position.
+///
+/// If the input can't be read, the error is returned unchanged.
+fn with_hex_dump<R: ?Sized + BufRead + Seek>(r: &mut R, position: u64, error: Error) -> Error {
+    // start the dump at a line boundary
+    let start = position.saturating_sub(HEX_DUMP_BEFORE) & !15;
+    let mut data = Vec::new();
+    let read = r.seek(SeekFrom::Start(start))
+        .and_then(|_| r.take(position - start + HEX_DUMP_AFTER).read_to_end(&mut data));
+    match read {
+        Ok(_) => error.with_context(&format!("\ninput bytes around offset {}:{}",
+                                             position, utils::hex_dump(&data, start))),
+        Err(_) => error
     }
+}
 
-    Err(invalid_format!("unknown or unsupported file type"))
+#[cfg(feature = "gzip")]
+fn is_gzip<R: ?Sized + BufRead>(r: &mut R) -> Result<bool> {
+    let buf = try!(r.fill_buf());
+    Ok(buf.len() >= 2 && buf[0] == 0x1f && buf[1] == 0x8b)
+}
+
+#[cfg(feature = "gzip")]
+fn load_gzip<R: ?Sized + BufRead>(r: &mut R, options: &LoadOptions, depth: u32) -> PartialResult<GenericMetadata> {
+    use std::io::Read;
+    use flate2::bufread::GzDecoder;
+
+    if let Some(max) = options.limits.max_nesting_depth {
+        if depth >= max {
+            return Err(limit_exceeded!("gzip container at nesting depth {} exceeds the limit of {}", depth, max).into());
+        }
+    }
+
+    let mut data = Vec::new();
+    let mut decoder = GzDecoder::new(r);
+    match options.limits.max_decompressed_size {
+        Some(max) => {
+            try!(decoder.take(max.saturating_add(1)).read_to_end(&mut data)
+                 .map_err(if_eof!(std, "when decompressing gzip data")));
+            if data.len() as u64 > max {
+                return Err(limit_exceeded!("decompressed gzip data is larger than {} bytes", max).into());
+            }
+        }
+        None => {
+            try!(decoder.read_to_end(&mut data).map_err(if_eof!(std, "when decompressing gzip data")));
+        }
+    }
+    load_partial_nested(&mut Cursor::new(&data), options, depth + 1)
+}
+
+/// Attempts to load metadata for an image contained in a file identified by the provided path,
+/// returning metadata loaded so far together with the error if parsing fails midway.
+///
+/// This method delegates to `load_partial()` method.
+pub fn load_partial_from_file<P: AsRef<Path>>(p: P) -> PartialResult<GenericMetadata> {
+    let mut f = BufReader::new(try!(File::open(p)));
+    load_partial(&mut f)
 }
 
 /// Attempts to load metadata for an image contained in a file identified by the provided path.
@@ -325,3 +649,149 @@ pub fn load_from_file<P: AsRef<Path>>(p: P) -> Result<GenericMetadata> {
 pub fn load_from_buf(b: &[u8]) -> Result<GenericMetadata> {
     load(&mut Cursor::new(b))
 }
+
+/// Image format and dimensions determined by `probe()`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Probe {
+    /// Image format.
+    pub format: ImageFormat,
+    /// Image dimensions, as returned by `GenericMetadata::dimensions()`.
+    pub dimensions: Dimensions,
+    /// Number of bytes from the beginning of the stream which were needed to determine
+    /// the format and dimensions.
+    ///
+    /// Probing a prefix of the stream of this length gives the same result.
+    pub bytes_read: u64,
+}
+
+/// Determines the format and dimensions of an image contained in the provided input stream,
+/// reading as little of the stream as possible.
+///
+/// Unlike `load()`, which may read the whole stream to collect all metadata, this function
+/// stops as soon as the dimensions are known and reports how many bytes were consumed. For
+/// PNG, GIF and WebP images this is at most 30 bytes; for JPEG images, all segments up to
+/// the frame header are read.
+pub fn probe<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Probe> {
+    probe_with_options(r, &LoadOptions::default())
+}
+
+/// Determines the format and dimensions of an image contained in an in-memory buffer.
+///
+/// This method delegates to `probe()` method.
+pub fn probe_from_buf(b: &[u8]) -> Result<Probe> {
+    probe(&mut Cursor::new(b))
+}
+
+/// Checks whether an image contained in the provided input stream is complete, i.e. whether
+/// its end-of-image structure (EOI marker, IEND chunk, GIF trailer or the end of the RIFF
+/// container) ends before or at the end of the stream.
+///
+/// The format is detected with `probe()`, so the image must start at the beginning of the
+/// stream. See `verify_complete()` functions in format modules for details on each format.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// let mut f = BufReader::new(File::open("kitty.jpg").unwrap());
+/// if !immeta::verify_complete(&mut f).unwrap().is_complete() {
+///     println!("kitty.jpg is truncated");
+/// }
+/// ```
+pub fn verify_complete<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Completeness> {
+    let format = try!(probe(r)).format;
+    try!(r.seek(SeekFrom::Start(0)));
+    match format {
+        ImageFormat::Jpeg => jpeg::verify_complete(r),
+        ImageFormat::Png => png::verify_complete(r),
+        ImageFormat::Gif => gif::verify_complete(r),
+        ImageFormat::Webp => webp::verify_complete(r),
+    }
+}
+
+/// Finds where the pixel data of an image contained in the provided input stream is stored.
+///
+/// The returned ranges contain the compressed image data, as opposed to headers and metadata:
+/// entropy-coded scans of JPEG images, IDAT chunks of PNG images, image data blocks of GIF
+/// frames and VP8, VP8L and ALPH chunks of WebP images. Everything outside of them is
+/// metadata and container structure. This is useful e.g. for fetching only the image data
+/// with HTTP range requests or for detecting images which differ only in metadata.
+///
+/// The format is detected with `probe()`, so the image must start at the beginning of the
+/// stream. See `image_data_ranges()` functions in format modules for details on each format.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// let mut f = BufReader::new(File::open("kitty.jpg").unwrap());
+/// for range in immeta::image_data_ranges(&mut f).unwrap() {
+///     println!("{} bytes of image data at offset {}", range.len, range.offset);
+/// }
+/// ```
+pub fn image_data_ranges<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Vec<ByteRange>> {
+    let format = try!(probe(r)).format;
+    try!(r.seek(SeekFrom::Start(0)));
+    match format {
+        ImageFormat::Jpeg => jpeg::image_data_ranges(r),
+        ImageFormat::Png => png::image_data_ranges(r),
+        ImageFormat::Gif => gif::image_data_ranges(r),
+        ImageFormat::Webp => webp::image_data_ranges(r),
+    }
+}
+
+/// Implements `probe()` and `Immeta::probe()`, trying only the formats enabled in the provided
+/// options.
+pub(crate) fn probe_with_options<R: ?Sized + BufRead + Seek>(r: &mut R, options: &LoadOptions) -> Result<Probe> {
+    macro_rules! try_format {
+        ($name:expr, $var:ident, $read_dimensions:expr) => {
+            if options.is_format_enabled(ImageFormat::$var) {
+                try!(r.seek(SeekFrom::Start(0)));
+                let mut tracked = Tracked::new(&mut *r);
+                match $read_dimensions(&mut tracked) {
+                    Ok(dimensions) => return Ok(Probe {
+                        format: ImageFormat::$var,
+                        dimensions: dimensions,
+                        bytes_read: tracked.position,
+                    }),
+                    Err(e @ Error::LimitExceeded(_)) => return Err(e),
+                    Err(e) => log_debug!("not a {} image: {}", $name, e)
+                }
+            }
+        }
+    }
+
+    try_format!("PNG", Png, png::read_dimensions);
+    try_format!("GIF", Gif, gif::read_dimensions);
+    try_format!("WebP", Webp, webp::read_dimensions);
+    try_format!("JPEG", Jpeg, |r: &mut Tracked<&mut R>| jpeg::read_dimensions_impl(r, options.soi_search_limit));
+
+    Err(invalid_format!("unknown or unsupported file type"))
+}
+
+/// Number of bytes at the beginning of a non-seekable stream which are buffered for format
+/// detection by `load_from_reader()`.
+pub const SNIFF_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Attempts to load metadata for an image contained in the provided non-seekable stream,
+/// like a socket or a pipe.
+///
+/// The first `SNIFF_BUFFER_SIZE` bytes of the stream are buffered, so each format parser
+/// can start reading at the beginning of the stream during format detection. Detection fails
+/// if a parser for a format which turns out to be wrong reads more than that, which can
+/// only happen for JPEG images preceded by garbage with a large `soi_search_limit`.
+///
+/// Once the format is detected, its metadata is read sequentially, skipping data instead of
+/// seeking, so the stream is never copied into memory as a whole. The only metadata which
+/// depends on seeking backwards is the JPEG gain map image referenced by an MPF index
+/// pointing before the end of the primary image; such gain map is reported without
+/// its dimensions.
+///
+/// This method delegates to `load()` method.
+pub fn load_from_reader<R: Read>(r: R) -> Result<GenericMetadata> {
+    load(&mut PeekReader::with_capacity(r, SNIFF_BUFFER_SIZE))
+}
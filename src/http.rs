@@ -0,0 +1,400 @@
+//! Loading metadata of remote images via HTTP range requests.
+//!
+//! This module is only available when `http` feature is enabled.
+//!
+//! Image metadata is almost always located at the very beginning of a file, so it is wasteful
+//! to download the whole image just to learn its dimensions. `HttpRangeReader` implements
+//! `Read` and `Seek` on top of HTTP range requests, fetching the remote file in blocks and
+//! caching them, so only the parts of the file which are actually inspected are downloaded.
+
+use std::io::{self, Read, Seek, SeekFrom, BufReader};
+use std::collections::{HashMap, VecDeque};
+use std::cmp;
+
+use ureq;
+
+use types::Result;
+use generic::{self, GenericMetadata};
+use utils::{InputLimitExceeded, MAX_EXACT_VEC_LEN};
+
+/// Default size of a single block fetched from the server.
+///
+/// Most metadata is located in the first few kilobytes of an image, therefore relatively
+/// small blocks work best.
+pub const DEFAULT_BLOCK_SIZE: u64 = 16 * 1024;
+
+/// Default maximum number of blocks kept in the cache.
+pub const DEFAULT_CACHE_CAPACITY: usize = 64;
+
+/// A `Read + Seek` adapter over a remote file accessed with HTTP range requests.
+///
+/// The remote file is fetched lazily in blocks of a fixed size. Fetched blocks are cached,
+/// and the least recently fetched ones are evicted when the cache becomes full. This is
+/// tuned for the way immeta parsers access data: mostly sequential reads from the beginning
+/// of the file with occasional backward and forward seeks (e.g. when format detection
+/// rewinds the stream or when TIFF IFDs are followed).
+///
+/// If the server does not support range requests and returns the whole file instead,
+/// the returned body is kept in memory and served directly.
+pub struct HttpRangeReader {
+    agent: ureq::Agent,
+    url: String,
+    len: u64,
+    position: u64,
+    block_size: u64,
+    capacity: usize,
+    blocks: HashMap<u64, Vec<u8>>,
+    order: VecDeque<u64>,
+    whole: Option<Vec<u8>>,
+}
+
+impl HttpRangeReader {
+    /// Creates a new reader for the given URL with default block size and cache capacity.
+    ///
+    /// This method performs a request for the first block of the file in order to find out
+    /// the total length of the file.
+    pub fn new(url: &str) -> Result<HttpRangeReader> {
+        HttpRangeReader::with_settings(url, DEFAULT_BLOCK_SIZE, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Creates a new reader for the given URL with the provided block size (in bytes) and
+    /// cache capacity (in blocks).
+    ///
+    /// Zero values of `block_size` and `capacity` are replaced with one.
+    pub fn with_settings(url: &str, block_size: u64, capacity: usize) -> Result<HttpRangeReader> {
+        let mut reader = HttpRangeReader {
+            agent: ureq::AgentBuilder::new().build(),
+            url: url.to_owned(),
+            len: 0,
+            position: 0,
+            block_size: cmp::max(block_size, 1),
+            capacity: cmp::max(capacity, 1),
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+            whole: None,
+        };
+        try!(reader.fetch_block(0));
+        Ok(reader)
+    }
+
+    /// Returns the total length of the remote file in bytes.
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the remote file is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the URL this reader reads from.
+    #[inline]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn fetch_block(&mut self, index: u64) -> io::Result<()> {
+        let start = index * self.block_size;
+        let end = start + self.block_size - 1;
+
+        let response = match self.agent.get(&self.url)
+            .set("Range", &format!("bytes={}-{}", start, end))
+            // ranges and lengths must refer to the file itself, not to a compressed encoding of it
+            .set("Accept-Encoding", "identity")
+            .call()
+        {
+            Ok(response) => response,
+            Err(ureq::Error::Status(code, _)) => return Err(io::Error::new(
+                io::ErrorKind::Other, format!("HTTP request failed with status {}", code)
+            )),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+        };
+
+        let status = response.status();
+        let content_range = response.header("Content-Range").and_then(parse_content_range);
+        let content_length = match response.header("Content-Length") {
+            Some(value) => match value.trim().parse::<u64>() {
+                Ok(n) => Some(n),
+                Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length header"))
+            },
+            None => None
+        };
+
+        // a partial body must not be longer than the requested range, and a whole file must
+        // fit into memory
+        let limit = if status == 206 { end - start + 1 } else { MAX_EXACT_VEC_LEN };
+        let data = try!(read_body(response.into_reader(), limit));
+
+        match (status, content_range) {
+            (206, Some(range)) => {
+                try!(check_partial_body(start, end, range, content_length, data.len() as u64));
+                self.len = range.total;
+                self.insert_block(index, data);
+            }
+            // the server has ignored the range header and sent the whole file
+            (200, _) => {
+                if let Some(n) = content_length {
+                    try!(check_body_len(n, data.len() as u64));
+                }
+                self.len = data.len() as u64;
+                self.whole = Some(data);
+            }
+            (206, None) => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "invalid or missing Content-Range header"
+            )),
+            (status, _) => return Err(io::Error::new(
+                io::ErrorKind::Other, format!("unexpected HTTP status {}", status)
+            )),
+        }
+
+        Ok(())
+    }
+
+    fn insert_block(&mut self, index: u64, data: Vec<u8>) {
+        if self.blocks.len() >= self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.blocks.remove(&evicted);
+            }
+        }
+        self.blocks.insert(index, data);
+        self.order.push_back(index);
+    }
+}
+
+impl Read for HttpRangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len {
+            return Ok(0);
+        }
+
+        if let Some(ref whole) = self.whole {
+            let start = self.position as usize;
+            let n = cmp::min(buf.len(), whole.len() - start);
+            buf[..n].copy_from_slice(&whole[start..start + n]);
+            self.position += n as u64;
+            return Ok(n);
+        }
+
+        let index = self.position / self.block_size;
+        if !self.blocks.contains_key(&index) {
+            try!(self.fetch_block(index));
+            // the server may have switched to sending the whole file
+            if self.whole.is_some() {
+                return self.read(buf);
+            }
+        }
+
+        let block = &self.blocks[&index];
+        let offset = (self.position % self.block_size) as usize;
+        if offset >= block.len() {
+            // blocks are only cached after their length is checked, so this is a server which
+            // has changed the length of the file between requests
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof, "remote file is shorter than its reported length"
+            ));
+        }
+
+        let n = cmp::min(buf.len(), block.len() - offset);
+        buf[..n].copy_from_slice(&block[offset..offset + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for HttpRangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => offset_position(self.len, n),
+            SeekFrom::Current(n) => offset_position(self.position, n),
+        };
+        match new_position {
+            Some(n) => {
+                self.position = n;
+                Ok(n)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"
+            ))
+        }
+    }
+}
+
+fn offset_position(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+/// A byte range of a partial response, as described by the `Content-Range` header.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct ContentRange {
+    /// Offset of the first byte of the body in the file.
+    first: u64,
+    /// Offset of the last byte of the body in the file.
+    last: u64,
+    /// Total length of the file.
+    total: u64,
+}
+
+/// Parses a `Content-Range` header value of a partial response, e.g. `bytes 0-1023/4096`.
+///
+/// Ranges with an unknown total length or whose last byte lies before the first one or after
+/// the end of the file are rejected.
+fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let value = value.trim();
+    if !value.starts_with("bytes ") {
+        return None;
+    }
+    let mut parts = value["bytes ".len()..].splitn(2, '/');
+    let (range, total) = match (parts.next(), parts.next()) {
+        (Some(range), Some(total)) => (range, total),
+        _ => return None
+    };
+    let mut bounds = range.splitn(2, '-');
+    let (first, last) = match (bounds.next(), bounds.next()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return None
+    };
+    let range = ContentRange {
+        first: match first.trim().parse() { Ok(n) => n, Err(_) => return None },
+        last: match last.trim().parse() { Ok(n) => n, Err(_) => return None },
+        total: match total.trim().parse() { Ok(n) => n, Err(_) => return None },
+    };
+    if range.first > range.last || range.last >= range.total {
+        return None;
+    }
+    Some(range)
+}
+
+/// Checks that the body of a partial response covers the requested range from `start` to
+/// `end`, inclusive, as far as the file extends.
+///
+/// A body cut short, e.g. by a dropped connection, would otherwise look like the end of
+/// the file to readers.
+fn check_partial_body(start: u64, end: u64, range: ContentRange, content_length: Option<u64>,
+                      body_len: u64) -> io::Result<()> {
+    let expected_last = cmp::min(end, range.total - 1);
+    if range.first != start || range.last != expected_last {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "server returned bytes {}-{} instead of the requested bytes {}-{}",
+            range.first, range.last, start, expected_last
+        )));
+    }
+    let range_len = range.last - range.first + 1;
+    if let Some(n) = content_length {
+        if n != range_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "Content-Length {} does not match Content-Range of {} bytes", n, range_len
+            )));
+        }
+    }
+    check_body_len(range_len, body_len)
+}
+
+/// Reads a response body of at most `limit` bytes.
+///
+/// Only `limit + 1` bytes are read from longer bodies, which are rejected with
+/// an `InputLimitExceeded` error.
+fn read_body<R: Read>(body: R, limit: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    try!(body.take(limit.saturating_add(1)).read_to_end(&mut data));
+    if data.len() as u64 > limit {
+        return Err(io::Error::new(io::ErrorKind::Other, InputLimitExceeded(limit)));
+    }
+    Ok(data)
+}
+
+/// Checks that the received body has the announced length.
+fn check_body_len(expected: u64, body_len: u64) -> io::Result<()> {
+    if body_len < expected {
+        Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!(
+            "response body ended after {} of {} bytes", body_len, expected
+        )))
+    } else if body_len > expected {
+        Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "response body is longer than {} bytes", expected
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Attempts to load metadata for an image located at the provided URL.
+///
+/// Only the parts of the remote file which are needed to determine the image format and
+/// to read its metadata are downloaded, using HTTP range requests.
+pub fn load_from_url(url: &str) -> Result<GenericMetadata> {
+    let reader = try!(HttpRangeReader::new(url));
+    generic::load(&mut BufReader::new(reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use types::Error;
+
+    use super::{ContentRange, parse_content_range, check_partial_body, check_body_len, read_body, offset_position};
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(parse_content_range("bytes 0-1023/4096"), Some(ContentRange { first: 0, last: 1023, total: 4096 }));
+        assert_eq!(parse_content_range("bytes 16384-32767/1683992").map(|r| r.total), Some(1683992));
+        assert_eq!(parse_content_range("bytes */4096"), None);
+        assert_eq!(parse_content_range("bytes 0-1023/*"), None);
+        assert_eq!(parse_content_range("bytes 1023-0/4096"), None);
+        assert_eq!(parse_content_range("bytes 0-4096/4096"), None);
+        assert_eq!(parse_content_range("items 0-1023/4096"), None);
+    }
+
+    #[test]
+    fn test_check_partial_body() {
+        let range = |first, last, total| ContentRange { first: first, last: last, total: total };
+        let kind = |r: io::Result<()>| r.unwrap_err().kind();
+
+        assert!(check_partial_body(0, 1023, range(0, 1023, 4096), Some(1024), 1024).is_ok());
+        assert!(check_partial_body(0, 1023, range(0, 1023, 4096), None, 1024).is_ok());
+        // the last block is shorter than the requested range
+        assert!(check_partial_body(4096, 5119, range(4096, 4999, 5000), Some(904), 904).is_ok());
+
+        // the connection was dropped in the middle of the body
+        assert_eq!(kind(check_partial_body(0, 1023, range(0, 1023, 4096), Some(1024), 1000)),
+                   io::ErrorKind::UnexpectedEof);
+        assert_eq!(kind(check_partial_body(0, 1023, range(0, 1023, 4096), None, 0)),
+                   io::ErrorKind::UnexpectedEof);
+        // the server has sent less or something else than requested
+        assert_eq!(kind(check_partial_body(0, 1023, range(0, 511, 4096), Some(512), 512)),
+                   io::ErrorKind::InvalidData);
+        assert_eq!(kind(check_partial_body(1024, 2047, range(0, 1023, 4096), Some(1024), 1024)),
+                   io::ErrorKind::InvalidData);
+        assert_eq!(kind(check_partial_body(0, 1023, range(0, 1023, 4096), Some(4096), 1024)),
+                   io::ErrorKind::InvalidData);
+
+        assert!(check_body_len(10, 10).is_ok());
+        assert_eq!(kind(check_body_len(10, 9)), io::ErrorKind::UnexpectedEof);
+        assert_eq!(kind(check_body_len(10, 11)), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_body() {
+        assert_eq!(read_body(&b"abc"[..], 3).unwrap(), b"abc");
+        assert_eq!(read_body(&b"abc"[..], 10).unwrap(), b"abc");
+        match read_body(&b"abcd"[..], 3).map_err(Error::from) {
+            Err(Error::LimitExceeded(_)) => {}
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+
+    #[test]
+    fn test_offset_position() {
+        assert_eq!(offset_position(10, 5), Some(15));
+        assert_eq!(offset_position(10, -10), Some(0));
+        assert_eq!(offset_position(10, -11), None);
+        assert_eq!(offset_position(u64::max_value(), 1), None);
+    }
+}
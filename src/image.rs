@@ -0,0 +1,140 @@
+//! Interoperability with the [image](https://crates.io/crates/image) crate.
+//!
+//! This module is only available when `image` feature is enabled.
+//!
+//! immeta determines the format and dimensions of an image much faster than a full decoder
+//! can, so it is convenient to inspect an image with immeta first and then decide whether and
+//! how it should be decoded. `ProbedImage` supports exactly this: it probes the input stream,
+//! exposes the result and then hands the stream over to `image` with the detected format
+//! already set, so `image` does not need to guess it again.
+//!
+//! Note that the `image` dependency is built without its default features, that is, without
+//! any decoders. Enable the decoders you need by depending on `image` with the respective
+//! features in your own crate.
+//!
+//! Image formats can also be converted between the two crates directly:
+//!
+//! ```
+//! use std::convert::TryFrom;
+//!
+//! use immeta::ImageFormat;
+//! use immeta::image::image_crate;
+//!
+//! assert_eq!(image_crate::ImageFormat::from(ImageFormat::Png), image_crate::ImageFormat::Png);
+//! assert_eq!(ImageFormat::try_from(image_crate::ImageFormat::WebP), Ok(ImageFormat::Webp));
+//! assert!(ImageFormat::try_from(image_crate::ImageFormat::Bmp).is_err());
+//! ```
+
+/// The `image` crate, re-exported to make sure that the matching version is used.
+pub extern crate image as image_crate;
+
+use std::convert::TryFrom;
+use std::io::{BufRead, Seek, SeekFrom};
+
+use self::image_crate::{DynamicImage, ImageResult};
+use self::image_crate::io::Reader;
+
+use types::{Result, ImageFormat};
+use generic::Probe;
+use loader::Immeta;
+
+impl From<ImageFormat> for image_crate::ImageFormat {
+    fn from(format: ImageFormat) -> image_crate::ImageFormat {
+        match format {
+            ImageFormat::Jpeg => image_crate::ImageFormat::Jpeg,
+            ImageFormat::Png => image_crate::ImageFormat::Png,
+            ImageFormat::Gif => image_crate::ImageFormat::Gif,
+            ImageFormat::Webp => image_crate::ImageFormat::WebP,
+        }
+    }
+}
+
+impl TryFrom<image_crate::ImageFormat> for ImageFormat {
+    type Error = image_crate::ImageFormat;
+
+    /// Converts the `image` crate format into the immeta format, returning the original value
+    /// if immeta does not support it.
+    fn try_from(format: image_crate::ImageFormat) -> ::std::result::Result<ImageFormat, image_crate::ImageFormat> {
+        match format {
+            image_crate::ImageFormat::Jpeg => Ok(ImageFormat::Jpeg),
+            image_crate::ImageFormat::Png => Ok(ImageFormat::Png),
+            image_crate::ImageFormat::Gif => Ok(ImageFormat::Gif),
+            image_crate::ImageFormat::WebP => Ok(ImageFormat::Webp),
+            format => Err(format)
+        }
+    }
+}
+
+/// An input stream which has been probed with immeta and can be decoded with `image`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// use immeta::image::ProbedImage;
+///
+/// let f = BufReader::new(File::open("kitty.png").unwrap());
+/// let probed = ProbedImage::new(f).unwrap();
+/// if probed.probe().dimensions.width <= 4096 {
+///     let image = probed.decode().unwrap();
+/// }
+/// ```
+pub struct ProbedImage<R> {
+    probe: Probe,
+    reader: R,
+}
+
+impl<R: BufRead + Seek> ProbedImage<R> {
+    /// Probes the provided input stream with the default options.
+    ///
+    /// See `immeta::probe()` for more information.
+    #[inline]
+    pub fn new(r: R) -> Result<ProbedImage<R>> {
+        ProbedImage::with_loader(r, &Immeta::new())
+    }
+
+    /// Probes the provided input stream with the options of the provided loader.
+    ///
+    /// See `Immeta::probe()` for more information.
+    pub fn with_loader(mut r: R, loader: &Immeta) -> Result<ProbedImage<R>> {
+        let probe = try!(loader.probe(&mut r));
+        Ok(ProbedImage {
+            probe: probe,
+            reader: r,
+        })
+    }
+
+    /// Returns the format and dimensions of the image.
+    #[inline]
+    pub fn probe(&self) -> &Probe {
+        &self.probe
+    }
+
+    /// Returns the `image` crate format of the image.
+    #[inline]
+    pub fn image_format(&self) -> image_crate::ImageFormat {
+        self.probe.format.into()
+    }
+
+    /// Rewinds the input stream and returns an `image` reader for it with the detected format.
+    ///
+    /// The returned reader can be configured further, e.g. with decoding limits, before the
+    /// image is decoded.
+    pub fn into_reader(mut self) -> ImageResult<Reader<R>> {
+        try!(self.reader.seek(SeekFrom::Start(0)));
+        Ok(Reader::with_format(self.reader, self.probe.format.into()))
+    }
+
+    /// Decodes the image with `image` using the detected format.
+    pub fn decode(self) -> ImageResult<DynamicImage> {
+        try!(self.into_reader()).decode()
+    }
+
+    /// Returns the input stream, positioned where probing has stopped.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
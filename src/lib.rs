@@ -33,17 +33,42 @@
 
 extern crate byteorder;
 extern crate num;
-extern crate arrayvec;
+#[cfg(feature = "http")] extern crate ureq;
+#[cfg(feature = "zip")] extern crate zip;
+#[cfg(feature = "tar")] extern crate tar;
+#[cfg(any(feature = "gzip", feature = "zlib"))] extern crate flate2;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde;
+#[cfg(feature = "serde_json")] extern crate serde_json;
+#[cfg(feature = "log")] #[macro_use] extern crate log;
+#[cfg(feature = "mime")] extern crate mime;
+#[cfg(feature = "exif")] extern crate exif;
+#[cfg(feature = "arbitrary")] extern crate arbitrary;
 
 pub use types::*;
 pub use traits::*;
 pub use generic::*;
+pub use diff::diff;
+pub use loader::{Immeta, LoadOptions, Limits, Parser};
 
 #[macro_use] mod macros;
 #[macro_use] mod generic;
 mod traits;
 mod types;
 mod utils;
+mod loader;
 
 pub mod common;
+pub mod animation;
 pub mod formats;
+pub mod cache;
+pub mod diff;
+pub mod detect;
+pub mod transplant;
+pub mod validation;
+pub mod polyglot;
+pub mod fuzz;
+#[cfg(feature = "serde")] pub mod export;
+#[cfg(feature = "http")] pub mod http;
+#[cfg(any(feature = "zip", feature = "tar"))] pub mod archive;
+#[cfg(feature = "audio")] pub mod audio;
+#[cfg(feature = "image")] pub mod image;
@@ -0,0 +1,497 @@
+//! Configurable metadata loading.
+
+use std::io::{Read, BufRead, Seek, Cursor, BufReader};
+use std::fs::File;
+use std::path::Path;
+
+use types::{Result, PartialResult, ImageFormat, Dimensions};
+use generic::{self, GenericMetadata, Probe};
+use utils::{LimitedReader, PeekReader, BorrowedBufReader};
+
+/// Resource limits applied when loading metadata.
+///
+/// Exceeding any of the limits results in `Error::LimitExceeded`. By default only containers
+/// are limited: at most `Limits::DEFAULT_MAX_NESTING_DEPTH` levels of nesting and at most
+/// `Limits::DEFAULT_MAX_DECOMPRESSED_SIZE` bytes of decompressed data, so that even the default
+/// options are safe against gzip bombs and self-reproducing gzip streams. All other limits
+/// are disabled by default.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Limits {
+    /// Maximum estimated size of the decoded image in bytes.
+    ///
+    /// See `GenericMetadata::estimated_decoded_size()`.
+    pub max_decoded_size: Option<u64>,
+    /// Maximum number of bytes read from the input stream.
+    ///
+    /// Parsers which need to read past this limit, including seeking past it, fail.
+    /// Note that some formats, like PNG and WebP, are read until the end of the stream.
+    pub max_input_size: Option<u64>,
+    /// Maximum number of containers the image may be nested into.
+    ///
+    /// Currently the only supported container is gzip (with `gzip` feature), which may wrap
+    /// another gzip stream; zero rejects gzip-compressed input altogether.
+    pub max_nesting_depth: Option<u32>,
+    /// Maximum number of bytes produced by decompressing a container.
+    ///
+    /// Compressed containers are decompressed into memory, and their decompressed size is not
+    /// known in advance, so without this limit a small input may expand to an arbitrarily large
+    /// buffer.
+    pub max_decompressed_size: Option<u64>,
+    /// Maximum image width in pixels.
+    pub max_width: Option<u32>,
+    /// Maximum image height in pixels.
+    pub max_height: Option<u32>,
+    /// Maximum number of pixels in the image, i.e. the product of its width and height.
+    ///
+    /// Unlike `max_decoded_size`, this limit does not depend on the color depth and the
+    /// number of frames.
+    pub max_pixels: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_decoded_size: None,
+            max_input_size: None,
+            max_nesting_depth: Some(Limits::DEFAULT_MAX_NESTING_DEPTH),
+            max_decompressed_size: Some(Limits::DEFAULT_MAX_DECOMPRESSED_SIZE),
+            max_width: None,
+            max_height: None,
+            max_pixels: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Default value of `max_nesting_depth`.
+    pub const DEFAULT_MAX_NESTING_DEPTH: u32 = 4;
+
+    /// Default value of `max_decompressed_size`, 256 MiB.
+    pub const DEFAULT_MAX_DECOMPRESSED_SIZE: u64 = 256 * 1024 * 1024;
+
+    /// Checks that the provided metadata fits into these limits.
+    pub fn check(&self, md: &GenericMetadata) -> Result<()> {
+        try!(self.check_dimensions(md.dimensions()));
+        if let Some(max) = self.max_decoded_size {
+            let size = md.estimated_decoded_size();
+            if size > max {
+                return Err(limit_exceeded!("decoded image size {} is greater than {}", size, max));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the provided image dimensions fit into the dimension limits.
+    pub fn check_dimensions(&self, dimensions: Dimensions) -> Result<()> {
+        if let Some(max) = self.max_width {
+            if dimensions.width > max {
+                return Err(limit_exceeded!("image width {} is greater than {}", dimensions.width, max));
+            }
+        }
+        if let Some(max) = self.max_height {
+            if dimensions.height > max {
+                return Err(limit_exceeded!("image height {} is greater than {}", dimensions.height, max));
+            }
+        }
+        if let Some(max) = self.max_pixels {
+            let pixels = dimensions.width as u64 * dimensions.height as u64;
+            if pixels > max {
+                return Err(limit_exceeded!("image pixel count {} ({}x{}) is greater than {}",
+                                           pixels, dimensions.width, dimensions.height, max));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options controlling how metadata is loaded.
+///
+/// Usually it is more convenient to configure these options through `Immeta` builder.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LoadOptions {
+    /// Image formats which are tried during format detection.
+    pub formats: Vec<ImageFormat>,
+    /// Whether incomplete metadata is an error.
+    ///
+    /// In strict mode (the default), an error is returned if the image metadata can't be
+    /// parsed completely. In lenient mode, partially loaded metadata is returned instead when
+    /// it is available, e.g. when a GIF image is truncated after several frames.
+    pub strict: bool,
+    /// Resource limits.
+    pub limits: Limits,
+    /// Maximum number of bytes preceding the JPEG SOI marker which are skipped.
+    ///
+    /// Some camera-produced and recovered JPEG files have leading padding or other data before
+    /// the actual JPEG stream. By default this limit is zero, that is, JPEG images must start
+    /// with the SOI marker. Note that JPEG detection is attempted after all other formats, so
+    /// with a non-zero limit, a file in an unsupported format containing the SOI marker
+    /// sequence near its beginning may be detected as a JPEG image.
+    pub soi_search_limit: u64,
+    /// Whether format errors include a hex dump of the input around the failure.
+    ///
+    /// When enabled, the description of the error returned for a recognized image whose
+    /// metadata can't be parsed is extended with the bytes around the position where parsing
+    /// stopped, and the error returned for an unknown file type is extended with the first
+    /// bytes of the input. This is disabled by default; it is intended for debugging and for
+    /// reporting problems with specific files.
+    pub hex_dump_errors: bool,
+    /// Whether parsing of extended metadata is deferred until it is requested.
+    ///
+    /// When enabled, the parts of metadata which are expensive to parse and which most callers
+    /// do not need are not parsed during loading. Their locations in the stream are recorded
+    /// instead, and they can be parsed later with `GenericMetadata::load_extensions()`. This
+    /// applies to EXIF data and XMP packets of JPEG, PNG and GIF images and to PNG text chunks,
+    /// which may be compressed; see the `pending_*` fields of the respective metadata types.
+    /// ICC profiles are never parsed during loading; they are only read by the
+    /// `extract_metadata()` functions of format modules. This is disabled by default.
+    pub lazy_extensions: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            formats: ImageFormat::ALL.to_vec(),
+            strict: true,
+            limits: Limits::default(),
+            soi_search_limit: 0,
+            hex_dump_errors: false,
+            lazy_extensions: false,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// Returns options suitable for loading metadata of untrusted, possibly malicious images,
+    /// e.g. user uploads.
+    ///
+    /// Parsers of this library check length fields against the data actually available and
+    /// never allocate more than 256 MiB for a single structure, but the default options still
+    /// allow a hostile input to consume a lot of resources. This
+    /// profile differs from the default options as follows:
+    ///
+    /// * at most 256 MiB are read from the input stream (`Limits::max_input_size`);
+    /// * images whose decoded size exceeds 1 GiB are rejected (`Limits::max_decoded_size`),
+    ///   so that decompression bombs are not passed to decoders;
+    /// * gzip-compressed input is accepted only if it is not nested into another gzip stream
+    ///   (`Limits::max_nesting_depth`) and is decompressed into at most 64 MiB
+    ///   (`Limits::max_decompressed_size`);
+    /// * extended metadata, e.g. EXIF data or compressed PNG text, is not parsed during loading
+    ///   (`lazy_extensions`);
+    /// * JPEG streams must start with the SOI marker, and incomplete metadata is an error.
+    ///
+    /// Individual settings can be relaxed after the profile is created. Resource exhaustion
+    /// which is not bounded by the input size, such as wall clock time spent on a slow stream,
+    /// is the responsibility of the caller.
+    pub fn hardened() -> LoadOptions {
+        LoadOptions {
+            formats: ImageFormat::ALL.to_vec(),
+            strict: true,
+            limits: Limits {
+                max_decoded_size: Some(1024 * 1024 * 1024),
+                max_input_size: Some(256 * 1024 * 1024),
+                max_nesting_depth: Some(1),
+                max_decompressed_size: Some(64 * 1024 * 1024),
+                max_width: None,
+                max_height: None,
+                max_pixels: None,
+            },
+            soi_search_limit: 0,
+            hex_dump_errors: false,
+            lazy_extensions: true,
+        }
+    }
+
+    /// Returns `true` if the given format is enabled in these options.
+    #[inline]
+    pub fn is_format_enabled(&self, format: ImageFormat) -> bool {
+        self.formats.contains(&format)
+    }
+}
+
+/// A configurable metadata loader.
+///
+/// `Immeta` bundles all options of the loading process, that is, the set of formats to detect,
+/// strict or lenient parsing mode and resource limits. Free functions like `immeta::load()`
+/// are equivalent to using `Immeta::new()` with the default options.
+///
+/// # Examples
+///
+/// ```no_run
+/// use immeta::{Immeta, ImageFormat};
+///
+/// let loader = Immeta::new()
+///     .formats(&[ImageFormat::Jpeg, ImageFormat::Png])
+///     .lenient()
+///     .max_decoded_size(256 * 1024 * 1024);
+///
+/// let md = loader.load_from_file("kitty.jpg").unwrap();
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Immeta {
+    options: LoadOptions,
+}
+
+impl Immeta {
+    /// Creates a new loader with the default options.
+    #[inline]
+    pub fn new() -> Immeta {
+        Immeta::default()
+    }
+
+    /// Creates a new loader with the hardened profile for untrusted input.
+    /// See `LoadOptions::hardened()`.
+    #[inline]
+    pub fn hardened() -> Immeta {
+        Immeta::with_options(LoadOptions::hardened())
+    }
+
+    /// Creates a new loader with the provided options.
+    #[inline]
+    pub fn with_options(options: LoadOptions) -> Immeta {
+        Immeta {
+            options: options
+        }
+    }
+
+    /// Returns the options of this loader.
+    #[inline]
+    pub fn options(&self) -> &LoadOptions {
+        &self.options
+    }
+
+    /// Restricts format detection to the provided image formats.
+    pub fn formats(mut self, formats: &[ImageFormat]) -> Immeta {
+        self.options.formats = formats.to_vec();
+        self
+    }
+
+    /// Sets strict or lenient parsing mode. See `LoadOptions::strict`.
+    pub fn strict(mut self, strict: bool) -> Immeta {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Enables lenient parsing mode. See `LoadOptions::strict`.
+    #[inline]
+    pub fn lenient(self) -> Immeta {
+        self.strict(false)
+    }
+
+    /// Sets resource limits.
+    pub fn limits(mut self, limits: Limits) -> Immeta {
+        self.options.limits = limits;
+        self
+    }
+
+    /// Sets maximum estimated size of the decoded image in bytes. See `Limits`.
+    pub fn max_decoded_size(mut self, max: u64) -> Immeta {
+        self.options.limits.max_decoded_size = Some(max);
+        self
+    }
+
+    /// Sets maximum number of bytes read from the input stream. See `Limits`.
+    pub fn max_input_size(mut self, max: u64) -> Immeta {
+        self.options.limits.max_input_size = Some(max);
+        self
+    }
+
+    /// Sets maximum number of containers the image may be nested into. See `Limits`.
+    pub fn max_nesting_depth(mut self, max: u32) -> Immeta {
+        self.options.limits.max_nesting_depth = Some(max);
+        self
+    }
+
+    /// Sets maximum image width and height in pixels. See `Limits`.
+    pub fn max_dimensions(mut self, max_width: u32, max_height: u32) -> Immeta {
+        self.options.limits.max_width = Some(max_width);
+        self.options.limits.max_height = Some(max_height);
+        self
+    }
+
+    /// Sets maximum number of pixels in the image. See `Limits`.
+    pub fn max_pixels(mut self, max: u64) -> Immeta {
+        self.options.limits.max_pixels = Some(max);
+        self
+    }
+
+    /// Sets maximum number of bytes produced by decompressing a container. See `Limits`.
+    pub fn max_decompressed_size(mut self, max: u64) -> Immeta {
+        self.options.limits.max_decompressed_size = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of bytes preceding the JPEG SOI marker which are skipped.
+    /// See `LoadOptions::soi_search_limit`.
+    pub fn soi_search_limit(mut self, limit: u64) -> Immeta {
+        self.options.soi_search_limit = limit;
+        self
+    }
+
+    /// Enables or disables hex dumps of the input in format errors.
+    /// See `LoadOptions::hex_dump_errors`.
+    pub fn hex_dump_errors(mut self, enabled: bool) -> Immeta {
+        self.options.hex_dump_errors = enabled;
+        self
+    }
+
+    /// Enables or disables deferred parsing of extended metadata.
+    /// See `LoadOptions::lazy_extensions`.
+    pub fn lazy_extensions(mut self, enabled: bool) -> Immeta {
+        self.options.lazy_extensions = enabled;
+        self
+    }
+
+    /// Creates a reusable parser with the options of this loader. See `Parser`.
+    #[inline]
+    pub fn parser(self) -> Parser {
+        Parser::with_loader(self)
+    }
+
+    /// Attempts to load metadata for an image contained in the provided input stream.
+    ///
+    /// See `immeta::load()` for more information.
+    pub fn load<R: ?Sized + BufRead + Seek>(&self, r: &mut R) -> Result<GenericMetadata> {
+        let md = match self.load_unchecked(r) {
+            Ok(md) => md,
+            Err(e) => match e.partial {
+                Some(md) if !self.options.strict => {
+                    log_debug!("returning partial metadata in lenient mode: {}", e.error);
+                    md
+                }
+                _ => return Err(e.error)
+            }
+        };
+        try!(self.options.limits.check(&md));
+        Ok(md)
+    }
+
+    /// Attempts to load metadata for an image contained in the provided input stream,
+    /// returning metadata loaded so far together with the error if parsing fails midway.
+    ///
+    /// See `immeta::load_partial()` for more information. Resource limits are checked for
+    /// complete metadata only.
+    pub fn load_partial<R: ?Sized + BufRead + Seek>(&self, r: &mut R) -> PartialResult<GenericMetadata> {
+        let md = try!(self.load_unchecked(r));
+        try!(self.options.limits.check(&md));
+        Ok(md)
+    }
+
+    fn load_unchecked<R: ?Sized + BufRead + Seek>(&self, r: &mut R) -> PartialResult<GenericMetadata> {
+        match self.options.limits.max_input_size {
+            Some(max) => generic::load_partial_with_options(&mut LimitedReader::new(r, max), &self.options),
+            None => generic::load_partial_with_options(r, &self.options)
+        }
+    }
+
+    /// Determines the format and dimensions of an image contained in the provided input
+    /// stream, reading as little of the stream as possible.
+    ///
+    /// See `immeta::probe()` for more information. Only the enabled formats, JPEG SOI search
+    /// limit, the input size limit and the dimension limits are taken into account.
+    pub fn probe<R: ?Sized + BufRead + Seek>(&self, r: &mut R) -> Result<Probe> {
+        let probe = try!(match self.options.limits.max_input_size {
+            Some(max) => generic::probe_with_options(&mut LimitedReader::new(r, max), &self.options),
+            None => generic::probe_with_options(r, &self.options)
+        });
+        try!(self.options.limits.check_dimensions(probe.dimensions));
+        Ok(probe)
+    }
+
+    /// Determines the format and dimensions of an image contained in an in-memory buffer.
+    pub fn probe_from_buf(&self, b: &[u8]) -> Result<Probe> {
+        self.probe(&mut Cursor::new(b))
+    }
+
+    /// Attempts to load metadata for an image contained in a file identified by the provided path.
+    pub fn load_from_file<P: AsRef<Path>>(&self, p: P) -> Result<GenericMetadata> {
+        let mut f = BufReader::new(try!(File::open(p)));
+        self.load(&mut f)
+    }
+
+    /// Attempts to load metadata for an image contained in an in-memory buffer.
+    pub fn load_from_buf(&self, b: &[u8]) -> Result<GenericMetadata> {
+        self.load(&mut Cursor::new(b))
+    }
+
+    /// Attempts to load metadata for an image contained in the provided non-seekable stream.
+    ///
+    /// See `immeta::load_from_reader()` for more information.
+    pub fn load_from_reader<R: Read>(&self, r: R) -> Result<GenericMetadata> {
+        self.load(&mut PeekReader::with_capacity(r, generic::SNIFF_BUFFER_SIZE))
+    }
+}
+
+/// Size of the read buffer of `Parser`.
+const PARSER_BUFFER_SIZE: usize = 8192;
+
+/// A reusable metadata parser.
+///
+/// `Parser` combines a configured loader with scratch buffers which are kept between calls
+/// to `parse()`. When metadata is loaded from many unbuffered streams or files, e.g. in a
+/// batch job, this avoids allocating a new read buffer for each of them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use immeta::Immeta;
+///
+/// let mut parser = Immeta::new().lenient().parser();
+/// for path in &["kitty.jpg", "puppy.png"] {
+///     let md = parser.parse_file(path).unwrap();
+///     println!("{}: {:?}", path, md.dimensions());
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Parser {
+    loader: Immeta,
+    buffer: Vec<u8>,
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+impl Parser {
+    /// Creates a new parser with the default options.
+    #[inline]
+    pub fn new() -> Parser {
+        Parser::with_loader(Immeta::new())
+    }
+
+    /// Creates a new parser which uses the provided loader.
+    pub fn with_loader(loader: Immeta) -> Parser {
+        Parser {
+            loader: loader,
+            buffer: vec![0; PARSER_BUFFER_SIZE],
+        }
+    }
+
+    /// Returns the loader used by this parser.
+    #[inline]
+    pub fn loader(&self) -> &Immeta {
+        &self.loader
+    }
+
+    /// Attempts to load metadata for an image contained in the provided input stream.
+    ///
+    /// The stream does not need to be buffered; reads are buffered with the internal buffer
+    /// of this parser. See `Immeta::load()` for more information.
+    pub fn parse<R: Read + Seek>(&mut self, r: R) -> Result<GenericMetadata> {
+        let mut r = BorrowedBufReader::new(r, &mut self.buffer);
+        self.loader.load(&mut r)
+    }
+
+    /// Attempts to load metadata for an image contained in a file identified by the provided path.
+    pub fn parse_file<P: AsRef<Path>>(&mut self, p: P) -> Result<GenericMetadata> {
+        let f = try!(File::open(p));
+        self.parse(f)
+    }
+
+    /// Attempts to load metadata for an image contained in an in-memory buffer.
+    #[inline]
+    pub fn parse_buf(&mut self, b: &[u8]) -> Result<GenericMetadata> {
+        self.loader.load_from_buf(b)
+    }
+}
@@ -19,6 +19,15 @@ macro_rules! unexpected_eof {
     }
 }
 
+macro_rules! limit_exceeded {
+    ($s:expr) => {
+        $crate::types::Error::LimitExceeded($s.into())
+    };
+    ($fmt:expr, $($args:tt)*) => {
+        $crate::types::Error::LimitExceeded(format!($fmt, $($args)*).into())
+    }
+}
+
 macro_rules! if_eof {
     (std, $s:expr) => {
         |e| match e {
@@ -60,3 +69,24 @@ macro_rules! try_if_eof {
         try!($e.map_err(if_eof!($fmt, $($args)*)))
     }
 }
+
+// Diagnostic logging macros. When `log` feature is disabled, they expand to nothing
+// (the arguments are still type-checked to avoid unused variable warnings).
+
+macro_rules! log_debug {
+    ($($args:tt)*) => {{
+        #[cfg(feature = "log")]
+        { debug!($($args)*); }
+        #[cfg(not(feature = "log"))]
+        { if false { let _ = format!($($args)*); } }
+    }}
+}
+
+macro_rules! log_trace {
+    ($($args:tt)*) => {{
+        #[cfg(feature = "log")]
+        { trace!($($args)*); }
+        #[cfg(not(feature = "log"))]
+        { if false { let _ = format!($($args)*); } }
+    }}
+}
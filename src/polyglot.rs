@@ -0,0 +1,404 @@
+//! Detection of polyglot files.
+//!
+//! A polyglot file is valid in more than one format at the same time, e.g. a JPEG image with
+//! a ZIP archive appended to it, or a GIF image whose header is also the beginning of
+//! a JavaScript comment. Such files are often used to smuggle content past upload filters
+//! which only check the image, so `analyze()` reports every format the file matches,
+//! together with the byte range occupied by each of them:
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use immeta::polyglot;
+//!
+//! let mut f = BufReader::new(File::open("kitty.jpg").unwrap());
+//! let analysis = polyglot::analyze(&mut f).unwrap();
+//! if analysis.is_polyglot() {
+//!     for m in &analysis.matches {
+//!         println!("{} at {}..{}", m.kind, m.start, m.end);
+//!     }
+//! }
+//! ```
+//!
+//! The following formats are recognized:
+//!
+//!   * images of all supported formats at the beginning of the file and images appended
+//!     after it; images are verified with `verify_complete()` functions of the format
+//!     modules and are only reported if they are not truncated;
+//!   * ZIP archives, located through their end of central directory record like ZIP readers
+//!     do (ZIP64 archives are not recognized);
+//!   * RAR archives, whose signature may appear anywhere in the file;
+//!   * PDF documents whose header is in the first 1024 bytes and which have an end-of-file
+//!     marker;
+//!   * HTML documents and PHP scripts, by the presence of `<html`, `<script` or `<?php` tags
+//!     anywhere in the file;
+//!   * JavaScript files starting with an identifier immediately followed by a comment, like
+//!     `GIF89a/*`, which is the usual form of GIF/JavaScript polyglots.
+//!
+//! Detection of non-image formats is based on signatures and is deliberately permissive,
+//! since it is meant for flagging suspicious files rather than for parsing them.
+
+use std::fmt;
+use std::io::{Read, BufRead, Seek, SeekFrom};
+
+use types::{Result, Error, ImageFormat, Completeness};
+use generic;
+use detect;
+use formats::{jpeg, png, gif, webp};
+
+/// Size of blocks in which the stream is scanned for signatures.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Maximum number of occurrences of each signature which are considered.
+const MAX_OCCURRENCES: usize = 256;
+
+/// PDF readers only look for the header in this many bytes at the beginning of the file.
+const PDF_HEADER_SEARCH_LIMIT: u64 = 1024;
+
+/// Signatures the stream is scanned for; ASCII letters in the last three are matched ignoring
+/// case.
+const SIGNATURES: [&'static [u8]; 12] = [
+    b"\xff\xd8\xff",
+    b"\x89PNG\r\n\x1a\n",
+    b"GIF8",
+    b"RIFF",
+    b"PK\x05\x06",
+    b"Rar!\x1a\x07",
+    b"%PDF-",
+    b"%%EOF",
+    b"*/",
+    b"<html",
+    b"<script",
+    b"<?php",
+];
+const SIG_IMAGES: [usize; 4] = [0, 1, 2, 3];
+const SIG_ZIP_EOCD: usize = 4;
+const SIG_RAR: usize = 5;
+const SIG_PDF_HEADER: usize = 6;
+const SIG_PDF_EOF: usize = 7;
+const SIG_COMMENT_END: usize = 8;
+const SIG_HTML: [usize; 2] = [9, 10];
+const SIG_PHP: usize = 11;
+const FIRST_CASE_INSENSITIVE_SIG: usize = 9;
+const MAX_SIGNATURE_LEN: usize = 8;
+
+/// A format recognized by `analyze()`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Kind {
+    /// An image of the given format.
+    Image(ImageFormat),
+    /// A ZIP archive, including formats based on it, like JAR or Office Open XML.
+    Zip,
+    /// A RAR archive.
+    Rar,
+    /// A PDF document.
+    Pdf,
+    /// An HTML document.
+    Html,
+    /// A PHP script.
+    Php,
+    /// A JavaScript program.
+    JavaScript,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Kind::Image(ImageFormat::Jpeg) => f.write_str("JPEG image"),
+            Kind::Image(ImageFormat::Png) => f.write_str("PNG image"),
+            Kind::Image(ImageFormat::Gif) => f.write_str("GIF image"),
+            Kind::Image(ImageFormat::Webp) => f.write_str("WebP image"),
+            Kind::Zip => f.write_str("ZIP archive"),
+            Kind::Rar => f.write_str("RAR archive"),
+            Kind::Pdf => f.write_str("PDF document"),
+            Kind::Html => f.write_str("HTML document"),
+            Kind::Php => f.write_str("PHP script"),
+            Kind::JavaScript => f.write_str("JavaScript program"),
+        }
+    }
+}
+
+/// A format matched by the file, and the byte range it occupies.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Match {
+    /// The recognized format.
+    pub kind: Kind,
+    /// Offset of the first byte of the match.
+    pub start: u64,
+    /// Offset past the last byte of the match.
+    ///
+    /// For formats which are interpreted until the end of the file, like HTML, this is
+    /// the length of the file.
+    pub end: u64,
+}
+
+impl Match {
+    /// Returns the length of the match in bytes.
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+/// Result of `analyze()`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Analysis {
+    /// Length of the file.
+    pub len: u64,
+    /// All matched formats, ordered by their start offsets.
+    pub matches: Vec<Match>,
+}
+
+impl Analysis {
+    /// Returns `true` if the file matches more than one format.
+    #[inline]
+    pub fn is_polyglot(&self) -> bool {
+        self.matches.len() > 1
+    }
+
+    /// Returns the match of the given format, if there is one.
+    pub fn find(&self, kind: Kind) -> Option<&Match> {
+        self.matches.iter().find(|m| m.kind == kind)
+    }
+}
+
+/// Occurrences of a signature in the stream.
+#[derive(Clone, Default)]
+struct Occurrences {
+    /// Offsets of the first `MAX_OCCURRENCES` occurrences.
+    first: Vec<u64>,
+    /// Offset of the last occurrence.
+    last: Option<u64>,
+}
+
+/// Analyzes the whole stream for formats it matches.
+///
+/// The file must start at the beginning of the stream. The stream is read until the end,
+/// in blocks of limited size, so large files are not loaded into memory. An error is only
+/// returned if an I/O error occurs; a file which matches no format at all results in
+/// an empty list of matches.
+pub fn analyze<R: ?Sized + BufRead + Seek>(r: &mut R) -> Result<Analysis> {
+    let len = try!(r.seek(SeekFrom::End(0)));
+    let occurrences = try!(scan(r));
+    let mut matches = Vec::new();
+
+    // the primary image and images appended to it
+    if let Some(primary) = try!(primary_image(r, len)) {
+        let mut end = primary.end;
+        matches.push(primary);
+        let mut candidates: Vec<u64> = SIG_IMAGES.iter()
+            .flat_map(|&i| occurrences[i].first.iter().cloned())
+            .filter(|&offset| offset >= end)
+            .collect();
+        candidates.sort();
+        for offset in candidates {
+            if offset < end {
+                continue;
+            }
+            if let Some(image) = try!(image_at(r, offset, len)) {
+                end = image.end;
+                matches.push(image);
+            }
+        }
+    }
+
+    if let Some(last) = occurrences[SIG_ZIP_EOCD].last {
+        if let Some(zip) = try!(zip_archive(r, last, len)) {
+            matches.push(zip);
+        }
+    }
+
+    if let Some(&start) = occurrences[SIG_RAR].first.first() {
+        matches.push(Match { kind: Kind::Rar, start: start, end: len });
+    }
+
+    if let Some(&start) = occurrences[SIG_PDF_HEADER].first.first() {
+        if start < PDF_HEADER_SEARCH_LIMIT {
+            if let Some(eof) = occurrences[SIG_PDF_EOF].last.filter(|&eof| eof > start) {
+                matches.push(Match { kind: Kind::Pdf, start: start, end: eof + 5 });
+            }
+        }
+    }
+
+    let html = SIG_HTML.iter().filter_map(|&i| occurrences[i].first.first().cloned()).min();
+    if let Some(start) = html {
+        matches.push(Match { kind: Kind::Html, start: start, end: len });
+    }
+    if let Some(&start) = occurrences[SIG_PHP].first.first() {
+        matches.push(Match { kind: Kind::Php, start: start, end: len });
+    }
+
+    if let Some(comment_start) = try!(leading_comment(r)) {
+        if occurrences[SIG_COMMENT_END].last.map_or(false, |end| end >= comment_start + 2) {
+            matches.push(Match { kind: Kind::JavaScript, start: 0, end: len });
+        }
+    }
+
+    matches.sort_by_key(|m| m.start);
+    try!(r.seek(SeekFrom::Start(0)));
+    Ok(Analysis {
+        len: len,
+        matches: matches,
+    })
+}
+
+/// Finds occurrences of all signatures in the stream.
+fn scan<R: ?Sized + Read + Seek>(r: &mut R) -> Result<Vec<Occurrences>> {
+    try!(r.seek(SeekFrom::Start(0)));
+    let mut occurrences = vec![Occurrences::default(); SIGNATURES.len()];
+    let mut buf = vec![0u8; BLOCK_SIZE + MAX_SIGNATURE_LEN];
+    // number of bytes carried over from the previous block and the offset of the buffer
+    let mut kept = 0;
+    let mut base = 0u64;
+    loop {
+        let mut filled = kept;
+        while filled < buf.len() {
+            match try!(r.read(&mut buf[filled..])) {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        let eof = filled < buf.len();
+        // signatures starting here can't be cut off by the end of the buffer
+        let limit = if eof { filled } else { filled - MAX_SIGNATURE_LEN };
+
+        for i in 0..limit {
+            for (j, signature) in SIGNATURES.iter().enumerate() {
+                let data = &buf[i..filled];
+                let found = if j >= FIRST_CASE_INSENSITIVE_SIG {
+                    data.len() >= signature.len() && data[..signature.len()].eq_ignore_ascii_case(signature)
+                } else {
+                    data.starts_with(signature)
+                };
+                if found {
+                    let o = &mut occurrences[j];
+                    if o.first.len() < MAX_OCCURRENCES {
+                        o.first.push(base + i as u64);
+                    }
+                    o.last = Some(base + i as u64);
+                }
+            }
+        }
+
+        if eof {
+            break;
+        }
+        buf.copy_within(limit..filled, 0);
+        kept = filled - limit;
+        base += limit as u64;
+    }
+    Ok(occurrences)
+}
+
+/// Returns the match of the image at the beginning of the stream, if it is not truncated.
+fn primary_image<R: ?Sized + BufRead + Seek>(r: &mut R, len: u64) -> Result<Option<Match>> {
+    match generic::probe(r) {
+        Ok(_) => image_at(r, 0, len),
+        Err(Error::Io(e)) => Err(Error::Io(e)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Returns the match of the image starting at the given offset, if there is a complete image
+/// of a supported format there.
+fn image_at<R: ?Sized + BufRead + Seek>(r: &mut R, offset: u64, len: u64) -> Result<Option<Match>> {
+    try!(r.seek(SeekFrom::Start(offset)));
+    let mut header = [0u8; 12];
+    let n = try!(read_up_to(r, &mut header));
+    let format = match detect::detect(&header[..n]) {
+        Some(format) => format,
+        None => return Ok(None),
+    };
+
+    try!(r.seek(SeekFrom::Start(offset)));
+    let completeness = match format {
+        ImageFormat::Jpeg => jpeg::verify_complete(r),
+        ImageFormat::Png => png::verify_complete(r),
+        ImageFormat::Gif => gif::verify_complete(r),
+        ImageFormat::Webp => webp::verify_complete(r),
+    };
+    let end = match completeness {
+        Ok(Completeness::Complete) => len,
+        Ok(Completeness::TrailingData(n)) => len - n,
+        Ok(Completeness::Truncated) => return Ok(None),
+        Err(Error::Io(e)) => return Err(Error::Io(e)),
+        Err(_) => return Ok(None),
+    };
+    Ok(Some(Match { kind: Kind::Image(format), start: offset, end: end }))
+}
+
+/// Returns the match of the ZIP archive whose end of central directory record is at
+/// the given offset, if the record points to a central directory and a local file header.
+fn zip_archive<R: ?Sized + Read + Seek>(r: &mut R, eocd: u64, len: u64) -> Result<Option<Match>> {
+    let u16_at = |b: &[u8], i: usize| b[i] as u64 | (b[i + 1] as u64) << 8;
+    let u32_at = |b: &[u8], i: usize| u16_at(b, i) | u16_at(b, i + 2) << 16;
+
+    let mut record = [0u8; 22];
+    try!(r.seek(SeekFrom::Start(eocd)));
+    if try!(read_up_to(r, &mut record)) < record.len() {
+        return Ok(None);
+    }
+    let entries = u16_at(&record, 10);
+    let cd_size = u32_at(&record, 12);
+    let cd_offset = u32_at(&record, 16);
+    let end = eocd + 22 + u16_at(&record, 20);
+    if end > len || cd_size + cd_offset > eocd {
+        return Ok(None);
+    }
+    // offsets are relative to the beginning of the archive, which is not the beginning
+    // of the file if the archive is appended to something else
+    let base = eocd - cd_size - cd_offset;
+
+    let start = if entries == 0 {
+        base + cd_offset
+    } else {
+        let mut header = [0u8; 46];
+        try!(r.seek(SeekFrom::Start(base + cd_offset)));
+        if try!(read_up_to(r, &mut header)) < header.len() || &header[..4] != b"PK\x01\x02" {
+            return Ok(None);
+        }
+        let start = base + u32_at(&header, 42);
+        let mut signature = [0u8; 4];
+        try!(r.seek(SeekFrom::Start(start)));
+        if try!(read_up_to(r, &mut signature)) < signature.len() || &signature != b"PK\x03\x04" {
+            return Ok(None);
+        }
+        start
+    };
+    Ok(Some(Match { kind: Kind::Zip, start: start, end: end }))
+}
+
+/// Returns the offset of `/*` if the stream starts with a JavaScript identifier immediately
+/// followed by a comment.
+fn leading_comment<R: ?Sized + Read + Seek>(r: &mut R) -> Result<Option<u64>> {
+    let mut header = [0u8; 64];
+    try!(r.seek(SeekFrom::Start(0)));
+    let n = try!(read_up_to(r, &mut header));
+    let header = &header[..n];
+
+    let identifier_len = header.iter()
+        .take_while(|&&b| b.is_ascii_alphanumeric() || b == b'_' || b == b'$')
+        .count();
+    let starts_like_identifier = header.first().map_or(false, |b| !b.is_ascii_digit());
+    if identifier_len > 0 && starts_like_identifier && header[identifier_len..].starts_with(b"/*") {
+        Ok(Some(identifier_len as u64))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads into the buffer until it is full or the stream ends, returning the number of bytes
+/// read.
+fn read_up_to<R: ?Sized + Read>(r: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match try!(r.read(&mut buf[n..])) {
+            0 => break,
+            k => n += k,
+        }
+    }
+    Ok(n)
+}
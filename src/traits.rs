@@ -2,13 +2,24 @@ use std::io::{BufReader, BufRead, Seek, Cursor};
 use std::fs::File;
 use std::path::Path;
 
-use types::Result;
+use types::{Result, PartialResult, ErrorWithPartial};
+use loader::LoadOptions;
 
 /// Provides several convenience functions for loading metadata from various sources.
 pub trait LoadableMetadata: Sized {
     /// Loads the implementing type from the given buffered input stream.
     fn load<R: ?Sized + BufRead>(r: &mut R) -> Result<Self>;
 
+    /// Loads the implementing type from the given buffered input stream, returning metadata
+    /// loaded so far together with the error if parsing fails midway.
+    ///
+    /// By default, delegates to `LoadableMetadata::load()` and never returns partial metadata.
+    /// Implementations for image formats whose metadata may be useful even when incomplete
+    /// override this behavior.
+    fn load_partial<R: ?Sized + BufRead>(r: &mut R) -> PartialResult<Self> {
+        LoadableMetadata::load(r).map_err(ErrorWithPartial::from)
+    }
+
     /// Loads the implementing type from the given buffered and seekable input stream.
     ///
     /// Delegates to `LoadableMetadata::load()` method by default. Implementations
@@ -18,6 +29,26 @@ pub trait LoadableMetadata: Sized {
         LoadableMetadata::load(r)
     }
 
+    /// Loads the implementing type from the given buffered and seekable input stream,
+    /// returning metadata loaded so far together with the error if parsing fails midway.
+    ///
+    /// Delegates to `LoadableMetadata::load_partial()` method by default.
+    fn load_partial_from_seek<R: ?Sized + BufRead + Seek>(r: &mut R) -> PartialResult<Self> {
+        LoadableMetadata::load_partial(r)
+    }
+
+    /// Loads the implementing type from the given buffered and seekable input stream according
+    /// to the provided options, returning metadata loaded so far together with the error if
+    /// parsing fails midway.
+    ///
+    /// This method is used by `Immeta` loader. Implementations which support format-specific
+    /// options override it; by default, it delegates to
+    /// `LoadableMetadata::load_partial_from_seek()` method and ignores the options.
+    fn load_partial_with_options<R: ?Sized + BufRead + Seek>(r: &mut R, options: &LoadOptions) -> PartialResult<Self> {
+        let _ = options;
+        LoadableMetadata::load_partial_from_seek(r)
+    }
+
     /// Loads the implementing type from a file specified by the given path.
     ///
     /// Delegates to `LoadableMetadata::load_from_seek()` method by default.
@@ -0,0 +1,93 @@
+//! Copying metadata between images.
+//!
+//! Transcoding an image usually loses its metadata, since encoders write pixel data only.
+//! This module extracts EXIF data, the XMP packet and the ICC color profile from the original
+//! image and embeds them into the transcoded one, possibly of a different format, copying the
+//! rest of it unchanged:
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::{BufReader, BufWriter};
+//! use immeta::transplant;
+//!
+//! let mut original = BufReader::new(File::open("kitty.jpg").unwrap());
+//! let mut transcoded = BufReader::new(File::open("kitty-encoded.webp").unwrap());
+//! let mut w = BufWriter::new(File::create("kitty.webp").unwrap());
+//! transplant::transplant(&mut original, &mut transcoded, &mut w).unwrap();
+//! ```
+//!
+//! Metadata can be extracted from JPEG, PNG, WebP and GIF (XMP only) images and embedded
+//! into JPEG, PNG and WebP images. Note that ICC profiles in PNG are compressed, so they can
+//! only be read and written when `zlib` feature is enabled.
+
+use std::io::{BufRead, Write, Seek, SeekFrom};
+
+use types::{Result, ImageFormat};
+use generic;
+use formats::{jpeg, png, gif, webp};
+use common::exif::ExifData;
+use common::xmp::Xmp;
+
+/// Metadata embedded into an image which can be copied to other images.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct EmbeddedMetadata {
+    /// EXIF data.
+    pub exif: Option<ExifData>,
+    /// XMP packet.
+    ///
+    /// Extended XMP of JPEG images is not extracted.
+    pub xmp: Option<Xmp>,
+    /// ICC color profile.
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+impl EmbeddedMetadata {
+    /// Returns `true` if no metadata is present.
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.xmp.is_none() && self.icc_profile.is_none()
+    }
+}
+
+/// Extracts metadata from an image of the given format.
+///
+/// The image must start at the current position of the stream.
+pub fn extract<R: ?Sized + BufRead>(format: ImageFormat, r: &mut R) -> Result<EmbeddedMetadata> {
+    match format {
+        ImageFormat::Jpeg => jpeg::extract_metadata(r),
+        ImageFormat::Png => png::extract_metadata(r),
+        ImageFormat::Gif => gif::extract_metadata(r),
+        ImageFormat::Webp => webp::extract_metadata(r),
+    }
+}
+
+/// Copies an image of the given format while embedding the provided metadata into it.
+///
+/// Each kind of metadata which is present replaces the respective metadata of the image;
+/// the kinds which are absent are left as they are. Embedding metadata into GIF images is
+/// not supported.
+pub fn embed<R: ?Sized + BufRead, W: ?Sized + Write>(format: ImageFormat, r: &mut R, w: &mut W,
+                                                     md: &EmbeddedMetadata) -> Result<()> {
+    match format {
+        ImageFormat::Jpeg => jpeg::embed_metadata(r, w, md),
+        ImageFormat::Png => png::embed_metadata(r, w, md),
+        ImageFormat::Webp => webp::embed_metadata(r, w, md),
+        ImageFormat::Gif => Err(invalid_format!("embedding metadata into GIF images is not supported")),
+    }
+}
+
+/// Copies the target image into the writer, embedding the metadata of the source image.
+///
+/// Formats of both images are detected automatically, and both streams must contain only
+/// the image, i.e. the images must start at the beginning of the streams. See `embed()` for
+/// how the metadata is replaced.
+pub fn transplant<S, R, W>(source: &mut S, target: &mut R, w: &mut W) -> Result<()>
+    where S: ?Sized + BufRead + Seek, R: ?Sized + BufRead + Seek, W: ?Sized + Write
+{
+    let source_format = try!(generic::probe(source)).format;
+    try!(source.seek(SeekFrom::Start(0)));
+    let md = try!(extract(source_format, source));
+
+    let target_format = try!(generic::probe(target)).format;
+    try!(target.seek(SeekFrom::Start(0)));
+    embed(target_format, target, w, &md)
+}
@@ -5,6 +5,9 @@ use std::borrow::Cow;
 use std::error;
 
 use num::ToPrimitive;
+#[cfg(feature = "mime")] use mime::{self, Mime};
+
+use utils::InputLimitExceeded;
 
 /// Library-specific error type which is returned by metadata loading operations.
 #[derive(Debug)]
@@ -22,7 +25,12 @@ pub enum Error {
     UnexpectedEndOfFile(Option<Cow<'static, str>>),
 
     /// Returned when an I/O error occurs when reading an input stream.
-    Io(io::Error)
+    Io(io::Error),
+
+    /// Returned when a configured resource limit is exceeded by the image.
+    ///
+    /// See `immeta::Limits` for more information.
+    LimitExceeded(Cow<'static, str>)
 }
 
 impl fmt::Display for Error {
@@ -31,7 +39,8 @@ impl fmt::Display for Error {
             Error::InvalidFormat(ref s) => write!(f, "invalid image format: {}", s),
             Error::UnexpectedEndOfFile(None) => write!(f, "unexpected end of file"),
             Error::UnexpectedEndOfFile(Some(ref s)) => write!(f, "unexpected end of file: {}", s),
-            Error::Io(ref e) => write!(f, "I/O error: {}", e)
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::LimitExceeded(ref s) => write!(f, "limit exceeded: {}", s)
         }
     }
 }
@@ -41,7 +50,8 @@ impl error::Error for Error {
         match *self {
             Error::InvalidFormat(_) => "invalid image format",
             Error::UnexpectedEndOfFile(_) => "unexpected end of file",
-            Error::Io(_) => "i/o error"
+            Error::Io(_) => "i/o error",
+            Error::LimitExceeded(_) => "limit exceeded"
         }
     }
 
@@ -53,10 +63,30 @@ impl error::Error for Error {
     }
 }
 
+impl Error {
+    /// Appends additional context to the description of format errors.
+    ///
+    /// Errors of other kinds are returned unchanged.
+    pub(crate) fn with_context(self, context: &str) -> Error {
+        match self {
+            Error::InvalidFormat(s) => Error::InvalidFormat(format!("{}{}", s, context).into()),
+            Error::UnexpectedEndOfFile(s) => Error::UnexpectedEndOfFile(
+                Some(format!("{}{}", s.unwrap_or_else(|| "".into()), context).into())
+            ),
+            e => e
+        }
+    }
+}
+
 impl From<io::Error> for Error {
-    #[inline]
     fn from(e: io::Error) -> Error {
-        Error::Io(e)
+        // resource limits enforced by readers are reported as I/O errors
+        let limit_exceeded = e.get_ref().map(|e| e.is::<InputLimitExceeded>()).unwrap_or(false);
+        if limit_exceeded {
+            Error::LimitExceeded(e.to_string().into())
+        } else {
+            Error::Io(e)
+        }
     }
 }
 
@@ -65,7 +95,7 @@ impl From<::byteorder::Error> for Error {
     fn from(e: ::byteorder::Error) -> Error {
         match e {
             ::byteorder::Error::UnexpectedEOF => Error::UnexpectedEndOfFile(None),
-            ::byteorder::Error::Io(e) => Error::Io(e)
+            ::byteorder::Error::Io(e) => Error::from(e)
         }
     }
 }
@@ -73,6 +103,167 @@ impl From<::byteorder::Error> for Error {
 /// Library-specific result type.
 pub type Result<T> = result::Result<T, Error>;
 
+/// An error returned by partial loading operations.
+///
+/// When parsing fails midway, e.g. because the file is truncated, some metadata may already
+/// have been loaded successfully. In this case, it is returned in `partial` field together with
+/// the error which stopped the parsing. Naturally, partial metadata may be incomplete; for
+/// example, a GIF image may have less blocks than it actually has.
+#[derive(Debug)]
+pub struct ErrorWithPartial<T> {
+    /// The error which stopped the parsing.
+    pub error: Error,
+    /// Metadata loaded before the error occurred, if any.
+    pub partial: Option<T>
+}
+
+impl<T> ErrorWithPartial<T> {
+    /// Converts the partial metadata contained in this error with the provided function.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> ErrorWithPartial<U> {
+        ErrorWithPartial {
+            error: self.error,
+            partial: self.partial.map(f)
+        }
+    }
+}
+
+impl<T> fmt::Display for ErrorWithPartial<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.partial {
+            Some(_) => write!(f, "{} (partial metadata available)", self.error),
+            None => self.error.fmt(f)
+        }
+    }
+}
+
+impl<T: fmt::Debug> error::Error for ErrorWithPartial<T> {
+    #[allow(deprecated)]
+    fn description(&self) -> &str {
+        self.error.description()
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        Some(&self.error)
+    }
+}
+
+impl<T> From<Error> for ErrorWithPartial<T> {
+    #[inline]
+    fn from(e: Error) -> ErrorWithPartial<T> {
+        ErrorWithPartial {
+            error: e,
+            partial: None
+        }
+    }
+}
+
+impl<T> From<io::Error> for ErrorWithPartial<T> {
+    #[inline]
+    fn from(e: io::Error) -> ErrorWithPartial<T> {
+        Error::from(e).into()
+    }
+}
+
+/// Result type of partial loading operations.
+pub type PartialResult<T> = result::Result<T, ErrorWithPartial<T>>;
+
+/// Result of checking whether an image stream is complete, returned by `verify_complete()`
+/// functions.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Completeness {
+    /// The image ends exactly at the end of the stream.
+    Complete,
+    /// The image is complete, but it is followed by the given number of bytes of other data.
+    TrailingData(u64),
+    /// The stream ends before the end of the image.
+    Truncated,
+}
+
+impl Completeness {
+    /// Returns `true` if the image is not truncated, regardless of trailing data.
+    #[inline]
+    pub fn is_complete(self) -> bool {
+        self != Completeness::Truncated
+    }
+}
+
+/// A contiguous range of bytes of an image stream, returned by `image_data_ranges()`
+/// functions.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ByteRange {
+    /// Offset of the first byte of the range from the beginning of the stream.
+    pub offset: u64,
+    /// Number of bytes in the range.
+    pub len: u64,
+}
+
+impl ByteRange {
+    /// Returns the offset of the first byte following the range.
+    #[inline]
+    pub fn end(self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+/// Image formats supported by this library.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Gif,
+    Webp
+}
+
+impl ImageFormat {
+    /// All supported image formats, in the order in which they are tried by format
+    /// detection.
+    pub const ALL: [ImageFormat; 4] = [ImageFormat::Png, ImageFormat::Gif, ImageFormat::Webp, ImageFormat::Jpeg];
+
+    /// Returns the MIME type of this image format.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Webp => "image/webp"
+        }
+    }
+
+    /// Returns the MIME type of this image format as a typed `Mime` value.
+    ///
+    /// This method is only available when `mime` feature is enabled.
+    #[cfg(feature = "mime")]
+    pub fn mime(self) -> Mime {
+        match self {
+            ImageFormat::Jpeg => mime::IMAGE_JPEG,
+            ImageFormat::Png => mime::IMAGE_PNG,
+            ImageFormat::Gif => mime::IMAGE_GIF,
+            ImageFormat::Webp => self.mime_type().parse().unwrap()
+        }
+    }
+
+    /// Returns the conventional file name extension (without the dot) of this image format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Webp => "webp"
+        }
+    }
+}
+
+#[cfg(feature = "mime")]
+impl From<ImageFormat> for Mime {
+    #[inline]
+    fn from(format: ImageFormat) -> Mime {
+        format.mime()
+    }
+}
+
 /// Represents image dimensions in pixels.
 ///
 /// As it turns out, this is essentially the only common piece of information across
@@ -81,6 +272,7 @@ pub type Result<T> = result::Result<T, Error>;
 /// It is possible to convert pairs of type `(T1, T2)`, where `T1` and `T2` are primitive
 /// number types, to this type, however, this is mostly needed for internal usage.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Dimensions {
     /// Image width in pixels.
     pub width: u32,
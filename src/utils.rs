@@ -1,26 +1,47 @@
-use std::io::{self, Read, BufRead, ErrorKind};
+use std::io::{self, Read, BufRead, BufReader, Seek, SeekFrom, ErrorKind};
+use std::cmp;
+use std::mem;
+use std::error;
+use std::fmt;
 
 use byteorder::{self, ReadBytesExt, LittleEndian, BigEndian};
 use byteorder::ByteOrder as ByteOrderTrait;
 
-pub trait ReadExt: Read {
-    fn read_exact_0(&mut self, mut buf: &mut [u8]) -> io::Result<usize> {
-        let orig_len = buf.len() as u64;
-        io::copy(&mut self.take(orig_len), &mut buf).map(|r| r as usize)
-    }
+use types::{Result, Error, Completeness, Dimensions};
+
+/// A reader which keeps track of the number of bytes consumed from the underlying stream.
+pub struct Tracked<R> {
+    pub inner: R,
+    pub position: u64,
+}
 
-    fn skip_exact_0(&mut self, n: u64) -> io::Result<u64> {
-        io::copy(&mut self.take(n), &mut io::sink())
+impl<R> Tracked<R> {
+    pub fn new(inner: R) -> Tracked<R> {
+        Tracked {
+            inner: inner,
+            position: 0,
+        }
     }
+}
 
-    fn read_to_vec(&mut self) -> io::Result<Vec<u8>> {
-        let mut buf = Vec::new();
-        try!(self.read_to_end(&mut buf));
-        Ok(buf)
+impl<R: BufRead> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.position += n as u64;
+        Ok(n)
     }
 }
 
-impl<R: ?Sized + Read> ReadExt for R {}
+impl<R: BufRead> BufRead for Tracked<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.position += amt as u64;
+    }
+}
 
 pub trait BufReadExt: BufRead {
     fn skip_exact(&mut self, n: u64) -> io::Result<u64> {
@@ -69,6 +90,16 @@ pub trait BufReadExt: BufRead {
             }
         }
     }
+
+    /// Replaces the contents of `buf` with at most `n` bytes read from this stream.
+    ///
+    /// Unlike `ByteOrderReadExt::read_exact_vec()`, fewer bytes are read if the stream ends
+    /// first; requests larger than `MAX_EXACT_VEC_LEN` are truncated to that size.
+    fn read_up_to(&mut self, n: u64, buf: &mut Vec<u8>) -> io::Result<()> {
+        buf.clear();
+        try!(Read::take(&mut *self, cmp::min(n, MAX_EXACT_VEC_LEN)).read_to_end(buf));
+        Ok(())
+    }
 }
 
 impl<R: ?Sized + BufRead> BufReadExt for R {}
@@ -85,9 +116,13 @@ macro_rules! gen_byte_order_ops {
             $(
             #[inline]
             pub fn $read_name(self, source: &[u8]) -> $tpe {
+                // byteorder reads through a pointer cast, which requires aligned input, while
+                // values in parsed slices may be located at any offset
+                let mut bytes = [0u8; mem::size_of::<$tpe>()];
+                bytes.copy_from_slice(&source[..mem::size_of::<$tpe>()]);
                 match self {
-                    ByteOrder::Little => LittleEndian::$read_name(source),
-                    ByteOrder::Big => BigEndian::$read_name(source),
+                    ByteOrder::Little => <$tpe>::from_le_bytes(bytes),
+                    ByteOrder::Big => <$tpe>::from_be_bytes(bytes),
                 }
             }
 
@@ -114,8 +149,16 @@ gen_byte_order_ops! {
 }
 
 
+/// The maximum number of bytes `ByteOrderReadExt::read_exact_vec()` agrees to read.
+///
+/// Lengths read from the input are not trusted, so larger requests are rejected instead of
+/// trying to allocate and fill a buffer of the requested size.
+pub const MAX_EXACT_VEC_LEN: u64 = 256 * 1024 * 1024;
+
 macro_rules! gen_read_byte_order_ext {
-    ($tr:ident, $($name:ident -> $tpe:ty),+) => {
+    ($tr:ident, $($name:ident, $into_name:ident -> $tpe:ty),+) => {
+        // not every format needs every width, but all of them are a part of the reader API
+        #[allow(dead_code)]
         pub trait $tr: Read {
             $(
             #[inline]
@@ -125,21 +168,621 @@ macro_rules! gen_read_byte_order_ext {
                     ByteOrder::Big => ReadBytesExt::$name::<BigEndian>(self),
                 }
             }
+
+            /// Fills the whole target slice with values read in the given byte order.
+            fn $into_name(&mut self, byte_order: ByteOrder, target: &mut [$tpe]) -> byteorder::Result<()> {
+                for v in target {
+                    *v = try!($tr::$name(self, byte_order));
+                }
+                Ok(())
+            }
             )+
+
+            /// Reads exactly `n` bytes into a new vector.
+            ///
+            /// Requests larger than `MAX_EXACT_VEC_LEN` fail with an `InputLimitExceeded` error
+            /// without reading anything. The vector grows as the data is read, so a bogus length
+            /// in a truncated file does not cause a huge allocation. If the stream ends before
+            /// `n` bytes are read, an error of `UnexpectedEof` kind is returned.
+            fn read_exact_vec(&mut self, n: u64) -> io::Result<Vec<u8>> {
+                if n > MAX_EXACT_VEC_LEN {
+                    return Err(io::Error::new(ErrorKind::Other, InputLimitExceeded(MAX_EXACT_VEC_LEN)));
+                }
+                let mut data = Vec::with_capacity(cmp::min(n, 64 * 1024) as usize);
+                try!(Read::take(&mut *self, n).read_to_end(&mut data));
+                if (data.len() as u64) < n {
+                    return Err(io::Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+                }
+                Ok(data)
+            }
         }
     }
 }
 
 gen_read_byte_order_ext! {
     ByteOrderReadExt,
-    read_u16 -> u16,
-    read_u32 -> u32,
-    read_u64 -> u64,
-    read_i16 -> i16,
-    read_i32 -> i32,
-    read_i64 -> i64,
-    read_f32 -> f32,
-    read_f64 -> f64
+    read_u16, read_u16_into -> u16,
+    read_u32, read_u32_into -> u32,
+    read_u64, read_u64_into -> u64,
+    read_i16, read_i16_into -> i16,
+    read_i32, read_i32_into -> i32,
+    read_i64, read_i64_into -> i64,
+    read_f32, read_f32_into -> f32,
+    read_f64, read_f64_into -> f64
+}
+
+impl<R: ?Sized + Read> ByteOrderReadExt for R {}
+
+/// Computes the size in bytes of decoded pixel data with the given parameters.
+///
+/// Saturates at `u64::max_value()` instead of overflowing.
+pub fn decoded_size(dimensions: Dimensions, channels: u64, bytes_per_channel: u64, frames: u64) -> u64 {
+    (dimensions.width as u64)
+        .saturating_mul(dimensions.height as u64)
+        .saturating_mul(channels)
+        .saturating_mul(bytes_per_channel)
+        .saturating_mul(frames)
+}
+
+/// Formats the provided bytes as a hex dump with 16 bytes per line.
+///
+/// Each line starts with the offset of its first byte, computed from `offset` of the first
+/// provided byte, and ends with the printable ASCII representation of the bytes.
+pub fn hex_dump(data: &[u8], offset: u64) -> String {
+    let mut result = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        result.push_str(&format!("\n{:08x} ", offset + i as u64 * 16));
+        for b in line {
+            result.push_str(&format!(" {:02x}", b));
+        }
+        for _ in line.len()..16 {
+            result.push_str("   ");
+        }
+        result.push_str("  |");
+        result.extend(line.iter().map(|&b| if b >= 0x20 && b < 0x7f { b as char } else { '.' }));
+        result.push('|');
+    }
+    result
+}
+
+/// The error which is returned by `LimitedReader` when its limit is exceeded.
+///
+/// It is wrapped into `io::Error` and converted to `Error::LimitExceeded` when the I/O error
+/// is converted to the library error type.
+#[derive(Debug)]
+pub struct InputLimitExceeded(pub u64);
+
+impl fmt::Display for InputLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "input is larger than {} bytes", self.0)
+    }
+}
+
+impl error::Error for InputLimitExceeded {
+    fn description(&self) -> &str {
+        "input limit exceeded"
+    }
+}
+
+/// A reader which fails once more than the configured number of bytes would be read from
+/// the underlying stream or a seek past this number of bytes is attempted.
+///
+/// Positions are counted from the current position of the underlying stream, which is
+/// expected to be its beginning. Reaching the end of the stream exactly at the limit is
+/// not an error.
+pub struct LimitedReader<R> {
+    inner: R,
+    limit: u64,
+    position: u64,
+}
+
+impl<R> LimitedReader<R> {
+    pub fn new(inner: R, limit: u64) -> LimitedReader<R> {
+        LimitedReader {
+            inner: inner,
+            limit: limit,
+            position: 0,
+        }
+    }
+
+    fn exceeded(&self) -> io::Error {
+        io::Error::new(ErrorKind::Other, InputLimitExceeded(self.limit))
+    }
+}
+
+impl<R: BufRead> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = try!(self.fill_buf());
+            let n = cmp::min(available.len(), buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for LimitedReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position >= self.limit {
+            // only fail if there is actually more data
+            let exceeded = self.exceeded();
+            let buf = try!(self.inner.fill_buf());
+            return if buf.is_empty() { Ok(buf) } else { Err(exceeded) };
+        }
+        let remaining = self.limit - self.position;
+        let buf = try!(self.inner.fill_buf());
+        let n = cmp::min(buf.len() as u64, remaining) as usize;
+        Ok(&buf[..n])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.position += amt as u64;
+    }
+}
+
+impl<R: Seek> Seek for LimitedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if let SeekFrom::Start(n) = pos {
+            if n > self.limit {
+                return Err(self.exceeded());
+            }
+        }
+        self.position = try!(self.inner.seek(pos));
+        if self.position > self.limit {
+            return Err(self.exceeded());
+        }
+        Ok(self.position)
+    }
+}
+
+/// A reader which buffers a prefix of a non-seekable stream so it can be read several times.
+///
+/// The first `capacity` bytes of the stream are kept in memory as they are read. `Seek`
+/// implementation allows seeking backwards anywhere inside this prefix as long as no data
+/// past it has been consumed, and seeking forwards by skipping data. This is enough for
+/// format detection, where each format parser starts reading at the beginning of the stream
+/// and most of them fail after reading a few bytes.
+pub struct PeekReader<R> {
+    inner: BufReader<R>,
+    prefix: Vec<u8>,
+    capacity: usize,
+    position: u64,
+}
+
+impl<R: Read> PeekReader<R> {
+    pub fn with_capacity(inner: R, capacity: usize) -> PeekReader<R> {
+        PeekReader {
+            inner: BufReader::new(inner),
+            prefix: Vec::new(),
+            capacity: capacity,
+            position: 0,
+        }
+    }
+
+    #[inline]
+    fn in_prefix(&self) -> bool {
+        self.position < self.prefix.len() as u64
+    }
+}
+
+impl<R: Read> Read for PeekReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = try!(self.fill_buf());
+            let n = cmp::min(available.len(), buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for PeekReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if !self.in_prefix() && self.position == self.prefix.len() as u64 && self.prefix.len() < self.capacity {
+            let n = {
+                let data = try!(self.inner.fill_buf());
+                let n = cmp::min(data.len(), self.capacity - self.prefix.len());
+                self.prefix.extend_from_slice(&data[..n]);
+                n
+            };
+            self.inner.consume(n);
+        }
+        if self.in_prefix() {
+            Ok(&self.prefix[self.position as usize..])
+        } else {
+            self.inner.fill_buf()
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if !self.in_prefix() {
+            self.inner.consume(amt);
+        }
+        self.position += amt as u64;
+    }
+}
+
+impl<R: Read> Seek for PeekReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => n,
+            SeekFrom::Current(n) if n >= 0 => self.position + n as u64,
+            SeekFrom::Current(n) if self.position >= n.wrapping_neg() as u64 => self.position - n.wrapping_neg() as u64,
+            SeekFrom::Current(_) =>
+                return Err(io::Error::new(ErrorKind::InvalidInput, "seek to a negative position")),
+            SeekFrom::End(_) =>
+                return Err(io::Error::new(ErrorKind::Other, "can't seek relative to the end of a stream")),
+        };
+
+        if target < self.position {
+            // the data between the prefix and the current position is lost
+            if self.position > self.prefix.len() as u64 {
+                return Err(io::Error::new(ErrorKind::Other, "can't seek backwards past the buffered prefix"));
+            }
+            self.position = target;
+        } else {
+            let n = target - self.position;
+            try!(self.skip_exact(n));
+        }
+        Ok(self.position)
+    }
+}
+
+/// A buffered reader which uses a borrowed buffer.
+///
+/// This works like `BufReader`, but the buffer is provided by the caller, so it can be reused
+/// for reading many streams without allocating a new buffer for each of them. The length of
+/// the provided slice is used as the buffer capacity; its contents are overwritten.
+pub struct BorrowedBufReader<'a, R> {
+    inner: R,
+    buf: &'a mut [u8],
+    pos: usize,
+    cap: usize,
+}
+
+impl<'a, R: Read> BorrowedBufReader<'a, R> {
+    pub fn new(inner: R, buf: &'a mut [u8]) -> BorrowedBufReader<'a, R> {
+        BorrowedBufReader {
+            inner: inner,
+            buf: buf,
+            pos: 0,
+            cap: 0,
+        }
+    }
+}
+
+impl<'a, R: Read> Read for BorrowedBufReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // large reads bypass the buffer if it is empty
+        if self.pos == self.cap && buf.len() >= self.buf.len() {
+            return self.inner.read(buf);
+        }
+        let n = {
+            let available = try!(self.fill_buf());
+            let n = cmp::min(available.len(), buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, R: Read> BufRead for BorrowedBufReader<'a, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.cap {
+            self.cap = try!(self.inner.read(self.buf));
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+    }
+}
+
+impl<'a, R: Read + Seek> Seek for BorrowedBufReader<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // the buffered data is discarded, like in `BufReader`
+        let result = match pos {
+            SeekFrom::Current(n) => {
+                let remainder = (self.cap - self.pos) as i64;
+                try!(self.inner.seek(SeekFrom::Current(n - remainder)))
+            }
+            pos => try!(self.inner.seek(pos))
+        };
+        self.pos = 0;
+        self.cap = 0;
+        Ok(result)
+    }
+}
+
+/// A reader which restricts a `Read + Seek` implementation to a window of `length` bytes
+/// starting at `offset`.
+///
+/// Positions inside the window are counted from its beginning, so a parser of a nested
+/// container, e.g. a TIFF document embedded into a JPEG segment, can work with offsets
+/// relative to the container without translating them by hand. Reading stops at the end
+/// of the window even if the underlying stream continues. The underlying stream is only
+/// positioned on the first read or seek, so creating a sub-reader never fails.
+pub struct SubReader<R> {
+    inner: R,
+    offset: u64,
+    length: u64,
+    position: u64,
+    synced: bool,
+}
+
+impl<R: Read + Seek> SubReader<R> {
+    pub fn new(inner: R, offset: u64, length: u64) -> SubReader<R> {
+        SubReader {
+            inner: inner,
+            offset: offset,
+            length: length,
+            position: 0,
+            synced: false,
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if !self.synced {
+            let target = match self.offset.checked_add(self.position) {
+                Some(target) => target,
+                None => return Err(io::Error::new(ErrorKind::InvalidInput, "sub-reader position overflow"))
+            };
+            try!(self.inner.seek(SeekFrom::Start(target)));
+            self.synced = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SubReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.length {
+            return Ok(0);
+        }
+        try!(self.sync());
+        let max = cmp::min(buf.len() as u64, self.length - self.position) as usize;
+        let n = try!(self.inner.read(&mut buf[..max]));
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for SubReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(n) => (n, 0),
+            SeekFrom::Current(d) => (self.position, d),
+            SeekFrom::End(d) => (self.length, d),
+        };
+        let target = if delta >= 0 {
+            base.checked_add(delta as u64)
+        } else {
+            base.checked_sub(delta.wrapping_neg() as u64)
+        };
+        match target {
+            Some(target) => {
+                if target != self.position {
+                    self.position = target;
+                    self.synced = false;
+                }
+                try!(self.sync());
+                Ok(self.position)
+            }
+            None => Err(io::Error::new(ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"))
+        }
+    }
 }
 
-impl<R: Read> ByteOrderReadExt for R {}
+/// Compares the length of an image starting at the current position of the stream with the
+/// length of the rest of the stream, restoring the position afterwards.
+///
+/// `measure` reads the image structure and returns its length; if it fails because of
+/// the end of the stream, the image is truncated.
+pub fn verify_length<R: ?Sized + Seek, F>(r: &mut R, measure: F) -> Result<Completeness>
+    where F: FnOnce(&mut R) -> Result<u64>
+{
+    let start = try!(r.seek(SeekFrom::Current(0)));
+    let stream_len = try!(r.seek(SeekFrom::End(0))) - start;
+    try!(r.seek(SeekFrom::Start(start)));
+
+    let result = match measure(r) {
+        Ok(len) if len > stream_len => Ok(Completeness::Truncated),
+        Ok(len) if len < stream_len => Ok(Completeness::TrailingData(stream_len - len)),
+        Ok(_) => Ok(Completeness::Complete),
+        Err(Error::UnexpectedEndOfFile(msg)) => {
+            log_debug!("image is truncated: {}", msg.as_ref().map(|s| &**s).unwrap_or("unexpected end of file"));
+            Ok(Completeness::Truncated)
+        }
+        Err(e) => Err(e)
+    };
+    try!(r.seek(SeekFrom::Start(start)));
+    result
+}
+
+/// Adds two offsets or sizes, returning an error instead of overflowing.
+pub fn checked_add(a: u64, b: u64) -> Result<u64> {
+    a.checked_add(b).ok_or_else(|| invalid_format!("offset overflow: {} + {}", a, b))
+}
+
+/// Computes the total size of `count` elements of `element_size` bytes each.
+///
+/// Saturates at `u64::max_value()` instead of overflowing.
+#[inline]
+pub fn saturating_size(count: u64, element_size: u64) -> u64 {
+    count.saturating_mul(element_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::u64;
+    use std::io::{self, Read, BufRead, Seek, SeekFrom, Cursor};
+
+    use types::Error;
+
+    use super::{checked_add, saturating_size, hex_dump, LimitedReader, PeekReader, SubReader,
+                BorrowedBufReader};
+    use super::{ByteOrder, ByteOrderReadExt, MAX_EXACT_VEC_LEN};
+
+    #[test]
+    fn test_byte_order_read_ext() {
+        let data = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a];
+
+        let mut r = Cursor::new(&data[..]);
+        assert_eq!(r.read_u64(ByteOrder::Big).unwrap(), 0x0102030405060708);
+        assert_eq!(r.read_i16(ByteOrder::Little).unwrap(), 0x0a09);
+
+        let mut values = [0u16; 3];
+        let mut r = Cursor::new(&data[..]);
+        r.read_u16_into(ByteOrder::Little, &mut values).unwrap();
+        assert_eq!(values, [0x0201, 0x0403, 0x0605]);
+
+        let mut values = [0u32; 3];
+        assert!(r.read_u32_into(ByteOrder::Big, &mut values).is_err());
+    }
+
+    #[test]
+    fn test_read_exact_vec() {
+        let data = [1u8, 2, 3, 4, 5];
+
+        let mut r = Cursor::new(&data[..]);
+        assert_eq!(r.read_exact_vec(3).unwrap(), vec![1, 2, 3]);
+        assert!(r.read_exact_vec(0).unwrap().is_empty());
+        assert_eq!(r.read_exact_vec(3).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut r = Cursor::new(&data[..]);
+        match Error::from(r.read_exact_vec(MAX_EXACT_VEC_LEN + 1).unwrap_err()) {
+            Error::LimitExceeded(_) => {}
+            e => panic!("unexpected error: {}", e),
+        }
+        assert_eq!(r.position(), 0);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(hex_dump(b"", 0), "");
+        assert_eq!(
+            hex_dump(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\x01", 0x20),
+            "\n00000020  89 50 4e 47 0d 0a 1a 0a 00 00 00 0d 49 48 44 52  |.PNG........IHDR|\
+             \n00000030  00 00 01                                         |...|"
+        );
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        assert_eq!(checked_add(8, 2).unwrap(), 10);
+        assert_eq!(saturating_size(0xffff_ffff, 8), 0x7_ffff_fff8);
+
+        match checked_add(u64::MAX - 1, 2) {
+            Err(Error::InvalidFormat(_)) => {}
+            r => panic!("unexpected result: {:?}", r)
+        }
+        assert_eq!(saturating_size(u64::MAX / 2, 3), u64::MAX);
+    }
+
+    #[test]
+    fn test_limited_reader() {
+        let data = b"0123456789";
+
+        let mut r = LimitedReader::new(&data[..], 10);
+        let mut buf = Vec::new();
+        assert_eq!(r.read_to_end(&mut buf).unwrap(), 10);
+        assert_eq!(r.read(&mut [0u8; 4]).unwrap(), 0);
+
+        let mut r = LimitedReader::new(&data[..], 4);
+        let mut buf = [0u8; 6];
+        assert_eq!(r.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"0123");
+        match r.fill_buf().map_err(Error::from) {
+            Err(Error::LimitExceeded(_)) => {}
+            res => panic!("unexpected result: {:?}", res)
+        }
+
+        let mut r = LimitedReader::new(Cursor::new(&data[..]), 4);
+        assert_eq!(r.seek(SeekFrom::Start(4)).unwrap(), 4);
+        assert!(r.seek(SeekFrom::Start(5)).is_err());
+        assert!(r.seek(SeekFrom::End(0)).is_err());
+    }
+
+    #[test]
+    fn test_peek_reader() {
+        let data: Vec<u8> = (0..100).collect();
+
+        let mut r = PeekReader::with_capacity(&data[..], 10);
+        let mut buf = [0u8; 4];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0, 1, 2, 3]);
+        assert_eq!(r.seek(SeekFrom::Start(0)).unwrap(), 0);
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0, 1, 2, 3]);
+
+        assert_eq!(r.seek(SeekFrom::Current(4)).unwrap(), 8);
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [8, 9, 10, 11]);
+        assert_eq!(r.seek(SeekFrom::Current(0)).unwrap(), 12);
+        assert!(r.seek(SeekFrom::Start(0)).is_err());
+        assert!(r.seek(SeekFrom::End(0)).is_err());
+
+        assert_eq!(r.seek(SeekFrom::Start(50)).unwrap(), 50);
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[50..]);
+    }
+
+    #[test]
+    fn test_borrowed_buf_reader() {
+        let data: Vec<u8> = (0..20).collect();
+        let mut buf = [0u8; 8];
+        let mut r = BorrowedBufReader::new(Cursor::new(&data[..]), &mut buf);
+
+        assert_eq!(r.fill_buf().unwrap(), &data[..8]);
+        r.consume(3);
+        let mut out = [0u8; 4];
+        r.read_exact(&mut out).unwrap();
+        assert_eq!(out, [3, 4, 5, 6]);
+
+        // the position accounts for the buffered data
+        assert_eq!(r.seek(SeekFrom::Current(0)).unwrap(), 7);
+        assert_eq!(r.seek(SeekFrom::Current(-2)).unwrap(), 5);
+        assert_eq!(r.fill_buf().unwrap(), &data[5..13]);
+
+        let mut rest = Vec::new();
+        r.seek(SeekFrom::Start(15)).unwrap();
+        r.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, vec![15, 16, 17, 18, 19]);
+    }
+
+    #[test]
+    fn test_sub_reader() {
+        let data: Vec<u8> = (0..20).collect();
+        let mut r = SubReader::new(Cursor::new(&data[..]), 5, 10);
+
+        let mut buf = [0u8; 4];
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [5, 6, 7, 8]);
+
+        // seeks are relative to the window
+        assert_eq!(r.seek(SeekFrom::End(-2)).unwrap(), 8);
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, vec![13, 14]);
+
+        assert_eq!(r.seek(SeekFrom::Start(1)).unwrap(), 1);
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [6, 7, 8, 9]);
+        assert_eq!(r.seek(SeekFrom::Current(-5)).unwrap(), 0);
+        assert!(r.seek(SeekFrom::Current(-1)).is_err());
+
+        // reading past the end of the window returns nothing
+        r.seek(SeekFrom::Start(12)).unwrap();
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+    }
+}
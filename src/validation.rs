@@ -0,0 +1,319 @@
+//! Structural validation of images.
+//!
+//! `Validator` checks the structure of an image of any supported format without decoding it
+//! and produces a `Report` listing all problems found, each with a severity, a byte offset,
+//! a human-readable message and a stable machine-readable code:
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use immeta::validation::Validator;
+//!
+//! let mut f = BufReader::new(File::open("kitty.png").unwrap());
+//! let report = Validator::new().validate(&mut f).unwrap();
+//! for finding in &report.findings {
+//!     println!("{}", finding);
+//! }
+//! ```
+//!
+//! In the default advisory mode, deviations which decoders usually tolerate, like data after
+//! the end of the image, are reported as warnings, and only violations which are likely to
+//! break decoding are errors. In strict mode, every finding is an error.
+//!
+//! PNG, GIF and WebP images are checked with `validate()` functions of the respective format
+//! modules, and JPEG images are checked for the presence and order of their frame header,
+//! scans and EOI marker.
+
+use std::fmt;
+use std::io::{BufRead, Seek, SeekFrom};
+
+use types::{Result, Error, ImageFormat};
+use generic;
+use formats::{jpeg, png, gif, webp};
+
+/// Severity of a finding.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Severity {
+    /// The image deviates from the specification, but decoders usually handle it.
+    Warning,
+    /// The image is broken, and decoders are likely to reject it or display it incorrectly.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A single problem found by `Validator`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Finding {
+    /// Severity of the problem.
+    pub severity: Severity,
+    /// Offset of the offending structure or of the place where the problem is found, from
+    /// the beginning of the stream.
+    ///
+    /// It is `None` if the problem is not related to a particular place, e.g. when a required
+    /// structure is missing altogether.
+    pub offset: Option<u64>,
+    /// Machine-readable code of the problem, consisting of the format name and the kind of
+    /// the problem, e.g. `png.duplicate_chunk`.
+    ///
+    /// Codes are stable across versions, unlike messages.
+    pub code: &'static str,
+    /// Human-readable description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}: {}", self.severity, self.message));
+        if let Some(offset) = self.offset {
+            try!(write!(f, " at offset {}", offset));
+        }
+        write!(f, " [{}]", self.code)
+    }
+}
+
+/// Result of validating an image.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Report {
+    /// Format of the image.
+    pub format: ImageFormat,
+    /// All problems found, in the order of their offsets where possible.
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Returns `true` if there are no findings with `Error` severity.
+    pub fn is_valid(&self) -> bool {
+        self.count(Severity::Error) == 0
+    }
+
+    /// Returns the number of findings with the given severity.
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|f| f.severity == severity).count()
+    }
+
+    /// Converts the report into an `InvalidFormat` error describing the first error finding,
+    /// if there is one.
+    pub fn into_result(self) -> Result<()> {
+        match self.findings.iter().find(|f| f.severity == Severity::Error) {
+            Some(finding) => Err(invalid_format!("{}", finding)),
+            None => Ok(())
+        }
+    }
+}
+
+/// Structural validator of images of all supported formats.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Validator {
+    strict: bool,
+}
+
+impl Validator {
+    /// Creates a validator in advisory mode.
+    #[inline]
+    pub fn new() -> Validator {
+        Validator::default()
+    }
+
+    /// Sets whether the validator works in strict mode, where all findings are errors.
+    #[inline]
+    pub fn strict(mut self, strict: bool) -> Validator {
+        self.strict = strict;
+        self
+    }
+
+    /// Validates an image contained in the provided input stream.
+    ///
+    /// The format is detected with `immeta::probe()`, so the image must start at the
+    /// beginning of the stream, and the stream is read until the end. Broken structures are
+    /// reported as findings; an error is returned only if the format can't be detected or if
+    /// an I/O error occurs.
+    pub fn validate<R: ?Sized + BufRead + Seek>(&self, r: &mut R) -> Result<Report> {
+        let format = try!(generic::probe(r)).format;
+        try!(r.seek(SeekFrom::Start(0)));
+
+        let mut findings = Vec::new();
+        let result = match format {
+            ImageFormat::Jpeg => validate_jpeg(r, &mut findings),
+            ImageFormat::Png => png::validate(&mut *r).map(|violations| {
+                findings.extend(violations.into_iter().map(png_finding));
+            }),
+            ImageFormat::Gif => gif::validate(&mut *r).map(|violations| {
+                findings.extend(violations.into_iter().map(gif_finding));
+            }),
+            ImageFormat::Webp => webp::validate(&mut *r).map(|violations| {
+                findings.extend(violations.into_iter().map(webp_finding));
+            }),
+        };
+        match result {
+            Ok(()) => {}
+            Err(Error::Io(e)) => return Err(Error::Io(e)),
+            Err(e) => findings.push(Finding {
+                severity: Severity::Error,
+                offset: None,
+                code: unreadable_code(format),
+                message: e.to_string(),
+            })
+        }
+
+        if self.strict {
+            for finding in &mut findings {
+                finding.severity = Severity::Error;
+            }
+        }
+        Ok(Report {
+            format: format,
+            findings: findings,
+        })
+    }
+}
+
+fn unreadable_code(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "jpeg.unreadable",
+        ImageFormat::Png => "png.unreadable",
+        ImageFormat::Gif => "gif.unreadable",
+        ImageFormat::Webp => "webp.unreadable",
+    }
+}
+
+fn finding(severity: Severity, offset: Option<u64>, code: &'static str, message: String) -> Finding {
+    Finding {
+        severity: severity,
+        offset: offset,
+        code: code,
+        message: message,
+    }
+}
+
+fn validate_jpeg<R: ?Sized + BufRead>(r: &mut R, findings: &mut Vec<Finding>) -> Result<()> {
+    let mut frame_offset = None;
+    let mut hierarchical = false;
+    let mut scan_seen = false;
+    let mut eoi_offset = None;
+
+    let mut segments = jpeg::Segments::new(&mut *r);
+    while let Some(segment) = segments.next() {
+        let segment = try!(segment);
+        let (marker, offset) = (segment.marker(), segment.offset());
+        match marker {
+            // DHP marker starts a hierarchical image which consists of several frames
+            0xde => hierarchical = true,
+            // SOF markers, except DHT, JPG and DAC which share the range
+            0xc0...0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                if frame_offset.is_some() && !hierarchical {
+                    findings.push(finding(Severity::Error, Some(offset), "jpeg.duplicate_frame_header",
+                                          format!("repeated frame header SOF{}", marker - 0xc0)));
+                }
+                if scan_seen && !hierarchical {
+                    findings.push(finding(Severity::Error, Some(offset), "jpeg.frame_header_after_scan",
+                                          "frame header follows a scan".to_owned()));
+                }
+                frame_offset = Some(offset);
+            }
+            0xda => {
+                if frame_offset.is_none() && !scan_seen {
+                    findings.push(finding(Severity::Error, Some(offset), "jpeg.scan_before_frame_header",
+                                          "scan precedes the frame header".to_owned()));
+                }
+                scan_seen = true;
+            }
+            0xd9 => eoi_offset = Some(offset),
+            _ => {}
+        }
+    }
+
+    let end = segments.position();
+    if frame_offset.is_none() {
+        findings.push(finding(Severity::Error, None, "jpeg.missing_frame_header", "no frame header".to_owned()));
+    }
+    if !scan_seen {
+        findings.push(finding(Severity::Error, None, "jpeg.missing_scan", "no scans".to_owned()));
+    }
+    match eoi_offset {
+        Some(offset) => {
+            let r = segments.into_inner();
+            if !try!(r.fill_buf()).is_empty() {
+                findings.push(finding(Severity::Warning, Some(offset + 2), "jpeg.data_after_eoi",
+                                      "data after EOI marker".to_owned()));
+            }
+        }
+        None => findings.push(finding(Severity::Error, Some(end), "jpeg.missing_eoi", "no EOI marker".to_owned())),
+    }
+    Ok(())
+}
+
+fn png_finding(violation: png::Violation) -> Finding {
+    use formats::png::ViolationKind::*;
+
+    let (severity, code) = match violation.kind {
+        IhdrNotFirst => (Severity::Error, "png.ihdr_not_first"),
+        DuplicateChunk => (Severity::Error, "png.duplicate_chunk"),
+        UnknownCriticalChunk => (Severity::Error, "png.unknown_critical_chunk"),
+        ReservedBitSet => (Severity::Warning, "png.reserved_bit_set"),
+        ChunkAfterPlte => (Severity::Error, "png.chunk_after_plte"),
+        ChunkBeforePlte => (Severity::Error, "png.chunk_before_plte"),
+        ChunkAfterIdat => (Severity::Error, "png.chunk_after_idat"),
+        NonConsecutiveIdat => (Severity::Error, "png.non_consecutive_idat"),
+        MissingPlte => (Severity::Error, "png.missing_plte"),
+        UnexpectedPlte => (Severity::Error, "png.unexpected_plte"),
+        ConflictingColorProfiles => (Severity::Warning, "png.conflicting_color_profiles"),
+        MissingIdat => (Severity::Error, "png.missing_idat"),
+        MissingIend => (Severity::Error, "png.missing_iend"),
+        DataAfterIend => (Severity::Warning, "png.data_after_iend"),
+    };
+    let message = match violation.chunk_type {
+        Some(ref t) => format!("{} ({})", violation.kind.description(), String::from_utf8_lossy(t)),
+        None => violation.kind.description().to_owned()
+    };
+    finding(severity, Some(violation.offset), code, message)
+}
+
+fn gif_finding(violation: gif::Violation) -> Finding {
+    use formats::gif::ViolationKind::*;
+
+    let (severity, code) = match violation.kind {
+        TruncatedBlock => (Severity::Error, "gif.truncated_block"),
+        MissingTrailer => (Severity::Warning, "gif.missing_trailer"),
+        DataAfterTrailer => (Severity::Warning, "gif.data_after_trailer"),
+        UnknownBlock => (Severity::Error, "gif.unknown_block"),
+        UnknownExtension => (Severity::Warning, "gif.unknown_extension"),
+        InvalidBlockSize => (Severity::Error, "gif.invalid_block_size"),
+        FrameOutsideScreen => (Severity::Warning, "gif.frame_outside_screen"),
+        EmptyFrame => (Severity::Warning, "gif.empty_frame"),
+        MissingColorTable => (Severity::Error, "gif.missing_color_table"),
+        InvalidLzwCodeSize => (Severity::Error, "gif.invalid_lzw_code_size"),
+    };
+    finding(severity, Some(violation.offset), code, violation.kind.description().to_owned())
+}
+
+fn webp_finding(violation: webp::Violation) -> Finding {
+    use formats::webp::ViolationKind::*;
+
+    let (severity, code) = match violation.kind {
+        InvalidFirstChunk => (Severity::Error, "webp.invalid_first_chunk"),
+        MisplacedVp8x => (Severity::Error, "webp.misplaced_vp8x"),
+        DuplicateChunk => (Severity::Error, "webp.duplicate_chunk"),
+        ChunkAfterImageData => (Severity::Warning, "webp.chunk_after_image_data"),
+        ChunkBeforeImageData => (Severity::Warning, "webp.chunk_before_image_data"),
+        UnexpectedChunk => (Severity::Warning, "webp.unexpected_chunk"),
+        MissingImageData => (Severity::Error, "webp.missing_image_data"),
+        TruncatedChunk => (Severity::Error, "webp.truncated_chunk"),
+        RiffSizeMismatch => (Severity::Warning, "webp.riff_size_mismatch"),
+    };
+    let message = match violation.chunk_id {
+        Some(id) => format!("{} ({})", violation.kind.description(), id),
+        None => violation.kind.description().to_owned()
+    };
+    finding(severity, Some(violation.offset), code, message)
+}
@@ -1,7 +1,9 @@
 extern crate immeta;
 
 use immeta::Dimensions;
-use immeta::formats::{png, gif};
+use std::convert::TryFrom;
+
+use immeta::formats::{jpeg, png, gif, webp, pdf};
 use immeta::markers::{Png, Gif, Jpeg, Webp};
 
 const OWLET_DIM: Dimensions = Dimensions {
@@ -27,8 +29,372 @@ fn test_jpeg() {
     assert_eq!(md.dimensions(), OWLET_DIM);
 
     // let md = Jpeg::from(md).ok()
+    assert_eq!(md.estimated_decoded_size(), 1280 * 857 * 3);
+
     let md = md.into::<Jpeg>().ok().expect("not JPEG metadata");
     assert_eq!(md.dimensions, OWLET_DIM);
+    assert_eq!(md.coding_process, jpeg::CodingProcess::Baseline);
+    assert_eq!(md.entropy_coding, jpeg::EntropyCoding::Huffman);
+    assert!(!md.differential);
+    assert!(!md.is_arithmetic_coded());
+    assert_eq!(md.frame_type(), jpeg::FrameType::Sof0);
+    assert_eq!(md.restart_interval, None);
+    assert_eq!(md.scan_count, 1);
+    assert_eq!(md.precision, 8);
+    assert_eq!(md.components, 3);
+    assert_eq!(md.color_space, jpeg::ColorSpace::YCbCr);
+    assert_eq!(md.density, Some(jpeg::Density { unit: jpeg::DensityUnit::AspectRatio, x: 1, y: 1 }));
+    assert_eq!(md.dpi(), None);
+    assert_eq!(md.quantization_tables.len(), 2);
+    assert_eq!(md.quantization_tables[0].id, 0);
+    assert_eq!(md.quantization_tables[0].precision, 8);
+    assert_eq!(&md.quantization_tables[0].values[..4], &[3, 2, 2, 2]);
+    assert_eq!(md.estimated_quality(), Some(92));
+}
+
+#[test]
+fn test_jpeg_density() {
+    // SOI, APP0 JFIF segment with 300x300 dots per centimeter, then SOF0 with one component
+    let data = [
+        0xff, 0xd8,
+        0xff, 0xe0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00, 0x01, 0x02, 0x02, 0x01, 0x2c, 0x01, 0x2c, 0x00, 0x00,
+        0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00,
+    ];
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.density, Some(jpeg::Density { unit: jpeg::DensityUnit::DotsPerCentimeter, x: 300, y: 300 }));
+    assert_eq!(md.dpi(), Some((762.0, 762.0)));
+}
+
+#[test]
+fn test_jpeg_segments() {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let f = BufReader::new(File::open("tests/images/owlet.jpg").unwrap());
+    let mut segments = jpeg::Segments::new(f);
+    let mut markers = Vec::new();
+    while let Some(segment) = segments.next() {
+        let mut segment = segment.unwrap();
+        if segment.marker() == 0xe0 {
+            assert_eq!(segment.offset(), 2);
+            assert_eq!(segment.len(), 14);
+            assert!(segment.read_payload().unwrap().starts_with(b"JFIF\0"));
+        }
+        markers.push(segment.marker());
+    }
+
+    assert_eq!(&markers[..5], &[0xd8, 0xe0, 0xdb, 0xdb, 0xc0]);
+    assert!(markers.contains(&0xda));
+    assert_eq!(markers.last(), Some(&0xd9));
+}
+
+#[test]
+fn test_jpeg_progressive() {
+    // SOI, then SOF2 with 8-bit precision, 16x8 pixels and a single component
+    let data = [
+        0xff, 0xd8,
+        0xff, 0xc2, 0x00, 0x0b, 0x08, 0x00, 0x08, 0x00, 0x10, 0x01, 0x01, 0x11, 0x00,
+    ];
+    let md = immeta::load_from_buf(&data).unwrap();
+    let md = md.into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.dimensions, Dimensions { width: 16, height: 8 });
+    assert_eq!(md.coding_process, jpeg::CodingProcess::Progressive);
+    assert!(md.is_progressive());
+    assert_eq!(md.frame_type(), jpeg::FrameType::Sof2);
+    assert_eq!(md.components, 1);
+    assert_eq!(md.color_space, jpeg::ColorSpace::Grayscale);
+}
+
+#[test]
+fn test_jpeg_mpf() {
+    let mut data = vec![0xff, 0xd8];
+    // APP2 segment with MP header and MP index IFD of three entries
+    data.extend_from_slice(&[0xff, 0xe2, 0x00, 0x58]);
+    data.extend_from_slice(b"MPF\0MM\0\x2a\0\0\0\x08");
+    data.extend_from_slice(&[0x00, 0x03]);
+    data.extend_from_slice(&[0xb0, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x04, b'0', b'1', b'0', b'0']);
+    data.extend_from_slice(&[0xb0, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02]);
+    data.extend_from_slice(&[0xb0, 0x02, 0x00, 0x07, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x32]);
+    data.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    // MP entries: representative primary image and a disparity image
+    data.extend_from_slice(&[0x20, 0x03, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00,
+                             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    data.extend_from_slice(&[0x00, 0x02, 0x00, 0x02, 0x00, 0x00, 0x08, 0x00,
+                             0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    // SOF0 with a single component
+    data.extend_from_slice(&[0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00]);
+
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.mpf_images, vec![
+        jpeg::MpImage {
+            image_type: jpeg::MpImageType::BaselinePrimary,
+            format: 0,
+            representative: true,
+            dependent_parent: false,
+            dependent_child: false,
+            size: 0x1000,
+            offset: 0,
+            dependent_images: [0, 0],
+        },
+        jpeg::MpImage {
+            image_type: jpeg::MpImageType::Disparity,
+            format: 0,
+            representative: false,
+            dependent_parent: false,
+            dependent_child: false,
+            size: 0x800,
+            // APP2 marker is at offset 2, MP header follows it at offset 10
+            offset: 10 + 0xf00,
+            dependent_images: [0, 0],
+        },
+    ]);
+}
+
+/// Builds a JPEG stream consisting of SOI, the provided segments, SOF0 with the given size,
+/// SOS and EOI.
+fn build_jpeg(segments: &[(u8, &[u8])], width: u8, height: u8) -> Vec<u8> {
+    let mut data = vec![0xff, 0xd8];
+    for &(marker, payload) in segments {
+        let len = payload.len() + 2;
+        data.extend_from_slice(&[0xff, marker, (len >> 8) as u8, len as u8]);
+        data.extend_from_slice(payload);
+    }
+    data.extend_from_slice(&[0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, height, 0x00, width, 0x01, 0x01, 0x11, 0x00]);
+    data.extend_from_slice(&[0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00, 0x12, 0x34]);
+    data.extend_from_slice(&[0xff, 0xd9]);
+    data
+}
+
+#[test]
+fn test_jpeg_app_segments() {
+    use std::io::Cursor;
+
+    let data = build_jpeg(&[
+        (0xe0, b"JFIF\0\x01\x02\0\0\x01\0\x01\0\0"),
+        (0xe4, b"DJI\0telemetry"),
+        (0xe5, b"\x01\x02\x03"),
+        (0xe4, b"DJI\0more"),
+        (0xec, b"Ducky\x01\0\x04"),
+    ], 16, 8);
+    let md = jpeg::parse_bytes(&data).unwrap();
+    let identifiers: Vec<_> = md.application_segments.iter().map(|s| (s.n, &*s.identifier)).collect();
+    assert_eq!(identifiers, [(0, "JFIF"), (4, "DJI"), (5, ""), (4, "DJI"), (12, "")]);
+
+    let app4 = md.app_segments(4);
+    assert_eq!(app4.len(), 2);
+    assert_eq!(app4[0].offset, 2 + 18);
+    assert_eq!(app4[0].payload_offset(), 2 + 18 + 4);
+    assert_eq!(app4[0].len, 13);
+    assert_eq!(app4[0].read_payload(&mut Cursor::new(&data)).unwrap(), b"DJI\0telemetry");
+    assert_eq!(md.app_segments_with_identifier("DJI")[1].read_payload(&mut Cursor::new(&data)).unwrap(),
+               b"DJI\0more");
+    assert_eq!(md.app_segments(5)[0].read_payload(&mut Cursor::new(&data)).unwrap(), b"\x01\x02\x03");
+    assert!(md.app_segments(1).is_empty());
+    assert!(app4[0].read_payload(&mut Cursor::new(&data[..30])).is_err());
+}
+
+#[test]
+fn test_jpeg_scans() {
+    let mut data = build_jpeg(&[(0xdd, &[0x00, 0x10])], 8, 8);
+    // insert two more scans with a restart marker and a stuffed byte before EOI
+    let eoi = data.len() - 2;
+    let scans = [
+        0xff, 0xc4, 0x00, 0x02,
+        0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00, 0xff, 0x00, 0xff, 0xd0, 0x56,
+        0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00, 0x78,
+    ];
+    data.splice(eoi..eoi, scans.iter().cloned());
+
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.restart_interval, Some(16));
+    assert_eq!(md.scan_count, 3);
+}
+
+#[test]
+fn test_jpeg_previews() {
+    let thumbnail = build_jpeg(&[], 4, 3);
+    // EXIF segment with empty IFD0 and IFD1 pointing to the thumbnail
+    let mut exif = Vec::new();
+    exif.extend_from_slice(b"Exif\0\0II\x2a\0\x08\0\0\0");
+    exif.extend_from_slice(&[0x01, 0x00, 0x0e, 0x01, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&[0x1a, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&[0x02, 0x00]);
+    exif.extend_from_slice(&[0x01, 0x02, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&[0x02, 0x02, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, thumbnail.len() as u8, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&thumbnail);
+
+    let mut data = build_jpeg(&[(0xe1, &exif)], 64, 48);
+    let main_len = data.len() as u64;
+    let preview = build_jpeg(&[], 32, 24);
+    data.extend_from_slice(&preview);
+
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.dimensions, Dimensions { width: 64, height: 48 });
+    assert_eq!(md.previews, vec![
+        jpeg::PreviewImage {
+            source: jpeg::PreviewSource::ExifThumbnail,
+            // SOI, APP1 marker and size, EXIF header, TIFF data before the thumbnail
+            offset: 2 + 4 + 6 + 0x38,
+            size: thumbnail.len() as u64,
+            dimensions: Some(Dimensions { width: 4, height: 3 }),
+        },
+        jpeg::PreviewImage {
+            source: jpeg::PreviewSource::Trailing,
+            offset: main_len,
+            size: preview.len() as u64,
+            dimensions: Some(Dimensions { width: 32, height: 24 }),
+        },
+    ]);
+    assert_eq!(md.largest_preview(), Some(&md.previews[1]));
+}
+
+#[test]
+fn test_jpeg_flir() {
+    // FFF header and a single raw data record of a 160x120 thermal image
+    let mut fff = vec![0u8; 0x40 + 0x20 + 0x10];
+    fff[0..4].copy_from_slice(b"FFF\0");
+    fff[0x14..0x20].copy_from_slice(&[0, 0, 0, 100, 0, 0, 0, 0x40, 0, 0, 0, 1]);
+    fff[0x40..0x42].copy_from_slice(&[0, 1]);
+    fff[0x4c..0x54].copy_from_slice(&[0, 0, 0, 0x60, 0, 0, 0, 0x10]);
+    fff[0x60..0x66].copy_from_slice(&[0, 2, 0, 160, 0, 120]);
+
+    // the container is split into two APP1 segments
+    let (first, second) = fff.split_at(0x50);
+    let mut app1 = (b"FLIR\0\x01\x00\x01".to_vec(), b"FLIR\0\x01\x01\x01".to_vec());
+    app1.0.extend_from_slice(first);
+    app1.1.extend_from_slice(second);
+
+    let data = build_jpeg(&[(0xe1, &app1.0), (0xe1, &app1.1)], 64, 48);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    let flir = md.flir.expect("no FLIR metadata");
+    assert_eq!(flir.raw_thermal_dimensions, Some(Dimensions { width: 160, height: 120 }));
+    assert_eq!(flir.camera_model, None);
+}
+
+#[test]
+fn test_jpeg_gain_map() {
+    let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF \
+                xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"><rdf:Description \
+                xmlns:hdrgm=\"http://ns.adobe.com/hdr-gain-map/1.0/\" hdrgm:Version=\"1.0\"/>\
+                </rdf:RDF></x:xmpmeta>";
+
+    let gain_map = build_jpeg(&[(0xe1, &xmp[..])], 4, 2);
+    // MP header, index IFD with a single MP Entry tag and two MP entries
+    let mut mpf = Vec::new();
+    mpf.extend_from_slice(b"MPF\0MM\0\x2a\0\0\0\x08\0\x01");
+    mpf.extend_from_slice(&[0xb0, 0x02, 0x00, 0x07, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x1a]);
+    mpf.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    mpf.extend_from_slice(&[0x20, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    mpf.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+    let mut data = build_jpeg(&[(0xe1, &xmp[..]), (0xe2, &mpf)], 16, 8);
+    // fill in sizes and the offset of the gain map relative to the MP header
+    let mpf_offset = data.windows(4).position(|w| w == b"MPF\0").unwrap() + 4;
+    let entries = mpf_offset + 8 + 2 + 12 + 4;
+    let (primary_len, gain_map_len) = (data.len() as u32, gain_map.len() as u32);
+    data[entries + 4..entries + 8].copy_from_slice(&primary_len.to_be_bytes());
+    data[entries + 20..entries + 24].copy_from_slice(&gain_map_len.to_be_bytes());
+    data[entries + 24..entries + 28].copy_from_slice(&(primary_len - mpf_offset as u32).to_be_bytes());
+    data.extend_from_slice(&gain_map);
+
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.dimensions, Dimensions { width: 16, height: 8 });
+    assert_eq!(md.mpf_images.len(), 2);
+    assert_eq!(md.mpf_images[1].offset, primary_len as u64);
+    assert!(md.has_gain_map());
+    assert_eq!(md.gain_map, Some(jpeg::GainMap {
+        version: Some("1.0".into()),
+        mpf_index: Some(1),
+        dimensions: Some(Dimensions { width: 4, height: 2 }),
+    }));
+
+    assert_eq!(jpeg::parse_bytes(&data).unwrap(), md);
+
+    // the gain map image follows the primary image, so it is reachable in a forward-only stream
+    let from_reader = immeta::load_from_reader(&data[..]).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(from_reader, md);
+
+    // without seeking the gain map image itself is not inspected
+    let md = <jpeg::Metadata as immeta::LoadableMetadata>::load(&mut &data[..]).unwrap();
+    assert!(md.has_gain_map());
+    assert_eq!(md.gain_map.unwrap().dimensions, None);
+}
+
+#[test]
+fn test_jpeg_precision() {
+    // SOI, then SOF1 with 12-bit precision and a single component
+    let data = [
+        0xff, 0xd8,
+        0xff, 0xc1, 0x00, 0x0b, 0x0c, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00,
+    ];
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.precision, 12);
+    assert_eq!(md.estimated_decoded_size(), 8 * 8 * 2);
+}
+
+#[test]
+fn test_jpeg_frame_types() {
+    for &(marker, frame_type) in &[(0xc3, jpeg::FrameType::Sof3), (0xc7, jpeg::FrameType::Sof7),
+                                   (0xcd, jpeg::FrameType::Sof13), (0xce, jpeg::FrameType::Sof14)] {
+        let data = [
+            0xff, 0xd8,
+            0xff, marker, 0x00, 0x0b, 0x08, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00,
+        ];
+        let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+        assert_eq!(md.frame_type(), frame_type);
+        assert_eq!(md.frame_type().marker(), marker);
+        assert_eq!(md.is_lossless(), marker & 0x03 == 3);
+        assert_eq!(md.is_hierarchical(), marker & 0x04 != 0);
+    }
+}
+
+#[test]
+fn test_jpeg_arithmetic_coding() {
+    // SOI, then SOF9 with a single component
+    let data = [
+        0xff, 0xd8,
+        0xff, 0xc9, 0x00, 0x0b, 0x08, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00,
+    ];
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.coding_process, jpeg::CodingProcess::ExtendedSequential);
+    assert_eq!(md.entropy_coding, jpeg::EntropyCoding::Arithmetic);
+    assert!(md.is_arithmetic_coded());
+    assert_eq!(md.frame_type(), jpeg::FrameType::Sof9);
+
+    // SOI, SOF0, then DAC followed by SOS
+    let data = [
+        0xff, 0xd8,
+        0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00,
+        0xff, 0xcc, 0x00, 0x04, 0x00, 0x10,
+        0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00,
+    ];
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.entropy_coding, jpeg::EntropyCoding::Huffman);
+    assert!(md.arithmetic_conditioning);
+    assert!(md.is_arithmetic_coded());
+}
+
+#[test]
+fn test_jpeg_adobe_color_space() {
+    fn load(transform: u8) -> jpeg::Metadata {
+        // SOI, APP14 "Adobe" segment, then SOF0 with four components
+        let data = [
+            0xff, 0xd8,
+            0xff, 0xee, 0x00, 0x0e, b'A', b'd', b'o', b'b', b'e', 0x00, 0x64, 0x00, 0x00, 0x00, 0x00, transform,
+            0xff, 0xc0, 0x00, 0x14, 0x08, 0x00, 0x08, 0x00, 0x08, 0x04,
+            0x01, 0x11, 0x00, 0x02, 0x11, 0x00, 0x03, 0x11, 0x00, 0x04, 0x11, 0x00,
+        ];
+        immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata")
+    }
+
+    let md = load(0);
+    assert_eq!(md.components, 4);
+    assert_eq!(md.color_space, jpeg::ColorSpace::Cmyk);
+    assert_eq!(md.estimated_decoded_size(), 8 * 8 * 4);
+    assert_eq!(load(2).color_space, jpeg::ColorSpace::Ycck);
 }
 
 #[test]
@@ -38,6 +404,12 @@ fn test_png() {
     assert_eq!(md.mime_type(), "image/png");
     assert_eq!(md.dimensions(), OWLET_DIM);
 
+    assert!(md.as_png().is_some());
+    assert!(md.as_jpeg().is_none());
+    assert!(jpeg::Metadata::try_from(md.clone()).is_err());
+    assert_eq!(<&png::Metadata>::try_from(&md).ok(), md.as_png());
+    assert_eq!(png::Metadata::try_from(md.clone()).ok().as_ref(), md.as_png());
+
     let md = md.into::<Png>().ok().expect("not PNG metadata");
     assert_eq!(md.dimensions, OWLET_DIM);
     assert_eq!(md.color_type, png::ColorType::Rgb);
@@ -45,6 +417,397 @@ fn test_png() {
     assert_eq!(md.compression_method, png::CompressionMethod::DeflateInflate);
     assert_eq!(md.filter_method, png::FilterMethod::AdaptiveFiltering);
     assert_eq!(md.interlace_method, png::InterlaceMethod::Disabled);
+    assert_eq!(md.estimated_decoded_size(), 1280 * 857 * 3);
+    assert_eq!(md.gamma, Some(45455));
+    assert_eq!(md.gamma_value(), Some(0.45455));
+    assert_eq!(md.chromaticities, Some(png::Chromaticities {
+        white_point: (31270, 32900),
+        red: (64000, 33000),
+        green: (30000, 60000),
+        blue: (15000, 6000),
+    }));
+    assert_eq!(md.srgb_intent, None);
+    assert!(!md.is_srgb());
+    assert_eq!(md.background_color, Some(png::BackgroundColor::Rgb(255, 255, 255)));
+    assert_eq!(md.significant_bits, None);
+    assert!(!md.truncated);
+    assert_eq!(md.image_data_size, 1683147);
+    assert_eq!(md.zlib_header, Some(png::ZlibHeader {
+        window_size: 32768,
+        compression_level: png::CompressionLevel::Maximum,
+        preset_dictionary: false,
+    }));
+    assert_eq!(md.raw_data_size(), 857 * (1280 * 3 + 1));
+    assert!(md.compression_ratio().unwrap() > 1.9);
+}
+
+/// Builds a PNG stream consisting of the signature, IHDR of an 8-bit RGB image with the given
+/// size, the provided chunks and IEND. CRCs are not computed.
+fn build_png(chunks: &[(&[u8; 4], &[u8])], width: u8, height: u8) -> Vec<u8> {
+    let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+    let ihdr = [0, 0, 0, width, 0, 0, 0, height, 8, 2, 0, 0, 0];
+    for &(chunk_type, payload) in [(b"IHDR", &ihdr[..])].iter().chain(chunks).chain(&[(b"IEND", &[][..])]) {
+        let len = payload.len();
+        data.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+        data.extend_from_slice(chunk_type);
+        data.extend_from_slice(payload);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+    }
+    data
+}
+
+#[test]
+fn test_png_text() {
+    let md = immeta::load_from_file("tests/images/owlet.png").unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.text_chunks.len(), 2);
+    assert_eq!(md.text_chunks[0].kind, png::TextChunkKind::Text);
+    assert_eq!(md.text_chunks[0].keyword, "date:create");
+    assert_eq!(md.text("date:modify"), Some("2015-06-13T00:47:18+03:00"));
+
+    let ztxt = [
+        &b"Comment\0\0"[..],
+        &[0x78, 0x9c, 0x73, 0xce, 0xcf, 0x2d, 0x28, 0x4a, 0x2d, 0x2e, 0x4e, 0x4d, 0x51, 0x28, 0x49,
+          0xad, 0x28, 0x01, 0x00, 0x2f, 0x70, 0x05, 0xfb],
+    ].concat();
+    let itxt = "Title\0\0\0de\0Titel\0Überschrift".as_bytes();
+    let itxt_compressed = [
+        &b"Description\x00\x01\x00\x00\x00"[..],
+        &[0x78, 0x9c, 0x3b, 0x3c, 0x27, 0xef, 0xf0, 0xfa, 0xe4, 0xc3, 0xdb, 0x52, 0x0e, 0xaf, 0x04,
+          0x00, 0x29, 0x97, 0x06, 0xec],
+    ].concat();
+    let data = build_png(&[
+        (b"tEXt", b"Software\0immeta \xa9"),
+        (b"zTXt", &ztxt),
+        (b"IDAT", &[0x78, 0x9c]),
+        (b"iTXt", itxt),
+        (b"iTXt", &itxt_compressed),
+        (b"tEXt", b"no separator"),
+    ], 4, 4);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.text_chunks.len(), 4);
+    assert_eq!(md.text("Software"), Some("immeta \u{a9}"));
+
+    assert_eq!(md.text_chunks[1].kind, png::TextChunkKind::CompressedText);
+    assert!(md.text_chunks[1].compressed);
+    if cfg!(feature = "zlib") {
+        assert_eq!(md.text("Comment"), Some("Compressed text"));
+        assert_eq!(md.text("Description"), Some("Ünïcödé"));
+    } else {
+        assert_eq!(md.text_chunks[1].text, None);
+        assert_eq!(md.text("Description"), None);
+    }
+
+    assert_eq!(md.text_chunks[2], png::TextChunk {
+        kind: png::TextChunkKind::InternationalText,
+        keyword: "Title".into(),
+        compressed: false,
+        language_tag: Some("de".into()),
+        translated_keyword: Some("Titel".into()),
+        text: Some("Überschrift".into()),
+    });
+    assert!(md.text_chunks[3].compressed);
+    assert_eq!(md.text_chunks[3].language_tag, Some("".into()));
+}
+
+#[test]
+fn test_png_lazy_text_chunks() {
+    use std::io::Cursor;
+    use immeta::Immeta;
+
+    let data = build_png(&[
+        (b"tEXt", b"Software\0immeta"),
+        (b"IDAT", &[0x78, 0x9c]),
+        (b"iTXt", "Title\0\0\0de\0Titel\0Überschrift".as_bytes()),
+        (b"tEXt", b"no separator"),
+    ], 4, 4);
+    let eager = immeta::load_from_buf(&data).unwrap();
+
+    let mut md = Immeta::new().lazy_extensions(true).load_from_buf(&data).unwrap();
+    {
+        let png = md.as_ref::<Png>().unwrap();
+        assert!(png.text_chunks.is_empty());
+        assert_eq!(png.pending_text_chunks.len(), 3);
+        assert_eq!(png.pending_text_chunks[0], png::TextChunkLocation {
+            chunk_type: *b"tEXt",
+            offset: 41,
+            len: 15,
+        });
+    }
+
+    md.load_extensions(&mut Cursor::new(&data)).unwrap();
+    assert!(md.as_ref::<Png>().unwrap().pending_text_chunks.is_empty());
+    assert_eq!(md, eager);
+
+    // the truncated stream can't provide the text
+    let mut md = Immeta::new().lazy_extensions(true).load_from_buf(&data).unwrap();
+    assert!(md.load_extensions(&mut Cursor::new(&data[..60])).is_err());
+    assert_eq!(md.as_ref::<Png>().unwrap().pending_text_chunks.len(), 2);
+}
+
+#[test]
+fn test_png_srgb() {
+    let data = build_png(&[(b"sRGB", &[0]), (b"gAMA", &[0, 0])], 1, 1);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.srgb_intent, Some(png::RenderingIntent::Perceptual));
+    assert!(md.is_srgb());
+    assert_eq!(md.gamma, None);
+    assert_eq!(md.chromaticities, None);
+}
+
+#[test]
+fn test_png_hdr() {
+    let mdcv = [
+        0x84, 0xd0, 0x3e, 0x80, 0x33, 0x90, 0x86, 0xc4, 0x1d, 0x4c, 0x0b, 0xb8, 0x3d, 0x13, 0x40, 0x42,
+        0x00, 0x98, 0x96, 0x80, 0x00, 0x00, 0x00, 0x01,
+    ];
+    let data = build_png(&[
+        (b"cICP", &[9, 16, 0, 1]),
+        (b"mDCv", &mdcv),
+        (b"cLLi", &[0x00, 0x98, 0x96, 0x80, 0x00, 0x3d, 0x09, 0x00]),
+    ], 1, 1);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.cicp, Some(png::Cicp {
+        color_primaries: 9,
+        transfer_function: 16,
+        matrix_coefficients: 0,
+        full_range: true,
+    }));
+    assert!(md.is_hdr());
+    assert_eq!(md.mastering_display, Some(png::MasteringDisplayColorVolume {
+        primaries: [(34000, 16000), (13200, 34500), (7500, 3000)],
+        white_point: (15635, 16450),
+        max_luminance: 10000000,
+        min_luminance: 1,
+    }));
+    assert_eq!(md.content_light_level, Some(png::ContentLightLevel {
+        max_content_light_level: 10000000,
+        max_frame_average_light_level: 4000000,
+    }));
+
+    let md = immeta::load_from_file("tests/images/owlet.png").unwrap().into::<Png>().ok().unwrap();
+    assert!(!md.is_hdr());
+    assert_eq!(md.cicp, None);
+}
+
+#[test]
+fn test_png_offset_and_scale() {
+    let data = build_png(&[
+        (b"oFFs", &[0xff, 0xff, 0xff, 0xf6, 0x00, 0x00, 0x00, 0x14, 0x01]),
+        (b"sCAL", b"\x010.25\x001.5e-3"),
+    ], 1, 1);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.offset, Some(png::ImageOffset { x: -10, y: 20, unit: png::OffsetUnit::Micrometer }));
+    let scale = md.physical_scale.unwrap();
+    assert_eq!(scale.unit, png::ScaleUnit::Meter);
+    assert_eq!(scale.width, "0.25");
+    assert_eq!(scale.width_value(), Some(0.25));
+    assert_eq!(scale.height_value(), Some(0.0015));
+}
+
+#[test]
+fn test_png_interlaced_raw_size() {
+    let mut data = build_png(&[(b"IDAT", &[0x78])], 9, 3);
+    // switch to 1-bit grayscale with Adam7 interlacing
+    data[8 + 8 + 8] = 1;
+    data[8 + 8 + 9] = 0;
+    data[8 + 8 + 12] = 1;
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.image_data_size, 1);
+    assert_eq!(md.zlib_header, None);
+    // passes are 2x1, 1x1, 3x0, 2x1, 5x1, 4x2 and 9x1 pixels
+    assert_eq!(md.raw_data_size(), 2 + 2 + 0 + 2 + 2 + 4 + 3);
+}
+
+#[test]
+fn test_png_color_type() {
+    use png::ColorType;
+
+    assert_eq!(ColorType::try_from(6).unwrap(), ColorType::RgbAlpha);
+    assert!(ColorType::try_from(5).is_err());
+    assert_eq!(u8::from(ColorType::Indexed), 3);
+
+    assert_eq!(ColorType::Indexed.samples(), 1);
+    assert_eq!(ColorType::Indexed.channels(), 4);
+    assert!(ColorType::GrayscaleAlpha.has_alpha());
+    assert!(!ColorType::Indexed.has_alpha());
+    assert_eq!(ColorType::RgbAlpha.bits_per_pixel(16), Some(64));
+    assert_eq!(ColorType::Grayscale.bits_per_pixel(2), Some(2));
+    assert_eq!(ColorType::Rgb.bits_per_pixel(4), None);
+    assert_eq!(ColorType::Indexed.bits_per_pixel(16), None);
+
+    let mut data = build_png(&[(b"PLTE", &[0; 6])], 3, 3);
+    // switch to 2-bit indexed colors
+    data[8 + 8 + 8] = 2;
+    data[8 + 8 + 9] = 3;
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.color_type, ColorType::Indexed);
+    assert_eq!(md.color_depth, 2);
+    assert_eq!(md.bit_depth(), 2);
+
+    let data = build_png(&[], 3, 3);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.color_depth, 24);
+    assert_eq!(md.bit_depth(), 8);
+}
+
+#[test]
+fn test_png_significant_bits() {
+    let data = build_png(&[(b"sBIT", &[5, 6, 5]), (b"bKGD", &[0, 1])], 1, 1);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.significant_bits, Some(png::SignificantBits::Rgb(5, 6, 5)));
+    // bKGD of an RGB image must contain three samples
+    assert_eq!(md.background_color, None);
+}
+
+#[test]
+fn test_png_apng() {
+    use immeta::animation::{Animated, Blend, Disposal, FrameInfo, LoopCount};
+
+    let fctl = |seq: u8, x: u8, dispose: u8, blend: u8| vec![
+        0, 0, 0, seq, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, x, 0, 0, 0, 1, 0, 1, 0, 10, dispose, blend,
+    ];
+    let data = build_png(&[
+        (b"acTL", &[0, 0, 0, 2, 0, 0, 0, 3]),
+        (b"fcTL", &fctl(0, 0, 0, 0)),
+        (b"IDAT", &[0x78, 0x9c]),
+        (b"fcTL", &fctl(1, 2, 2, 1)),
+        (b"fdAT", &[0, 0, 0, 2, 0x78, 0x9c]),
+    ], 4, 4);
+    let generic = immeta::load_from_buf(&data).unwrap();
+    assert_eq!(generic.animation().map(|a| a.is_animated()), Some(true));
+    assert_eq!(generic.estimated_decoded_size(), 4 * 4 * 3 * 2);
+
+    let md = generic.into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.animation_control, Some(png::AnimationControl { num_frames: 2, num_plays: 3 }));
+    assert!(md.default_image_is_first_frame);
+    assert_eq!(md.frame_controls.len(), 2);
+    assert_eq!(md.frame_controls[1].sequence_number, 1);
+    assert_eq!(md.frame_controls[1].delay_ms(), 100);
+    assert!(md.is_animated());
+    assert_eq!(md.loop_count(), Some(LoopCount::Finite(3)));
+    assert_eq!(md.frames()[1], FrameInfo {
+        left: 2,
+        top: 1,
+        dimensions: Dimensions { width: 2, height: 3 },
+        delay_ms: 100,
+        disposal: Disposal::Previous,
+        blend: Blend::Over,
+    });
+    assert_eq!(md.total_duration_ms(), 200);
+
+    let mut frames = Vec::new();
+    assert_eq!(png::for_each_frame(&mut &data[..], |f| { frames.push(f); true }).unwrap(), 2);
+    assert_eq!(frames, md.frames());
+    assert_eq!(png::for_each_frame(&mut &data[..], |_| false).unwrap(), 1);
+
+    let md = immeta::load_from_file("tests/images/owlet.png").unwrap();
+    let animation = md.animation().unwrap();
+    assert!(!animation.is_animated());
+    assert_eq!(animation.loop_count(), None);
+    assert!(animation.frames().is_empty());
+    let mut f = std::fs::File::open("tests/images/owlet.png").map(std::io::BufReader::new).unwrap();
+    assert_eq!(png::for_each_frame(&mut f, |_| true).unwrap(), 0);
+}
+
+#[test]
+fn test_png_validate() {
+    use std::fs::File;
+    use std::io::BufReader;
+    use png::{Violation, ViolationKind};
+
+    let f = BufReader::new(File::open("tests/images/owlet.png").unwrap());
+    assert_eq!(png::validate(f).unwrap(), vec![]);
+
+    // indexed image with bKGD before PLTE, gAMA after it, interleaved IDAT and trailing data
+    let mut data = build_png(&[
+        (b"bKGD", &[0]),
+        (b"PLTE", &[0, 0, 0]),
+        (b"gAMA", &[0, 0, 0xb1, 0x8f]),
+        (b"IDAT", &[]),
+        (b"tEXt", b"a\0b"),
+        (b"IDAT", &[]),
+        (b"PLTE", &[0, 0, 0]),
+        (b"ABcD", &[]),
+    ], 1, 1);
+    data[8 + 8 + 9] = 3;
+    data.extend_from_slice(b"junk");
+    let violations = png::validate(&data[..]).unwrap();
+    let kinds: Vec<_> = violations.iter().map(|v| v.kind).collect();
+    assert_eq!(kinds, vec![
+        ViolationKind::ChunkBeforePlte,
+        ViolationKind::ChunkAfterPlte,
+        ViolationKind::NonConsecutiveIdat,
+        ViolationKind::DuplicateChunk,
+        ViolationKind::ChunkAfterIdat,
+        ViolationKind::ReservedBitSet,
+        ViolationKind::UnknownCriticalChunk,
+        ViolationKind::DataAfterIend,
+    ]);
+    assert_eq!(violations[0], Violation {
+        kind: ViolationKind::ChunkBeforePlte,
+        offset: 33,
+        chunk_type: Some(*b"bKGD"),
+    });
+    assert_eq!(violations[0].to_string(), "chunk must follow PLTE (bKGD at offset 33)");
+
+    let data = &build_png(&[], 1, 1)[..33];
+    let kinds: Vec<_> = png::validate(data).unwrap().into_iter().map(|v| v.kind).collect();
+    assert_eq!(kinds, vec![ViolationKind::MissingIdat, ViolationKind::MissingIend]);
+}
+
+#[test]
+fn test_png_truncated() {
+    use std::fs::File;
+    use std::io::{Cursor, Read};
+
+    let mut data = Vec::new();
+    File::open("tests/images/owlet.png").unwrap().read_to_end(&mut data).unwrap();
+    assert!(png::ends_with_iend(&mut Cursor::new(&data)).unwrap());
+
+    for &len in &[data.len() - 2, data.len() - 12, 200000] {
+        let md = immeta::load_from_buf(&data[..len]).unwrap().into::<Png>().ok().expect("not PNG metadata");
+        assert!(md.truncated);
+        let mut cursor = Cursor::new(&data[..len]);
+        cursor.set_position(5);
+        assert!(!png::ends_with_iend(&mut cursor).unwrap());
+        assert_eq!(cursor.position(), 5);
+    }
+}
+
+#[test]
+fn test_png_chunks() {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let f = BufReader::new(File::open("tests/images/owlet.png").unwrap());
+    let mut chunks = png::Chunks::new(f);
+    let mut types = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        let mut chunk = chunk.unwrap();
+        if &chunk.chunk_type() == b"gAMA" {
+            assert_eq!(chunk.offset(), 33);
+            assert_eq!(chunk.len(), 4);
+            assert!(!chunk.is_critical());
+            assert!(!chunk.is_private());
+            assert_eq!(chunk.read_payload().unwrap(), vec![0x00, 0x00, 0xb1, 0x8f]);
+            assert_eq!(chunk.crc().unwrap(), 0x0bfc6105);
+        }
+        if &chunk.chunk_type() == b"IEND" {
+            assert_eq!(chunk.offset(), 1683980);
+            assert_eq!(chunk.crc().unwrap(), 0xae426082);
+        }
+        types.push(chunk.type_str());
+    }
+
+    assert_eq!(types.len(), 59);
+    assert_eq!(&types[..5], &["IHDR", "gAMA", "cHRM", "bKGD", "IDAT"]);
+    assert_eq!(types.iter().filter(|t| *t == "tEXt").count(), 2);
+    assert_eq!(types.last().map(|t| &t[..]), Some("IEND"));
+    assert_eq!(chunks.position(), 1683992);
+
+    let mut chunks = png::Chunks::new(&b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHD"[..]);
+    assert!(chunks.next().unwrap().is_err());
+    assert!(chunks.next().is_none());
 }
 
 #[test]
@@ -66,6 +829,7 @@ fn test_gif_plain() {
     assert_eq!(md.pixel_aspect_ratio, 0);
     assert_eq!(md.frames_number(), 1);
     assert_eq!(md.is_animated(), false);
+    assert_eq!(md.loop_count, None);
     assert_eq!(md.blocks, vec![
         gif::Block::GraphicControlExtension(gif::GraphicControlExtension {
             disposal_method: gif::DisposalMethod::None,
@@ -101,10 +865,15 @@ fn test_gif_animated() {
         sorted: false
     }));
     assert_eq!(md.color_resolution, 128);
+    assert_eq!(md.color_resolution_bits(), 7);
+    assert_eq!(md.local_color_tables_number(), 0);
+    assert_eq!(md.frame_color_table_sizes(), vec![Some(256); 30]);
     assert_eq!(md.background_color_index, 255);
     assert_eq!(md.pixel_aspect_ratio, 0);
     assert_eq!(md.frames_number(), 30);
     assert_eq!(md.is_animated(), true);
+    assert_eq!(md.estimated_decoded_size(), 238 * 212 * 4 * 30);
+    assert_eq!(md.loop_count, Some(0));
 
     let mut blocks = md.blocks.iter();
 
@@ -157,6 +926,222 @@ fn test_gif_animated() {
     assert!(blocks.next().is_none());
 }
 
+#[test]
+fn test_gif_frames() {
+    use immeta::animation::{Animated, Disposal};
+
+    let md = immeta::load_from_file("tests/images/drop.gif").unwrap();
+    assert!(md.animation().unwrap().is_animated());
+
+    let md = md.into::<Gif>().ok().expect("not GIF metadata");
+    let frames: Vec<_> = md.frame_iter().collect();
+    assert_eq!(frames.len(), 30);
+    assert_eq!(frames[3].index, 3);
+    assert_eq!(frames[3].delay_time_ms(), 70);
+    assert_eq!(frames[3].disposal_method(), gif::DisposalMethod::DoNotDispose);
+    assert_eq!(frames[29].disposal_method(), gif::DisposalMethod::None);
+    assert!(frames[0].is_transparent());
+    assert_eq!(frames[0].transparent_color_index(), Some(255));
+    assert!(md.has_transparency());
+    assert!(!frames[0].has_local_color_table());
+    assert_eq!(frames[0].descriptor.width, 238);
+
+    let infos = md.frames();
+    assert_eq!(infos.len(), 30);
+    assert_eq!(infos[0].dimensions, DROP_DIM);
+    assert_eq!(infos[0].disposal, Disposal::None);
+    assert_eq!(md.total_duration_ms(), 30 * 70);
+    assert_eq!(md.duration_ms(), 30 * 70);
+    assert_eq!(md.normalized_duration_ms(), 30 * 70);
+    assert_eq!(md.loop_count(), Some(immeta::animation::LoopCount::Infinite));
+}
+
+#[test]
+fn test_gif_loop_count() {
+    use immeta::animation::{Animated, LoopCount};
+
+    let mut data = b"GIF89a\x01\x00\x01\x00\x00\x00\x00".to_vec();
+    data.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x02\x00\x00");
+    data.extend_from_slice(&[0x2c, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0x02, 0x01, 0x44, 0x00]);
+    // the second frame has a local color table of four entries
+    data.extend_from_slice(&[0x2c, 0, 0, 0, 0, 1, 0, 1, 0, 0x81]);
+    data.extend_from_slice(&[0; 12]);
+    data.extend_from_slice(&[0x02, 0x01, 0x44, 0x00, 0x3b]);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Gif>().ok().expect("not GIF metadata");
+    assert_eq!(md.loop_count, Some(2));
+    assert_eq!(md.color_resolution_bits(), 1);
+    assert_eq!(md.local_color_tables_number(), 1);
+    assert!(!md.has_transparency());
+    assert_eq!(md.frame_color_table_sizes(), vec![None, Some(4)]);
+    assert_eq!(Animated::loop_count(&md), Some(LoopCount::Finite(3)));
+    // the frames have no graphic control extension, so they have zero delay
+    assert_eq!(md.duration_ms(), 0);
+    assert_eq!(md.normalized_duration_ms(), 200);
+}
+
+#[test]
+fn test_gif_plain_text() {
+    let mut data = b"GIF89a\x40\x00\x10\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff".to_vec();
+    data.extend_from_slice(&[0x21, 0x01, 0x0c, 4, 0, 2, 0, 48, 0, 12, 0, 8, 12, 1, 0]);
+    data.extend_from_slice(b"\x06Hello,\x06 world\x00");
+    data.push(0x3b);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Gif>().ok().expect("not GIF metadata");
+    assert!(md.has_plain_text());
+    assert_eq!(md.frames_number(), 0);
+    match md.blocks[0] {
+        gif::Block::PlainTextExtension(ref pte) => {
+            assert_eq!((pte.left, pte.top, pte.width, pte.height), (4, 2, 48, 12));
+            assert_eq!((pte.cell_width, pte.cell_height), (8, 12));
+            assert_eq!(pte.grid_size(), (6, 1));
+            assert_eq!(pte.foreground_color_index, 1);
+            assert_eq!(pte.text_str(), Some("Hello, world"));
+        }
+        ref b => panic!("unexpected block: {:?}", b)
+    }
+
+    let md = immeta::load_from_file("tests/images/drop.gif").unwrap().into::<Gif>().ok().unwrap();
+    assert!(!md.has_plain_text());
+}
+
+#[test]
+fn test_gif_count_frames() {
+    use std::fs::File;
+    use std::io::{BufReader, Read};
+
+    let mut f = BufReader::new(File::open("tests/images/drop.gif").unwrap());
+    assert_eq!(gif::count_frames(&mut f).unwrap(), 30);
+    let mut f = BufReader::with_capacity(100, File::open("tests/images/owlet.gif").unwrap());
+    assert_eq!(gif::count_frames(&mut f).unwrap(), 1);
+
+    let mut data = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut data).unwrap();
+    let len = data.len();
+    assert!(gif::count_frames(&mut &data[..len - 1]).is_err());
+    assert!(gif::count_frames(&mut &data[..len / 2]).is_err());
+    assert!(gif::count_frames(&mut &b"GIF89b"[..]).is_err());
+}
+
+#[test]
+fn test_gif_for_each_frame() {
+    use std::fs::File;
+    use std::io::Read;
+    use immeta::animation::Animated;
+
+    let mut data = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut data).unwrap();
+    let md = gif::parse_bytes(&data).unwrap();
+
+    let mut frames = Vec::new();
+    assert_eq!(gif::for_each_frame(&mut &data[..], |f| { frames.push(f); true }).unwrap(), 30);
+    assert_eq!(frames, md.frames());
+
+    // stopping after the second frame leaves the rest of the stream unread
+    let mut r = &data[..];
+    let mut frames = Vec::new();
+    assert_eq!(gif::for_each_frame(&mut r, |f| { frames.push(f); frames.len() < 2 }).unwrap(), 2);
+    assert_eq!(&frames[..], &md.frames()[..2]);
+    assert!(r.len() > data.len() / 2);
+
+    // frames of a truncated stream are visited up to the point of truncation
+    let mut count = 0;
+    assert!(gif::for_each_frame(&mut &data[..data.len() / 2], |_| { count += 1; true }).is_err());
+    assert!(count > 0 && count < 30);
+    assert!(gif::for_each_frame(&mut &b"GIF89b"[..], |_| true).is_err());
+}
+
+#[test]
+fn test_gif_screen_descriptor() {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let mut f = BufReader::new(File::open("tests/images/owlet.gif").unwrap());
+    let sd = gif::read_screen_descriptor(&mut f).unwrap();
+    let md = immeta::load_from_file("tests/images/owlet.gif").unwrap().into::<Gif>().ok().unwrap();
+    assert_eq!(sd, md.screen_descriptor());
+    assert_eq!(sd.version.to_string(), "GIF89a");
+    assert_eq!(sd.background_color(), Some(0));
+
+    // GIF87a with a 2:1 pixel aspect ratio, background color 3 and no global color table
+    let data = b"GIF87a\x10\0\x08\0\x00\x03\x71;";
+    let sd = gif::read_screen_descriptor(&mut &data[..]).unwrap();
+    assert_eq!(sd.version, gif::Version::V87a);
+    assert_eq!(sd.version.as_str(), "87a");
+    assert_eq!(sd.dimensions, (16u16, 8u16).into());
+    assert_eq!(sd.global_color_table, None);
+    assert_eq!(sd.background_color_index, 3);
+    assert_eq!(sd.background_color(), None);
+    assert_eq!(sd.pixel_aspect_ratio_approx(), Some(2.0));
+
+    assert!(gif::read_screen_descriptor(&mut &b"GIF89a\x10\0\x08\0\0"[..]).is_err());
+    assert!(gif::read_screen_descriptor(&mut &b"PNG89a\x10\0\x08\0\0\0\0"[..]).is_err());
+}
+
+#[test]
+fn test_gif_validate() {
+    use std::fs::File;
+    use std::io::{BufReader, Read};
+    use immeta::formats::gif::{Violation, ViolationKind};
+
+    let f = BufReader::new(File::open("tests/images/drop.gif").unwrap());
+    assert_eq!(gif::validate(f).unwrap(), vec![]);
+
+    let mut data = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut data).unwrap();
+    let len = data.len();
+    assert_eq!(gif::validate(&data[..len - 1]).unwrap(), vec![
+        Violation { kind: ViolationKind::MissingTrailer, offset: len as u64 - 1 }
+    ]);
+    let violations = gif::validate(&data[..len / 2]).unwrap();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].kind, ViolationKind::TruncatedBlock);
+    assert!(gif::validate(&b"GIF89b"[..]).is_err());
+
+    let mut data = b"GIF89a\x02\x00\x02\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff".to_vec();
+    // frame at (1, 1) of size 2x1, which does not fit into the 2x2 screen
+    data.extend_from_slice(&[0x2c, 1, 0, 1, 0, 2, 0, 1, 0, 0, 0x02, 0x01, 0x44, 0x00]);
+    // unknown extension
+    data.extend_from_slice(&[0x21, 0x99, 0x01, 0x00, 0x00]);
+    // graphic control extension with invalid size
+    data.extend_from_slice(&[0x21, 0xf9, 0x03, 0x00, 0x00, 0x00, 0x00]);
+    // frame without color table and with invalid LZW code size
+    data.extend_from_slice(&[0x2c, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0x0c, 0x00]);
+    data.extend_from_slice(b"\x3bxyz");
+    let violations = gif::validate(&data[..]).unwrap();
+    assert_eq!(violations, vec![
+        Violation { kind: ViolationKind::FrameOutsideScreen, offset: 19 },
+        Violation { kind: ViolationKind::UnknownExtension, offset: 33 },
+        Violation { kind: ViolationKind::InvalidBlockSize, offset: 38 },
+        Violation { kind: ViolationKind::EmptyFrame, offset: 45 },
+        Violation { kind: ViolationKind::InvalidLzwCodeSize, offset: 45 },
+        Violation { kind: ViolationKind::DataAfterTrailer, offset: 58 },
+    ]);
+    assert_eq!(violations[0].to_string(), "frame exceeds logical screen (at offset 19)");
+
+    // remove the global color table
+    data[10] = 0;
+    data.drain(13..19);
+    let violations = gif::validate(&data[..]).unwrap();
+    assert!(violations.contains(&Violation { kind: ViolationKind::MissingColorTable, offset: 13 }));
+}
+
+#[test]
+fn test_gif_xmp() {
+    let packet: &[u8] = b"<x:xmpmeta xmlns:x='adobe:ns:meta/' xmp:Rating='3'/>";
+    let mut data = b"GIF89a\x01\x00\x01\x00\x00\x00\x00".to_vec();
+    data.extend_from_slice(b"\x21\xff\x0bXMP DataXMP");
+    data.extend_from_slice(packet);
+    data.push(0x01);
+    data.extend((0..256).rev().map(|b| b as u8));
+    data.push(0x00);
+    data.extend_from_slice(&[0x2c, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0x02, 0x01, 0x44, 0x00, 0x3b]);
+
+    let md = immeta::load_from_buf(&data).unwrap().into::<Gif>().ok().expect("not GIF metadata");
+    let xmp = md.xmp.as_ref().expect("no XMP packet");
+    assert_eq!(xmp.as_str().as_bytes(), packet);
+    assert_eq!(xmp.property("xmp:Rating"), Some("3"));
+    assert_eq!(md.frames_number(), 1);
+}
+
 #[test]
 fn test_webp() {
     let md = immeta::load_from_file("tests/images/cherry.webp").unwrap();
@@ -165,6 +1150,1850 @@ fn test_webp() {
     assert_eq!(md.dimensions(), CHERRY_DIM);
 
     let md = md.into::<Webp>().ok().expect("not WEBP metadata");
+    assert_eq!(md.flavor(), webp::Flavor::SimpleLossy);
 
     println!("{:?}", md);
 }
+
+#[test]
+fn test_webp_vp8x() {
+    let mut data = b"RIFF\x16\x00\x00\x00WEBPVP8X\x0a\x00\x00\x00".to_vec();
+    data.extend_from_slice(&[0x2c, 0, 0, 0, 0x3f, 0x01, 0x00, 0xff, 0xff, 0x00]);
+
+    let md = immeta::load_from_buf(&data).unwrap();
+    assert_eq!(md.dimensions(), Dimensions { width: 320, height: 65536 });
+    assert_eq!(md.as_webp().unwrap().flavor(), webp::Flavor::Extended);
+
+    let md = match md.into::<Webp>().ok().expect("not WEBP metadata") {
+        webp::Metadata::VP8X(md) => md,
+        md => panic!("not VP8X metadata: {:?}", md)
+    };
+    assert!(md.has_icc_profile);
+    assert!(!md.has_alpha);
+    assert!(md.has_exif);
+    assert!(md.has_xmp);
+    assert!(!md.has_animation);
+    assert_eq!(md.canvas_dimensions, Dimensions { width: 320, height: 65536 });
+
+    assert!(immeta::load_from_buf(&data[..25]).is_err());
+}
+
+#[test]
+fn test_webp_lossless() {
+    // 400x300 image with alpha: (399 | 299 << 14 | 1 << 28) in little-endian order
+    let data = build_webp(&[(b"VP8L", &[0x2f, 0x8f, 0xc1, 0x4a, 0x10, 0x00])]);
+
+    let md = immeta::load_from_buf(&data).unwrap();
+    assert_eq!(md.dimensions(), Dimensions { width: 400, height: 300 });
+    assert_eq!(md.estimated_decoded_size(), 400 * 300 * 4);
+
+    let md = md.into::<Webp>().ok().expect("not WEBP metadata");
+    assert_eq!(md.flavor(), webp::Flavor::SimpleLossless);
+    assert_eq!(md, webp::Metadata::VP8L(webp::VP8LMetadata {
+        dimensions: (400u32, 300u32).into(),
+        alpha_is_used: true,
+        version_number: 0
+    }));
+
+    let data = build_webp(&[(b"VP8L", &[0x2e, 0x8f, 0xc1, 0x4a, 0x10, 0x00])]);
+    assert!(immeta::load_from_buf(&data).is_err());
+    let data = build_webp(&[(b"VP8L", &[0x2f, 0x8f, 0xc1, 0x4a, 0x30, 0x00])]);
+    assert!(immeta::load_from_buf(&data).is_err());
+}
+
+fn build_webp(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut body = b"WEBP".to_vec();
+    for &(id, payload) in chunks {
+        let len = payload.len() as u32;
+        body.extend_from_slice(id);
+        body.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+        body.extend_from_slice(payload);
+    }
+    let len = body.len() as u32;
+    let mut data = b"RIFF".to_vec();
+    data.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+    data.extend_from_slice(&body);
+    data
+}
+
+#[test]
+fn test_webp_animation() {
+    use immeta::animation::{Animated, Blend, Disposal, FrameInfo, LoopCount};
+
+    let data = build_webp(&[
+        (b"VP8X", &[0x12, 0, 0, 0, 99, 0, 0, 49, 0, 0]),
+        (b"ANIM", &[0x10, 0x20, 0x30, 0xff, 3, 0]),
+        // 64x32 frame at (0, 0) shown for 80 ms
+        (b"ANMF", &[
+            0, 0, 0, 0, 0, 0, 63, 0, 0, 31, 0, 0, 80, 0, 0, 0x00,
+            b'A', b'L', b'P', b'H', 1, 0, 0, 0, 0xff, 0,
+            b'V', b'P', b'8', b'L', 5, 0, 0, 0, 0x2f, 0x3f, 0xc0, 0x07, 0x00, 0
+        ]),
+        // 20x10 frame at (10, 4) shown for 1000 ms without blending, disposed to background
+        (b"ANMF", &[5, 0, 0, 2, 0, 0, 19, 0, 0, 9, 0, 0, 0xe8, 0x03, 0, 0x03]),
+    ]);
+
+    let md = immeta::load_from_buf(&data).unwrap();
+    assert_eq!(md.dimensions(), Dimensions { width: 100, height: 50 });
+    {
+        let md = md.as_webp().unwrap();
+        assert_eq!(md.canvas_dimensions(), Dimensions { width: 100, height: 50 });
+        assert_eq!(md.frame_dimensions(), Some(Dimensions { width: 64, height: 32 }));
+    }
+    {
+        let animation = md.animation().expect("no animation info");
+        assert!(animation.is_animated());
+        assert_eq!(animation.frames(), vec![
+            FrameInfo {
+                left: 0, top: 0, dimensions: (64u32, 32u32).into(), delay_ms: 80,
+                disposal: Disposal::None, blend: Blend::Over
+            },
+            FrameInfo {
+                left: 10, top: 4, dimensions: (20u32, 10u32).into(), delay_ms: 1000,
+                disposal: Disposal::Background, blend: Blend::Source
+            },
+        ]);
+        assert_eq!(animation.total_duration_ms(), 1080);
+        assert_eq!(animation.loop_count(), Some(LoopCount::Finite(3)));
+
+        let mut frames = Vec::new();
+        assert_eq!(webp::for_each_frame(&mut &data[..], |f| { frames.push(f); true }).unwrap(), 2);
+        assert_eq!(frames, animation.frames());
+        assert_eq!(webp::for_each_frame(&mut &data[..], |_| false).unwrap(), 1);
+    }
+
+    let md = match md.into::<Webp>().ok().expect("not WEBP metadata") {
+        webp::Metadata::VP8X(md) => md,
+        md => panic!("not VP8X metadata: {:?}", md)
+    };
+    assert!(md.has_animation);
+    assert!(md.has_alpha);
+    assert_eq!(md.frames.len(), 2);
+    assert_eq!(md.frames[1].x_offset, 10);
+    assert_eq!(md.animation_parameters, Some(webp::AnimationParameters {
+        background_color: (0x30, 0x20, 0x10, 0xff),
+        loop_count: 3
+    }));
+
+    let md = immeta::load_from_file("tests/images/cherry.webp").unwrap();
+    assert!(!md.animation().expect("no animation info").is_animated());
+    assert_eq!(md.as_webp().unwrap().frame_dimensions(), Some(CHERRY_DIM));
+    let mut f = std::fs::File::open("tests/images/cherry.webp").map(std::io::BufReader::new).unwrap();
+    assert_eq!(webp::for_each_frame(&mut f, |_| true).unwrap(), 0);
+
+    let data = build_webp(&[
+        (b"VP8X", &[0x10, 0, 0, 0, 99, 0, 0, 49, 0, 0]),
+        (b"VP8L", &[0x2f, 0x3f, 0xc0, 0x07, 0x00, 0]),
+    ]);
+    let md = immeta::load_from_buf(&data).unwrap();
+    let md = md.as_webp().unwrap();
+    assert!(!md.is_animated());
+    assert_eq!(md.canvas_dimensions(), Dimensions { width: 100, height: 50 });
+    assert_eq!(md.frame_dimensions(), Some(Dimensions { width: 64, height: 32 }));
+}
+
+#[test]
+fn test_webp_chunks() {
+    use std::fs::File;
+    use std::io::BufReader;
+    use immeta::common::riff::ChunkId;
+
+    let mut chunks = webp::Chunks::new(BufReader::new(File::open("tests/images/cherry.webp").unwrap()));
+    {
+        let chunk = chunks.next().unwrap().unwrap();
+        assert_eq!(chunk.chunk_id(), ChunkId(*b"VP8 "));
+        assert_eq!(chunk.offset(), 12);
+        assert_eq!(chunk.len(), 176952);
+    }
+    assert!(chunks.next().is_none());
+    assert_eq!(chunks.position(), 176972);
+
+    let mut data = build_webp(&[
+        (b"VP8L", &[0x2f, 0x3f, 0xc0, 0x07, 0x00, 0]),
+        (b"ZZZ1", &[1, 2, 3, 0]),
+        (b"EXIF", &[]),
+    ]);
+    // shrink the private chunk to an odd length, keeping its padding byte
+    data[30] = 3;
+    data.extend_from_slice(b"trailing data");
+
+    let mut chunks = webp::Chunks::new(&data[..]);
+    let mut found = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        let mut chunk = chunk.unwrap();
+        found.push((chunk.chunk_id(), chunk.offset(), chunk.len(), chunk.read_payload().unwrap()));
+    }
+    assert_eq!(found, vec![
+        (ChunkId(*b"VP8L"), 12, 6, vec![0x2f, 0x3f, 0xc0, 0x07, 0x00, 0]),
+        (ChunkId(*b"ZZZ1"), 26, 3, vec![1, 2, 3]),
+        (ChunkId(*b"EXIF"), 38, 0, vec![]),
+    ]);
+
+    let mut chunks = webp::Chunks::new(&data[..30]);
+    assert!(chunks.next().unwrap().is_ok());
+    assert!(chunks.next().unwrap().is_err());
+    assert!(chunks.next().is_none());
+
+    assert!(webp::Chunks::new(&b"RIFF\x04\x00\x00\x00WAVE"[..]).next().unwrap().is_err());
+}
+
+#[test]
+fn test_webp_validate() {
+    use std::fs::File;
+    use std::io::BufReader;
+    use immeta::common::riff::ChunkId;
+    use immeta::formats::webp::{Violation, ViolationKind};
+
+    let f = BufReader::new(File::open("tests/images/cherry.webp").unwrap());
+    assert_eq!(webp::validate(f).unwrap(), vec![]);
+
+    let vp8x: &[u8] = &[0x0c, 0, 0, 0, 99, 0, 0, 49, 0, 0];
+    let vp8l: &[u8] = &[0x2f, 0x3f, 0xc0, 0x07, 0x00, 0];
+    let data = build_webp(&[
+        (b"VP8X", vp8x), (b"EXIF", &[]), (b"VP8L", vp8l), (b"ICCP", &[]), (b"XMP ", &[]),
+        (b"XMP ", &[]), (b"VP8X", vp8x), (b"VP8L", vp8l),
+    ]);
+    let violation = |kind, offset, id: &[u8; 4]| Violation { kind: kind, offset: offset, chunk_id: Some(ChunkId(*id)) };
+    assert_eq!(webp::validate(&data[..]).unwrap(), vec![
+        violation(ViolationKind::ChunkBeforeImageData, 30, b"EXIF"),
+        violation(ViolationKind::ChunkAfterImageData, 52, b"ICCP"),
+        violation(ViolationKind::DuplicateChunk, 68, b"XMP "),
+        violation(ViolationKind::MisplacedVp8x, 76, b"VP8X"),
+        violation(ViolationKind::DuplicateChunk, 76, b"VP8X"),
+        violation(ViolationKind::DuplicateChunk, 94, b"VP8L"),
+    ]);
+
+    let mut data = build_webp(&[(b"VP8L", vp8l), (b"EXIF", &[])]);
+    let violations = webp::validate(&data[..]).unwrap();
+    assert_eq!(violations, vec![violation(ViolationKind::UnexpectedChunk, 26, b"EXIF")]);
+    assert_eq!(violations[0].to_string(), "simple file has chunks after image data (EXIF at offset 26)");
+
+    // concatenated files
+    let len = data.len();
+    data.extend_from_slice(&build_webp(&[(b"VP8L", vp8l)]));
+    assert_eq!(webp::validate(&data[..]).unwrap(), vec![
+        violation(ViolationKind::UnexpectedChunk, 26, b"EXIF"),
+        Violation { kind: ViolationKind::RiffSizeMismatch, offset: len as u64, chunk_id: None },
+    ]);
+
+    let data = build_webp(&[(b"ALPH", &[]), (b"XYZW", &[1, 2])]);
+    assert_eq!(webp::validate(&data[..]).unwrap(), vec![
+        violation(ViolationKind::InvalidFirstChunk, 12, b"ALPH"),
+        violation(ViolationKind::UnexpectedChunk, 20, b"XYZW"),
+        Violation { kind: ViolationKind::MissingImageData, offset: 30, chunk_id: None },
+    ]);
+
+    let data = build_webp(&[(b"VP8L", vp8l), (b"VP8L", vp8l)]);
+    assert_eq!(webp::validate(&data[..data.len() - 3]).unwrap(), vec![
+        violation(ViolationKind::UnexpectedChunk, 26, b"VP8L"),
+        violation(ViolationKind::TruncatedChunk, 26, b"VP8L"),
+    ]);
+
+    assert!(webp::validate(&b"RIFF\x04\x00\x00\x00WAVE"[..]).is_err());
+}
+
+#[cfg(feature = "tar")]
+#[test]
+fn test_tar_members_declared_size() {
+    extern crate tar;
+
+    // a member whose base-256 encoded size is 2^62 bytes, followed by 1 KiB of data
+    let mut header = [0u8; 512];
+    header[..5].copy_from_slice(b"a.png");
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(&[0x80, 0, 0, 0, 0x40, 0, 0, 0, 0, 0, 0, 0]);
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = b'0';
+    header[257..265].copy_from_slice(b"ustar\000");
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    let data = [&header[..], &[0x89; 1024][..]].concat();
+
+    let mut archive = tar::Archive::new(&data[..]);
+    let members: Vec<_> = immeta::archive::tar_members(&mut archive).unwrap().collect();
+    match members.first() {
+        Some(&Err(immeta::Error::LimitExceeded(_))) => {}
+        _ => panic!("unexpected members: {:?}", members)
+    }
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn test_zip_members() {
+    extern crate zip;
+
+    use std::io::{Cursor, Read, Write};
+    use std::fs::File;
+
+    let mut png = Vec::new();
+    File::open("tests/images/owlet.png").unwrap().read_to_end(&mut png).unwrap();
+
+    let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored);
+    archive.start_file("readme.txt", options).unwrap();
+    archive.write_all(b"not an image").unwrap();
+    archive.add_directory("pages/", options).unwrap();
+    archive.start_file("pages/001.png", options).unwrap();
+    archive.write_all(&png).unwrap();
+    let data = archive.finish().unwrap().into_inner();
+
+    let members: Vec<_> = immeta::archive::zip_members(Cursor::new(data)).unwrap()
+        .map(|m| m.unwrap())
+        .collect();
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0].0, "pages/001.png");
+    assert_eq!(members[0].1.mime_type(), "image/png");
+    assert_eq!(members[0].1.dimensions(), OWLET_DIM);
+
+    // a broken image is not mistaken for a member of another type
+    let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    archive.start_file("pages/002.jpg", options).unwrap();
+    archive.write_all(b"\xff\xd8\xff\xe0\x00").unwrap();
+    let data = archive.finish().unwrap().into_inner();
+    let members: Vec<_> = immeta::archive::zip_members(Cursor::new(data)).unwrap().collect();
+    assert_eq!(members.len(), 1);
+    assert!(members[0].is_err());
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_gzip_compressed() {
+    extern crate flate2;
+
+    use std::io::{Read, Write};
+    use std::fs::File;
+
+    let mut gif = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut gif).unwrap();
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&gif).unwrap();
+    let data = encoder.finish().unwrap();
+
+    let md = immeta::load_from_buf(&data).unwrap();
+    assert_eq!(md.mime_type(), "image/gif");
+    assert_eq!(md.dimensions(), DROP_DIM);
+
+    // the hardened profile accepts a single gzip layer only
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&data).unwrap();
+    let nested = encoder.finish().unwrap();
+    assert_eq!(immeta::load_from_buf(&nested).unwrap().dimensions(), DROP_DIM);
+    assert_eq!(immeta::Immeta::hardened().load_from_buf(&data).unwrap().dimensions(), DROP_DIM);
+    match immeta::Immeta::hardened().load_from_buf(&nested) {
+        Err(immeta::Error::LimitExceeded(_)) => {}
+        r => panic!("unexpected result: {:?}", r)
+    }
+    match immeta::Immeta::new().max_decompressed_size(gif.len() as u64 - 1).load_from_buf(&data) {
+        Err(immeta::Error::LimitExceeded(_)) => {}
+        r => panic!("unexpected result: {:?}", r)
+    }
+    assert!(immeta::Immeta::new().max_decompressed_size(gif.len() as u64).load_from_buf(&data).is_ok());
+
+    // the default options limit nesting, so self-reproducing gzip streams do not recurse
+    // indefinitely
+    let mut layers = data.clone();
+    for _ in 1..immeta::Limits::DEFAULT_MAX_NESTING_DEPTH {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&layers).unwrap();
+        layers = encoder.finish().unwrap();
+    }
+    assert_eq!(immeta::load_from_buf(&layers).unwrap().dimensions(), DROP_DIM);
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&layers).unwrap();
+    match immeta::load_from_buf(&encoder.finish().unwrap()) {
+        Err(immeta::Error::LimitExceeded(_)) => {}
+        r => panic!("unexpected result: {:?}", r)
+    }
+
+    // and they limit the size of decompressed data, so gzip bombs are not inflated fully
+    let limits = immeta::Limits::default();
+    assert_eq!(limits.max_nesting_depth, Some(immeta::Limits::DEFAULT_MAX_NESTING_DEPTH));
+    assert_eq!(limits.max_decompressed_size, Some(immeta::Limits::DEFAULT_MAX_DECOMPRESSED_SIZE));
+    assert_eq!(immeta::LoadOptions::default().limits, limits);
+}
+
+#[test]
+fn test_metadata_cache() {
+    use immeta::cache::MetadataCache;
+
+    let mut cache = MetadataCache::new(16);
+    let first = cache.load_from_file("tests/images/owlet.png").unwrap();
+    let second = cache.load_from_file("tests/images/owlet.png").unwrap();
+    assert_eq!(first, second);
+    assert_eq!(cache.storage().len(), 1);
+
+    assert!(cache.load_from_file("tests/images/missing.png").is_err());
+    assert_eq!(cache.storage().len(), 1);
+}
+
+#[test]
+fn test_partial_gif() {
+    use std::io::{Read, Cursor};
+    use std::fs::File;
+
+    let mut data = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut data).unwrap();
+    data.truncate(data.len() / 2);
+
+    assert!(immeta::load_from_buf(&data).is_err());
+
+    let e = immeta::load_partial(&mut Cursor::new(&data)).err().expect("truncated GIF loaded");
+    match e.error {
+        immeta::Error::UnexpectedEndOfFile(_) => {}
+        ref e => panic!("unexpected error: {}", e)
+    }
+    let md = e.partial.expect("no partial metadata").into::<Gif>().ok().expect("not GIF metadata");
+    assert_eq!(md.dimensions, DROP_DIM);
+    assert!(md.frames_number() > 0 && md.frames_number() < 30);
+}
+
+#[test]
+fn test_loader_formats() {
+    use immeta::{Immeta, ImageFormat};
+
+    let loader = Immeta::new().formats(&[ImageFormat::Jpeg, ImageFormat::Gif]);
+    assert_eq!(loader.load_from_file("tests/images/owlet.jpg").unwrap().format(), ImageFormat::Jpeg);
+    assert!(loader.load_from_file("tests/images/owlet.png").is_err());
+}
+
+#[test]
+fn test_loader_soi_search_limit() {
+    use std::io::Read;
+    use std::fs::File;
+    use immeta::Immeta;
+
+    let mut data = vec![0u8; 100];
+    File::open("tests/images/owlet.jpg").unwrap().read_to_end(&mut data).unwrap();
+
+    assert!(immeta::load_from_buf(&data).is_err());
+    assert!(Immeta::new().soi_search_limit(99).load_from_buf(&data).is_err());
+
+    let md = Immeta::new().soi_search_limit(100).load_from_buf(&data).unwrap();
+    assert_eq!(md.dimensions(), OWLET_DIM);
+    assert_eq!(md.format(), immeta::ImageFormat::Jpeg);
+}
+
+#[test]
+fn test_loader_lenient() {
+    use std::io::Read;
+    use std::fs::File;
+    use immeta::Immeta;
+
+    let mut data = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut data).unwrap();
+    data.truncate(data.len() / 2);
+
+    assert!(Immeta::new().load_from_buf(&data).is_err());
+
+    let md = Immeta::new().lenient().load_from_buf(&data).unwrap();
+    let md = md.into::<Gif>().ok().expect("not GIF metadata");
+    assert!(md.frames_number() > 0 && md.frames_number() < 30);
+}
+
+#[test]
+fn test_loader_hex_dump_errors() {
+    use std::io::Read;
+    use std::fs::File;
+    use immeta::Immeta;
+
+    let e = Immeta::new().hex_dump_errors(true).load_from_buf(b"not an image").unwrap_err();
+    assert_eq!(e.to_string(), "invalid image format: unknown or unsupported file type\n\
+                               input bytes around offset 0:\n\
+                               00000000  6e 6f 74 20 61 6e 20 69 6d 61 67 65              |not an image|");
+
+    let mut data = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut data).unwrap();
+    data.truncate(1000);
+
+    let plain = Immeta::new().load_from_buf(&data).unwrap_err().to_string();
+    let dumped = Immeta::new().hex_dump_errors(true).load_from_buf(&data).unwrap_err().to_string();
+    assert!(dumped.starts_with(&plain));
+    assert!(dumped.contains("input bytes around offset 1000:\n000003c0  "));
+}
+
+#[test]
+fn test_parse_bytes() {
+    use std::io::Read;
+    use std::fs::File;
+    use immeta::LoadableMetadata;
+
+    fn read(path: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    let data = read("tests/images/owlet.jpg");
+    assert_eq!(jpeg::parse_bytes(&data).unwrap(), jpeg::Metadata::load_from_file("tests/images/owlet.jpg").unwrap());
+    let data = read("tests/images/owlet.png");
+    assert_eq!(png::parse_bytes(&data).unwrap(), png::Metadata::load_from_file("tests/images/owlet.png").unwrap());
+    let data = read("tests/images/drop.gif");
+    assert_eq!(gif::parse_bytes(&data).unwrap(), gif::Metadata::load_from_file("tests/images/drop.gif").unwrap());
+    let data = read("tests/images/cherry.webp");
+    assert_eq!(webp::parse_bytes(&data).unwrap(), webp::Metadata::load_from_file("tests/images/cherry.webp").unwrap());
+
+    assert!(png::parse_bytes(&data).is_err());
+}
+
+#[test]
+fn test_probe() {
+    use std::io::Read;
+    use std::fs::File;
+    use immeta::{Immeta, ImageFormat};
+
+    for &(path, format, bytes_read) in &[("tests/images/owlet.png", ImageFormat::Png, Some(24)),
+                                        ("tests/images/drop.gif", ImageFormat::Gif, Some(10)),
+                                        ("tests/images/cherry.webp", ImageFormat::Webp, Some(30)),
+                                        ("tests/images/owlet.jpg", ImageFormat::Jpeg, None)] {
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+
+        let probe = immeta::probe_from_buf(&data).unwrap();
+        assert_eq!(probe.format, format);
+        assert_eq!(probe.dimensions, immeta::load_from_buf(&data).unwrap().dimensions());
+        if let Some(bytes_read) = bytes_read {
+            assert_eq!(probe.bytes_read, bytes_read);
+        }
+
+        // the reported prefix is enough, but nothing shorter is
+        let n = probe.bytes_read as usize;
+        assert_eq!(immeta::probe_from_buf(&data[..n]).unwrap(), probe);
+        assert!(immeta::probe_from_buf(&data[..n - 1]).is_err());
+    }
+
+    assert!(Immeta::new().formats(&[ImageFormat::Gif]).probe_from_buf(b"\x89PNG\r\n\x1a\n").is_err());
+}
+
+#[test]
+fn test_load_from_reader() {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use immeta::Immeta;
+
+    // a reader which is not seekable, like a pipe
+    struct Pipe(File);
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    for &(path, mime_type) in &[("tests/images/owlet.jpg", "image/jpeg"), ("tests/images/owlet.png", "image/png"),
+                                ("tests/images/drop.gif", "image/gif"), ("tests/images/cherry.webp", "image/webp")] {
+        let md = immeta::load_from_reader(Pipe(File::open(path).unwrap())).unwrap();
+        assert_eq!(md, immeta::load_from_file(path).unwrap());
+        assert_eq!(md.mime_type(), mime_type);
+    }
+
+    let md = Immeta::new().max_input_size(100).load_from_reader(Pipe(File::open("tests/images/owlet.png").unwrap()));
+    match md {
+        Err(immeta::Error::LimitExceeded(_)) => {}
+        r => panic!("unexpected result: {:?}", r)
+    }
+}
+
+#[test]
+fn test_loader_limits() {
+    use std::fs::File;
+    use std::io::BufReader;
+    use immeta::Immeta;
+
+    // 1280x857 RGB
+    let size = 1280 * 857 * 3;
+    assert!(Immeta::new().max_decoded_size(size).load_from_file("tests/images/owlet.png").is_ok());
+    match Immeta::new().max_decoded_size(size - 1).load_from_file("tests/images/owlet.png") {
+        Err(immeta::Error::LimitExceeded(_)) => {}
+        r => panic!("unexpected result: {:?}", r)
+    }
+
+    let file_size = 1683992;
+    assert!(Immeta::new().max_input_size(file_size).load_from_file("tests/images/owlet.png").is_ok());
+    match Immeta::new().max_input_size(file_size - 1).load_from_file("tests/images/owlet.png") {
+        Err(immeta::Error::LimitExceeded(_)) => {}
+        r => panic!("unexpected result: {:?}", r)
+    }
+    match Immeta::new().max_input_size(1000).load_partial(&mut BufReader::new(File::open("tests/images/drop.gif").unwrap())) {
+        Err(ref e) if e.partial.is_some() => match e.error {
+            immeta::Error::LimitExceeded(_) => {}
+            ref e => panic!("unexpected error: {:?}", e)
+        },
+        r => panic!("unexpected result: {:?}", r)
+    }
+
+    assert!(Immeta::new().max_dimensions(1280, 857).max_pixels(1280 * 857).load_from_file("tests/images/owlet.png").is_ok());
+    for loader in &[Immeta::new().max_dimensions(1279, 857), Immeta::new().max_dimensions(1280, 856),
+                    Immeta::new().max_pixels(1280 * 857 - 1)] {
+        match loader.load_from_file("tests/images/owlet.png") {
+            Err(immeta::Error::LimitExceeded(_)) => {}
+            r => panic!("unexpected result: {:?}", r)
+        }
+        match loader.probe(&mut BufReader::new(File::open("tests/images/owlet.png").unwrap())) {
+            Err(immeta::Error::LimitExceeded(_)) => {}
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+
+    // images without dimensions are rejected instead of being checked against the limits
+    for loader in &[Immeta::new().max_dimensions(100, 100), Immeta::new().max_pixels(100)] {
+        match loader.load_from_buf(WEBP_VP8_INTERFRAME) {
+            Err(immeta::Error::InvalidFormat(_)) => {}
+            r => panic!("unexpected result: {:?}", r)
+        }
+        match loader.probe(&mut std::io::Cursor::new(WEBP_VP8_INTERFRAME)) {
+            Err(immeta::Error::InvalidFormat(_)) => {}
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+}
+
+/// A simple WebP file whose only frame is a VP8 interframe, which has no dimensions.
+const WEBP_VP8_INTERFRAME: &'static [u8] =
+    b"RIFF\x16\0\0\0WEBPVP8 \x0a\0\0\0\x01\0\0\0\0\0\0\0\0\0";
+
+#[test]
+fn test_loader_hardened_vp8_interframe() {
+    use immeta::Immeta;
+
+    assert_eq!(WEBP_VP8_INTERFRAME.len(), 30);
+    match Immeta::hardened().load_from_buf(WEBP_VP8_INTERFRAME) {
+        Err(immeta::Error::InvalidFormat(_)) => {}
+        r => panic!("unexpected result: {:?}", r)
+    }
+    assert!(immeta::load_from_buf(WEBP_VP8_INTERFRAME).is_err());
+    assert!(webp::parse_bytes(WEBP_VP8_INTERFRAME).is_err());
+}
+
+#[test]
+fn test_webp_estimated_decoded_size() {
+    let md = immeta::load_from_file("tests/images/cherry.webp").unwrap().into::<Webp>().ok().unwrap();
+    assert_eq!(md.estimated_decoded_size(), Some(CHERRY_DIM.width as u64 * CHERRY_DIM.height as u64 * 3));
+
+    // an interframe has no dimensions to estimate the size from
+    let md = webp::Metadata::VP8(webp::VP8Metadata {
+        version_number: 0,
+        show_frame: true,
+        first_partition_len: 0,
+        frame: webp::VP8Frame::Inter,
+    });
+    assert_eq!(md.estimated_decoded_size(), None);
+    assert_eq!(md.frame_dimensions(), None);
+
+    // each frame of an animation is decoded to a separate canvas
+    let data = build_webp(&[
+        (b"VP8X", &[0x02, 0, 0, 0, 99, 0, 0, 49, 0, 0]),
+        (b"ANIM", &[0, 0, 0, 0, 0, 0]),
+        (b"ANMF", &[0, 0, 0, 0, 0, 0, 63, 0, 0, 31, 0, 0, 80, 0, 0, 0x00]),
+        (b"ANMF", &[5, 0, 0, 2, 0, 0, 19, 0, 0, 9, 0, 0, 80, 0, 0, 0x00]),
+        (b"ANMF", &[0, 0, 0, 0, 0, 0, 99, 0, 0, 49, 0, 0, 80, 0, 0, 0x00]),
+    ]);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Webp>().ok().unwrap();
+    assert_eq!(md.estimated_decoded_size(), Some(100 * 50 * 4 * 3));
+}
+
+#[test]
+fn test_parser() {
+    use std::fs::File;
+    use immeta::{Immeta, Parser};
+
+    let mut parser = Parser::new();
+    for &path in &["tests/images/owlet.jpg", "tests/images/owlet.png",
+                   "tests/images/drop.gif", "tests/images/cherry.webp",
+                   "tests/images/owlet.jpg"] {
+        let expected = immeta::load_from_file(path).unwrap();
+        assert_eq!(parser.parse_file(path).unwrap(), expected);
+        assert_eq!(parser.parse(File::open(path).unwrap()).unwrap(), expected);
+    }
+
+    let mut parser = Immeta::new().max_input_size(1000).parser();
+    assert!(parser.parse_file("tests/images/owlet.png").is_err());
+    assert!(parser.parse_file("tests/images/drop.gif").is_err());
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn test_image_interop() {
+    use std::convert::TryFrom;
+    use std::fs::File;
+    use std::io::{BufReader, Seek, SeekFrom};
+    use immeta::{Immeta, ImageFormat};
+    use immeta::image::{image_crate, ProbedImage};
+
+    for &format in &ImageFormat::ALL {
+        assert_eq!(ImageFormat::try_from(image_crate::ImageFormat::from(format)), Ok(format));
+    }
+    assert_eq!(ImageFormat::try_from(image_crate::ImageFormat::Tiff), Err(image_crate::ImageFormat::Tiff));
+
+    let f = BufReader::new(File::open("tests/images/owlet.png").unwrap());
+    let probed = ProbedImage::new(f).unwrap();
+    assert_eq!(probed.probe().format, ImageFormat::Png);
+    assert_eq!(probed.probe().dimensions, Dimensions { width: 1280, height: 857 });
+    assert_eq!(probed.image_format(), image_crate::ImageFormat::Png);
+    let reader = probed.into_reader().unwrap();
+    assert_eq!(reader.format(), Some(image_crate::ImageFormat::Png));
+    assert_eq!(reader.into_inner().seek(SeekFrom::Current(0)).unwrap(), 0);
+
+    let f = BufReader::new(File::open("tests/images/owlet.png").unwrap());
+    assert!(ProbedImage::with_loader(f, &Immeta::new().formats(&[ImageFormat::Jpeg])).is_err());
+}
+
+#[cfg(feature = "mime")]
+#[test]
+fn test_mime() {
+    extern crate mime;
+    use immeta::ImageFormat;
+
+    for &format in &ImageFormat::ALL {
+        assert_eq!(format.mime().as_ref(), format.mime_type());
+        assert_eq!(mime::Mime::from(format), format.mime());
+        assert_eq!(format.mime().type_(), mime::IMAGE);
+    }
+    assert_eq!(ImageFormat::Webp.mime().subtype().as_str(), "webp");
+
+    let md = immeta::load_from_file("tests/images/owlet.jpg").unwrap();
+    assert_eq!(md.mime(), mime::IMAGE_JPEG);
+}
+
+#[test]
+fn test_exif_data() {
+    use immeta::common::exif::ByteOrder;
+
+    // IFD0 with a single Orientation tag
+    let mut tiff = Vec::new();
+    tiff.extend_from_slice(b"MM\0\x2a\0\0\0\x08\0\x01");
+    tiff.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00]);
+    tiff.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend_from_slice(&tiff);
+    let data = build_jpeg(&[(0xe1, &app1)], 16, 8);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    let exif = md.exif.expect("no EXIF data");
+    assert_eq!(exif.as_bytes(), &tiff[..]);
+    assert_eq!(exif.byte_order(), ByteOrder::Big);
+    // SOI, APP1 marker and size, EXIF header
+    assert_eq!(exif.offset(), 2 + 4 + 6);
+    assert_eq!(&data[exif.offset() as usize..][..tiff.len()], &tiff[..]);
+
+    let ifds = exif.tiff_reader().ifds().unwrap();
+    let ifd0 = (&ifds).into_iter().next().unwrap().unwrap();
+    assert_eq!(ifd0.into_iter().next().unwrap().unwrap().tag(), 0x0112);
+
+    #[cfg(feature = "exif")]
+    {
+        extern crate exif;
+
+        let parsed = exif.to_exif().unwrap();
+        assert_eq!(parsed.get_field(exif::Tag::Orientation, exif::In::PRIMARY).unwrap().value.get_uint(0), Some(6));
+    }
+
+    let data = build_png(&[(b"eXIf", &tiff)], 16, 8);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    let exif = md.exif.expect("no EXIF data");
+    assert_eq!(exif.as_bytes(), &tiff[..]);
+    assert_eq!(&data[exif.offset() as usize..][..tiff.len()], &tiff[..]);
+
+    // invalid EXIF data is ignored
+    let data = build_png(&[(b"eXIf", b"not a TIFF header")], 16, 8);
+    let md = immeta::load_from_buf(&data).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.exif, None);
+}
+
+#[test]
+fn test_diff_exif_and_text() {
+    use immeta::diff::{diff, Change, ChangeKind};
+
+    /// Builds a big-endian TIFF document with IFD0 consisting of entries with inline values.
+    fn tiff(entries: &[(u16, u16, &[u8])]) -> Vec<u8> {
+        let mut data = b"MM\0\x2a\0\0\0\x08".to_vec();
+        data.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        for &(tag, entry_type, value) in entries {
+            let count = if entry_type == 3 { value.len() / 2 } else { value.len() };
+            data.extend_from_slice(&tag.to_be_bytes());
+            data.extend_from_slice(&entry_type.to_be_bytes());
+            data.extend_from_slice(&(count as u32).to_be_bytes());
+            data.extend_from_slice(value);
+            data.extend(std::iter::repeat(0).take(4 - value.len()));
+        }
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data
+    }
+    fn load(tiff: &[u8], text: &[&[u8]]) -> immeta::GenericMetadata {
+        let mut chunks: Vec<(&[u8; 4], &[u8])> = vec![(b"eXIf", tiff)];
+        chunks.extend(text.iter().map(|&t| (b"tEXt", t)));
+        immeta::load_from_buf(&build_png(&chunks, 4, 4)).unwrap()
+    }
+    let change = |field: &str, kind| Change { field: field.into(), kind: kind };
+    let modified = |old: &str, new: &str| ChangeKind::Modified { old: old.into(), new: new.into() };
+
+    let orientation: (u16, u16, &[u8]) = (0x0112, 3, &[0, 1]);
+    let make: (u16, u16, &[u8]) = (0x010f, 2, b"Abc\0");
+    let a = load(&tiff(&[orientation, make]), &[b"Title\0one", b"Comment\0x"]);
+    assert_eq!(diff(&a, &a), vec![]);
+
+    // a tag is modified, the orientation follows it
+    let b = load(&tiff(&[(0x0112, 3, &[0, 6]), make]), &[b"Title\0one", b"Comment\0x"]);
+    assert_eq!(diff(&a, &b), vec![
+        change("orientation", modified("1", "6")),
+        change("exif.ifd0.0x0112", modified("1", "6")),
+    ]);
+
+    // a tag of the same size is modified
+    let b = load(&tiff(&[orientation, (0x010f, 2, b"Xyz\0")]), &[b"Title\0one", b"Comment\0x"]);
+    assert_eq!(diff(&a, &b), vec![change("exif.ifd0.0x010F", modified("Abc", "Xyz"))]);
+
+    // a tag is removed and another one is added
+    let b = load(&tiff(&[orientation, (0x0131, 2, b"sw\0")]), &[b"Title\0one", b"Comment\0x"]);
+    assert_eq!(diff(&a, &b), vec![
+        change("exif.ifd0.0x010F", ChangeKind::Removed("Abc".into())),
+        change("exif.ifd0.0x0131", ChangeKind::Added("sw".into())),
+    ]);
+
+    // text chunks are matched by keyword, not by position
+    let b = load(&tiff(&[orientation, make]), &[b"Author\0me", b"Title\0one", b"Comment\0y"]);
+    assert_eq!(diff(&a, &b), vec![
+        change("png.text.Comment", modified("x", "y")),
+        change("png.text.Author", ChangeKind::Added("me".into())),
+    ]);
+}
+
+#[test]
+fn test_lazy_exif_and_xmp() {
+    use std::io::Cursor;
+    use immeta::{Immeta, ByteRange};
+
+    let tiff = b"MM\0\x2a\0\0\0\x08\0\0\0\0\0\0";
+    let mut exif = b"Exif\0\0".to_vec();
+    exif.extend_from_slice(tiff);
+    let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF \
+                xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"><rdf:Description \
+                xmlns:hdrgm=\"http://ns.adobe.com/hdr-gain-map/1.0/\" hdrgm:Version=\"1.0\"/>\
+                </rdf:RDF></x:xmpmeta>";
+    let jpeg_data = build_jpeg(&[(0xe1, &exif), (0xe1, &xmp[..])], 16, 8);
+    let png_data = build_png(&[(b"eXIf", tiff)], 16, 8);
+    let mut gif_data = b"GIF89a\x01\x00\x01\x00\x00\x00\x00\x21\xff\x0bXMP DataXMP<x:xmpmeta/>".to_vec();
+    gif_data.push(0x01);
+    gif_data.extend((0..256).rev().map(|b| b as u8));
+    gif_data.extend_from_slice(&[0x00, 0x3b]);
+
+    for data in &[&jpeg_data, &png_data, &gif_data] {
+        let eager = immeta::load_from_buf(data).unwrap();
+        let mut md = Immeta::new().lazy_extensions(true).load_from_buf(data).unwrap();
+        assert!(md != eager);
+        md.load_extensions(&mut Cursor::new(data)).unwrap();
+        assert_eq!(md, eager);
+    }
+
+    let mut md = Immeta::new().lazy_extensions(true).load_from_buf(&jpeg_data).unwrap();
+    {
+        let jpeg = md.as_ref::<Jpeg>().unwrap();
+        assert_eq!((jpeg.exif.as_ref(), jpeg.xmp.as_ref(), jpeg.gain_map.as_ref()), (None, None, None));
+        // SOI, APP1 marker and size
+        assert_eq!(jpeg.pending_exif, Some(ByteRange { offset: 2 + 4, len: exif.len() as u64 }));
+    }
+    md.load_extensions(&mut Cursor::new(&jpeg_data)).unwrap();
+    let jpeg = md.as_ref::<Jpeg>().unwrap();
+    assert_eq!(jpeg.exif.as_ref().map(|exif| exif.as_bytes()), Some(&tiff[..]));
+    assert_eq!(jpeg.gain_map.as_ref().and_then(|g| g.version.as_ref()).map(|v| &**v), Some("1.0"));
+    assert_eq!((jpeg.pending_exif, jpeg.pending_xmp), (None, None));
+
+    // the truncated stream can't provide the EXIF data
+    let mut md = Immeta::new().lazy_extensions(true).load_from_buf(&png_data).unwrap();
+    assert!(md.load_extensions(&mut Cursor::new(&png_data[..40])).is_err());
+    assert!(md.as_ref::<Png>().unwrap().pending_exif.is_some());
+}
+
+#[test]
+fn test_timestamps() {
+    use immeta::common::datetime::DateTime;
+
+    // IFD0 with DateTime and the EXIF IFD pointer, EXIF IFD with DateTimeOriginal and
+    // OffsetTimeOriginal
+    let mut tiff = Vec::new();
+    tiff.extend_from_slice(b"MM\0\x2a\0\0\0\x08\0\x02");
+    tiff.extend_from_slice(&[0x01, 0x32, 0x00, 0x02, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x26]);
+    tiff.extend_from_slice(&[0x87, 0x69, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3a]);
+    tiff.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    tiff.extend_from_slice(b"2020:01:02 03:04:05\0");
+    tiff.extend_from_slice(&[0x00, 0x02]);
+    tiff.extend_from_slice(&[0x90, 0x03, 0x00, 0x02, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x58]);
+    tiff.extend_from_slice(&[0x90, 0x11, 0x00, 0x02, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x6c]);
+    tiff.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    tiff.extend_from_slice(b"2019:12:31 23:59:58\0");
+    tiff.extend_from_slice(b"+09:00\0");
+
+    let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF \
+                xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"><rdf:Description \
+                xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\" xmp:CreateDate=\"2018-06-07T08:09:10Z\" \
+                xmp:ModifyDate=\"2018-06-08\"/></rdf:RDF></x:xmpmeta>";
+
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend_from_slice(&tiff);
+    let data = build_jpeg(&[(0xe1, &app1), (0xe1, &xmp[..])], 16, 8);
+    let md = immeta::load_from_buf(&data).unwrap();
+    assert_eq!(md.created_at(), DateTime::new(2019, 12, 31, 23, 59, 58).map(|dt| dt.with_utc_offset(Some(540))));
+    assert_eq!(md.modified_at(), DateTime::new(2020, 1, 2, 3, 4, 5));
+
+    // XMP is used when there is no EXIF data
+    let data = build_jpeg(&[(0xe1, &xmp[..])], 16, 8);
+    let md = immeta::load_from_buf(&data).unwrap();
+    assert_eq!(md.created_at(), DateTime::new(2018, 6, 7, 8, 9, 10).map(|dt| dt.with_utc_offset(Some(0))));
+    assert_eq!(md.modified_at(), DateTime::new(2018, 6, 8, 0, 0, 0));
+
+    // tIME takes precedence over EXIF and XMP in PNG
+    let mut itxt = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+    itxt.extend_from_slice(&xmp[29..]);
+    let data = build_png(&[(b"eXIf", &tiff), (b"iTXt", &itxt), (b"tIME", b"\x07\xe5\x02\x03\x04\x05\x06")], 16, 8);
+    let md = immeta::load_from_buf(&data).unwrap();
+    assert_eq!(md.modified_at(), DateTime::new(2021, 2, 3, 4, 5, 6).map(|dt| dt.with_utc_offset(Some(0))));
+    assert_eq!(md.created_at(), DateTime::new(2019, 12, 31, 23, 59, 58).map(|dt| dt.with_utc_offset(Some(540))));
+
+    let data = build_png(&[(b"iTXt", &itxt)], 16, 8);
+    let md = immeta::load_from_buf(&data).unwrap();
+    assert_eq!(md.created_at(), DateTime::new(2018, 6, 7, 8, 9, 10).map(|dt| dt.with_utc_offset(Some(0))));
+
+    let md = immeta::load_from_file("tests/images/drop.gif").unwrap();
+    assert_eq!(md.created_at(), None);
+    assert_eq!(md.modified_at(), None);
+}
+
+#[test]
+fn test_jpeg_strip_metadata() {
+    use std::fs::File;
+    use std::io::Read;
+    use jpeg::StripOptions;
+
+    let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>";
+    let exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+    let icc = b"ICC_PROFILE\0\x01\x01profile";
+    let jfif = b"JFIF\0\x01\x02\x01\0\x48\0\x48\0\0";
+    let mut data = build_jpeg(&[(0xe0, &jfif[..]), (0xe1, &exif[..]), (0xe1, &xmp[..]), (0xe2, &icc[..]),
+                                (0xfe, b"comment"), (0xeb, b"vendor")], 16, 8);
+    // entropy-coded data with a stuffed byte and a restart marker, and trailing data
+    let eoi = data.len() - 2;
+    data.splice(eoi..eoi, [0x01, 0xff, 0x00, 0x02, 0xff, 0xd0, 0x03].iter().cloned());
+    data.extend_from_slice(b"trailer");
+
+    let mut out = Vec::new();
+    assert_eq!(jpeg::strip_metadata(&mut &data[..], &mut out, &StripOptions::none()).unwrap(), 0);
+    assert_eq!(out, data);
+
+    let mut out = Vec::new();
+    let options = StripOptions { exif: true, comments: true, ..StripOptions::none() };
+    assert_eq!(jpeg::strip_metadata(&mut &data[..], &mut out, &options).unwrap(), 2);
+    let md = immeta::load_from_buf(&out).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.exif, None);
+    assert!(md.xmp.is_some());
+    assert!(out.windows(11).any(|w| w == b"ICC_PROFILE"));
+    assert!(!out.windows(7).any(|w| w == b"comment"));
+
+    let mut out = Vec::new();
+    assert_eq!(jpeg::strip_metadata(&mut &data[..], &mut out, &StripOptions::all()).unwrap(), 5);
+    let mut expected = build_jpeg(&[(0xe0, &jfif[..])], 16, 8);
+    let eoi = expected.len() - 2;
+    expected.splice(eoi..eoi, [0x01, 0xff, 0x00, 0x02, 0xff, 0xd0, 0x03].iter().cloned());
+    assert_eq!(out, expected);
+
+    // real images keep their scan data intact
+    let mut data = Vec::new();
+    File::open("tests/images/owlet.jpg").unwrap().read_to_end(&mut data).unwrap();
+    let mut out = Vec::new();
+    jpeg::strip_metadata(&mut &data[..], &mut out, &StripOptions::all()).unwrap();
+    let sos = |d: &[u8]| d.windows(2).position(|w| w == [0xff, 0xda]).unwrap();
+    assert_eq!(&out[sos(&out)..], &data[sos(&data)..]);
+    let (original, stripped) = (immeta::load_from_buf(&data).unwrap(), immeta::load_from_buf(&out).unwrap());
+    assert_eq!(original.dimensions(), stripped.dimensions());
+
+    assert!(jpeg::strip_metadata(&mut &b"\x89PNG"[..], &mut Vec::new(), &StripOptions::all()).is_err());
+}
+
+#[test]
+fn test_jpeg_set_orientation() {
+    use std::io::{Cursor, Seek, SeekFrom};
+    use immeta::common::tiff::entry_types;
+
+    fn orientation(data: &[u8]) -> u16 {
+        let md = immeta::load_from_buf(data).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+        let exif = md.exif.expect("no EXIF data");
+        let ifds = exif.tiff_reader().ifds().unwrap();
+        let ifd0 = (&ifds).into_iter().next().unwrap().unwrap();
+        let entry = ifd0.map(|e| e.unwrap()).find(|e| e.tag() == 0x0112).unwrap();
+        let value = entry.all_values::<entry_types::Short>().unwrap().unwrap()[0];
+        value
+    }
+
+    for &tiff in &[&b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0"[..],
+                   &b"II\x2a\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0"[..]] {
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend_from_slice(tiff);
+        let data = build_jpeg(&[(0xe1, &app1)], 16, 8);
+        assert_eq!(orientation(&data), 6);
+
+        // the stream does not need to start at the beginning of the JPEG data
+        let mut f = Cursor::new(b"prefix".to_vec());
+        f.seek(SeekFrom::End(0)).unwrap();
+        f.get_mut().extend_from_slice(&data);
+        assert_eq!(jpeg::set_orientation(&mut f, 1).unwrap(), Some(6));
+        let patched = f.into_inner().split_off(6);
+        assert_eq!(orientation(&patched), 1);
+        assert_eq!(patched.len(), data.len());
+        assert_eq!(patched.iter().zip(&data).filter(|&(a, b)| a != b).count(), 1);
+    }
+
+    let data = build_jpeg(&[], 16, 8);
+    let mut f = Cursor::new(data.clone());
+    assert_eq!(jpeg::set_orientation(&mut f, 1).unwrap(), None);
+    assert_eq!(f.into_inner(), data);
+}
+
+#[test]
+fn test_write_xmp() {
+    use immeta::common::riff::ChunkId;
+    use immeta::common::xmp::Xmp;
+
+    let old = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta xmlns:x=\"adobe:ns:meta/\" old=\"1\"/>";
+    let extension = b"http://ns.adobe.com/xmp/extension/\0ABCDEF";
+    let exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\0\0\0\0\0";
+    let jfif = b"JFIF\0\x01\x02\x01\0\x48\0\x48\0\0";
+    let xmp = Xmp::new("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>");
+
+    // JPEG: the packet is placed after JFIF and EXIF segments or in place of the old one
+    let data = build_jpeg(&[(0xe0, &jfif[..]), (0xe1, &exif[..]), (0xfe, b"comment")], 16, 8);
+    let mut out = Vec::new();
+    jpeg::write_xmp(&mut &data[..], &mut out, &xmp).unwrap();
+    let segment = [&b"http://ns.adobe.com/xap/1.0/\0"[..], xmp.as_str().as_bytes()].concat();
+    let expected = build_jpeg(&[(0xe0, &jfif[..]), (0xe1, &exif[..]), (0xe1, &segment[..]),
+                                (0xfe, b"comment")], 16, 8);
+    assert_eq!(out, expected);
+    let md = immeta::load_from_buf(&out).unwrap().into::<Jpeg>().ok().expect("not JPEG metadata");
+    assert_eq!(md.xmp, Some(xmp.clone()));
+
+    let data = build_jpeg(&[(0xe1, &old[..]), (0xe1, &extension[..]), (0xfe, b"comment")], 16, 8);
+    let mut out = Vec::new();
+    jpeg::write_xmp(&mut &data[..], &mut out, &xmp).unwrap();
+    assert_eq!(out, build_jpeg(&[(0xe1, &segment[..]), (0xfe, b"comment")], 16, 8));
+
+    let large = Xmp::new(String::from_utf8(vec![b' '; 65505]).unwrap());
+    assert!(jpeg::write_xmp(&mut &data[..], &mut Vec::new(), &large).is_err());
+
+    // PNG: the packet is placed before the image data, and old XMP chunks are removed
+    let old_itxt = b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta/>";
+    let data = build_png(&[(b"tEXt", b"Title\0kitty"), (b"iTXt", &old_itxt[..])], 16, 8);
+    let mut out = Vec::new();
+    png::write_xmp(&mut &data[..], &mut out, &xmp).unwrap();
+    let mut expected = build_png(&[(b"tEXt", b"Title\0kitty"),
+                                   (b"iTXt", b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>")],
+                                 16, 8);
+    // build_png() writes zero CRCs, so the CRC of the new chunk has to be filled in
+    let crc_offset = 8 + 25 + 23 + 8 + 59;
+    expected[crc_offset..crc_offset + 4].copy_from_slice(&[0x53, 0x0a, 0x18, 0xd9]);
+    assert_eq!(out, expected);
+    let md = immeta::load_from_buf(&out).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.xmp(), Some(xmp.clone()));
+    assert!(png::write_xmp(&mut &b"\x89PNG\r\n\x1a\n"[..], &mut Vec::new(), &xmp).is_err());
+
+    // WebP: simple files are converted to the extended format
+    let data = build_webp(&[(b"VP8L", &[0x2f, 0x8f, 0xc1, 0x4a, 0x10, 0x00])]);
+    let mut out = Vec::new();
+    webp::write_xmp(&mut &data[..], &mut out, &xmp).unwrap();
+    let packet = xmp.as_str().as_bytes();
+    let mut expected = build_webp(&[(b"VP8X", &[0x14, 0, 0, 0, 0x8f, 0x01, 0x00, 0x2b, 0x01, 0x00]),
+                                    (b"VP8L", &[0x2f, 0x8f, 0xc1, 0x4a, 0x10, 0x00]),
+                                    (b"XMP ", packet)]);
+    // the packet has an odd length, so it is padded
+    assert_eq!(packet.len() % 2, 1);
+    expected.push(0);
+    expected[4] += 1;
+    assert_eq!(out, expected);
+    let md = immeta::load_from_buf(&out).unwrap();
+    assert_eq!(md.dimensions(), Dimensions { width: 400, height: 300 });
+    match md.into::<Webp>().ok().expect("not WEBP metadata") {
+        webp::Metadata::VP8X(md) => assert!(md.has_xmp && md.has_alpha),
+        md => panic!("not VP8X metadata: {:?}", md)
+    }
+
+    // existing XMP chunks are replaced and unknown chunks stay last
+    let data = build_webp(&[(b"VP8X", &[0x0c, 0, 0, 0, 0x8f, 0x01, 0x00, 0x2b, 0x01, 0x00]),
+                            (b"VP8L", &[0x2f, 0x8f, 0xc1, 0x4a, 0x10, 0x00]),
+                            (b"XMP ", b"<old/>"), (b"EXIF", &exif[6..]), (b"ZZZ1", b"zz")]);
+    let mut out = Vec::new();
+    webp::write_xmp(&mut &data[..], &mut out, &xmp).unwrap();
+    let mut chunks = webp::Chunks::new(&out[..]);
+    let mut found = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        let mut chunk = chunk.unwrap();
+        found.push((chunk.chunk_id(), chunk.read_payload().unwrap()));
+    }
+    assert_eq!(found.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+               vec![ChunkId(*b"VP8X"), ChunkId(*b"VP8L"), ChunkId(*b"EXIF"), ChunkId(*b"XMP "), ChunkId(*b"ZZZ1")]);
+    assert_eq!(found[0].1[0], 0x0c);
+    assert_eq!(&found[3].1[..], packet);
+    assert!(webp::validate(&out[..]).unwrap().is_empty());
+}
+
+#[test]
+fn test_png_chunk_editor() {
+    use std::fs::File;
+    use std::io::Read;
+    use png::{ChunkEditor, TextChunk};
+
+    let time = [0x07, 0xe0, 5, 21, 14, 3, 10];
+    let mut data = build_png(&[(b"gAMA", &[0, 0, 0xb1, 0x8f]), (b"tEXt", b"Title\0kitty"), (b"tIME", &time[..]),
+                               (b"IDAT", b"data"), (b"zzZz", b"private")], 16, 8);
+    data.extend_from_slice(b"trailer");
+
+    let mut out = Vec::new();
+    assert_eq!(ChunkEditor::new().copy(&mut &data[..], &mut out).unwrap(), 0);
+    assert_eq!(out, data);
+
+    let mut out = Vec::new();
+    let editor = ChunkEditor::new()
+        .strip_ancillary(true)
+        .keep(*b"tIME")
+        .insert_text(TextChunk::new("Software", "immeta"));
+    assert_eq!(editor.copy(&mut &data[..], &mut out).unwrap(), 2);
+    let mut expected = build_png(&[(b"gAMA", &[0, 0, 0xb1, 0x8f]), (b"tIME", &time[..]),
+                                   (b"tEXt", b"Software\0immeta"), (b"IDAT", b"data")], 16, 8);
+    let crc_offset = 8 + 25 + 16 + 19 + 8 + 15;
+    expected[crc_offset..crc_offset + 4].copy_from_slice(&[0x69, 0xf3, 0x79, 0xdc]);
+    expected.extend_from_slice(b"trailer");
+    assert_eq!(out, expected);
+
+    // HDR metadata affects rendering and survives stripping
+    let mdcv = [0u8; 24];
+    let clli = [0, 0, 0x03, 0xe8, 0, 0, 0x01, 0x90];
+    let hdr = build_png(&[(b"mDCv", &mdcv[..]), (b"cLLi", &clli[..]), (b"tEXt", b"Title\0kitty"),
+                          (b"IDAT", b"data")], 16, 8);
+    let mut out = Vec::new();
+    assert_eq!(ChunkEditor::new().strip_ancillary(true).copy(&mut &hdr[..], &mut out).unwrap(), 1);
+    assert_eq!(out, build_png(&[(b"mDCv", &mdcv[..]), (b"cLLi", &clli[..]), (b"IDAT", b"data")], 16, 8));
+
+    let mut out = Vec::new();
+    let editor = ChunkEditor::new().remove(*b"gAMA").remove_text("Title");
+    assert_eq!(editor.copy(&mut &data[..], &mut out).unwrap(), 2);
+    let md = immeta::load_from_buf(&out).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.gamma, None);
+    assert!(md.text_chunks.is_empty());
+    assert!(md.modification_time.is_some());
+
+    // international text round-trips through the reader
+    let mut chunk = TextChunk::international("Description", "Kätzchen");
+    chunk.language_tag = Some("de".to_owned());
+    let mut out = Vec::new();
+    ChunkEditor::new().insert_text(chunk.clone()).copy(&mut &data[..], &mut out).unwrap();
+    let md = immeta::load_from_buf(&out).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert_eq!(md.text_chunks[1], chunk);
+
+    // compressed text can only be written with zlib
+    chunk.compressed = true;
+    let mut out = Vec::new();
+    let result = ChunkEditor::new().insert_text(chunk.clone()).copy(&mut &data[..], &mut out);
+    assert_eq!(result.is_ok(), cfg!(feature = "zlib"));
+    if result.is_ok() {
+        let md = immeta::load_from_buf(&out).unwrap().into::<Png>().ok().expect("not PNG metadata");
+        assert_eq!(md.text_chunks[1], chunk);
+    }
+
+    let editor = ChunkEditor::new().insert_text(TextChunk::new("Description", "Kätzchen 猫"));
+    assert!(editor.copy(&mut &data[..], &mut Vec::new()).is_err());
+    let editor = ChunkEditor::new().insert_text(TextChunk::new("", "text"));
+    assert!(editor.copy(&mut &data[..], &mut Vec::new()).is_err());
+
+    // real images keep their image data intact
+    let mut data = Vec::new();
+    File::open("tests/images/owlet.png").unwrap().read_to_end(&mut data).unwrap();
+    let mut out = Vec::new();
+    ChunkEditor::new().strip_ancillary(true).copy(&mut &data[..], &mut out).unwrap();
+    assert!(out.len() < data.len());
+    let md = immeta::load_from_buf(&out).unwrap().into::<Png>().ok().expect("not PNG metadata");
+    assert!(md.text_chunks.is_empty());
+    assert_eq!(md.dimensions, immeta::load_from_buf(&data).unwrap().dimensions());
+    let idat = |d: &[u8]| {
+        let mut chunks = png::Chunks::new(d);
+        let mut result = Vec::new();
+        while let Some(chunk) = chunks.next() {
+            let mut chunk = chunk.unwrap();
+            if &chunk.chunk_type() == b"IDAT" {
+                result.push((chunk.read_payload().unwrap(), chunk.crc().unwrap()));
+            }
+        }
+        result
+    };
+    assert_eq!(idat(&out), idat(&data));
+}
+
+#[test]
+fn test_gif_set_loop_count() {
+    use std::fs::File;
+    use std::io::Read;
+
+    // existing looping extensions are modified in place
+    let mut data = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut data).unwrap();
+    let mut out = Vec::new();
+    assert_eq!(gif::set_loop_count(&mut &data[..], &mut out, 5).unwrap(), Some(0));
+    assert_eq!(out.len(), data.len());
+    assert_eq!(out.iter().zip(&data).filter(|&(a, b)| a != b).count(), 1);
+    let md = immeta::load_from_buf(&out).unwrap().into::<Gif>().ok().expect("not GIF metadata");
+    assert_eq!(md.loop_count, Some(5));
+    assert_eq!(md.frames_number(), 30);
+
+    // a new extension is inserted after the global color table of a GIF87a image
+    let mut data = b"GIF87a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff".to_vec();
+    let frame = [0x2c, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0x02, 0x02, 0x44, 0x01, 0x00];
+    data.extend_from_slice(&frame);
+    data.extend_from_slice(&frame);
+    data.extend_from_slice(b"\x3btrailer");
+    let mut out = Vec::new();
+    assert_eq!(gif::set_loop_count(&mut &data[..], &mut out, 0).unwrap(), None);
+    let mut expected = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff".to_vec();
+    expected.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+    expected.extend_from_slice(&data[19..]);
+    assert_eq!(out, expected);
+    let md = immeta::load_from_buf(&out).unwrap().into::<Gif>().ok().expect("not GIF metadata");
+    assert_eq!(md.loop_count, Some(0));
+    assert_eq!(md.frames_number(), 2);
+
+    // other extensions, including XMP with its magic trailer, are copied unchanged; since
+    // the looping extension follows the first frame, a new one is inserted as well
+    let mut data = b"GIF89a\x01\x00\x01\x00\x00\x00\x00".to_vec();
+    data.extend_from_slice(b"\x21\xfe\x07comment\x00");
+    data.extend_from_slice(b"\x21\xff\x0bXMP DataXMP<x:xmpmeta/>\x01");
+    data.extend((0..256).rev().map(|b| b as u8));
+    data.push(0x00);
+    data.extend_from_slice(&frame);
+    data.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x02\x00\x00\x3b");
+    let mut out = Vec::new();
+    assert_eq!(gif::set_loop_count(&mut &data[..], &mut out, 1).unwrap(), Some(2));
+    let md = immeta::load_from_buf(&out).unwrap().into::<Gif>().ok().expect("not GIF metadata");
+    assert_eq!(md.loop_count, Some(1));
+    assert!(md.xmp.is_some());
+    let mut expected = data[..13].to_vec();
+    expected.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x01\x00\x00");
+    expected.extend_from_slice(&data[13..]);
+    let n = expected.len();
+    expected[n - 4] = 1;
+    assert_eq!(out, expected);
+
+    assert!(gif::set_loop_count(&mut &b"GIF89b"[..], &mut Vec::new(), 0).is_err());
+    assert!(gif::set_loop_count(&mut &data[..data.len() - 1], &mut Vec::new(), 0).is_err());
+}
+
+#[test]
+fn test_transplant() {
+    use std::io::Cursor;
+    use immeta::common::xmp::Xmp;
+    use immeta::transplant::{self, EmbeddedMetadata};
+
+    let exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\0\0\0\0\0";
+    let xmp_segment = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>";
+    let profile: Vec<u8> = (0..70000u32).map(|i| (i % 251) as u8).collect();
+    let icc_segment = |seq: u8, data: &[u8]| [&b"ICC_PROFILE\0"[..], &[seq, 2], data].concat();
+    let (icc1, icc2) = (icc_segment(1, &profile[..65519]), icc_segment(2, &profile[65519..]));
+
+    // ICC chunks are reassembled in the order of their sequence numbers
+    let source = build_jpeg(&[(0xe1, &exif[..]), (0xe2, &icc2[..]), (0xe1, &xmp_segment[..]),
+                              (0xe2, &icc1[..])], 16, 8);
+    let md = transplant::extract(immeta::ImageFormat::Jpeg, &mut &source[..]).unwrap();
+    assert_eq!(md.exif.as_ref().map(|exif| exif.as_bytes()), Some(&exif[6..]));
+    assert_eq!(md.exif.as_ref().map(|exif| exif.offset()), Some(12));
+    assert_eq!(md.xmp, Some(Xmp::new("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>")));
+    assert_eq!(md.icc_profile.as_ref(), Some(&profile));
+
+    // JPEG: the profile is split again, and the new segments follow JFIF segment
+    let jfif = b"JFIF\0\x01\x02\x01\0\x48\0\x48\0\0";
+    let target = build_jpeg(&[(0xe0, &jfif[..]), (0xe2, b"ICC_PROFILE\0\x01\x01old"), (0xfe, b"comment")], 16, 8);
+    let mut out = Vec::new();
+    transplant::embed(immeta::ImageFormat::Jpeg, &mut &target[..], &mut out, &md).unwrap();
+    let expected = build_jpeg(&[(0xe0, &jfif[..]), (0xe1, &exif[..]), (0xe1, &xmp_segment[..]),
+                                (0xe2, &icc1[..]), (0xe2, &icc2[..]), (0xfe, b"comment")], 16, 8);
+    assert_eq!(out, expected);
+
+    // WebP: simple files are converted to the extended format with all metadata flags set
+    let target = build_webp(&[(b"VP8L", &[0x2f, 0x8f, 0xc1, 0x4a, 0x10, 0x00])]);
+    let mut out = Vec::new();
+    transplant::transplant(&mut Cursor::new(&source), &mut Cursor::new(&target), &mut out).unwrap();
+    let mut chunks = webp::Chunks::new(&out[..]);
+    let mut ids = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        ids.push(chunk.unwrap().chunk_id().as_str().unwrap().to_owned());
+    }
+    assert_eq!(ids, ["VP8X", "ICCP", "VP8L", "EXIF", "XMP "]);
+    match immeta::load_from_buf(&out).unwrap().into::<Webp>().ok().expect("not WEBP metadata") {
+        webp::Metadata::VP8X(md) => assert!(md.has_icc_profile && md.has_exif && md.has_xmp && md.has_alpha),
+        md => panic!("not VP8X metadata: {:?}", md)
+    }
+    let webp_md = transplant::extract(immeta::ImageFormat::Webp, &mut &out[..]).unwrap();
+    assert_eq!(webp_md.exif.as_ref().map(|exif| exif.as_bytes()), Some(&exif[6..]));
+    assert_eq!((webp_md.xmp, webp_md.icc_profile), (md.xmp.clone(), md.icc_profile.clone()));
+
+    // a VP8 key frame without width can not be described by a VP8X canvas
+    let target = build_webp(&[(b"VP8 ", &[0x10, 0, 0, 0x9d, 0x01, 0x2a, 0, 0, 8, 0])]);
+    let mut out = Vec::new();
+    assert!(transplant::embed(immeta::ImageFormat::Webp, &mut &target[..], &mut out, &md).is_err());
+
+    // PNG: only kinds of metadata which are present are replaced
+    let partial = EmbeddedMetadata { exif: md.exif.clone(), ..EmbeddedMetadata::default() };
+    let target = build_png(&[(b"eXIf", b"II*\0\x08\0\0\0"), (b"sRGB", b"\0"), (b"PLTE", b"\0\0\0")], 16, 8);
+    let mut out = Vec::new();
+    transplant::embed(immeta::ImageFormat::Png, &mut &target[..], &mut out, &partial).unwrap();
+    let mut chunks = png::Chunks::new(&out[..]);
+    let mut types = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        types.push(chunk.unwrap().type_str());
+    }
+    assert_eq!(types, ["IHDR", "sRGB", "eXIf", "PLTE", "IEND"]);
+    let png_md = transplant::extract(immeta::ImageFormat::Png, &mut &out[..]).unwrap();
+    assert_eq!(png_md.exif.as_ref().map(|exif| exif.as_bytes()), Some(&exif[6..]));
+    assert!(png_md.xmp.is_none() && png_md.icc_profile.is_none());
+
+    let mut out = Vec::new();
+    let result = transplant::embed(immeta::ImageFormat::Png, &mut &target[..], &mut out, &md);
+    if cfg!(feature = "zlib") {
+        result.unwrap();
+        let png_md = transplant::extract(immeta::ImageFormat::Png, &mut &out[..]).unwrap();
+        assert_eq!((png_md.xmp, png_md.icc_profile), (md.xmp.clone(), md.icc_profile.clone()));
+        let png_md = immeta::load_from_buf(&out).unwrap().into::<Png>().ok().expect("not PNG metadata");
+        assert!(png_md.srgb_intent.is_none());
+    } else {
+        assert!(result.is_err());
+    }
+
+    assert!(transplant::embed(immeta::ImageFormat::Gif, &mut &b"GIF89a"[..], &mut Vec::new(), &md).is_err());
+}
+
+#[test]
+fn test_png_set_dpi() {
+    let data = build_png(&[(b"pHYs", &[0, 0, 0, 1, 0, 0, 0, 1, 0]), (b"IDAT", b"data")], 16, 8);
+    let mut out = Vec::new();
+    png::set_dpi(&mut &data[..], &mut out, 72.0, 300.0).unwrap();
+
+    let mut chunks = png::Chunks::new(&out[..]);
+    let mut found = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        let mut chunk = chunk.unwrap();
+        found.push((chunk.type_str(), chunk.read_payload().unwrap()));
+    }
+    // 2835 and 11811 pixels per meter
+    assert_eq!(found[1], ("pHYs".to_owned(), vec![0, 0, 0x0b, 0x13, 0, 0, 0x2e, 0x23, 1]));
+    assert_eq!(found.len(), 4);
+    assert_eq!(found[2].0, "IDAT");
+}
+
+#[test]
+fn test_verify_complete() {
+    use std::fs::File;
+    use std::io::{Cursor, Read};
+    use immeta::Completeness;
+
+    for name in &["owlet.jpg", "owlet.png", "owlet.gif", "drop.gif", "cherry.webp"] {
+        let mut data = Vec::new();
+        File::open(format!("tests/images/{}", name)).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(immeta::verify_complete(&mut Cursor::new(&data)).unwrap(), Completeness::Complete, "{}", name);
+
+        let mut extended = data.clone();
+        extended.extend_from_slice(b"trailer");
+        assert_eq!(immeta::verify_complete(&mut Cursor::new(&extended)).unwrap(),
+                   Completeness::TrailingData(7), "{}", name);
+
+        let truncated = &data[..data.len() - 1];
+        let result = immeta::verify_complete(&mut Cursor::new(truncated)).unwrap();
+        assert_eq!(result, Completeness::Truncated, "{}", name);
+        assert!(!result.is_complete());
+    }
+
+    // the image starts at the current position, which is restored afterwards
+    let data = [&b"xx"[..], &build_png(&[], 16, 8)].concat();
+    let mut r = Cursor::new(&data);
+    r.set_position(2);
+    assert_eq!(png::verify_complete(&mut r).unwrap(), Completeness::Complete);
+    assert_eq!(r.position(), 2);
+    assert_eq!(png::verify_complete(&mut Cursor::new(&data[2..22])).unwrap(), Completeness::Truncated);
+    assert!(png::verify_complete(&mut Cursor::new(&b"GIF89a\x10\x00\x08\x00\x00\x00\x00;"[..])).is_err());
+}
+
+#[test]
+fn test_image_data_ranges() {
+    use std::fs::File;
+    use std::io::{Cursor, Read};
+    use immeta::ByteRange;
+
+    for name in &["owlet.jpg", "owlet.png", "owlet.gif", "drop.gif", "cherry.webp"] {
+        let mut data = Vec::new();
+        File::open(format!("tests/images/{}", name)).unwrap().read_to_end(&mut data).unwrap();
+        let ranges = immeta::image_data_ranges(&mut Cursor::new(&data)).unwrap();
+        assert!(!ranges.is_empty(), "{}", name);
+        // ranges follow the header, are ordered and do not overlap
+        assert!(ranges[0].offset > 0, "{}", name);
+        assert!(ranges.windows(2).all(|w| w[0].end() <= w[1].offset), "{}", name);
+        assert!(ranges.last().unwrap().end() <= data.len() as u64, "{}", name);
+    }
+
+    let mut data = Vec::new();
+    File::open("tests/images/drop.gif").unwrap().read_to_end(&mut data).unwrap();
+    let ranges = gif::image_data_ranges(&mut &data[..]).unwrap();
+    assert_eq!(ranges.len(), 30);
+    // each range ends with the block terminator
+    assert!(ranges.iter().all(|r| data[r.end() as usize - 1] == 0));
+
+    // IDAT chunks are merged into one range
+    let data = build_png(&[(b"IDAT", &[0x78, 0x9c]), (b"IDAT", &[1, 2, 3]), (b"tEXt", b"a\0b")], 4, 4);
+    assert_eq!(png::image_data_ranges(&mut &data[..]).unwrap(), vec![ByteRange { offset: 33, len: 14 + 15 }]);
+
+    // scans end at the next marker, including restart markers and stuffed bytes
+    let data = [
+        &[0xff, 0xd8, 0xff, 0xda, 0x00, 0x03, 0x01][..],
+        &[0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56][..],
+        &[0xff, 0xda, 0x00, 0x02, 0x78, 0xff, 0xd9][..],
+    ].concat();
+    assert_eq!(jpeg::image_data_ranges(&mut &data[..]).unwrap(), vec![
+        ByteRange { offset: 7, len: 7 },
+        ByteRange { offset: 18, len: 1 },
+    ]);
+    // a truncated scan extends to the end of the stream
+    assert_eq!(jpeg::image_data_ranges(&mut &data[..12]).unwrap(), vec![ByteRange { offset: 7, len: 5 }]);
+
+    // frame bitstreams inside ANMF chunks are found
+    let data = build_webp(&[
+        (b"VP8X", &[0x12, 0, 0, 0, 99, 0, 0, 49, 0, 0]),
+        (b"EXIF", b"II*\0"),
+        (b"ANMF", &[
+            0, 0, 0, 0, 0, 0, 63, 0, 0, 31, 0, 0, 80, 0, 0, 0x00,
+            b'A', b'L', b'P', b'H', 1, 0, 0, 0, 0xff, 0,
+            b'V', b'P', b'8', b'L', 5, 0, 0, 0, 0x2f, 0x3f, 0xc0, 0x07, 0x00, 0
+        ]),
+    ]);
+    assert_eq!(webp::image_data_ranges(&mut &data[..]).unwrap(), vec![
+        ByteRange { offset: 66, len: 9 },
+        ByteRange { offset: 76, len: 13 },
+    ]);
+}
+
+#[test]
+fn test_validator() {
+    use std::fs::File;
+    use std::io::{Cursor, Read};
+    use immeta::validation::{Validator, Severity};
+
+    for name in &["owlet.jpg", "owlet.png", "owlet.gif", "drop.gif", "cherry.webp"] {
+        let mut data = Vec::new();
+        File::open(format!("tests/images/{}", name)).unwrap().read_to_end(&mut data).unwrap();
+        let report = Validator::new().strict(true).validate(&mut Cursor::new(&data)).unwrap();
+        assert!(report.findings.is_empty(), "{}: {:?}", name, report.findings);
+
+        // trailing data is only a warning in advisory mode
+        data.extend_from_slice(b"trailer");
+        let report = Validator::new().validate(&mut Cursor::new(&data)).unwrap();
+        assert!(report.is_valid(), "{}: {:?}", name, report.findings);
+        let report = Validator::new().strict(true).validate(&mut Cursor::new(&data)).unwrap();
+        assert!(!report.is_valid(), "{}: {:?}", name, report.findings);
+    }
+
+    let data = build_png(&[(b"gAMA", &[0, 0, 0xb1, 0x8f]), (b"IDAT", b"data"), (b"gAMA", &[0, 0, 0xb1, 0x8f])], 16, 8);
+    let report = Validator::new().validate(&mut Cursor::new(&data)).unwrap();
+    assert_eq!(report.format, immeta::ImageFormat::Png);
+    let codes: Vec<_> = report.findings.iter().map(|f| f.code).collect();
+    assert_eq!(codes, ["png.duplicate_chunk", "png.chunk_after_idat"]);
+    assert_eq!(report.findings[0].offset, Some(8 + 25 + 16 + 16));
+    assert_eq!(report.findings[0].message, "chunk must not be repeated (gAMA)");
+    assert_eq!(report.count(Severity::Error), 2);
+    assert!(report.into_result().unwrap_err().to_string().contains("[png.duplicate_chunk]"));
+
+    // JPEG cut off after the frame header
+    let data = build_jpeg(&[(0xfe, b"comment")], 16, 8);
+    let report = Validator::new().validate(&mut Cursor::new(&data[..data.len() - 14])).unwrap();
+    let codes: Vec<_> = report.findings.iter().map(|f| f.code).collect();
+    assert_eq!(codes, ["jpeg.missing_scan", "jpeg.missing_eoi"]);
+
+    assert!(Validator::new().validate(&mut Cursor::new(&b"not an image"[..])).is_err());
+}
+
+#[test]
+fn test_fuzz_inputs() {
+    use std::fs::File;
+    use std::io::Read;
+    use immeta::fuzz::{self, JpegInput, JpegSegment, PngInput, Chunk, GifInput, GifBlock, WebpInput};
+
+    let data = JpegInput {
+        segments: vec![
+            JpegSegment { marker: 0xc0, payload: vec![8, 0, 8, 0, 16, 1, 1, 0x11, 0] },
+            JpegSegment { marker: 0xda, payload: vec![1, 1, 0, 0, 63, 0] },
+        ],
+        entropy_data: vec![0x12, 0x34],
+    }.to_bytes();
+    assert_eq!(jpeg::parse_bytes(&data).unwrap().dimensions, (16u32, 8u32).into());
+
+    let data = PngInput {
+        width: 16, height: 8, bit_depth: 8, color_type: 2, interlace_method: 0,
+        chunks: vec![Chunk { chunk_type: *b"tEXt", payload: b"Title\0kitty".to_vec() }],
+    }.to_bytes();
+    let md = png::parse_bytes(&data).unwrap();
+    assert_eq!(md.dimensions, (16u32, 8u32).into());
+    assert_eq!(md.text("Title"), Some("kitty"));
+
+    let data = GifInput {
+        screen_descriptor: [16, 0, 8, 0, 0x80, 0, 0],
+        blocks: vec![
+            GifBlock::Extension { label: 0xfe, data: vec![b'x'; 300] },
+            GifBlock::Image { descriptor: [0, 0, 0, 0, 16, 0, 8, 0, 0], lzw_code_size: 2, data: vec![0; 10] },
+        ],
+    }.to_bytes();
+    let md = gif::parse_bytes(&data).unwrap();
+    assert_eq!(md.dimensions, (16u16, 8u16).into());
+    assert_eq!(md.frames_number(), 1);
+
+    let data = WebpInput {
+        chunks: vec![Chunk { chunk_type: *b"VP8X", payload: vec![0, 0, 0, 0, 15, 0, 0, 7, 0, 0] }],
+    }.to_bytes();
+    assert_eq!(webp::parse_bytes(&data).unwrap().dimensions(), (16u32, 8u32).into());
+
+    // truncated and corrupted inputs must result in errors rather than panics
+    fuzz::parse_all(WEBP_VP8_INTERFRAME);
+    let mut seed = 1u32;
+    for name in &["owlet.jpg", "owlet.png", "drop.gif", "cherry.webp"] {
+        let mut data = Vec::new();
+        File::open(format!("tests/images/{}", name)).unwrap().read_to_end(&mut data).unwrap();
+        data.truncate(4096);
+        for i in 0..data.len() / 16 {
+            fuzz::parse_all(&data[..i * 16]);
+        }
+        for _ in 0..200 {
+            let mut data = data.clone();
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let i = (seed >> 8) as usize % 512;
+            data[i] ^= (seed >> 24) as u8 | 1;
+            fuzz::parse_all(&data);
+        }
+    }
+}
+
+#[test]
+fn test_parse_bytes_matches_load() {
+    use std::fs::File;
+    use std::io::{BufReader, Read};
+    use immeta::LoadableMetadata;
+
+    // parse_bytes() and load() share the parsing code, but locate chunks and segments differently
+    fn check<T, F>(data: &[u8], parse_bytes: F)
+        where T: LoadableMetadata + PartialEq + ::std::fmt::Debug, F: Fn(&[u8]) -> immeta::Result<T>
+    {
+        // a tiny buffer makes the reader return data in small pieces
+        let loaded = T::load(&mut BufReader::with_capacity(3, data));
+        assert_eq!(parse_bytes(data).ok(), loaded.ok());
+    }
+
+    let mut seed = 1u32;
+    for name in &["owlet.jpg", "owlet.png", "drop.gif", "owlet.gif", "cherry.webp"] {
+        let mut data = Vec::new();
+        File::open(format!("tests/images/{}", name)).unwrap().read_to_end(&mut data).unwrap();
+        data.truncate(8192);
+        for i in 0..200 {
+            let mut data = data.clone();
+            if i > 0 {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let i = (seed >> 8) as usize % 512;
+                data[i] ^= (seed >> 24) as u8 | 1;
+                if seed & 1 == 0 {
+                    let len = (seed >> 4) as usize % data.len();
+                    data.truncate(len);
+                }
+            }
+            match &name[name.len() - 3..] {
+                "jpg" => check(&data, jpeg::parse_bytes),
+                "png" => check(&data, png::parse_bytes),
+                "gif" => check(&data, gif::parse_bytes),
+                _ => check(&data, webp::parse_bytes)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_polyglot() {
+    use std::fs::File;
+    use std::io::{Cursor, Read};
+    use immeta::ImageFormat;
+    use immeta::polyglot::{self, Kind, Match};
+
+    let mut jpeg = Vec::new();
+    File::open("tests/images/owlet.jpg").unwrap().read_to_end(&mut jpeg).unwrap();
+    let analysis = polyglot::analyze(&mut Cursor::new(&jpeg)).unwrap();
+    assert!(!analysis.is_polyglot());
+    assert_eq!(analysis.matches, [Match { kind: Kind::Image(ImageFormat::Jpeg), start: 0, end: jpeg.len() as u64 }]);
+
+    // a ZIP archive with a single stored file "a", appended to the image
+    let mut zip = b"PK\x03\x04\x0a\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\0\0\0\x01\0\0\0\x01\0\0\0ax".to_vec();
+    let cd_offset = zip.len() as u8;
+    zip.extend_from_slice(b"PK\x01\x02\x0a\0\x0a\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\0\0\0\x01\0\0\0\x01\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0a");
+    zip.extend_from_slice(&[b'P', b'K', 5, 6, 0, 0, 0, 0, 1, 0, 1, 0, 47, 0, 0, 0, cd_offset, 0, 0, 0, 0, 0]);
+    let png = build_png(&[(b"tEXt", b"Comment\0<SCRIPT>alert(1)</script>")], 16, 8);
+    let mut data = jpeg.clone();
+    data.extend_from_slice(&png);
+    data.extend_from_slice(&zip);
+    let analysis = polyglot::analyze(&mut Cursor::new(&data)).unwrap();
+    assert!(analysis.is_polyglot());
+    let png_start = jpeg.len() as u64;
+    let zip_start = png_start + png.len() as u64;
+    let html_start = png_start + 8 + 25 + 8 + 8;
+    assert_eq!(analysis.matches, [
+        Match { kind: Kind::Image(ImageFormat::Jpeg), start: 0, end: png_start },
+        Match { kind: Kind::Image(ImageFormat::Png), start: png_start, end: zip_start },
+        Match { kind: Kind::Html, start: html_start, end: data.len() as u64 },
+        Match { kind: Kind::Zip, start: zip_start, end: data.len() as u64 },
+    ]);
+    assert_eq!(analysis.find(Kind::Zip).unwrap().len(), zip.len() as u64);
+
+    // a GIF image which is also a JavaScript program
+    let mut data = b"GIF89a/*\x01\0\0\0\0,\0\0\0\0\x01\0\x01\0\0\x02\x02\x44\x01\0;".to_vec();
+    data.extend_from_slice(b"*/=1;alert(1);");
+    let analysis = polyglot::analyze(&mut Cursor::new(&data)).unwrap();
+    assert_eq!(analysis.matches, [
+        Match { kind: Kind::Image(ImageFormat::Gif), start: 0, end: data.len() as u64 - 14 },
+        Match { kind: Kind::JavaScript, start: 0, end: data.len() as u64 },
+    ]);
+
+    // a PDF document whose header is hidden in a JPEG comment
+    let mut data = build_jpeg(&[(0xfe, b"%PDF-1.4\n")], 16, 8);
+    let jpeg_len = data.len() as u64;
+    data.extend_from_slice(b"1 0 obj\n<<>>\nendobj\n%%EOF\n");
+    let analysis = polyglot::analyze(&mut Cursor::new(&data)).unwrap();
+    assert_eq!(analysis.matches, [
+        Match { kind: Kind::Image(ImageFormat::Jpeg), start: 0, end: jpeg_len },
+        Match { kind: Kind::Pdf, start: 6, end: data.len() as u64 - 1 },
+    ]);
+
+    let analysis = polyglot::analyze(&mut Cursor::new(&b"not an image"[..])).unwrap();
+    assert!(analysis.matches.is_empty());
+}
+
+#[cfg(feature = "audio")]
+#[test]
+fn test_audio_cover_art() {
+    use std::io::Cursor;
+    use immeta::ImageFormat;
+    use immeta::audio::{self, Source};
+
+    fn be32(n: usize) -> [u8; 4] {
+        [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+    }
+
+    fn mp4_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut result = be32(8 + payload.len()).to_vec();
+        result.extend_from_slice(box_type);
+        result.extend_from_slice(payload);
+        result
+    }
+
+    // MP3 with an ID3v2.3 tag containing a back cover and a front cover
+    let png = build_png(&[], 16, 8);
+    let jpeg = build_jpeg(&[], 32, 24);
+    let mut frames = Vec::new();
+    for &(picture_type, mime_type, image) in &[(4u8, &b"image/jpeg"[..], &jpeg), (3, b"image/png", &png)] {
+        let mut frame = vec![0];
+        frame.extend_from_slice(mime_type);
+        frame.push(0);
+        frame.push(picture_type);
+        frame.extend_from_slice(b"cover\0");
+        frame.extend_from_slice(image);
+        frames.extend_from_slice(b"APIC");
+        frames.extend_from_slice(&be32(frame.len()));
+        frames.extend_from_slice(&[0, 0]);
+        frames.extend_from_slice(&frame);
+    }
+    frames.extend_from_slice(&[0; 16]);
+    let size = frames.len();
+    let mut data = b"ID3\x03\x00\x00".to_vec();
+    data.extend_from_slice(&[(size >> 21) as u8 & 0x7f, (size >> 14) as u8 & 0x7f,
+                             (size >> 7) as u8 & 0x7f, size as u8 & 0x7f]);
+    data.extend_from_slice(&frames);
+    data.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+
+    let pictures = audio::load_pictures(&mut Cursor::new(&data)).unwrap();
+    assert_eq!(pictures.len(), 2);
+    assert_eq!(pictures[0].source, Source::Id3);
+    assert_eq!(pictures[0].format(), Some(ImageFormat::Jpeg));
+    assert_eq!(pictures[0].size, jpeg.len() as u64);
+    assert_eq!(pictures[1].mime_type, Some("image/png".to_owned()));
+    assert_eq!(pictures[1].description, "cover");
+    let cover = audio::load_front_cover(&mut Cursor::new(&data)).unwrap().unwrap();
+    assert!(cover.is_front_cover());
+    assert_eq!(cover.dimensions(), Some(Dimensions { width: 16, height: 8 }));
+
+    // FLAC with a STREAMINFO block and a PICTURE block
+    let mut picture = be32(3).to_vec();
+    picture.extend_from_slice(&be32(10));
+    picture.extend_from_slice(b"image/jpeg");
+    picture.extend_from_slice(&be32(0));
+    picture.extend_from_slice(&[0; 16]);
+    picture.extend_from_slice(&be32(jpeg.len()));
+    picture.extend_from_slice(&jpeg);
+    let mut data = b"fLaC\x00\x00\x00\x22".to_vec();
+    data.extend_from_slice(&[0; 34]);
+    data.extend_from_slice(&be32(picture.len() | 0x8600_0000));
+    data.extend_from_slice(&picture);
+
+    let cover = audio::load_front_cover(&mut Cursor::new(&data)).unwrap().unwrap();
+    assert_eq!(cover.source, Source::Flac);
+    assert_eq!(cover.picture_type, Some(3));
+    assert_eq!(cover.dimensions(), Some(Dimensions { width: 32, height: 24 }));
+
+    // MP4 with iTunes metadata
+    let mut covr = vec![0, 0, 0, 14, 0, 0, 0, 0];
+    covr.extend_from_slice(&png);
+    let meta = [&[0u8; 4][..], &mp4_box(b"hdlr", &[0; 25]),
+                &mp4_box(b"ilst", &mp4_box(b"covr", &mp4_box(b"data", &covr)))].concat();
+    let mut data = mp4_box(b"ftyp", b"M4A \0\0\0\0");
+    data.extend_from_slice(&mp4_box(b"mdat", &[0; 8]));
+    data.extend_from_slice(&mp4_box(b"moov", &mp4_box(b"udta", &mp4_box(b"meta", &meta))));
+
+    let pictures = audio::load_pictures(&mut Cursor::new(&data)).unwrap();
+    assert_eq!(pictures.len(), 1);
+    assert_eq!(pictures[0].source, Source::Mp4);
+    assert_eq!(pictures[0].picture_type, None);
+    assert_eq!(pictures[0].mime_type, Some("image/png".to_owned()));
+    assert_eq!(pictures[0].format(), Some(ImageFormat::Png));
+
+    assert!(audio::load_pictures(&mut Cursor::new(&b"RIFF\0\0\0\0WAVE"[..])).is_err());
+}
+
+fn build_pdf(objects: &[&str], trailer: &str) -> Vec<u8> {
+    let mut data = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(data.len());
+        data.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+    let xref = data.len();
+    data.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f\r\n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        data.extend_from_slice(format!("{:010} 00000 n\r\n", offset).as_bytes());
+    }
+    data.extend_from_slice(format!("trailer\n<< /Size {} {} >>\nstartxref\n{}\n%%EOF\n",
+                                   objects.len() + 1, trailer, xref).as_bytes());
+    data
+}
+
+#[test]
+fn test_pdf_first_page() {
+    use std::io::Cursor;
+
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R] /Count 2 /MediaBox [0 0 595.28 841.89] /Rotate 90 >>",
+        "<< /Type /Pages /Parent 2 0 R /Kids [4 0 R 5 0 R] /Count 2 >>",
+        "<< /Type /Page /Parent 3 0 R /Resources << /Font << /F1 (a\\)b) >> >> >>",
+        "<< /Type /Page /Parent 3 0 R /MediaBox [0 0 100 100] >>",
+    ], "/Root 1 0 R % comment\n/ID [<0123> <4567>]");
+    let md = pdf::parse_bytes(&data).unwrap();
+    assert_eq!(md.version, (1, 4));
+    assert!(!md.encrypted);
+    assert_eq!(md.page_count, 2);
+    assert!(!md.is_single_page());
+    assert_eq!(md.media_box, pdf::Rectangle { left: 0.0, bottom: 0.0, right: 595.28, top: 841.89 });
+    assert_eq!(md.rotation, 90);
+    assert_eq!(md.dimensions, Dimensions { width: 842, height: 595 });
+    assert_eq!(md, <pdf::Metadata as immeta::LoadableMetadata>::load(&mut &data[..]).unwrap());
+    assert_eq!(md, <pdf::Metadata as immeta::LoadableMetadata>::load_from_seek(&mut Cursor::new(&data)).unwrap());
+
+    // an encrypted document with an indirect media box
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox 4 0 R /UserUnit 2 >>",
+        "[0 100 200 0]",
+        "<< /Filter /Standard /V 2 /R 3 >>",
+    ], "/Root 1 0 R /Encrypt 5 0 R");
+    let md = pdf::parse_bytes(&data).unwrap();
+    assert!(md.encrypted);
+    assert!(md.is_single_page());
+    assert_eq!(md.media_box, pdf::Rectangle { left: 0.0, bottom: 0.0, right: 200.0, top: 100.0 });
+    assert_eq!(md.user_unit, 2.0);
+    assert_eq!(md.dimensions, Dimensions { width: 400, height: 200 });
+
+    let data = build_pdf(&["<< /Type /Catalog /Pages 2 0 R >>", "<< /Type /Pages /Kids [] /Count 0 >>"],
+                         "/Root 1 0 R");
+    assert!(pdf::parse_bytes(&data).is_err());
+    assert!(pdf::parse_bytes(&data[..data.len() - 40]).is_err());
+    assert!(pdf::parse_bytes(b"GIF89a").is_err());
+}
+
+#[cfg(feature = "zlib")]
+#[test]
+fn test_pdf_object_streams() {
+    extern crate flate2;
+
+    use std::io::{Cursor, Write};
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    let mut data = b"%PDF-1.5\n".to_vec();
+    let mut offsets = vec![0];
+    offsets.push(data.len());
+    data.extend_from_slice(b"1 0 obj\n<< /Type /Catalog /Pages 2 0 R >>\nendobj\n");
+
+    // the page tree is stored in object stream 3
+    let objects = b"2 0 4 66 << /Type /Pages /Kids [4 0 R] /Count 1 /MediaBox [0 0 612 792] >> << /Type /Page /Parent 2 0 R >>";
+    let stream = compress(objects);
+    let stream_len = stream.len();
+    offsets.push(data.len());
+    data.extend_from_slice(format!("3 0 obj\n<< /Type /ObjStm /N 2 /First 9 /Filter /FlateDecode /Length {} >>\nstream\n",
+                                   stream.len()).as_bytes());
+    data.extend_from_slice(&stream);
+    data.extend_from_slice(b"\nendstream\nendobj\n");
+
+    // the cross-reference stream has rows of 4 bytes encoded with PNG Up predictor
+    let xref = data.len();
+    let rows: Vec<[u8; 4]> = vec![
+        [0, 0, 0, 0],
+        [1, 0, offsets[1] as u8, 0],
+        [2, 0, 3, 0],
+        [1, 0, offsets[2] as u8, 0],
+        [2, 0, 3, 1],
+        [1, 0, xref as u8, 0],
+    ];
+    let mut predicted = Vec::new();
+    let mut prev = [0u8; 4];
+    for row in &rows {
+        predicted.push(2);
+        predicted.extend(row.iter().zip(&prev).map(|(&b, &p)| b.wrapping_sub(p)));
+        prev = *row;
+    }
+    let stream = compress(&predicted);
+    data.extend_from_slice(format!("5 0 obj\n<< /Type /XRef /Size 6 /W [1 2 1] /Root 1 0 R /Filter /FlateDecode \
+                                    /DecodeParms << /Columns 4 /Predictor 12 >> /Length {} >>\nstream\r\n",
+                                   stream.len()).as_bytes());
+    data.extend_from_slice(&stream);
+    data.extend_from_slice(format!("\nendstream\nendobj\nstartxref\n{}\n%%EOF\n", xref).as_bytes());
+    assert!(xref < 256);
+
+    let md = pdf::parse_bytes(&data).unwrap();
+    assert_eq!(md.version, (1, 5));
+    assert_eq!(md.page_count, 1);
+    assert_eq!(md.dimensions, Dimensions { width: 612, height: 792 });
+    assert_eq!(md, <pdf::Metadata as immeta::LoadableMetadata>::load_from_seek(&mut Cursor::new(&data)).unwrap());
+
+    // the object stream length is stored in the object stream itself
+    let dict = format!("/Type /ObjStm /N 2 /First 9 /Filter /FlateDecode /Length {} ", stream_len);
+    let cyclic = format!("{:1$}", "/N 2 /First 9 /Filter /FlateDecode /Length 2 0 R", dict.len());
+    let i = data.windows(dict.len()).position(|w| w == dict.as_bytes()).unwrap();
+    let malformed = [&data[..i], cyclic.as_bytes(), &data[i + dict.len()..]].concat();
+    assert!(pdf::parse_bytes(&malformed).is_err());
+    assert!(<pdf::Metadata as immeta::LoadableMetadata>::load_from_seek(&mut Cursor::new(&malformed)).is_err());
+
+    // the number of predictor columns is checked against the size of the stream
+    let columns = b"/Columns 4 ";
+    let i = data.windows(columns.len()).position(|w| w == columns).unwrap();
+    for &malformed in &["/Columns 1000000000000000 ", "/Columns 0 ", "/Columns 25 "] {
+        let data = [&data[..i], malformed.as_bytes(), &data[i + columns.len()..]].concat();
+        assert!(pdf::parse_bytes(&data).is_err(), "{}", malformed);
+    }
+}